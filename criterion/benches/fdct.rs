@@ -39,6 +39,19 @@ fn criterion_benchmark(c: &mut Criterion) {
         })
     });
 
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    group.bench_function("fdct neon", |b| {
+        b.iter(|| {
+            use jpeg_encoder::fdct_neon;
+
+            let mut input = INPUT1.clone();
+            fdct_neon(
+                black_box(&mut input),
+            );
+            black_box(&input);
+        })
+    });
+
     group.finish();
 }
 
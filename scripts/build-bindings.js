@@ -6,8 +6,9 @@
  * This script:
  * 1. Generates ESM bindings using wasm-bindgen with --target web
  * 2. Generates CommonJS bindings using wasm-bindgen with --target bundler
- * 3. Creates wrapper files for both formats
- * 4. Copies TypeScript definitions
+ * 3. Generates both bindings for the SIMD build of the WASM module
+ * 4. Creates wrapper files for both formats, which load the SIMD build if it is supported
+ * 5. Copies TypeScript definitions
  */
 
 import { execSync } from 'child_process';
@@ -20,6 +21,10 @@ const __dirname = dirname(__filename);
 const rootDir = join(__dirname, '..');
 const pkgDir = join(rootDir, 'pkg');
 const wasmPath = join(rootDir, 'target/wasm32-unknown-unknown/release/jpeg_encoder_wasm.wasm');
+const simdWasmPath = join(rootDir, 'target/simd/wasm32-unknown-unknown/release/jpeg_encoder_wasm.wasm');
+
+// Smallest module using a SIMD instruction, it only validates if WebAssembly SIMD is supported
+const simdTestModule = '[0, 97, 115, 109, 1, 0, 0, 0, 1, 5, 1, 96, 0, 1, 123, 3, 2, 1, 0, 10, 10, 1, 8, 0, 65, 0, 253, 15, 253, 98, 11]';
 
 // Clean and create output directories
 console.log('Creating output directories...');
@@ -41,18 +46,55 @@ execSync(
   { stdio: 'inherit' }
 );
 
+// Generate ESM bindings for the SIMD build
+console.log('Generating ESM SIMD bindings...');
+execSync(
+  `wasm-bindgen ${simdWasmPath} --out-dir ${join(pkgDir, 'esm')} --target web --typescript --out-name jpeg_encoder_simd`,
+  { stdio: 'inherit' }
+);
+
+// Generate CommonJS bindings for the SIMD build
+console.log('Generating CommonJS SIMD bindings...');
+execSync(
+  `wasm-bindgen ${simdWasmPath} --out-dir ${join(pkgDir, 'cjs')} --target nodejs --typescript --out-name jpeg_encoder_simd`,
+  { stdio: 'inherit' }
+);
+
 // Create ESM wrapper (index.js in pkg/esm)
 console.log('Creating ESM wrapper...');
-const esmWrapper = `import initWasm, { StreamingJpegEncoder as RawStreamingJpegEncoder, WasmColorType } from "./jpeg_encoder.js";
+const esmWrapper = `import * as baselineBindings from "./jpeg_encoder.js";
+
+const { WasmColorType } = baselineBindings;
+
+const SIMD_TEST_MODULE = new Uint8Array(${simdTestModule});
+
+let bindings = baselineBindings;
+
+function simdSupported() {
+    try {
+        return WebAssembly.validate(SIMD_TEST_MODULE);
+    } catch {
+        return false;
+    }
+}
 
 /**
  * Initialize the underlying WebAssembly module.
  *
  * This must be awaited before constructing a {@link StreamingJpegEncoder}.
- * By default it loads the bundled \`jpeg_encoder_bg.wasm\` next to this file.
+ * By default it loads the bundled \`jpeg_encoder_simd_bg.wasm\` next to this file
+ * if WebAssembly SIMD is supported and \`jpeg_encoder_bg.wasm\` otherwise.
+ * An explicitly passed module has to be the baseline \`jpeg_encoder_bg.wasm\`.
  */
 export async function init(module) {
-    await initWasm(module);
+    if (module === undefined && simdSupported()) {
+        const simdBindings = await import("./jpeg_encoder_simd.js");
+        await simdBindings.default();
+        bindings = simdBindings;
+    } else {
+        await baselineBindings.default(module);
+        bindings = baselineBindings;
+    }
 }
 
 // Maintain compatibility with the wasm-bindgen generated default export.
@@ -66,7 +108,7 @@ export class StreamingJpegEncoder {
     #inner;
 
     constructor(width, height, color_type, quality) {
-        this.#inner = new RawStreamingJpegEncoder(width, height, color_type, quality);
+        this.#inner = new bindings.StreamingJpegEncoder(width, height, color_type, quality);
     }
 
     #requireActive() {
@@ -114,7 +156,7 @@ export class StreamingJpegEncoder {
      * This is a static method for advanced use cases.
      */
     static header_bytes(width, height, color_type, quality) {
-        return RawStreamingJpegEncoder.header_bytes(width, height, color_type, quality);
+        return bindings.StreamingJpegEncoder.header_bytes(width, height, color_type, quality);
     }
 
     /**
@@ -122,7 +164,7 @@ export class StreamingJpegEncoder {
      * This is a static method for advanced use cases.
      */
     static footer_bytes() {
-        return RawStreamingJpegEncoder.footer_bytes();
+        return bindings.StreamingJpegEncoder.footer_bytes();
     }
 }
 
@@ -141,7 +183,8 @@ export type { InitInput, InitOutput } from './jpeg_encoder.js';
  * Initialize the underlying WebAssembly module.
  *
  * This must be awaited before constructing a {@link StreamingJpegEncoder}.
- * By default it loads the bundled \`jpeg_encoder_bg.wasm\` next to this file.
+ * By default it loads the bundled \`jpeg_encoder_simd_bg.wasm\` next to this file
+ * if WebAssembly SIMD is supported and \`jpeg_encoder_bg.wasm\` otherwise.
  *
  * @param module - Optional WebAssembly module or bytes to initialize with
  * @returns Promise that resolves when initialization is complete
@@ -156,8 +199,21 @@ writeFileSync(join(pkgDir, 'esm', 'index.d.ts'), esmIndexDts);
 console.log('Creating CommonJS wrapper...');
 const cjsWrapper = `"use strict";
 
-// For nodejs target, wasm-bindgen auto-loads the WASM module
-const { StreamingJpegEncoder: RawStreamingJpegEncoder, WasmColorType } = require("./jpeg_encoder.js");
+const SIMD_TEST_MODULE = new Uint8Array(${simdTestModule});
+
+function simdSupported() {
+    try {
+        return WebAssembly.validate(SIMD_TEST_MODULE);
+    } catch {
+        return false;
+    }
+}
+
+// For nodejs target, wasm-bindgen auto-loads the WASM module.
+// The SIMD build is used if the runtime supports it.
+const { StreamingJpegEncoder: RawStreamingJpegEncoder, WasmColorType } = simdSupported()
+    ? require("./jpeg_encoder_simd.js")
+    : require("./jpeg_encoder.js");
 
 /**
  * Initialize the underlying WebAssembly module.
@@ -280,7 +336,8 @@ export type { InitInput, InitOutput } from './esm/jpeg_encoder.js';
  * Initialize the underlying WebAssembly module.
  *
  * This must be awaited before constructing a {@link StreamingJpegEncoder}.
- * By default it loads the bundled \`jpeg_encoder_bg.wasm\` next to this file.
+ * By default it loads the bundled \`jpeg_encoder_simd_bg.wasm\` next to this file
+ * if WebAssembly SIMD is supported and \`jpeg_encoder_bg.wasm\` otherwise.
  *
  * @param module - Optional WebAssembly module or bytes to initialize with
  * @returns Promise that resolves when initialization is complete
@@ -387,6 +444,10 @@ Initialize the WebAssembly module. Must be called before creating encoders.
 
 - **module** (optional): WebAssembly module or bytes. Auto-loaded in ESM, required in CommonJS.
 
+If the JavaScript runtime supports WebAssembly SIMD, the auto-loaded module is
+\`jpeg_encoder_simd_bg.wasm\`, a build using SIMD instructions, otherwise the baseline
+\`jpeg_encoder_bg.wasm\` is used. Both produce identical JPEG files.
+
 ### \`class StreamingJpegEncoder\`
 
 #### \`constructor(width, height, colorType, quality)\`
@@ -0,0 +1,1240 @@
+/*
+ * Arithmetic entropy coding (QM-coder) as described in Annex D, F.1.4 and G.1.3 of T.81.
+ *
+ * The implementation follows libjpeg's jcarith.c and jaricom.c.
+ */
+
+use crate::encoder::Component;
+use crate::marker::Marker;
+use crate::progressive::{for_each_mcu, BlockLayout, ScanInfo};
+use crate::writer::{JfifWrite, JfifWriter};
+use crate::EncodingError;
+
+use alloc::vec::Vec;
+
+const DC_STAT_BINS: usize = 64;
+const AC_STAT_BINS: usize = 256;
+
+/// Index of the probability estimation state with a fixed probability of 0.5
+const FIXED_STATE: u8 = 113;
+
+macro_rules! qe {
+    ($qe:expr, $next_lps:expr, $next_mps:expr, $switch_mps:expr) => {
+        ($qe << 16) | ($next_mps << 8) | ($switch_mps << 7) | $next_lps
+    };
+}
+
+/// Probability estimation state machine
+///
+/// Table D.3 with an additional fixed state. Each entry contains the Qe value,
+/// the next state index after an MPS and after an LPS and the MPS switch flag.
+static QE_TABLE: [u32; 114] = [
+    qe!(0x5a1d, 1, 1, 1),
+    qe!(0x2586, 14, 2, 0),
+    qe!(0x1114, 16, 3, 0),
+    qe!(0x080b, 18, 4, 0),
+    qe!(0x03d8, 20, 5, 0),
+    qe!(0x01da, 23, 6, 0),
+    qe!(0x00e5, 25, 7, 0),
+    qe!(0x006f, 28, 8, 0),
+    qe!(0x0036, 30, 9, 0),
+    qe!(0x001a, 33, 10, 0),
+    qe!(0x000d, 35, 11, 0),
+    qe!(0x0006, 9, 12, 0),
+    qe!(0x0003, 10, 13, 0),
+    qe!(0x0001, 12, 13, 0),
+    qe!(0x5a7f, 15, 15, 1),
+    qe!(0x3f25, 36, 16, 0),
+    qe!(0x2cf2, 38, 17, 0),
+    qe!(0x207c, 39, 18, 0),
+    qe!(0x17b9, 40, 19, 0),
+    qe!(0x1182, 42, 20, 0),
+    qe!(0x0cef, 43, 21, 0),
+    qe!(0x09a1, 45, 22, 0),
+    qe!(0x072f, 46, 23, 0),
+    qe!(0x055c, 48, 24, 0),
+    qe!(0x0406, 49, 25, 0),
+    qe!(0x0303, 51, 26, 0),
+    qe!(0x0240, 52, 27, 0),
+    qe!(0x01b1, 54, 28, 0),
+    qe!(0x0144, 56, 29, 0),
+    qe!(0x00f5, 57, 30, 0),
+    qe!(0x00b7, 59, 31, 0),
+    qe!(0x008a, 60, 32, 0),
+    qe!(0x0068, 62, 33, 0),
+    qe!(0x004e, 63, 34, 0),
+    qe!(0x003b, 32, 35, 0),
+    qe!(0x002c, 33, 9, 0),
+    qe!(0x5ae1, 37, 37, 1),
+    qe!(0x484c, 64, 38, 0),
+    qe!(0x3a0d, 65, 39, 0),
+    qe!(0x2ef1, 67, 40, 0),
+    qe!(0x261f, 68, 41, 0),
+    qe!(0x1f33, 69, 42, 0),
+    qe!(0x19a8, 70, 43, 0),
+    qe!(0x1518, 72, 44, 0),
+    qe!(0x1177, 73, 45, 0),
+    qe!(0x0e74, 74, 46, 0),
+    qe!(0x0bfb, 75, 47, 0),
+    qe!(0x09f8, 77, 48, 0),
+    qe!(0x0861, 78, 49, 0),
+    qe!(0x0706, 79, 50, 0),
+    qe!(0x05cd, 48, 51, 0),
+    qe!(0x04de, 50, 52, 0),
+    qe!(0x040f, 50, 53, 0),
+    qe!(0x0363, 51, 54, 0),
+    qe!(0x02d4, 52, 55, 0),
+    qe!(0x025c, 53, 56, 0),
+    qe!(0x01f8, 54, 57, 0),
+    qe!(0x01a4, 55, 58, 0),
+    qe!(0x0160, 56, 59, 0),
+    qe!(0x0125, 57, 60, 0),
+    qe!(0x00f6, 58, 61, 0),
+    qe!(0x00cb, 59, 62, 0),
+    qe!(0x00ab, 61, 63, 0),
+    qe!(0x008f, 61, 32, 0),
+    qe!(0x5b12, 65, 65, 1),
+    qe!(0x4d04, 80, 66, 0),
+    qe!(0x412c, 81, 67, 0),
+    qe!(0x37d8, 82, 68, 0),
+    qe!(0x2fe8, 83, 69, 0),
+    qe!(0x293c, 84, 70, 0),
+    qe!(0x2379, 86, 71, 0),
+    qe!(0x1edf, 87, 72, 0),
+    qe!(0x1aa9, 87, 73, 0),
+    qe!(0x174e, 72, 74, 0),
+    qe!(0x1424, 72, 75, 0),
+    qe!(0x119c, 74, 76, 0),
+    qe!(0x0f6b, 74, 77, 0),
+    qe!(0x0d51, 75, 78, 0),
+    qe!(0x0bb6, 77, 79, 0),
+    qe!(0x0a40, 77, 48, 0),
+    qe!(0x5832, 80, 81, 1),
+    qe!(0x4d1c, 88, 82, 0),
+    qe!(0x438e, 89, 83, 0),
+    qe!(0x3bdd, 90, 84, 0),
+    qe!(0x34ee, 91, 85, 0),
+    qe!(0x2eae, 92, 86, 0),
+    qe!(0x299a, 93, 87, 0),
+    qe!(0x2516, 86, 71, 0),
+    qe!(0x5570, 88, 89, 1),
+    qe!(0x4ca9, 95, 90, 0),
+    qe!(0x44d9, 96, 91, 0),
+    qe!(0x3e22, 97, 92, 0),
+    qe!(0x3824, 99, 93, 0),
+    qe!(0x32b4, 99, 94, 0),
+    qe!(0x2e17, 93, 86, 0),
+    qe!(0x56a8, 95, 96, 1),
+    qe!(0x4f46, 101, 97, 0),
+    qe!(0x47e5, 102, 98, 0),
+    qe!(0x41cf, 103, 99, 0),
+    qe!(0x3c3d, 104, 100, 0),
+    qe!(0x375e, 99, 93, 0),
+    qe!(0x5231, 105, 102, 0),
+    qe!(0x4c0f, 106, 103, 0),
+    qe!(0x4639, 107, 104, 0),
+    qe!(0x415e, 103, 99, 0),
+    qe!(0x5627, 105, 106, 1),
+    qe!(0x50e7, 108, 107, 0),
+    qe!(0x4b85, 109, 103, 0),
+    qe!(0x5597, 110, 109, 0),
+    qe!(0x504f, 111, 107, 0),
+    qe!(0x5a10, 110, 111, 1),
+    qe!(0x5522, 112, 109, 0),
+    qe!(0x59eb, 112, 111, 1),
+    qe!(0x5a1d, 113, 113, 0),
+];
+
+/// # Conditioning of the arithmetic coder statistics
+///
+/// The values are written to the DAC segment. See F.1.4.4 of T.81 for details.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ArithmeticConditioning {
+    /// Lower bound L of the DC difference conditioning (0 - 15)
+    pub dc_lower: u8,
+
+    /// Upper bound U of the DC difference conditioning (L - 15)
+    pub dc_upper: u8,
+
+    /// Threshold Kx of the AC magnitude conditioning (1 - 63)
+    pub ac_kx: u8,
+}
+
+impl ArithmeticConditioning {
+    /// Create new conditioning values
+    pub fn new(dc_lower: u8, dc_upper: u8, ac_kx: u8) -> ArithmeticConditioning {
+        ArithmeticConditioning {
+            dc_lower,
+            dc_upper,
+            ac_kx,
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.dc_lower <= self.dc_upper && self.dc_upper <= 15 && (1..=63).contains(&self.ac_kx)
+    }
+}
+
+impl Default for ArithmeticConditioning {
+    /// The default conditioning of T.81 (L = 0, U = 1, Kx = 5)
+    fn default() -> Self {
+        ArithmeticConditioning::new(0, 1, 5)
+    }
+}
+
+/// The QM-coder of Annex D
+struct QmCoder<'a, W: JfifWrite> {
+    writer: &'a mut JfifWriter<W>,
+    c: u32,
+    a: u32,
+    // Number of stacked 0xFF bytes which might be modified by a carry
+    sc: u32,
+    // Number of pending 0x00 bytes which are dropped at the end of the data
+    zc: u32,
+    ct: i32,
+    // Last byte which might be modified by a carry, -1 if there is none
+    buffer: i32,
+}
+
+impl<'a, W: JfifWrite> QmCoder<'a, W> {
+    fn new(writer: &'a mut JfifWriter<W>) -> Self {
+        QmCoder {
+            writer,
+            c: 0,
+            a: 0x10000,
+            sc: 0,
+            zc: 0,
+            ct: 11,
+            buffer: -1,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.c = 0;
+        self.a = 0x10000;
+        self.sc = 0;
+        self.zc = 0;
+        self.ct = 11;
+        self.buffer = -1;
+    }
+
+    fn write_zeros(&mut self) -> Result<(), EncodingError> {
+        while self.zc > 0 {
+            self.writer.write_u8(0x00)?;
+            self.zc -= 1;
+        }
+        Ok(())
+    }
+
+    fn write_byte(&mut self, byte: u8) -> Result<(), EncodingError> {
+        self.writer.write_u8(byte)?;
+
+        if byte == 0xFF {
+            self.writer.write_u8(0x00)?;
+        }
+
+        Ok(())
+    }
+
+    /// Output the buffered byte with an added carry
+    fn carry(&mut self) -> Result<(), EncodingError> {
+        if self.buffer >= 0 {
+            self.write_zeros()?;
+            self.write_byte(self.buffer as u8 + 1)?;
+        }
+
+        // The carry converts the stacked 0xFF bytes to 0x00
+        self.zc += self.sc;
+        self.sc = 0;
+
+        Ok(())
+    }
+
+    /// Output the buffered byte and stacked 0xFF bytes without a carry
+    fn no_carry(&mut self) -> Result<(), EncodingError> {
+        if self.buffer == 0 {
+            self.zc += 1;
+        } else if self.buffer > 0 {
+            self.write_zeros()?;
+            self.write_byte(self.buffer as u8)?;
+        }
+
+        if self.sc > 0 {
+            self.write_zeros()?;
+
+            while self.sc > 0 {
+                self.write_byte(0xFF)?;
+                self.sc -= 1;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encode a binary decision with the given probability estimation state
+    ///
+    /// Bit 7 of the state contains the MPS, the lower bits the index into the Qe table.
+    fn encode(&mut self, state: &mut u8, value: bool) -> Result<(), EncodingError> {
+        let entry = QE_TABLE[usize::from(*state & 0x7F)];
+
+        let next_lps = (entry & 0xFF) as u8;
+        let next_mps = ((entry >> 8) & 0xFF) as u8;
+        let qe = entry >> 16;
+
+        self.a -= qe;
+
+        if value != (*state >> 7 == 1) {
+            // Encode the less probable symbol
+            if self.a >= qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *state = (*state & 0x80) ^ next_lps;
+        } else {
+            // Encode the more probable symbol
+            if self.a >= 0x8000 {
+                return Ok(());
+            }
+            if self.a < qe {
+                self.c += self.a;
+                self.a = qe;
+            }
+            *state = (*state & 0x80) ^ next_mps;
+        }
+
+        // Renormalization and output of data as described in D.1.6
+        loop {
+            self.a <<= 1;
+            self.c <<= 1;
+            self.ct -= 1;
+
+            if self.ct == 0 {
+                let temp = self.c >> 19;
+
+                if temp > 0xFF {
+                    self.carry()?;
+                    self.buffer = (temp & 0xFF) as i32;
+                } else if temp == 0xFF {
+                    self.sc += 1;
+                } else {
+                    self.no_carry()?;
+                    self.buffer = temp as i32;
+                }
+
+                self.c &= 0x7FFFF;
+                self.ct += 8;
+            }
+
+            if self.a >= 0x8000 {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Terminate the entropy coded data as described in D.1.8
+    fn flush(&mut self) -> Result<(), EncodingError> {
+        // Find the value in the interval with the most trailing zero bits
+        let temp = (self.a - 1 + self.c) & 0xFFFF0000;
+        self.c = if temp < self.c { temp + 0x8000 } else { temp };
+
+        self.c <<= self.ct;
+
+        if self.c & 0xF8000000 != 0 {
+            self.carry()?;
+        } else {
+            self.no_carry()?;
+        }
+
+        // Trailing zero bytes can be omitted
+        if self.c & 0x7FFF800 != 0 {
+            self.write_zeros()?;
+            self.write_byte(((self.c >> 19) & 0xFF) as u8)?;
+
+            if self.c & 0x7F800 != 0 {
+                self.write_byte(((self.c >> 11) & 0xFF) as u8)?;
+            }
+        }
+
+        self.reset();
+
+        Ok(())
+    }
+}
+
+struct ArithmeticEncoder<'a, W: JfifWrite> {
+    coder: QmCoder<'a, W>,
+    dc_stats: [[u8; DC_STAT_BINS]; 2],
+    ac_stats: [[u8; AC_STAT_BINS]; 2],
+    fixed_bin: u8,
+    conditioning: &'a [ArithmeticConditioning; 2],
+
+    // DC context and last DC value of each component
+    dc_context: [usize; 4],
+    last_dc: [i16; 4],
+}
+
+impl<'a, W: JfifWrite> ArithmeticEncoder<'a, W> {
+    /// Encode a DC difference as described in F.1.4.1
+    fn encode_dc(
+        &mut self,
+        component: usize,
+        table: usize,
+        value: i16,
+    ) -> Result<(), EncodingError> {
+        let stats = &mut self.dc_stats[table];
+        let coder = &mut self.coder;
+
+        let st = self.dc_context[component];
+        let diff = value.wrapping_sub(self.last_dc[component]);
+
+        if diff == 0 {
+            coder.encode(&mut stats[st], false)?;
+            self.dc_context[component] = 0;
+            return Ok(());
+        }
+
+        self.last_dc[component] = value;
+        coder.encode(&mut stats[st], true)?;
+
+        // Encode the sign of the difference
+        let sign_state = if diff > 0 {
+            coder.encode(&mut stats[st + 1], false)?;
+            self.dc_context[component] = 4;
+            st + 2
+        } else {
+            coder.encode(&mut stats[st + 1], true)?;
+            self.dc_context[component] = 8;
+            st + 3
+        };
+
+        let m = encode_dc_magnitude(coder, stats, sign_state, diff.unsigned_abs())?;
+
+        // Establish the conditioning category for the next difference (F.1.4.4.1.2)
+        let conditioning = &self.conditioning[table];
+
+        if m < (1 << conditioning.dc_lower) >> 1 {
+            self.dc_context[component] = 0;
+        } else if m > (1 << conditioning.dc_upper) >> 1 {
+            self.dc_context[component] += 8;
+        }
+
+        Ok(())
+    }
+
+    /// Encode the AC coefficients `ss..=se` with the point transform `al`
+    /// as described in F.1.4.2 and G.1.3.2
+    fn encode_ac(
+        &mut self,
+        table: usize,
+        block: &[i16; 64],
+        ss: usize,
+        se: usize,
+        al: u8,
+    ) -> Result<(), EncodingError> {
+        let stats = &mut self.ac_stats[table];
+        let coder = &mut self.coder;
+        let kx = usize::from(self.conditioning[table].ac_kx);
+
+        let magnitude = |k: usize| block[k].unsigned_abs() >> al;
+
+        // Find the end of block
+        let eob = (ss..=se).rev().find(|&k| magnitude(k) != 0);
+
+        let mut k = ss;
+
+        if let Some(eob) = eob {
+            while k <= eob {
+                let mut st = 3 * (k - 1);
+
+                // Not the end of block
+                coder.encode(&mut stats[st], false)?;
+
+                while magnitude(k) == 0 {
+                    coder.encode(&mut stats[st + 1], false)?;
+                    st += 3;
+                    k += 1;
+                }
+
+                coder.encode(&mut stats[st + 1], true)?;
+                coder.encode(&mut self.fixed_bin, block[k] < 0)?;
+
+                let x1 = if k <= kx { 189 } else { 217 };
+                encode_ac_magnitude(coder, stats, st + 2, x1, magnitude(k))?;
+
+                k += 1;
+            }
+        }
+
+        if k <= se {
+            coder.encode(&mut stats[3 * (k - 1)], true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Encode a correction bit of a DC refinement scan as described in G.1.3.1
+    fn encode_dc_refine(&mut self, value: i16, al: u8) -> Result<(), EncodingError> {
+        self.coder
+            .encode(&mut self.fixed_bin, (value >> al) & 1 == 1)
+    }
+
+    /// Encode the AC coefficients of a refinement scan as described in G.1.3.3
+    fn encode_ac_refine(
+        &mut self,
+        table: usize,
+        block: &[i16; 64],
+        scan: &ScanInfo,
+    ) -> Result<(), EncodingError> {
+        let stats = &mut self.ac_stats[table];
+        let coder = &mut self.coder;
+
+        let ss = usize::from(scan.ss);
+        let se = usize::from(scan.se);
+
+        let magnitude = |k: usize| block[k].unsigned_abs() >> scan.al;
+
+        // End of block in this and in the previous stage
+        let eob = (ss..=se).rev().find(|&k| magnitude(k) != 0);
+        let prev_eob = (ss..=se)
+            .rev()
+            .find(|&k| block[k].unsigned_abs() >> scan.ah != 0);
+
+        let mut k = ss;
+
+        if let Some(eob) = eob {
+            while k <= eob {
+                let mut st = 3 * (k - 1);
+
+                if prev_eob.map_or(true, |prev_eob| k > prev_eob) {
+                    coder.encode(&mut stats[st], false)?;
+                }
+
+                loop {
+                    let value = magnitude(k);
+
+                    if value > 1 {
+                        // Coefficient was already non zero, send the correction bit
+                        coder.encode(&mut stats[st + 2], value & 1 == 1)?;
+                        break;
+                    } else if value == 1 {
+                        // Newly non zero coefficient
+                        coder.encode(&mut stats[st + 1], true)?;
+                        coder.encode(&mut self.fixed_bin, block[k] < 0)?;
+                        break;
+                    }
+
+                    coder.encode(&mut stats[st + 1], false)?;
+                    st += 3;
+                    k += 1;
+                }
+
+                k += 1;
+            }
+        }
+
+        if k <= se {
+            coder.encode(&mut stats[3 * (k - 1)], true)?;
+        }
+
+        Ok(())
+    }
+
+    /// Terminate the current interval and reset the statistics used by the scan
+    fn restart(
+        &mut self,
+        nr: u8,
+        scan: &ScanInfo,
+        components: &[Component],
+    ) -> Result<(), EncodingError> {
+        self.coder.flush()?;
+        self.coder.writer.write_marker(Marker::RST(nr))?;
+        self.reset(scan, components);
+
+        Ok(())
+    }
+
+    fn reset(&mut self, scan: &ScanInfo, components: &[Component]) {
+        for &i in &scan.components {
+            let component = &components[i];
+
+            // DC refinement scans don't use statistics
+            if scan.is_dc() && scan.ah == 0 {
+                self.dc_stats[usize::from(component.dc_huffman_table)] = [0; DC_STAT_BINS];
+                self.last_dc[i] = 0;
+                self.dc_context[i] = 0;
+            }
+
+            if scan.se > 0 {
+                self.ac_stats[usize::from(component.ac_huffman_table)] = [0; AC_STAT_BINS];
+            }
+        }
+    }
+}
+
+/// Encode the magnitude of a DC difference as described in F.1.4.3
+///
+/// `st` is the index of the first magnitude statistics bin. Returns the magnitude category.
+fn encode_dc_magnitude<W: JfifWrite>(
+    coder: &mut QmCoder<W>,
+    stats: &mut [u8; DC_STAT_BINS],
+    mut st: usize,
+    value: u16,
+) -> Result<u16, EncodingError> {
+    let value = value - 1;
+    let mut m = 0;
+
+    if value != 0 {
+        coder.encode(&mut stats[st], true)?;
+        m = 1;
+
+        // Statistics bins X1 to X15
+        st = 20;
+
+        let mut v2 = value >> 1;
+        while v2 != 0 {
+            coder.encode(&mut stats[st], true)?;
+            m <<= 1;
+            st += 1;
+            v2 >>= 1;
+        }
+    }
+
+    coder.encode(&mut stats[st], false)?;
+
+    encode_magnitude_bits(coder, stats, st + 14, m, value)?;
+
+    Ok(m)
+}
+
+/// Encode the magnitude of an AC coefficient as described in F.1.4.3
+///
+/// `st` is the index of the first magnitude statistics bin and `x1` the index of the bins
+/// for larger magnitudes which depends on the Kx conditioning.
+fn encode_ac_magnitude<W: JfifWrite>(
+    coder: &mut QmCoder<W>,
+    stats: &mut [u8; AC_STAT_BINS],
+    mut st: usize,
+    x1: usize,
+    value: u16,
+) -> Result<(), EncodingError> {
+    let value = value - 1;
+    let mut m = 0;
+
+    if value != 0 {
+        coder.encode(&mut stats[st], true)?;
+        m = 1;
+
+        let mut v2 = value >> 1;
+
+        if v2 != 0 {
+            coder.encode(&mut stats[st], true)?;
+            m <<= 1;
+            st = x1;
+
+            v2 >>= 1;
+            while v2 != 0 {
+                coder.encode(&mut stats[st], true)?;
+                m <<= 1;
+                st += 1;
+                v2 >>= 1;
+            }
+        }
+    }
+
+    coder.encode(&mut stats[st], false)?;
+
+    encode_magnitude_bits(coder, stats, st + 14, m, value)
+}
+
+/// Encode the bits of `value` below the magnitude category `m` as described in F.1.4.3.2
+fn encode_magnitude_bits<W: JfifWrite>(
+    coder: &mut QmCoder<W>,
+    stats: &mut [u8],
+    st: usize,
+    mut m: u16,
+    value: u16,
+) -> Result<(), EncodingError> {
+    while m > 1 {
+        m >>= 1;
+        coder.encode(&mut stats[st], value & m != 0)?;
+    }
+
+    Ok(())
+}
+
+/// Arithmetic code a single scan
+///
+/// Sequential scans contain the DC and all AC coefficients of the components.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode_scan_arithmetic<W: JfifWrite>(
+    writer: &mut JfifWriter<W>,
+    scan: &ScanInfo,
+    progressive: bool,
+    components: &[Component],
+    conditioning: &[ArithmeticConditioning; 2],
+    layout: &BlockLayout,
+    blocks: &[Vec<[i16; 64]>; 4],
+    restart_interval: Option<u16>,
+) -> Result<(), EncodingError> {
+    let mut encoder = ArithmeticEncoder {
+        coder: QmCoder::new(writer),
+        dc_stats: [[0; DC_STAT_BINS]; 2],
+        ac_stats: [[0; AC_STAT_BINS]; 2],
+        fixed_bin: FIXED_STATE,
+        conditioning,
+        dc_context: [0; 4],
+        last_dc: [0; 4],
+    };
+
+    for_each_mcu(scan, layout, blocks, restart_interval, |restart, mcu| {
+        if let Some(nr) = restart {
+            encoder.restart(nr, scan, components)?;
+        }
+
+        for (i, block) in mcu {
+            let component = &components[i];
+            let dc_table = usize::from(component.dc_huffman_table);
+            let ac_table = usize::from(component.ac_huffman_table);
+
+            if !progressive {
+                encoder.encode_dc(i, dc_table, block[0])?;
+                encoder.encode_ac(ac_table, block, 1, 63, 0)?;
+                continue;
+            }
+
+            match (scan.is_dc(), scan.ah == 0) {
+                (true, true) => encoder.encode_dc(i, dc_table, block[0] >> scan.al)?,
+                (true, false) => encoder.encode_dc_refine(block[0], scan.al)?,
+                (false, true) => encoder.encode_ac(
+                    ac_table,
+                    block,
+                    usize::from(scan.ss),
+                    usize::from(scan.se),
+                    scan.al,
+                )?,
+                (false, false) => encoder.encode_ac_refine(ac_table, block, scan)?,
+            }
+        }
+
+        Ok(())
+    })?;
+
+    encoder.coder.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::progressive::build_scans;
+
+    /// Decoder of Annex D.2 following libjpeg's jdarith.c
+    struct QmDecoder<'a> {
+        data: &'a [u8],
+        c: i64,
+        a: i64,
+        ct: i32,
+    }
+
+    impl<'a> QmDecoder<'a> {
+        fn new(data: &'a [u8]) -> Self {
+            QmDecoder {
+                data,
+                c: 0,
+                a: 0,
+                ct: -16,
+            }
+        }
+
+        fn read_byte(&mut self) -> i64 {
+            match self.data {
+                [0xFF, 0x00, rest @ ..] => {
+                    self.data = rest;
+                    0xFF
+                }
+                [0xFF, ..] | [] => 0,
+                [byte, rest @ ..] => {
+                    let byte = *byte;
+                    self.data = rest;
+                    i64::from(byte)
+                }
+            }
+        }
+
+        fn decode(&mut self, state: &mut u8) -> bool {
+            while self.a < 0x8000 {
+                self.ct -= 1;
+                if self.ct < 0 {
+                    self.c = (self.c << 8) | self.read_byte();
+                    self.ct += 8;
+                    if self.ct < 0 {
+                        self.ct += 1;
+                        if self.ct == 0 {
+                            self.a = 0x8000;
+                        }
+                    }
+                }
+                self.a <<= 1;
+            }
+
+            let entry = QE_TABLE[usize::from(*state & 0x7F)];
+            let next_lps = (entry & 0xFF) as u8;
+            let next_mps = ((entry >> 8) & 0xFF) as u8;
+            let qe = i64::from(entry >> 16);
+
+            let mps = *state >> 7 == 1;
+
+            self.a -= qe;
+            let temp = self.a << self.ct;
+
+            let is_mps = if self.c >= temp {
+                self.c -= temp;
+                let is_mps = self.a < qe;
+                self.a = qe;
+                is_mps
+            } else if self.a < 0x8000 {
+                self.a >= qe
+            } else {
+                return mps;
+            };
+
+            if is_mps {
+                *state = (*state & 0x80) ^ next_mps;
+                mps
+            } else {
+                *state = (*state & 0x80) ^ next_lps;
+                !mps
+            }
+        }
+    }
+
+    /// Decoder of the coefficients following libjpeg's jdarith.c
+    struct ArithmeticDecoder<'a> {
+        decoder: QmDecoder<'a>,
+        dc_stats: [[u8; DC_STAT_BINS]; 2],
+        ac_stats: [[u8; AC_STAT_BINS]; 2],
+        fixed_bin: u8,
+        conditioning: [ArithmeticConditioning; 2],
+        dc_context: [usize; 4],
+        last_dc: [i16; 4],
+    }
+
+    impl<'a> ArithmeticDecoder<'a> {
+        fn new(data: &'a [u8], conditioning: [ArithmeticConditioning; 2]) -> Self {
+            ArithmeticDecoder {
+                decoder: QmDecoder::new(data),
+                dc_stats: [[0; DC_STAT_BINS]; 2],
+                ac_stats: [[0; AC_STAT_BINS]; 2],
+                fixed_bin: FIXED_STATE,
+                conditioning,
+                dc_context: [0; 4],
+                last_dc: [0; 4],
+            }
+        }
+
+        /// Decode a DC difference and return the DC value (F.2.4.1)
+        fn decode_dc(&mut self, component: usize, table: usize) -> i16 {
+            let stats = &mut self.dc_stats[table];
+            let decoder = &mut self.decoder;
+
+            let mut st = self.dc_context[component];
+
+            if !decoder.decode(&mut stats[st]) {
+                self.dc_context[component] = 0;
+                return self.last_dc[component];
+            }
+
+            let sign = decoder.decode(&mut stats[st + 1]);
+            st += 2 + usize::from(sign);
+
+            let mut m: u16 = 0;
+
+            if decoder.decode(&mut stats[st]) {
+                m = 1;
+                st = 20;
+
+                while decoder.decode(&mut stats[st]) {
+                    m <<= 1;
+                    st += 1;
+                }
+            }
+
+            let conditioning = &self.conditioning[table];
+            let sign_offset = if sign { 4 } else { 0 };
+
+            self.dc_context[component] = if m < (1 << conditioning.dc_lower) >> 1 {
+                0
+            } else if m > (1 << conditioning.dc_upper) >> 1 {
+                12 + sign_offset
+            } else {
+                4 + sign_offset
+            };
+
+            let diff = decode_magnitude_bits(decoder, stats, st + 14, m) as i16;
+            let diff = if sign { -diff } else { diff };
+
+            self.last_dc[component] = self.last_dc[component].wrapping_add(diff);
+            self.last_dc[component]
+        }
+
+        /// Decode the AC coefficients of a first scan (F.2.4.2 and G.2.3.2)
+        fn decode_ac(&mut self, table: usize, block: &mut [i16; 64], ss: usize, se: usize, al: u8) {
+            let stats = &mut self.ac_stats[table];
+            let decoder = &mut self.decoder;
+            let kx = usize::from(self.conditioning[table].ac_kx);
+
+            let mut k = ss;
+
+            while k <= se {
+                let mut st = 3 * (k - 1);
+
+                // End of block
+                if decoder.decode(&mut stats[st]) {
+                    break;
+                }
+
+                while !decoder.decode(&mut stats[st + 1]) {
+                    st += 3;
+                    k += 1;
+                    assert!(k <= se, "Spectral overflow");
+                }
+
+                let sign = decoder.decode(&mut self.fixed_bin);
+                st += 2;
+
+                let mut m: u16 = 0;
+
+                if decoder.decode(&mut stats[st]) {
+                    m = 1;
+
+                    if decoder.decode(&mut stats[st]) {
+                        m <<= 1;
+                        st = if k <= kx { 189 } else { 217 };
+
+                        while decoder.decode(&mut stats[st]) {
+                            m <<= 1;
+                            st += 1;
+                        }
+                    }
+                }
+
+                let value = decode_magnitude_bits(decoder, stats, st + 14, m) as i16;
+                block[k] = (if sign { -value } else { value }) << al;
+
+                k += 1;
+            }
+        }
+
+        /// Decode a correction bit of a DC refinement scan (G.2.3.1)
+        fn decode_dc_refine(&mut self, block: &mut [i16; 64], al: u8) {
+            if self.decoder.decode(&mut self.fixed_bin) {
+                block[0] |= 1 << al;
+            }
+        }
+
+        /// Decode the AC coefficients of a refinement scan (G.2.3.3)
+        fn decode_ac_refine(
+            &mut self,
+            table: usize,
+            block: &mut [i16; 64],
+            ss: usize,
+            se: usize,
+            al: u8,
+        ) {
+            let stats = &mut self.ac_stats[table];
+            let decoder = &mut self.decoder;
+
+            let p1 = 1 << al;
+
+            // End of block of the previous stage
+            let prev_eob = (1..=se).rev().find(|&k| block[k] != 0).unwrap_or(0);
+
+            let mut k = ss;
+
+            while k <= se {
+                let mut st = 3 * (k - 1);
+
+                if k > prev_eob && decoder.decode(&mut stats[st]) {
+                    break;
+                }
+
+                loop {
+                    if block[k] != 0 {
+                        if decoder.decode(&mut stats[st + 2]) {
+                            block[k] += if block[k] < 0 { -p1 } else { p1 };
+                        }
+                        break;
+                    }
+
+                    if decoder.decode(&mut stats[st + 1]) {
+                        block[k] = if decoder.decode(&mut self.fixed_bin) {
+                            -p1
+                        } else {
+                            p1
+                        };
+                        break;
+                    }
+
+                    st += 3;
+                    k += 1;
+                    assert!(k <= se, "Spectral overflow");
+                }
+
+                k += 1;
+            }
+        }
+    }
+
+    /// Decode the bits below the magnitude category `m` and return the magnitude (F.2.4.3.2)
+    fn decode_magnitude_bits(
+        decoder: &mut QmDecoder,
+        stats: &mut [u8],
+        st: usize,
+        mut m: u16,
+    ) -> u16 {
+        let mut value = m;
+
+        while m > 1 {
+            m >>= 1;
+
+            if decoder.decode(&mut stats[st]) {
+                value |= m;
+            }
+        }
+
+        value + 1
+    }
+
+    /// Blocks of a 32x32 image with 2x2 luma and 1x1 chroma sampling
+    fn random_blocks() -> [Vec<[i16; 64]>; 4] {
+        let mut seed = 0x9E37_79B9u32;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        let mut blocks: [Vec<[i16; 64]>; 4] = Default::default();
+
+        for (i, count) in [16, 4, 4].iter().enumerate() {
+            let mut dc = 0i16;
+
+            for _ in 0..*count {
+                let mut block = [0i16; 64];
+
+                // Mostly small DC differences with some large jumps
+                let step = if random() % 8 == 0 { 700 } else { 40 };
+                dc = (dc + (random() % (2 * step + 1)) as i16 - step as i16).clamp(-1024, 1023);
+                block[0] = dc;
+
+                for (k, value) in block.iter_mut().enumerate().skip(1) {
+                    if random() % 64 < 64 - k as u32 {
+                        let range = (1023 >> (k / 8)) as u32 + 1;
+                        let magnitude = (random() % range) as i16;
+                        *value = if random() % 2 == 0 {
+                            magnitude
+                        } else {
+                            -magnitude
+                        };
+                    }
+                }
+
+                blocks[i].push(block);
+            }
+        }
+
+        blocks
+    }
+
+    /// Encode the scans and decode them into a new set of blocks
+    fn round_trip(
+        scans: &[ScanInfo],
+        progressive: bool,
+        conditioning: [ArithmeticConditioning; 2],
+        blocks: &[Vec<[i16; 64]>; 4],
+    ) -> [Vec<[i16; 64]>; 4] {
+        let components: Vec<Component> = (0..3)
+            .map(|i| Component {
+                id: i as u8,
+                quantization_table: 0,
+                dc_huffman_table: (i > 0) as u8,
+                ac_huffman_table: (i > 0) as u8,
+                horizontal_sampling_factor: if i == 0 { 2 } else { 1 },
+                vertical_sampling_factor: if i == 0 { 2 } else { 1 },
+            })
+            .collect();
+
+        let layout = BlockLayout::new(32, 32, &components);
+
+        let mut decoded: [Vec<[i16; 64]>; 4] = Default::default();
+        for (decoded, blocks) in decoded.iter_mut().zip(blocks.iter()) {
+            *decoded = alloc::vec![[0; 64]; blocks.len()];
+        }
+
+        for scan in scans {
+            let mut data = Vec::new();
+            let mut writer = JfifWriter::new(&mut data);
+
+            encode_scan_arithmetic(
+                &mut writer,
+                scan,
+                progressive,
+                &components,
+                &conditioning,
+                &layout,
+                blocks,
+                None,
+            )
+            .unwrap();
+
+            // Block indices in the order of the scan
+            let mut order = Vec::new();
+
+            if let [component] = scan.components[..] {
+                order.extend((0..blocks[component].len()).map(|index| (component, index)));
+            } else {
+                for mcu in 0..4 {
+                    let (mcu_x, mcu_y) = (mcu % 2, mcu / 2);
+
+                    for &component in &scan.components {
+                        if component == 0 {
+                            for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                                order.push((0, (2 * mcu_y + y) * 4 + 2 * mcu_x + x));
+                            }
+                        } else {
+                            order.push((component, mcu));
+                        }
+                    }
+                }
+            }
+
+            let mut decoder = ArithmeticDecoder::new(&data, conditioning);
+
+            for (component, index) in order {
+                let table = usize::from(components[component].dc_huffman_table);
+                let block = &mut decoded[component][index];
+
+                let (ss, se) = (usize::from(scan.ss), usize::from(scan.se));
+
+                if !progressive {
+                    block[0] = decoder.decode_dc(component, table);
+                    decoder.decode_ac(table, block, 1, 63, 0);
+                    continue;
+                }
+
+                match (scan.is_dc(), scan.ah == 0) {
+                    (true, true) => block[0] = decoder.decode_dc(component, table) << scan.al,
+                    (true, false) => decoder.decode_dc_refine(block, scan.al),
+                    (false, true) => decoder.decode_ac(table, block, ss, se, scan.al),
+                    (false, false) => decoder.decode_ac_refine(table, block, ss, se, scan.al),
+                }
+            }
+        }
+
+        decoded
+    }
+
+    #[test]
+    fn test_sequential_round_trip() {
+        let blocks = random_blocks();
+        let scans = [ScanInfo::new(&[0, 1, 2], 0, 63, 0, 0)];
+
+        for conditioning in [
+            ArithmeticConditioning::default(),
+            ArithmeticConditioning::new(2, 6, 12),
+        ] {
+            let decoded = round_trip(&scans, false, [conditioning; 2], &blocks);
+            assert_eq!(decoded, blocks);
+        }
+    }
+
+    #[test]
+    fn test_progressive_round_trip() {
+        let blocks = random_blocks();
+
+        for point_transform in [0, 2] {
+            let scans = build_scans(3, 4, point_transform);
+
+            if point_transform > 0 {
+                assert!(scans.iter().any(|scan| scan.is_dc() && scan.ah > 0));
+                assert!(scans.iter().any(|scan| !scan.is_dc() && scan.ah > 0));
+            }
+
+            let conditioning = [
+                ArithmeticConditioning::default(),
+                ArithmeticConditioning::new(1, 3, 20),
+            ];
+
+            let decoded = round_trip(&scans, true, conditioning, &blocks);
+            assert_eq!(decoded, blocks);
+        }
+    }
+
+    #[test]
+    fn test_qe_table() {
+        for (i, &entry) in QE_TABLE.iter().enumerate() {
+            assert!((entry & 0x7F) < 114, "Bad LPS index in state {}", i);
+            assert!(((entry >> 8) & 0xFF) < 114, "Bad MPS index in state {}", i);
+        }
+
+        // The fixed state never changes
+        assert_eq!(QE_TABLE[usize::from(FIXED_STATE)] & 0xFFFF, 113 << 8 | 113);
+    }
+
+    #[test]
+    fn test_qm_coder_round_trip() {
+        let mut seed = 0x1234_5678u32;
+        let mut random = move || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+
+        // Decisions with different probabilities for each context
+        let decisions: Vec<(usize, bool)> = (0..20000)
+            .map(|_| {
+                let context = (random() % 4) as usize;
+                let value = random() % 16 < [1, 4, 8, 15][context];
+                (context, value)
+            })
+            .collect();
+
+        let mut data = Vec::new();
+        let mut writer = JfifWriter::new(&mut data);
+
+        let mut states = [0u8; 4];
+        let mut coder = QmCoder::new(&mut writer);
+
+        for &(context, value) in &decisions {
+            coder.encode(&mut states[context], value).unwrap();
+        }
+        coder.flush().unwrap();
+
+        assert!(data.len() < decisions.len() / 8);
+
+        let mut states = [0u8; 4];
+        let mut decoder = QmDecoder::new(&data);
+
+        for (i, &(context, value)) in decisions.iter().enumerate() {
+            assert_eq!(
+                decoder.decode(&mut states[context]),
+                value,
+                "Wrong decision {}",
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn test_conditioning_validity() {
+        assert!(ArithmeticConditioning::default().is_valid());
+        assert!(ArithmeticConditioning::new(15, 15, 63).is_valid());
+        assert!(!ArithmeticConditioning::new(2, 1, 5).is_valid());
+        assert!(!ArithmeticConditioning::new(0, 16, 5).is_valid());
+        assert!(!ArithmeticConditioning::new(0, 1, 0).is_valid());
+        assert!(!ArithmeticConditioning::new(0, 1, 64).is_valid());
+    }
+}
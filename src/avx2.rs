@@ -1,15 +1,51 @@
+mod block;
 mod fdct;
+mod quantize;
 mod ycbcr;
 
-use crate::encoder::Operations;
+use alloc::vec::Vec;
+
+use crate::encoder::{convert_row, Operations};
+use crate::quantization::QuantizationTable;
+use crate::{ColorType, ImageBuffer};
+pub(crate) use block::get_block_avx2;
 pub use fdct::fdct_avx2;
+pub(crate) use quantize::quantize_block_avx2;
 pub(crate) use ycbcr::*;
 
 pub(crate) struct AVX2Operations;
 
 impl Operations for AVX2Operations {
+    #[inline(always)]
+    fn convert_row(color_type: ColorType, data: &[u8], width: u16, buffers: &mut [Vec<u8>; 4]) {
+        match color_type {
+            ColorType::Rgb => RgbImageAVX2(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Rgba => RgbaImageAVX2(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Bgr => BgrImageAVX2(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Bgra => BgraImageAVX2(data, width, 1).fill_buffers(0, buffers),
+            _ => convert_row(color_type, data, width, buffers),
+        }
+    }
+
+    #[inline(always)]
+    fn get_block(
+        data: &[u8],
+        start_x: usize,
+        start_y: usize,
+        col_stride: usize,
+        row_stride: usize,
+        width: usize,
+    ) -> [i16; 64] {
+        get_block_avx2(data, start_x, start_y, col_stride, row_stride, width)
+    }
+
     #[inline(always)]
     fn fdct(data: &mut [i16; 64]) {
         fdct_avx2(data);
     }
+
+    #[inline(always)]
+    fn quantize_block(block: &[i16; 64], q_block: &mut [i16; 64], table: &QuantizationTable) {
+        quantize_block_avx2(block, q_block, table);
+    }
 }
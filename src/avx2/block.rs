@@ -0,0 +1,108 @@
+#[cfg(target_arch = "x86")]
+use core::arch::x86::{
+    __m128i, _mm_and_si128, _mm_cvtepu8_epi16, _mm_loadl_epi64, _mm_loadu_si128, _mm_set1_epi16,
+    _mm_storeu_si128, _mm_sub_epi16,
+};
+
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::{
+    __m128i, _mm_and_si128, _mm_cvtepu8_epi16, _mm_loadl_epi64, _mm_loadu_si128, _mm_set1_epi16,
+    _mm_storeu_si128, _mm_sub_epi16,
+};
+
+use crate::encoder::get_block;
+
+/// Get a level shifted block of a component buffer
+///
+/// Full resolution blocks and blocks horizontally downsampled by 2 are vectorized,
+/// all other blocks use [get_block].
+#[inline(always)]
+pub(crate) fn get_block_avx2(
+    data: &[u8],
+    start_x: usize,
+    start_y: usize,
+    col_stride: usize,
+    row_stride: usize,
+    width: usize,
+) -> [i16; 64] {
+    // Number of bytes loaded for each row
+    let row_length = match col_stride {
+        1 => 8,
+        2 => 16,
+        _ => return get_block(data, start_x, start_y, col_stride, row_stride, width),
+    };
+
+    let last_row = (start_y + 7 * row_stride) * width;
+
+    if start_x + row_length > width || last_row + start_x + row_length > data.len() {
+        return get_block(data, start_x, start_y, col_stride, row_stride, width);
+    }
+
+    unsafe { get_block_avx2_internal(data, start_x, start_y, col_stride, row_stride, width) }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn get_block_avx2_internal(
+    data: &[u8],
+    start_x: usize,
+    start_y: usize,
+    col_stride: usize,
+    row_stride: usize,
+    width: usize,
+) -> [i16; 64] {
+    let mut block = [0i16; 64];
+
+    let offset = _mm_set1_epi16(128);
+    let even_bytes = _mm_set1_epi16(0xFF);
+
+    for y in 0..8 {
+        let row = data
+            .as_ptr()
+            .add((start_y + y * row_stride) * width + start_x);
+
+        let values = if col_stride == 1 {
+            _mm_cvtepu8_epi16(_mm_loadl_epi64(row as *const __m128i))
+        } else {
+            _mm_and_si128(_mm_loadu_si128(row as *const __m128i), even_bytes)
+        };
+
+        _mm_storeu_si128(
+            block.as_mut_ptr().add(y * 8) as *mut __m128i,
+            _mm_sub_epi16(values, offset),
+        );
+    }
+
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::get_block_avx2;
+    use crate::encoder::get_block;
+    use alloc::vec::Vec;
+
+    #[test]
+    fn test_get_block_avx2() {
+        if !std::is_x86_feature_detected!("avx2") {
+            return;
+        }
+
+        let width = 48;
+        let data: Vec<u8> = (0..width * 32).map(|i| (i * 31 % 256) as u8).collect();
+
+        for (col_stride, row_stride) in [(1, 1), (2, 1), (1, 2), (2, 2), (4, 1)] {
+            for start_x in [0, 8, 16, 32, 40] {
+                for start_y in [0, 8, 16] {
+                    if start_x + 7 * col_stride >= width || start_y + 7 * row_stride >= 32 {
+                        continue;
+                    }
+
+                    assert_eq!(
+                        get_block_avx2(&data, start_x, start_y, col_stride, row_stride, width),
+                        get_block(&data, start_x, start_y, col_stride, row_stride, width)
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,159 @@
+#[cfg(target_arch = "x86")]
+use core::arch::x86::{
+    __m128i, _mm256_abs_epi32, _mm256_add_epi32, _mm256_castsi256_si128, _mm256_cvtepi16_epi32,
+    _mm256_extracti128_si256, _mm256_loadu_si256, _mm256_mullo_epi32, _mm256_sign_epi32,
+    _mm256_srai_epi32, _mm_loadu_si128, _mm_or_si128, _mm_packs_epi32, _mm_setzero_si128,
+    _mm_shuffle_epi8, _mm_storeu_si128,
+};
+
+#[cfg(target_arch = "x86_64")]
+use core::arch::x86_64::{
+    __m128i, _mm256_abs_epi32, _mm256_add_epi32, _mm256_castsi256_si128, _mm256_cvtepi16_epi32,
+    _mm256_extracti128_si256, _mm256_loadu_si256, _mm256_mullo_epi32, _mm256_sign_epi32,
+    _mm256_srai_epi32, _mm_loadu_si128, _mm_or_si128, _mm_packs_epi32, _mm_setzero_si128,
+    _mm_shuffle_epi8, _mm_storeu_si128,
+};
+
+use crate::quantization::{QuantizationTable, SHIFT};
+use crate::writer::ZIGZAG;
+
+/// Byte shuffles moving the coefficients of a row in natural order to their
+/// position in a row in zigzag order, indexed by the zigzag row and the natural row.
+///
+/// Bytes with a negative index are cleared by the shuffle.
+const fn zigzag_shuffles() -> [[[i8; 16]; 8]; 8] {
+    let mut shuffles = [[[-1i8; 16]; 8]; 8];
+
+    let mut i = 0;
+    while i < 64 {
+        let z = ZIGZAG[i] as usize;
+
+        shuffles[i / 8][z / 8][(i % 8) * 2] = ((z % 8) * 2) as i8;
+        shuffles[i / 8][z / 8][(i % 8) * 2 + 1] = ((z % 8) * 2 + 1) as i8;
+
+        i += 1;
+    }
+
+    shuffles
+}
+
+/// Returns which natural rows contain coefficients of each zigzag row
+const fn zigzag_sources() -> [[bool; 8]; 8] {
+    let mut sources = [[false; 8]; 8];
+
+    let mut i = 0;
+    while i < 64 {
+        sources[i / 8][ZIGZAG[i] as usize / 8] = true;
+        i += 1;
+    }
+
+    sources
+}
+
+static ZIGZAG_SHUFFLES: [[[i8; 16]; 8]; 8] = zigzag_shuffles();
+const ZIGZAG_SOURCES: [[bool; 8]; 8] = zigzag_sources();
+
+/// Quantize a block of DCT coefficients in natural order into zigzag order
+///
+/// Uses the same reciprocals as [QuantizationTable::quantize], so the results are identical.
+#[inline(always)]
+pub(crate) fn quantize_block_avx2(
+    block: &[i16; 64],
+    q_block: &mut [i16; 64],
+    table: &QuantizationTable,
+) {
+    unsafe {
+        quantize_block_avx2_internal(block, q_block, table);
+    }
+}
+
+#[target_feature(enable = "avx2")]
+unsafe fn quantize_block_avx2_internal(
+    block: &[i16; 64],
+    q_block: &mut [i16; 64],
+    table: &QuantizationTable,
+) {
+    let (reciprocals, corrections) = table.reciprocals();
+
+    let mut rows = [_mm_setzero_si128(); 8];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        let values =
+            _mm256_cvtepi16_epi32(_mm_loadu_si128(block.as_ptr().add(i * 8) as *const __m128i));
+
+        let reciprocals = _mm256_loadu_si256(reciprocals.as_ptr().add(i * 8) as *const _);
+        let corrections = _mm256_loadu_si256(corrections.as_ptr().add(i * 8) as *const _);
+
+        let product = _mm256_mullo_epi32(
+            _mm256_add_epi32(_mm256_abs_epi32(values), corrections),
+            reciprocals,
+        );
+        let product = _mm256_srai_epi32::<{ SHIFT as i32 }>(product);
+
+        // Zero values always result in zero, so sign doesn't change the result
+        let product = _mm256_sign_epi32(product, values);
+
+        *row = _mm_packs_epi32(
+            _mm256_castsi256_si128(product),
+            _mm256_extracti128_si256::<1>(product),
+        );
+    }
+
+    for (i, (shuffles, sources)) in ZIGZAG_SHUFFLES
+        .iter()
+        .zip(ZIGZAG_SOURCES.iter())
+        .enumerate()
+    {
+        let mut result = _mm_setzero_si128();
+
+        for ((row, shuffle), &source) in rows.iter().zip(shuffles.iter()).zip(sources.iter()) {
+            if source {
+                let shuffle = _mm_loadu_si128(shuffle.as_ptr() as *const __m128i);
+                result = _mm_or_si128(result, _mm_shuffle_epi8(*row, shuffle));
+            }
+        }
+
+        _mm_storeu_si128(q_block.as_mut_ptr().add(i * 8) as *mut __m128i, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantize_block_avx2;
+    use crate::encoder::{DefaultOperations, Operations};
+    use crate::quantization::{QuantizationTable, QuantizationTableType};
+
+    #[test]
+    fn test_quantize_avx2() {
+        if !std::is_x86_feature_detected!("avx2") {
+            return;
+        }
+
+        let mut block = [0i16; 64];
+
+        for (i, v) in block.iter_mut().enumerate() {
+            *v = (i as i16 * 257 - 8000) * if i % 3 == 0 { -1 } else { 1 };
+        }
+
+        block[5] = 0;
+        block[63] = i16::MIN;
+
+        for quality in [1, 50, 90, 100] {
+            for luma in [true, false] {
+                let table = QuantizationTable::new_with_quality(
+                    &QuantizationTableType::Default,
+                    quality,
+                    luma,
+                );
+
+                let mut expected = [0i16; 64];
+                DefaultOperations::quantize_block(&block, &mut expected, &table);
+
+                let mut q_block = [0i16; 64];
+                quantize_block_avx2(&block, &mut q_block, &table);
+
+                assert_eq!(q_block, expected);
+            }
+        }
+    }
+}
@@ -12,6 +12,7 @@ use core::arch::x86_64::{
 
 use alloc::vec::Vec;
 
+use crate::image_buffer::fill_rgb_buffers;
 use crate::{rgb_to_ycbcr, ImageBuffer, JpegColorType};
 
 macro_rules! ycbcr_image_avx2 {
@@ -34,6 +35,10 @@ macro_rules! ycbcr_image_avx2 {
                     )
                 }
 
+                for buffer in buffers.iter_mut().take(3) {
+                    buffer.reserve(self.width() as usize);
+                }
+
                 let mut y_buffer = buffers[0].as_mut_ptr().add(buffers[0].len());
                 buffers[0].set_len(buffers[0].len() + self.width() as usize);
                 let mut cb_buffer = buffers[1].as_mut_ptr().add(buffers[1].len());
@@ -56,7 +61,7 @@ macro_rules! ycbcr_image_avx2 {
                 let mut data = self
                     .0
                     .as_ptr()
-                    .offset((y as isize * self.1 as isize * $num_colors));
+                    .offset(y as isize * self.1 as isize * $num_colors);
 
                 for _ in 0..self.width() / 8 {
                     let r = load3(data.offset($o1));
@@ -147,6 +152,15 @@ macro_rules! ycbcr_image_avx2 {
                     self.fill_buffers_avx2(y, buffers);
                 }
             }
+
+            fn get_lossless_color_type(&self) -> JpegColorType {
+                JpegColorType::Rgb
+            }
+
+            fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let offsets = [$o1, $o2, $o3];
+                fill_rgb_buffers(self.0, y, self.1, $num_colors, offsets, buffers);
+            }
         }
     };
 }
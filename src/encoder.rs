@@ -1,14 +1,36 @@
-use crate::fdct::fdct;
+use crate::arithmetic::{encode_scan_arithmetic, ArithmeticConditioning};
+use crate::fdct::{fdct, fdct_wide};
 use crate::huffman::{CodingClass, HuffmanTable};
 use crate::image_buffer::*;
-use crate::marker::Marker;
-use crate::quantization::{QuantizationTable, QuantizationTableType};
-use crate::writer::{JfifWrite, JfifWriter, ZIGZAG};
-use crate::{Density, EncodingError};
-
+use crate::importance::{AdaptiveQuantizer, BlockLevel, ImportanceMap};
+use crate::iptc::{photoshop_segments, IPTC_RESOURCE_ID};
+use crate::lossless::{encode_lossless, Predictor};
+use crate::marker::{Marker, SOFType};
+#[cfg(feature = "rayon")]
+use crate::parallel::{self, McuRows};
+use crate::progressive::{
+    build_scans, encode_scan, validate_scans, BlockLayout, FrequencyCounter, ScanInfo, ScanScript,
+    ScanWriter, MAX_EOB_RUN,
+};
+use crate::quantization::{CoefficientHook, QuantizationTable, QuantizationTableType};
+use crate::thumbnail::{create_thumbnail, JfifThumbnail, Thumbnail};
+use crate::trellis::{TrellisQuantization, TrellisQuantizer};
+use crate::writer::{
+    non_zero_mask, JfifWrite, JfifWriter, MarkerLayout, SegmentPosition, StripScratch,
+    MAX_SEGMENT_LENGTH, ZIGZAG,
+};
+use crate::xmp::{extended_xmp_segments, xmp_segments};
+use crate::{Density, EncodingError, Exif, Iptc};
+
+use alloc::boxed::Box;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::marker::PhantomData;
+#[cfg(feature = "rayon")]
+use core::ops::Range;
+
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
 
 #[cfg(feature = "std")]
 use std::io::BufWriter;
@@ -28,6 +50,11 @@ pub enum JpegColorType {
     /// Three component YCbCr colorspace
     Ycbcr,
 
+    /// Three component RGB colorspace
+    ///
+    /// The file contains no JFIF header but an Adobe APP14 segment without color transform.
+    Rgb,
+
     /// 4 Component CMYK colorspace
     Cmyk,
 
@@ -41,7 +68,7 @@ impl JpegColorType {
 
         match self {
             Luma => 1,
-            Ycbcr => 3,
+            Ycbcr | Rgb => 3,
             Cmyk | Ycck => 4,
         }
     }
@@ -181,6 +208,7 @@ impl SamplingFactor {
     }
 }
 
+#[derive(Clone)]
 pub(crate) struct Component {
     pub id: u8,
     pub quantization_table: u8,
@@ -190,6 +218,18 @@ pub(crate) struct Component {
     pub vertical_sampling_factor: u8,
 }
 
+/// # Result of [encode_to_size](Encoder::encode_to_size)
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct TargetSizeResult {
+    /// Quality of the quantization tables
+    pub quality: u8,
+
+    /// Number of AC coefficients of each block which are kept in zigzag order
+    ///
+    /// All 63 coefficients are kept unless the image exceeds the target size with a quality of 1.
+    pub ac_coefficients: u8,
+}
+
 /// Public description of a JPEG component used by strip encoding constructors.
 #[derive(Clone, Debug)]
 pub struct ComponentSpec {
@@ -240,16 +280,30 @@ macro_rules! add_component {
     };
 }
 
+#[allow(clippy::too_many_arguments)]
 fn write_file_headers<W: JfifWrite>(
     writer: &mut JfifWriter<W>,
     density: Density,
     jpeg_color_type: JpegColorType,
+    exif: Option<&[u8]>,
+    thumbnail: Option<&Thumbnail>,
     app_segments: &[(u8, Vec<u8>)],
+    comments: &[Vec<u8>],
+    layout: MarkerLayout,
 ) -> Result<(), EncodingError> {
     writer.write_marker(Marker::SOI)?;
-    writer.write_header(&density)?;
 
-    if jpeg_color_type == JpegColorType::Cmyk {
+    // EXIF requires the APP1 segment directly after SOI
+    if let Some(exif) = exif {
+        writer.write_segment(Marker::APP(1), exif)?;
+    }
+
+    // JFIF implies a YCbCr or grayscale image
+    if layout.jfif && jpeg_color_type != JpegColorType::Rgb {
+        writer.write_header(&density, thumbnail)?;
+    }
+
+    if jpeg_color_type == JpegColorType::Cmyk || jpeg_color_type == JpegColorType::Rgb {
         // Set ColorTransform info to "Unknown"
         let app_14 = b"Adobe\0\0\0\0\0\0\0";
         writer.write_segment(Marker::APP(14), app_14.as_ref())?;
@@ -259,35 +313,88 @@ fn write_file_headers<W: JfifWrite>(
         writer.write_segment(Marker::APP(14), app_14.as_ref())?;
     }
 
-    for (nr, data) in app_segments {
-        writer.write_segment(Marker::APP(*nr), data)?;
+    for (marker, data) in metadata_segments(
+        SegmentPosition::BeforeTables,
+        app_segments,
+        comments,
+        layout,
+    ) {
+        writer.write_segment(marker, data)?;
     }
 
     Ok(())
 }
 
+fn validate_comment(comment: &[u8]) -> Result<Vec<u8>, EncodingError> {
+    if comment.len() > MAX_SEGMENT_LENGTH {
+        Err(EncodingError::Write(alloc::format!(
+            "Comment exceeds maximum allowed data length of {}: {}",
+            MAX_SEGMENT_LENGTH,
+            comment.len()
+        )))
+    } else {
+        Ok(comment.to_vec())
+    }
+}
+
+/// Returns the APP and COM segments which the layout places at the given position
+fn metadata_segments<'a>(
+    position: SegmentPosition,
+    app_segments: &'a [(u8, Vec<u8>)],
+    comments: &'a [Vec<u8>],
+    layout: MarkerLayout,
+) -> Vec<(Marker, &'a [u8])> {
+    let mut segments = Vec::new();
+
+    if layout.app_segments == position {
+        for (nr, data) in app_segments {
+            segments.push((Marker::APP(*nr), data.as_slice()));
+        }
+    }
+
+    if layout.comments == position {
+        for data in comments {
+            segments.push((Marker::COM, data.as_slice()));
+        }
+    }
+
+    segments
+}
+
+#[allow(clippy::too_many_arguments)]
 fn write_frame_header_common<W: JfifWrite>(
     writer: &mut JfifWriter<W>,
     width: u16,
     height: u16,
     components: &[Component],
-    progressive: bool,
+    frame_type: SOFType,
     q_tables: &[QuantizationTable; 2],
     huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    arithmetic_conditioning: Option<&[ArithmeticConditioning; 2]>,
     restart_interval: Option<u16>,
     component_count: usize,
+    precision: u8,
+    write_tables: bool,
 ) -> Result<(), EncodingError> {
-    writer.write_frame_header(width, height, components, progressive)?;
-
-    writer.write_quantization_segment(0, &q_tables[0])?;
-    writer.write_quantization_segment(1, &q_tables[1])?;
+    // Baseline frames are limited to 8 bit samples, 16 bit quantization tables only occur
+    // for 12 bit samples
+    let frame_type = if frame_type == SOFType::BaselineDCT && precision > 8 {
+        SOFType::ExtendedSequentialDCT
+    } else {
+        frame_type
+    };
 
-    writer.write_huffman_segment(CodingClass::Dc, 0, &huffman_tables[0].0)?;
-    writer.write_huffman_segment(CodingClass::Ac, 0, &huffman_tables[0].1)?;
+    writer.write_frame_header(width, height, components, frame_type, precision)?;
 
-    if component_count >= 3 {
-        writer.write_huffman_segment(CodingClass::Dc, 1, &huffman_tables[1].0)?;
-        writer.write_huffman_segment(CodingClass::Ac, 1, &huffman_tables[1].1)?;
+    // Abbreviated images use the tables of a separate table specification stream
+    if write_tables {
+        write_table_segments(
+            writer,
+            q_tables,
+            huffman_tables,
+            arithmetic_conditioning,
+            component_count,
+        )?;
     }
 
     if let Some(restart_interval) = restart_interval {
@@ -297,6 +404,30 @@ fn write_frame_header_common<W: JfifWrite>(
     Ok(())
 }
 
+fn write_table_segments<W: JfifWrite>(
+    writer: &mut JfifWriter<W>,
+    q_tables: &[QuantizationTable; 2],
+    huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    arithmetic_conditioning: Option<&[ArithmeticConditioning; 2]>,
+    component_count: usize,
+) -> Result<(), EncodingError> {
+    writer.write_quantization_segment(0, &q_tables[0])?;
+    writer.write_quantization_segment(1, &q_tables[1])?;
+
+    let num_tables = if component_count >= 3 { 2 } else { 1 };
+
+    if let Some(conditioning) = arithmetic_conditioning {
+        writer.write_arithmetic_conditioning_segment(&conditioning[..num_tables])?;
+    } else {
+        for (i, (dc_table, ac_table)) in huffman_tables[..num_tables].iter().enumerate() {
+            writer.write_huffman_segment(CodingClass::Dc, i as u8, dc_table)?;
+            writer.write_huffman_segment(CodingClass::Ac, i as u8, ac_table)?;
+        }
+    }
+
+    Ok(())
+}
+
 fn build_components(sampling_factor: SamplingFactor, color: JpegColorType) -> Vec<Component> {
     let (horizontal_sampling_factor, vertical_sampling_factor) =
         sampling_factor.get_sampling_factors();
@@ -318,6 +449,11 @@ fn build_components(sampling_factor: SamplingFactor, color: JpegColorType) -> Ve
             add_component!(components, 1, 1, 1, 1);
             add_component!(components, 2, 1, 1, 1);
         }
+        JpegColorType::Rgb => {
+            add_component!(components, 0, 0, 1, 1);
+            add_component!(components, 1, 0, 1, 1);
+            add_component!(components, 2, 0, 1, 1);
+        }
         JpegColorType::Cmyk => {
             add_component!(components, 0, 1, 1, 1);
             add_component!(components, 1, 1, 1, 1);
@@ -367,11 +503,35 @@ pub struct Encoder<W: JfifWrite> {
 
     progressive_scans: Option<u8>,
 
+    successive_approximation: u8,
+
+    scan_script: Option<ScanScript>,
+
     restart_interval: Option<u16>,
 
     optimize_huffman_table: bool,
 
+    abbreviated: bool,
+
+    arithmetic_coding: bool,
+
+    arithmetic_conditioning: [ArithmeticConditioning; 2],
+
+    trellis: Option<TrellisQuantization>,
+
+    importance_map: Option<ImportanceMap>,
+
+    lossless_predictor: Option<Predictor>,
+
+    lossless_point_transform: u8,
+
+    coefficient_hook: Option<Box<dyn CoefficientHook + Send>>,
+
+    exif: Option<Vec<u8>>,
+    jfif_thumbnail: Option<JfifThumbnail>,
     app_segments: Vec<(u8, Vec<u8>)>,
+    comments: Vec<Vec<u8>>,
+    marker_layout: MarkerLayout,
 }
 
 impl<W: JfifWrite> Encoder<W> {
@@ -412,9 +572,23 @@ impl<W: JfifWrite> Encoder<W> {
             huffman_tables,
             sampling_factor,
             progressive_scans: None,
+            successive_approximation: 0,
+            scan_script: None,
             restart_interval: None,
             optimize_huffman_table: false,
+            abbreviated: false,
+            arithmetic_coding: false,
+            arithmetic_conditioning: [ArithmeticConditioning::default(); 2],
+            trellis: None,
+            importance_map: None,
+            lossless_predictor: None,
+            lossless_point_transform: 0,
+            coefficient_hook: None,
+            exif: None,
+            jfif_thumbnail: None,
             app_segments: Vec::new(),
+            comments: Vec::new(),
+            marker_layout: MarkerLayout::default(),
         }
     }
 
@@ -458,8 +632,10 @@ impl<W: JfifWrite> Encoder<W> {
     ///
     /// By default, progressive encoding uses 4 scans.<br>
     /// Use [set_progressive_scans](Encoder::set_progressive_scans) to use a different number of scans
+    /// or [set_scan_script](Encoder::set_scan_script) for full control over the scans.
     pub fn set_progressive(&mut self, progressive: bool) {
         self.progressive_scans = if progressive { Some(4) } else { None };
+        self.scan_script = None;
     }
 
     /// Set number of scans per component for progressive encoding
@@ -476,6 +652,7 @@ impl<W: JfifWrite> Encoder<W> {
             scans
         );
         self.progressive_scans = Some(scans);
+        self.scan_script = None;
     }
 
     /// Return number of progressive scans if progressive encoding is enabled
@@ -483,6 +660,44 @@ impl<W: JfifWrite> Encoder<W> {
         self.progressive_scans
     }
 
+    /// Set the point transform used for successive approximation in progressive encoding
+    ///
+    /// The first scans of each spectral band omit the given number of low bits of the coefficients.
+    /// These bits are sent one bit plane at a time in additional refinement scans.
+    /// A value of 0 (the default) disables successive approximation.
+    ///
+    /// This setting has no effect if progressive encoding isn't enabled.
+    ///
+    /// # Panics
+    /// If the point transform is greater than 13
+    pub fn set_successive_approximation(&mut self, point_transform: u8) {
+        assert!(
+            point_transform <= 13,
+            "Invalid successive approximation point transform: {}",
+            point_transform
+        );
+        self.successive_approximation = point_transform;
+    }
+
+    /// Return the point transform used for successive approximation
+    pub fn successive_approximation(&self) -> u8 {
+        self.successive_approximation
+    }
+
+    /// Enable progressive encoding with the given scan script
+    ///
+    /// The script replaces the scans configured with [set_progressive_scans](Encoder::set_progressive_scans)
+    /// and [set_successive_approximation](Encoder::set_successive_approximation).
+    /// It is validated when the image gets encoded.
+    pub fn set_scan_script(&mut self, script: ScanScript) {
+        self.scan_script = Some(script);
+    }
+
+    /// Return the scan script if one is set
+    pub fn scan_script(&self) -> Option<&ScanScript> {
+        self.scan_script.as_ref()
+    }
+
     /// Set restart interval
     ///
     /// Set numbers of MCUs between restart markers.
@@ -507,6 +722,215 @@ impl<W: JfifWrite> Encoder<W> {
         self.optimize_huffman_table
     }
 
+    /// Omit the quantization and Huffman tables from the image
+    ///
+    /// The resulting abbreviated image can only be decoded together with the table
+    /// specification stream of [tables_only_bytes](Encoder::tables_only_bytes), as used
+    /// by Motion-JPEG, TIFF and DICOM. The setting is passed on to
+    /// [strip encoders](Encoder::into_strip_encoder).
+    ///
+    /// Encoding fails with an error for optimized Huffman tables, which includes lossless
+    /// encoding and samples with more than 8 bits.
+    ///
+    /// By default, the tables are written.
+    pub fn set_abbreviated_image(&mut self, abbreviated: bool) {
+        self.abbreviated = abbreviated;
+    }
+
+    /// Returns if the tables are omitted from the image
+    pub fn abbreviated_image(&self) -> bool {
+        self.abbreviated
+    }
+
+    /// Returns a table specification stream with the tables of this encoder
+    ///
+    /// The stream only contains the quantization tables for the current quality and the
+    /// Huffman tables, or the arithmetic coding conditioning tables, between the SOI and EOI
+    /// markers. It contains the tables of all components, so it pairs with the images of every
+    /// color type using the same settings, see
+    /// [set_abbreviated_image](Encoder::set_abbreviated_image).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder uses optimized Huffman tables or lossless encoding.
+    pub fn tables_only_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        if self.lossless_predictor.is_some() {
+            return Err(EncodingError::Write(
+                "Lossless images always use optimized Huffman tables".into(),
+            ));
+        }
+
+        if self.optimize_huffman_table && !self.arithmetic_coding {
+            return Err(EncodingError::Write(
+                "Optimized Huffman tables are only known while encoding".into(),
+            ));
+        }
+
+        let q_tables = [
+            QuantizationTable::new_with_quality(&self.quantization_tables[0], self.quality, true),
+            QuantizationTable::new_with_quality(&self.quantization_tables[1], self.quality, false),
+        ];
+
+        let arithmetic_conditioning = if self.arithmetic_coding {
+            Some(&self.arithmetic_conditioning)
+        } else {
+            None
+        };
+
+        let mut buffer = Vec::new();
+        let mut writer = JfifWriter::new(&mut buffer);
+
+        writer.write_marker(Marker::SOI)?;
+        write_table_segments(
+            &mut writer,
+            &q_tables,
+            &self.huffman_tables,
+            arithmetic_conditioning,
+            4,
+        )?;
+        writer.write_marker(Marker::EOI)?;
+
+        Ok(buffer)
+    }
+
+    /// Controls if arithmetic coding is used instead of huffman coding
+    ///
+    /// Arithmetic coding usually results in 5-10% smaller files but isn't supported by all decoders.
+    /// Optimized huffman tables have no effect if arithmetic coding is enabled.
+    ///
+    /// By default, arithmetic coding is disabled.
+    pub fn set_arithmetic_coding(&mut self, arithmetic_coding: bool) {
+        self.arithmetic_coding = arithmetic_coding;
+    }
+
+    /// Returns if arithmetic coding is used
+    pub fn arithmetic_coding(&self) -> bool {
+        self.arithmetic_coding
+    }
+
+    /// Set the conditioning of the arithmetic coder for luma and chroma components
+    ///
+    /// # Panics
+    /// If the conditioning values are out of range
+    pub fn set_arithmetic_conditioning(
+        &mut self,
+        luma: ArithmeticConditioning,
+        chroma: ArithmeticConditioning,
+    ) {
+        for conditioning in [&luma, &chroma] {
+            assert!(
+                conditioning.is_valid(),
+                "Invalid arithmetic conditioning: {:?}",
+                conditioning
+            );
+        }
+        self.arithmetic_conditioning = [luma, chroma];
+    }
+
+    /// Get configured arithmetic conditioning for luma and chroma components
+    pub fn arithmetic_conditioning(&self) -> &[ArithmeticConditioning; 2] {
+        &self.arithmetic_conditioning
+    }
+
+    /// Enables trellis quantization with the given settings
+    ///
+    /// Trellis quantization reduces the file size at a similar visual quality by choosing
+    /// the quantized coefficients with the huffman code lengths of the components.
+    /// The coefficients of the whole image are buffered in memory, like for progressive encoding
+    /// or optimized huffman tables.
+    ///
+    /// By default, trellis quantization is disabled.
+    ///
+    /// # Panics
+    /// If the lambda settings aren't finite or `lambda_log_scale2` is negative
+    pub fn set_trellis_quantization(&mut self, trellis: Option<TrellisQuantization>) {
+        if let Some(trellis) = &trellis {
+            assert!(
+                trellis.is_valid(),
+                "Invalid trellis quantization: {:?}",
+                trellis
+            );
+        }
+        self.trellis = trellis;
+    }
+
+    /// Returns the trellis quantization settings if enabled
+    pub fn trellis_quantization(&self) -> Option<&TrellisQuantization> {
+        self.trellis.as_ref()
+    }
+
+    /// Set the importance of the image regions
+    ///
+    /// Blocks with a low importance are quantized more coarsely, see [ImportanceMap] for details.
+    /// Lossless encoding ignores the importance map.
+    ///
+    /// By default, all blocks are encoded with the same quantization.
+    pub fn set_importance_map(&mut self, importance_map: Option<ImportanceMap>) {
+        self.importance_map = importance_map;
+    }
+
+    /// Returns the importance map if set
+    pub fn importance_map(&self) -> Option<&ImportanceMap> {
+        self.importance_map.as_ref()
+    }
+
+    /// Enables lossless encoding with the given predictor
+    ///
+    /// Lossless images are encoded with a single scan and optimized huffman tables.
+    /// The samples are stored without chroma subsampling and color conversion,
+    /// RGB data is stored as RGB and CMYK data is never converted to YCCK.
+    /// [encode_image](Encoder::encode_image) takes the samples from
+    /// [fill_lossless_buffers](ImageBuffer::fill_lossless_buffers).
+    /// Quality, quantization tables, sampling factors, progressive and arithmetic coding settings
+    /// are ignored in lossless mode.
+    ///
+    /// Use [encode_16](Encoder::encode_16) for images with a sample precision of more than 8 bit.
+    ///
+    /// By default, lossless encoding is disabled.
+    pub fn set_lossless(&mut self, predictor: Option<Predictor>) {
+        self.lossless_predictor = predictor;
+    }
+
+    /// Return the predictor if lossless encoding is enabled
+    pub fn lossless(&self) -> Option<Predictor> {
+        self.lossless_predictor
+    }
+
+    /// Set the point transform for lossless encoding
+    ///
+    /// The given number of low bits of each sample are discarded before encoding.
+    /// The point transform must be lower than the sample precision of the image.
+    /// A value of 0 (the default) keeps all bits.
+    ///
+    /// # Panics
+    /// If the point transform is greater than 15
+    pub fn set_lossless_point_transform(&mut self, point_transform: u8) {
+        assert!(
+            point_transform <= 15,
+            "Invalid lossless point transform: {}",
+            point_transform
+        );
+        self.lossless_point_transform = point_transform;
+    }
+
+    /// Return the point transform for lossless encoding
+    pub fn lossless_point_transform(&self) -> u8 {
+        self.lossless_point_transform
+    }
+
+    /// Set a hook which can inspect and modify the quantized blocks before entropy coding
+    ///
+    /// The hook is called once for each block of DCT based images, including the coefficients
+    /// of [encode_coefficients](Encoder::encode_coefficients), and is passed on to
+    /// [strip encoders](Encoder::into_strip_encoder). See [CoefficientHook] for details.
+    /// Lossless encoding ignores the hook and [encode_to_size](Encoder::encode_to_size),
+    /// which encodes the image several times, doesn't support it.
+    ///
+    /// By default, no hook is set.
+    pub fn set_coefficient_hook(&mut self, hook: Option<Box<dyn CoefficientHook + Send>>) {
+        self.coefficient_hook = hook;
+    }
+
     /// Appends a custom app segment to the JFIF file
     ///
     /// Segment numbers need to be in the range between 1 and 15<br>
@@ -518,7 +942,7 @@ impl<W: JfifWrite> Encoder<W> {
     pub fn add_app_segment(&mut self, segment_nr: u8, data: &[u8]) -> Result<(), EncodingError> {
         if segment_nr == 0 || segment_nr > 15 {
             Err(EncodingError::InvalidAppSegment(segment_nr))
-        } else if data.len() > 65533 {
+        } else if data.len() > MAX_SEGMENT_LENGTH {
             Err(EncodingError::AppSegmentTooLarge(data.len()))
         } else {
             self.app_segments.push((segment_nr, data.to_vec()));
@@ -526,6 +950,66 @@ impl<W: JfifWrite> Encoder<W> {
         }
     }
 
+    /// Appends a comment
+    ///
+    /// The comment is written in a COM segment. The maximum allowed data length is 2^16 - 2 bytes.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the comment exceeds the allowed size
+    pub fn add_comment(&mut self, comment: &[u8]) -> Result<(), EncodingError> {
+        self.comments.push(validate_comment(comment)?);
+        Ok(())
+    }
+
+    /// Set the layout of the segments in the file header
+    ///
+    /// See [MarkerLayout] for details.
+    pub fn set_marker_layout(&mut self, layout: MarkerLayout) {
+        self.marker_layout = layout;
+    }
+
+    /// Returns the layout of the segments in the file header
+    pub fn marker_layout(&self) -> MarkerLayout {
+        self.marker_layout
+    }
+
+    /// Set the EXIF metadata
+    ///
+    /// The APP1 segment is written directly after the SOI marker as required by EXIF.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data exceeds the maximum size of an APP segment
+    pub fn set_exif(&mut self, exif: &Exif) -> Result<(), EncodingError> {
+        self.exif = Some(exif.to_bytes()?);
+        Ok(())
+    }
+
+    /// Set the thumbnail embedded in the JFIF header
+    ///
+    /// The thumbnail is downscaled from the image while encoding, see [JfifThumbnail]
+    /// for details. By default, no thumbnail is written.
+    ///
+    /// Thumbnails are not supported by [encode_coefficients](Encoder::encode_coefficients)
+    /// and the [StripEncoder], which don't have the whole image when the header is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a maximum dimension is zero or an RGB thumbnail of the maximum
+    /// dimensions exceeds the JFIF segment
+    pub fn set_jfif_thumbnail(
+        &mut self,
+        thumbnail: Option<JfifThumbnail>,
+    ) -> Result<(), EncodingError> {
+        if let Some(thumbnail) = thumbnail {
+            thumbnail.validate()?;
+        }
+
+        self.jfif_thumbnail = thumbnail;
+        Ok(())
+    }
+
     /// Add an ICC profile
     ///
     /// The maximum allowed data length is 16,707,345 bytes.
@@ -562,9 +1046,92 @@ impl<W: JfifWrite> Encoder<W> {
         Ok(())
     }
 
+    /// Add an XMP packet
+    ///
+    /// The packet is written in an APP1 segment with the `http://ns.adobe.com/xap/1.0/`
+    /// namespace. Packets larger than 65,504 bytes are written as extended XMP, which is split
+    /// into APP1 segments with the `http://ns.adobe.com/xmp/extension/` namespace. The standard
+    /// packet then only references the extended XMP by its GUID.
+    ///
+    /// The packet isn't split at the property level, so readers which don't support extended
+    /// XMP see none of the properties of a large packet. Use
+    /// [add_extended_xmp](Encoder::add_extended_xmp) to keep the important properties
+    /// in the standard packet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the packet exceeds 4 GiB
+    pub fn add_xmp(&mut self, xmp: &[u8]) -> Result<(), EncodingError> {
+        for segment in xmp_segments(xmp)? {
+            self.add_app_segment(1, &segment)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add a standard XMP packet and its extended XMP
+    ///
+    /// This writes an XMP packet which has already been split as described in
+    /// XMP Specification Part 3 (2016) 1.1.3.1, e.g. by an XMP toolkit. The standard packet
+    /// is written in an APP1 segment with the `http://ns.adobe.com/xap/1.0/` namespace and must
+    /// contain the `xmpNote:HasExtendedXMP` property with the GUID of the extended XMP. The
+    /// GUID is the MD5 digest of `extended` as 32 uppercase hexadecimal digits. The extended XMP
+    /// is split into APP1 segments with the `http://ns.adobe.com/xmp/extension/` namespace.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the standard packet exceeds 65,504 bytes or doesn't contain the GUID,
+    /// or if the extended XMP exceeds 4 GiB
+    pub fn add_extended_xmp(
+        &mut self,
+        standard: &[u8],
+        extended: &[u8],
+    ) -> Result<(), EncodingError> {
+        for segment in extended_xmp_segments(standard, extended)? {
+            self.add_app_segment(1, &segment)?;
+        }
+
+        Ok(())
+    }
+
+    /// Add IPTC-IIM metadata
+    ///
+    /// The datasets are written as IPTC-NAA image resource block (0x0404),
+    /// see [add_photoshop_resource](Encoder::add_photoshop_resource).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data exceeds 4 GiB
+    pub fn add_iptc(&mut self, iptc: &Iptc) -> Result<(), EncodingError> {
+        self.add_photoshop_resource(IPTC_RESOURCE_ID, &iptc.to_bytes())
+    }
+
+    /// Add a Photoshop image resource block
+    ///
+    /// The block is written in "Photoshop 3.0" APP13 segments. Blocks larger than a single
+    /// segment are split across several segments whose data is concatenated by readers.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data exceeds 4 GiB
+    pub fn add_photoshop_resource(&mut self, id: u16, data: &[u8]) -> Result<(), EncodingError> {
+        for segment in photoshop_segments(id, data)? {
+            self.add_app_segment(13, &segment)?;
+        }
+
+        Ok(())
+    }
+
     /// Encode an image
     ///
     /// Data format and length must conform to specified width, height and color type.
+    ///
+    /// With the `rayon` feature the image is encoded on the rayon thread pool.
+    /// The output is the same as without the feature.
+    #[cfg_attr(
+        all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"),
+        allow(unreachable_code)
+    )]
     pub fn encode(
         self,
         data: &[u8],
@@ -581,57 +1148,326 @@ impl<W: JfifWrite> Encoder<W> {
             });
         }
 
+        if self.lossless_predictor.is_some() {
+            return self.encode_image_lossless(RawImage::new(data, width, height, color_type, 8));
+        }
+
         #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
         {
             if std::is_x86_feature_detected!("avx2") {
                 use crate::avx2::*;
 
                 return match color_type {
-                    ColorType::Luma => self
-                        .encode_image_internal::<_, AVX2Operations>(GrayImage(data, width, height)),
-                    ColorType::Rgb => self.encode_image_internal::<_, AVX2Operations>(
-                        RgbImageAVX2(data, width, height),
-                    ),
-                    ColorType::Rgba => self.encode_image_internal::<_, AVX2Operations>(
-                        RgbaImageAVX2(data, width, height),
-                    ),
-                    ColorType::Bgr => self.encode_image_internal::<_, AVX2Operations>(
-                        BgrImageAVX2(data, width, height),
-                    ),
-                    ColorType::Bgra => self.encode_image_internal::<_, AVX2Operations>(
-                        BgraImageAVX2(data, width, height),
-                    ),
-                    ColorType::Ycbcr => self.encode_image_internal::<_, AVX2Operations>(
-                        YCbCrImage(data, width, height),
-                    ),
-                    ColorType::Cmyk => self
-                        .encode_image_internal::<_, AVX2Operations>(CmykImage(data, width, height)),
-                    ColorType::CmykAsYcck => self.encode_image_internal::<_, AVX2Operations>(
+                    ColorType::Luma => {
+                        self.encode_image_sync::<_, AVX2Operations>(GrayImage(data, width, height))
+                    }
+                    ColorType::Rgb => self
+                        .encode_image_sync::<_, AVX2Operations>(RgbImageAVX2(data, width, height)),
+                    ColorType::Rgba => self
+                        .encode_image_sync::<_, AVX2Operations>(RgbaImageAVX2(data, width, height)),
+                    ColorType::Bgr => self
+                        .encode_image_sync::<_, AVX2Operations>(BgrImageAVX2(data, width, height)),
+                    ColorType::Bgra => self
+                        .encode_image_sync::<_, AVX2Operations>(BgraImageAVX2(data, width, height)),
+                    ColorType::Ycbcr => {
+                        self.encode_image_sync::<_, AVX2Operations>(YCbCrImage(data, width, height))
+                    }
+                    ColorType::Cmyk => {
+                        self.encode_image_sync::<_, AVX2Operations>(CmykImage(data, width, height))
+                    }
+                    ColorType::CmykAsYcck => self.encode_image_sync::<_, AVX2Operations>(
                         CmykAsYcckImage(data, width, height),
                     ),
-                    ColorType::Ycck => self
-                        .encode_image_internal::<_, AVX2Operations>(YcckImage(data, width, height)),
+                    ColorType::Ycck => {
+                        self.encode_image_sync::<_, AVX2Operations>(YcckImage(data, width, height))
+                    }
                 };
             }
         }
 
-        match color_type {
-            ColorType::Luma => self.encode_image(GrayImage(data, width, height))?,
-            ColorType::Rgb => self.encode_image(RgbImage(data, width, height))?,
-            ColorType::Rgba => self.encode_image(RgbaImage(data, width, height))?,
-            ColorType::Bgr => self.encode_image(BgrImage(data, width, height))?,
-            ColorType::Bgra => self.encode_image(BgraImage(data, width, height))?,
-            ColorType::Ycbcr => self.encode_image(YCbCrImage(data, width, height))?,
-            ColorType::Cmyk => self.encode_image(CmykImage(data, width, height))?,
-            ColorType::CmykAsYcck => self.encode_image(CmykAsYcckImage(data, width, height))?,
-            ColorType::Ycck => self.encode_image(YcckImage(data, width, height))?,
+        #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                use crate::neon::*;
+
+                return match color_type {
+                    ColorType::Luma => {
+                        self.encode_image_sync::<_, NeonOperations>(GrayImage(data, width, height))
+                    }
+                    ColorType::Rgb => self
+                        .encode_image_sync::<_, NeonOperations>(RgbImageNeon(data, width, height)),
+                    ColorType::Rgba => self
+                        .encode_image_sync::<_, NeonOperations>(RgbaImageNeon(data, width, height)),
+                    ColorType::Bgr => self
+                        .encode_image_sync::<_, NeonOperations>(BgrImageNeon(data, width, height)),
+                    ColorType::Bgra => self
+                        .encode_image_sync::<_, NeonOperations>(BgraImageNeon(data, width, height)),
+                    ColorType::Ycbcr => {
+                        self.encode_image_sync::<_, NeonOperations>(YCbCrImage(data, width, height))
+                    }
+                    ColorType::Cmyk => {
+                        self.encode_image_sync::<_, NeonOperations>(CmykImage(data, width, height))
+                    }
+                    ColorType::CmykAsYcck => self.encode_image_sync::<_, NeonOperations>(
+                        CmykAsYcckImage(data, width, height),
+                    ),
+                    ColorType::Ycck => {
+                        self.encode_image_sync::<_, NeonOperations>(YcckImage(data, width, height))
+                    }
+                };
+            }
+        }
+
+        // SIMD128 can't be detected at runtime, it has to be enabled at compile time
+        #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+        {
+            use crate::simd128::*;
+
+            return match color_type {
+                ColorType::Luma => {
+                    self.encode_image_sync::<_, Simd128Operations>(GrayImage(data, width, height))
+                }
+                ColorType::Rgb => self.encode_image_sync::<_, Simd128Operations>(RgbImageSimd128(
+                    data, width, height,
+                )),
+                ColorType::Rgba => self.encode_image_sync::<_, Simd128Operations>(
+                    RgbaImageSimd128(data, width, height),
+                ),
+                ColorType::Bgr => self.encode_image_sync::<_, Simd128Operations>(BgrImageSimd128(
+                    data, width, height,
+                )),
+                ColorType::Bgra => self.encode_image_sync::<_, Simd128Operations>(
+                    BgraImageSimd128(data, width, height),
+                ),
+                ColorType::Ycbcr => {
+                    self.encode_image_sync::<_, Simd128Operations>(YCbCrImage(data, width, height))
+                }
+                ColorType::Cmyk => {
+                    self.encode_image_sync::<_, Simd128Operations>(CmykImage(data, width, height))
+                }
+                ColorType::CmykAsYcck => self.encode_image_sync::<_, Simd128Operations>(
+                    CmykAsYcckImage(data, width, height),
+                ),
+                ColorType::Ycck => {
+                    self.encode_image_sync::<_, Simd128Operations>(YcckImage(data, width, height))
+                }
+            };
+        }
+
+        match color_type {
+            ColorType::Luma => {
+                self.encode_image_sync::<_, DefaultOperations>(GrayImage(data, width, height))?
+            }
+            ColorType::Rgb => {
+                self.encode_image_sync::<_, DefaultOperations>(RgbImage(data, width, height))?
+            }
+            ColorType::Rgba => {
+                self.encode_image_sync::<_, DefaultOperations>(RgbaImage(data, width, height))?
+            }
+            ColorType::Bgr => {
+                self.encode_image_sync::<_, DefaultOperations>(BgrImage(data, width, height))?
+            }
+            ColorType::Bgra => {
+                self.encode_image_sync::<_, DefaultOperations>(BgraImage(data, width, height))?
+            }
+            ColorType::Ycbcr => {
+                self.encode_image_sync::<_, DefaultOperations>(YCbCrImage(data, width, height))?
+            }
+            ColorType::Cmyk => {
+                self.encode_image_sync::<_, DefaultOperations>(CmykImage(data, width, height))?
+            }
+            ColorType::CmykAsYcck => self
+                .encode_image_sync::<_, DefaultOperations>(CmykAsYcckImage(data, width, height))?,
+            ColorType::Ycck => {
+                self.encode_image_sync::<_, DefaultOperations>(YcckImage(data, width, height))?
+            }
         }
 
         Ok(())
     }
 
+    /// Encode an image with 16 bit samples
+    ///
+    /// Data format and length must conform to specified width, height and color type.
+    /// All samples must be smaller than `2^precision`.
+    ///
+    /// Lossless encoding supports a precision of 2 to 16 bits, DCT based encoding
+    /// a precision of 8 or 12 bits. 12 bit images always use optimized huffman tables
+    /// if arithmetic coding isn't enabled, because the default tables don't contain
+    /// codes for the larger coefficients.
+    pub fn encode_16(
+        self,
+        data: &[u16],
+        width: u16,
+        height: u16,
+        color_type: ColorType,
+        precision: u8,
+    ) -> Result<(), EncodingError> {
+        let required_data_len = width as usize * height as usize * color_type.get_bytes_per_pixel();
+
+        if data.len() < required_data_len {
+            return Err(EncodingError::BadImageData {
+                length: data.len(),
+                required: required_data_len,
+            });
+        }
+
+        let image = RawImage::new(data, width, height, color_type, precision);
+
+        if self.lossless_predictor.is_some() {
+            self.encode_image_lossless(image)
+        } else {
+            // The color conversion would hide invalid samples
+            if precision == 8 || precision == 12 {
+                let max_value = ((1u32 << precision) - 1) as u16;
+
+                if let Some(&value) = data[..required_data_len].iter().find(|&&v| v > max_value) {
+                    return Err(EncodingError::SampleOutOfRange { value, precision });
+                }
+            }
+
+            self.encode_image_wide(image.with_color_conversion())
+        }
+    }
+
+    /// Encode an image with 16 bit samples
+    ///
+    /// See [encode_16](Encoder::encode_16) for the supported precisions.
+    pub fn encode_image_16<I: ImageBuffer16>(self, image: I) -> Result<(), EncodingError> {
+        if self.lossless_predictor.is_some() {
+            self.encode_image_lossless(image)
+        } else {
+            self.encode_image_wide(image)
+        }
+    }
+
+    /// Encode quantized DCT coefficients
+    ///
+    /// Skips the color conversion, the DCT and the quantization, e.g. to transcode an image
+    /// or to modify its coefficients. `blocks` contains the blocks of each component of
+    /// `color_type` in the order of the components.
+    ///
+    /// The blocks of a component are in row-major order and cover the area of the component
+    /// without the padding of MCUs, i.e. `ceil(ceil(width / 8) * h / h_max)` blocks per row
+    /// and `ceil(ceil(height / 8) * v / v_max)` rows for the component sampling factors `h` and
+    /// `v` of the [sampling factor](Encoder::set_sampling_factor). The 64 coefficients of a
+    /// block are in zigzag order and quantized with the table of the component, which is set
+    /// by [set_quantization_tables](Encoder::set_quantization_tables), usually with
+    /// [QuantizationTableType::Custom]. The DC coefficients are level shifted, so they must be
+    /// in the range `-1024..=1023`, the AC coefficients in the range `-1023..=1023`.
+    ///
+    /// Sequential images are written with one scan per component. Progressive scans,
+    /// optimized Huffman tables and arithmetic coding are supported.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the number of components or blocks doesn't match the image or
+    /// a coefficient is out of range, and for lossless encoding.
+    pub fn encode_coefficients(
+        mut self,
+        blocks: &[Vec<[i16; 64]>],
+        width: u16,
+        height: u16,
+        color_type: JpegColorType,
+    ) -> Result<(), EncodingError> {
+        if width == 0 || height == 0 {
+            return Err(EncodingError::ZeroImageDimensions { width, height });
+        }
+
+        if self.lossless_predictor.is_some() {
+            return Err(EncodingError::Write(
+                "Lossless encoding does not use DCT coefficients".into(),
+            ));
+        }
+
+        if self.jfif_thumbnail.is_some() {
+            return Err(EncodingError::Write(
+                "JFIF thumbnails are not supported for DCT coefficients".into(),
+            ));
+        }
+
+        self.init_components(color_type);
+
+        if blocks.len() != self.components.len() {
+            return Err(EncodingError::Write(alloc::format!(
+                "Expected blocks of {} components but received {}",
+                self.components.len(),
+                blocks.len()
+            )));
+        }
+
+        let (max_h_sampling, max_v_sampling) = self.get_max_sampling_size();
+
+        let num_cols = ceil_div(usize::from(width), 8);
+        let num_rows = ceil_div(usize::from(height), 8);
+
+        let mut component_blocks: [Vec<[i16; 64]>; 4] = Default::default();
+
+        for (i, component) in self.components.iter().enumerate() {
+            let h_scale = max_h_sampling / usize::from(component.horizontal_sampling_factor);
+            let v_scale = max_v_sampling / usize::from(component.vertical_sampling_factor);
+
+            let required = ceil_div(num_cols, h_scale) * ceil_div(num_rows, v_scale);
+
+            if blocks[i].len() != required {
+                return Err(EncodingError::Write(alloc::format!(
+                    "Expected {} blocks for component {} but received {}",
+                    required,
+                    i,
+                    blocks[i].len()
+                )));
+            }
+
+            for block in &blocks[i] {
+                validate_coefficients(block, i, 8)?;
+            }
+
+            component_blocks[i] = blocks[i].clone();
+        }
+
+        let q_tables = [
+            QuantizationTable::new_with_quality(&self.quantization_tables[0], self.quality, true),
+            QuantizationTable::new_with_quality(&self.quantization_tables[1], self.quality, false),
+        ];
+
+        let scans = self.get_progressive_scans(color_type)?;
+
+        write_file_headers(
+            &mut self.writer,
+            self.density,
+            color_type,
+            self.exif.as_deref(),
+            None,
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        )?;
+
+        self.encode_quantized_blocks(
+            &mut component_blocks,
+            width,
+            height,
+            8,
+            scans.as_deref(),
+            &q_tables,
+        )?;
+
+        self.writer.write_marker(Marker::EOI)
+    }
+
     /// Encode an image
+    ///
+    /// With the `rayon` feature the color conversion of `image` is done on the calling thread,
+    /// the rest of the encoding on the rayon thread pool.
+    #[cfg_attr(
+        all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"),
+        allow(unreachable_code)
+    )]
     pub fn encode_image<I: ImageBuffer>(self, image: I) -> Result<(), EncodingError> {
+        if self.lossless_predictor.is_some() {
+            return self.encode_image_lossless(WideImage(image));
+        }
+
         #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
         {
             if std::is_x86_feature_detected!("avx2") {
@@ -639,54 +1475,283 @@ impl<W: JfifWrite> Encoder<W> {
                 return self.encode_image_internal::<_, AVX2Operations>(image);
             }
         }
+
+        #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                use crate::neon::*;
+                return self.encode_image_internal::<_, NeonOperations>(image);
+            }
+        }
+
+        #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+        {
+            use crate::simd128::*;
+            return self.encode_image_internal::<_, Simd128Operations>(image);
+        }
+
         self.encode_image_internal::<_, DefaultOperations>(image)
     }
 
-    #[allow(clippy::too_many_arguments)]
+    /// Encode an image with a maximum file size
+    ///
+    /// Searches the highest quality for which the encoded image isn't larger than `target_size` bytes.
+    /// The quality set with [new](Encoder::new) is ignored, all other settings
+    /// like sampling factors, progressive mode or trellis quantization are applied to every attempt.
+    /// The color conversion and DCT are only done once and the coefficients are reused
+    /// for each quality.
+    ///
+    /// If the image is too large even with a quality of 1, the high frequency AC coefficients
+    /// of all blocks are dropped in zigzag order until it fits, down to only the DC coefficients.
+    ///
+    /// Returns the chosen quality and the number of AC coefficients which are kept.
+    ///
+    /// Data format and length must conform to specified width, height and color type.
+    ///
+    /// # Errors
+    /// Returns [TargetSizeNotReached](EncodingError::TargetSizeNotReached) if the image
+    /// is larger than `target_size` with a quality of 1 and only the DC coefficients.
+    /// Nothing is written in this case.
+    pub fn encode_to_size(
+        self,
+        data: &[u8],
+        width: u16,
+        height: u16,
+        color_type: ColorType,
+        target_size: usize,
+    ) -> Result<TargetSizeResult, EncodingError> {
+        let required_data_len = width as usize * height as usize * color_type.get_bytes_per_pixel();
+
+        if data.len() < required_data_len {
+            return Err(EncodingError::BadImageData {
+                length: data.len(),
+                required: required_data_len,
+            });
+        }
+
+        match color_type {
+            ColorType::Luma => {
+                self.encode_image_to_size(GrayImage(data, width, height), target_size)
+            }
+            ColorType::Rgb => self.encode_image_to_size(RgbImage(data, width, height), target_size),
+            ColorType::Rgba => {
+                self.encode_image_to_size(RgbaImage(data, width, height), target_size)
+            }
+            ColorType::Bgr => self.encode_image_to_size(BgrImage(data, width, height), target_size),
+            ColorType::Bgra => {
+                self.encode_image_to_size(BgraImage(data, width, height), target_size)
+            }
+            ColorType::Ycbcr => {
+                self.encode_image_to_size(YCbCrImage(data, width, height), target_size)
+            }
+            ColorType::Cmyk => {
+                self.encode_image_to_size(CmykImage(data, width, height), target_size)
+            }
+            ColorType::CmykAsYcck => {
+                self.encode_image_to_size(CmykAsYcckImage(data, width, height), target_size)
+            }
+            ColorType::Ycck => {
+                self.encode_image_to_size(YcckImage(data, width, height), target_size)
+            }
+        }
+    }
+
+    /// Encode an image with a maximum file size
+    ///
+    /// See [encode_to_size](Encoder::encode_to_size) for details.
+    #[cfg_attr(
+        all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"),
+        allow(unreachable_code)
+    )]
+    pub fn encode_image_to_size<I: ImageBuffer>(
+        self,
+        image: I,
+        target_size: usize,
+    ) -> Result<TargetSizeResult, EncodingError> {
+        #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+        {
+            if std::is_x86_feature_detected!("avx2") {
+                use crate::avx2::*;
+                return self.encode_image_to_size_internal::<_, AVX2Operations>(image, target_size);
+            }
+        }
+
+        #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                use crate::neon::*;
+                return self.encode_image_to_size_internal::<_, NeonOperations>(image, target_size);
+            }
+        }
+
+        #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+        {
+            use crate::simd128::*;
+            return self.encode_image_to_size_internal::<_, Simd128Operations>(image, target_size);
+        }
+
+        self.encode_image_to_size_internal::<_, DefaultOperations>(image, target_size)
+    }
+
+    /// Create a [StripEncoder] which encodes the image in strips of rows
+    ///
+    /// Only one MCU row of samples (8 to 32 rows, depending on the sampling factor) is
+    /// buffered and the entropy coded data is written as soon as a MCU row is complete,
+    /// so the memory usage doesn't depend on the image height.
+    ///
+    /// Sampling factors which don't support interleaved scans, e.g. [SamplingFactor::F_4_2],
+    /// are encoded with one scan per component like [encode](Encoder::encode) does. The scan
+    /// of the first component is written while the strips arrive, the quantized coefficients
+    /// of the other components are kept in memory (128 bytes per 8x8 block) and written by
+    /// [finish](StripEncoder::finish).
+    ///
+    /// With [optimized Huffman tables](Encoder::set_optimized_huffman_tables) the tables
+    /// depend on the whole image. The quantized coefficients of all blocks are kept in memory
+    /// (128 bytes per 8x8 block of every component) and all data is written by
+    /// [finish](StripEncoder::finish). Use [into_strip_encoder_with_scratch](Encoder::into_strip_encoder_with_scratch)
+    /// to keep the memory usage bounded in this case.
+    ///
+    /// [Progressive scans](Encoder::set_progressive) need all coefficients as well. They are
+    /// kept in memory in the same way and the scans, which have the same layout as the ones of
+    /// [encode](Encoder::encode), are written by [finish](StripEncoder::finish).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for settings not supported by the strip encoder, i.e. arithmetic coding,
+    /// trellis quantization and lossless encoding.
     pub fn into_strip_encoder(
         self,
         width: u16,
         height: u16,
         color_type: ColorType,
     ) -> Result<StripEncoder<W>, EncodingError> {
+        self.into_strip_encoder_internal(width, height, color_type, None)
+    }
+
+    /// Create a [StripEncoder] with optimized Huffman tables which spills the quantized
+    /// coefficients into a scratch storage
+    ///
+    /// The tables are optimized regardless of [set_optimized_huffman_tables](Encoder::set_optimized_huffman_tables).
+    /// As with [into_strip_encoder](Encoder::into_strip_encoder) only one MCU row of samples
+    /// and coefficients is kept in memory. The scratch storage receives 128 bytes per
+    /// 8x8 block of every component and is read back by [finish](StripEncoder::finish),
+    /// which writes all data. With one scan per component only the blocks of the first
+    /// component are spilled.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [into_strip_encoder](Encoder::into_strip_encoder) and an error
+    /// for progressive scans, which need random access to the coefficients.
+    pub fn into_strip_encoder_with_scratch<S: StripScratch + Send + 'static>(
+        self,
+        width: u16,
+        height: u16,
+        color_type: ColorType,
+        scratch: S,
+    ) -> Result<StripEncoder<W>, EncodingError> {
+        if self.is_progressive() {
+            return Err(EncodingError::Write(
+                "Strip encoding with a scratch storage does not support progressive scans".into(),
+            ));
+        }
+
+        self.into_strip_encoder_internal(width, height, color_type, Some(Box::new(scratch)))
+    }
+
+    /// Create a [StripEncoder] for an image whose height is only known after the last row
+    ///
+    /// The frame header contains a height of 0 and the strip encoder accepts up to 65535 rows.
+    /// [finish](StripEncoder::finish) writes the number of received rows in a DNL segment after
+    /// the first scan, which isn't supported by every decoder.
+    /// [finish_patching_height](StripEncoder::finish_patching_height) writes it into the frame
+    /// header of seekable writers instead.
+    ///
+    /// With [optimized Huffman tables](Encoder::set_optimized_huffman_tables) all data is written
+    /// by [finish](StripEncoder::finish), so the frame header contains the actual height.
+    ///
+    /// # Errors
+    ///
+    /// Returns the same errors as [into_strip_encoder](Encoder::into_strip_encoder) and an error
+    /// for progressive scans, which need the height for the layout of the coefficients.
+    pub fn into_strip_encoder_unknown_height(
+        self,
+        width: u16,
+        color_type: ColorType,
+    ) -> Result<StripEncoder<W>, EncodingError> {
+        if self.is_progressive() {
+            return Err(EncodingError::Write(
+                "Strip encoding with unknown height does not support progressive scans".into(),
+            ));
+        }
+
+        let mut encoder = self.into_strip_encoder_internal(width, u16::MAX, color_type, None)?;
+        encoder.set_unknown_height();
+
+        Ok(encoder)
+    }
+
+    fn into_strip_encoder_internal(
+        mut self,
+        width: u16,
+        height: u16,
+        color_type: ColorType,
+        scratch: Option<Box<dyn StripScratch + Send>>,
+    ) -> Result<StripEncoder<W>, EncodingError> {
+        if width == 0 || height == 0 {
+            return Err(EncodingError::ZeroImageDimensions { width, height });
+        }
+
+        let jpeg_color_type = color_type_to_jpeg(color_type);
+
+        self.init_components(jpeg_color_type);
+        let scans = self.get_progressive_scans(jpeg_color_type)?;
+
+        if self.jfif_thumbnail.is_some() {
+            return Err(EncodingError::Write(
+                "Strip encoding does not support JFIF thumbnails".into(),
+            ));
+        }
+
         let Encoder {
             writer,
             density,
             quality,
             quantization_tables,
             huffman_tables,
-            sampling_factor,
-            progressive_scans,
+            components,
             restart_interval,
             optimize_huffman_table,
+            abbreviated,
+            arithmetic_coding,
+            trellis,
+            importance_map,
+            lossless_predictor,
+            coefficient_hook,
+            exif,
             app_segments,
+            comments,
+            marker_layout,
             ..
         } = self;
 
-        if width == 0 || height == 0 {
-            return Err(EncodingError::ZeroImageDimensions { width, height });
-        }
-
-        if progressive_scans.is_some() {
+        if arithmetic_coding {
             return Err(EncodingError::Write(
-                "Strip encoding does not support progressive scans".into(),
+                "Strip encoding does not support arithmetic coding".into(),
             ));
         }
 
-        if optimize_huffman_table {
+        if trellis.is_some() {
             return Err(EncodingError::Write(
-                "Strip encoding does not support optimized Huffman tables".into(),
+                "Strip encoding does not support trellis quantization".into(),
             ));
         }
 
-        if !sampling_factor.supports_interleaved() {
+        if lossless_predictor.is_some() {
             return Err(EncodingError::Write(
-                "Strip encoding requires interleaved sampling factors".into(),
+                "Strip encoding does not support lossless encoding".into(),
             ));
         }
 
-        let jpeg_color_type = color_type_to_jpeg(color_type);
-        let components = build_components(sampling_factor, jpeg_color_type);
         let component_specs: Vec<_> = components.iter().map(ComponentSpec::from).collect();
 
         let quantization_tables = [
@@ -694,7 +1759,7 @@ impl<W: JfifWrite> Encoder<W> {
             QuantizationTable::new_with_quality(&quantization_tables[1], quality, false),
         ];
 
-        StripEncoder::new_with_tables(
+        let mut encoder = StripEncoder::new_with_tables(
             writer.into_inner(),
             width,
             height,
@@ -706,12 +1771,59 @@ impl<W: JfifWrite> Encoder<W> {
             restart_interval,
             density,
             app_segments,
-        )
+        )?;
+
+        encoder.set_importance_map(importance_map);
+        encoder.set_coefficient_hook(coefficient_hook);
+        encoder.set_exif_data(exif)?;
+        encoder.set_marker_layout(marker_layout)?;
+
+        for comment in comments {
+            encoder.add_comment(&comment)?;
+        }
+
+        if let Some(scans) = scans {
+            encoder.defer_coding(DeferredMode::Progressive {
+                scans,
+                optimize: optimize_huffman_table,
+            });
+        } else if let Some(scratch) = scratch {
+            encoder.defer_coding(DeferredMode::Sequential(Some(scratch)));
+        } else if optimize_huffman_table {
+            encoder.defer_coding(DeferredMode::Sequential(None));
+        }
+
+        encoder.set_abbreviated_image(abbreviated)?;
+
+        Ok(encoder)
     }
 
     fn encode_image_internal<I: ImageBuffer, OP: Operations>(
+        self,
+        image: I,
+    ) -> Result<(), EncodingError> {
+        self.encode_image_with::<_, OP>(image, Self::encode_image_interleaved::<I, OP>)
+    }
+
+    /// Encode an image which can be color converted on several threads
+    fn encode_image_sync<I: ImageBuffer + Sync, OP: Operations>(
+        self,
+        image: I,
+    ) -> Result<(), EncodingError> {
+        #[cfg(feature = "rayon")]
+        let encode_interleaved = Self::encode_image_interleaved_sync::<I, OP>;
+
+        #[cfg(not(feature = "rayon"))]
+        let encode_interleaved = Self::encode_image_interleaved::<I, OP>;
+
+        self.encode_image_with::<_, OP>(image, encode_interleaved)
+    }
+
+    /// Encode an image with `encode_interleaved` used for interleaved baseline scans
+    fn encode_image_with<I: ImageBuffer, OP: Operations>(
         mut self,
         image: I,
+        encode_interleaved: fn(&mut Self, I, &[QuantizationTable; 2]) -> Result<(), EncodingError>,
     ) -> Result<(), EncodingError> {
         if image.width() == 0 || image.height() == 0 {
             return Err(EncodingError::ZeroImageDimensions {
@@ -720,32 +1832,440 @@ impl<W: JfifWrite> Encoder<W> {
             });
         }
 
-        let q_tables = [
-            QuantizationTable::new_with_quality(&self.quantization_tables[0], self.quality, true),
-            QuantizationTable::new_with_quality(&self.quantization_tables[1], self.quality, false),
-        ];
+        let q_tables = [
+            QuantizationTable::new_with_quality(&self.quantization_tables[0], self.quality, true),
+            QuantizationTable::new_with_quality(&self.quantization_tables[1], self.quality, false),
+        ];
+
+        let jpeg_color_type = image.get_jpeg_color_type();
+        self.init_components(jpeg_color_type);
+
+        let scans = self.get_progressive_scans(jpeg_color_type)?;
+
+        let thumbnail = self.create_jfif_thumbnail(
+            jpeg_color_type,
+            image.width(),
+            image.height(),
+            8,
+            |y, buffers| image.fill_buffers(y, buffers),
+        )?;
+
+        write_file_headers(
+            &mut self.writer,
+            self.density,
+            jpeg_color_type,
+            self.exif.as_deref(),
+            thumbnail.as_ref(),
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        )?;
+
+        if self.arithmetic_coding
+            || scans.is_some()
+            || self.optimize_huffman_table
+            || self.trellis.is_some()
+            || !self.sampling_factor.supports_interleaved()
+        {
+            let mut blocks = self.transform_blocks::<_, OP>(&image);
+            self.quantize_coefficients::<OP>(&mut blocks, image.width(), &q_tables);
+
+            self.encode_quantized_blocks(
+                &mut blocks,
+                image.width(),
+                image.height(),
+                8,
+                scans.as_deref(),
+                &q_tables,
+            )?;
+        } else {
+            encode_interleaved(&mut self, image, &q_tables)?;
+        }
+
+        self.writer.write_marker(Marker::EOI)?;
+
+        Ok(())
+    }
+
+    fn encode_image_to_size_internal<I: ImageBuffer, OP: Operations>(
+        mut self,
+        image: I,
+        target_size: usize,
+    ) -> Result<TargetSizeResult, EncodingError> {
+        let width = image.width();
+        let height = image.height();
+
+        if width == 0 || height == 0 {
+            return Err(EncodingError::ZeroImageDimensions { width, height });
+        }
+
+        if self.lossless_predictor.is_some() {
+            return Err(EncodingError::Write(
+                "Lossless encoding does not support a target size".into(),
+            ));
+        }
+
+        if self.coefficient_hook.is_some() {
+            return Err(EncodingError::Write(
+                "A coefficient hook does not support a target size".into(),
+            ));
+        }
+
+        let jpeg_color_type = image.get_jpeg_color_type();
+        self.init_components(jpeg_color_type);
+
+        let scans = self.get_progressive_scans(jpeg_color_type)?;
+
+        let thumbnail =
+            self.create_jfif_thumbnail(jpeg_color_type, width, height, 8, |y, buffers| {
+                image.fill_buffers(y, buffers)
+            })?;
+
+        // The file headers don't depend on the quality
+        let mut headers = JfifWriter::new(Vec::new());
+        write_file_headers(
+            &mut headers,
+            self.density,
+            jpeg_color_type,
+            self.exif.as_deref(),
+            thumbnail.as_ref(),
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        )?;
+        let headers = headers.into_inner();
+
+        let coefficients = self.transform_blocks::<_, OP>(&image);
+
+        // Encodes the image with the given quality, only the first `num_ac` AC coefficients
+        // of each block in zigzag order are kept
+        let encode = |quality: u8, num_ac: usize| -> Result<Vec<u8>, EncodingError> {
+            let mut encoder = self.buffered_copy(quality);
+
+            let q_tables = [
+                QuantizationTable::new_with_quality(&encoder.quantization_tables[0], quality, true),
+                QuantizationTable::new_with_quality(
+                    &encoder.quantization_tables[1],
+                    quality,
+                    false,
+                ),
+            ];
+
+            let mut blocks = coefficients.clone();
+            encoder.quantize_coefficients::<OP>(&mut blocks, width, &q_tables);
+
+            if num_ac < 63 {
+                for block in blocks.iter_mut().flatten() {
+                    block[num_ac + 1..].fill(0);
+                }
+            }
+
+            encoder.encode_quantized_blocks(
+                &mut blocks,
+                width,
+                height,
+                8,
+                scans.as_deref(),
+                &q_tables,
+            )?;
+            encoder.writer.write_marker(Marker::EOI)?;
+
+            Ok(encoder.writer.into_inner())
+        };
+
+        // Binary search for the highest quality that fits into the target size
+        let mut best: Option<(TargetSizeResult, Vec<u8>)> = None;
+        let mut min_size = usize::MAX;
+
+        let mut low = 1;
+        let mut high = 100;
+
+        while low <= high {
+            let quality = low + (high - low) / 2;
+            let data = encode(quality, 63)?;
+            let size = headers.len() + data.len();
+
+            min_size = min_size.min(size);
+
+            if size <= target_size {
+                let result = TargetSizeResult {
+                    quality,
+                    ac_coefficients: 63,
+                };
+
+                best = Some((result, data));
+                low = quality + 1;
+            } else {
+                high = quality - 1;
+            }
+        }
+
+        // The table values are mostly saturated at a quality of 1, so the quantization is
+        // coarsened further by dropping the high frequency AC coefficients
+        if best.is_none() {
+            let mut low = 0;
+            let mut high = 62;
+
+            while low <= high {
+                let num_ac = low + (high - low) / 2;
+                let data = encode(1, num_ac)?;
+                let size = headers.len() + data.len();
+
+                min_size = min_size.min(size);
+
+                if size <= target_size {
+                    let result = TargetSizeResult {
+                        quality: 1,
+                        ac_coefficients: num_ac as u8,
+                    };
+
+                    best = Some((result, data));
+                    low = num_ac + 1;
+                } else if num_ac == 0 {
+                    break;
+                } else {
+                    high = num_ac - 1;
+                }
+            }
+        }
+
+        let (result, data) = best.ok_or(EncodingError::TargetSizeNotReached {
+            target_size,
+            min_size,
+        })?;
+
+        self.writer.write(&headers)?;
+        self.writer.write(&data)?;
+
+        Ok(result)
+    }
+
+    /// Create an encoder with the same settings and the given quality that writes into a buffer
+    ///
+    /// App segments aren't copied.
+    fn buffered_copy(&self, quality: u8) -> Encoder<Vec<u8>> {
+        Encoder {
+            writer: JfifWriter::new(Vec::new()),
+            density: self.density,
+            quality,
+            components: self.components.clone(),
+            quantization_tables: self.quantization_tables.clone(),
+            huffman_tables: self.huffman_tables.clone(),
+            sampling_factor: self.sampling_factor,
+            progressive_scans: self.progressive_scans,
+            successive_approximation: self.successive_approximation,
+            scan_script: self.scan_script.clone(),
+            restart_interval: self.restart_interval,
+            optimize_huffman_table: self.optimize_huffman_table,
+            abbreviated: self.abbreviated,
+            arithmetic_coding: self.arithmetic_coding,
+            arithmetic_conditioning: self.arithmetic_conditioning,
+            trellis: self.trellis,
+            importance_map: self.importance_map.clone(),
+            lossless_predictor: self.lossless_predictor,
+            lossless_point_transform: self.lossless_point_transform,
+            coefficient_hook: None,
+            exif: None,
+            jfif_thumbnail: None,
+            // The file headers are written separately, only segments after the tables are needed
+            app_segments: if self.marker_layout.app_segments == SegmentPosition::AfterTables {
+                self.app_segments.clone()
+            } else {
+                Vec::new()
+            },
+            comments: if self.marker_layout.comments == SegmentPosition::AfterTables {
+                self.comments.clone()
+            } else {
+                Vec::new()
+            },
+            marker_layout: self.marker_layout,
+        }
+    }
+
+    /// DCT based encoding of images with 8 or 12 bit samples
+    fn encode_image_wide<I: ImageBuffer16>(mut self, image: I) -> Result<(), EncodingError> {
+        let width = image.width();
+        let height = image.height();
+        let precision = image.precision();
+
+        if width == 0 || height == 0 {
+            return Err(EncodingError::ZeroImageDimensions { width, height });
+        }
+
+        if precision != 8 && precision != 12 {
+            return Err(EncodingError::InvalidPrecision(precision));
+        }
+
+        let q_tables = [
+            QuantizationTable::new_with_precision(
+                &self.quantization_tables[0],
+                self.quality,
+                true,
+                precision,
+            ),
+            QuantizationTable::new_with_precision(
+                &self.quantization_tables[1],
+                self.quality,
+                false,
+                precision,
+            ),
+        ];
+
+        let jpeg_color_type = image.get_jpeg_color_type();
+        self.init_components(jpeg_color_type);
+
+        let scans = self.get_progressive_scans(jpeg_color_type)?;
+
+        let mut blocks = self.encode_blocks_wide(&image, &q_tables)?;
+
+        // The default huffman tables only contain codes for 8 bit samples
+        if precision > 8 {
+            self.optimize_huffman_table = true;
+        }
+
+        let thumbnail =
+            self.create_jfif_thumbnail(jpeg_color_type, width, height, precision, |y, buffers| {
+                image.fill_buffers(y, buffers)
+            })?;
+
+        write_file_headers(
+            &mut self.writer,
+            self.density,
+            jpeg_color_type,
+            self.exif.as_deref(),
+            thumbnail.as_ref(),
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        )?;
+
+        self.encode_quantized_blocks(
+            &mut blocks,
+            width,
+            height,
+            precision,
+            scans.as_deref(),
+            &q_tables,
+        )?;
+
+        self.writer.write_marker(Marker::EOI)
+    }
+
+    fn encode_image_lossless<I: ImageBuffer16>(mut self, image: I) -> Result<(), EncodingError> {
+        let predictor = self
+            .lossless_predictor
+            .expect("Lossless encoding must be enabled");
+
+        if self.abbreviated {
+            return Err(EncodingError::Write(
+                "Abbreviated images don't support lossless encoding".into(),
+            ));
+        }
+
+        let width = image.width();
+        let height = image.height();
+        let precision = image.precision();
+
+        if width == 0 || height == 0 {
+            return Err(EncodingError::ZeroImageDimensions { width, height });
+        }
+
+        if !(2..=16).contains(&precision) {
+            return Err(EncodingError::InvalidPrecision(precision));
+        }
+
+        if self.lossless_point_transform >= precision {
+            return Err(EncodingError::Write(alloc::format!(
+                "Point transform {} must be lower than the precision {}",
+                self.lossless_point_transform,
+                precision
+            )));
+        }
+
+        // Restarts must occur at the start of a row
+        if let Some(restart_interval) = self.restart_interval {
+            if restart_interval % width != 0 {
+                return Err(EncodingError::Write(
+                    "Lossless restart interval must be a multiple of the image width".into(),
+                ));
+            }
+        }
 
         let jpeg_color_type = image.get_jpeg_color_type();
-        self.init_components(jpeg_color_type);
+
+        // Lossless scans only use DC tables and there is no subsampling
+        self.components = build_components(SamplingFactor::F_1_1, jpeg_color_type);
+        for component in &mut self.components {
+            component.ac_huffman_table = 0;
+        }
+
+        let thumbnail =
+            self.create_jfif_thumbnail(jpeg_color_type, width, height, precision, |y, buffers| {
+                image.fill_buffers(y, buffers)
+            })?;
 
         write_file_headers(
             &mut self.writer,
             self.density,
             jpeg_color_type,
+            self.exif.as_deref(),
+            thumbnail.as_ref(),
             &self.app_segments,
+            &self.comments,
+            self.marker_layout,
         )?;
 
-        if let Some(scans) = self.progressive_scans {
-            self.encode_image_progressive::<_, OP>(image, scans, &q_tables)?;
-        } else if self.optimize_huffman_table || !self.sampling_factor.supports_interleaved() {
-            self.encode_image_sequential::<_, OP>(image, &q_tables)?;
-        } else {
-            self.encode_image_interleaved::<_, OP>(image, &q_tables)?;
-        }
+        encode_lossless(
+            &mut self.writer,
+            &image,
+            &self.components,
+            predictor,
+            self.lossless_point_transform,
+            self.restart_interval,
+            &metadata_segments(
+                SegmentPosition::AfterTables,
+                &self.app_segments,
+                &self.comments,
+                self.marker_layout,
+            ),
+        )?;
 
-        self.writer.write_marker(Marker::EOI)?;
+        self.writer.write_marker(Marker::EOI)
+    }
 
-        Ok(())
+    /// Downscale the image to the JFIF thumbnail if one is set
+    fn create_jfif_thumbnail<T, F>(
+        &self,
+        jpeg_color_type: JpegColorType,
+        width: u16,
+        height: u16,
+        precision: u8,
+        fill_buffers: F,
+    ) -> Result<Option<Thumbnail>, EncodingError>
+    where
+        T: Copy + Into<u32>,
+        F: FnMut(u16, &mut [Vec<T>; 4]),
+    {
+        let settings = match self.jfif_thumbnail {
+            Some(settings) => settings,
+            None => return Ok(None),
+        };
+
+        if !self.marker_layout.jfif {
+            return Err(EncodingError::Write(
+                "JFIF thumbnails require the JFIF segment of the marker layout".into(),
+            ));
+        }
+
+        create_thumbnail(
+            settings,
+            jpeg_color_type,
+            width,
+            height,
+            precision,
+            fill_buffers,
+        )
+        .map(Some)
     }
 
     fn init_components(&mut self, color: JpegColorType) {
@@ -756,24 +2276,82 @@ impl<W: JfifWrite> Encoder<W> {
         get_max_sampling_size_for(&self.components)
     }
 
-    fn write_frame_header<I: ImageBuffer>(
+    fn is_progressive(&self) -> bool {
+        self.progressive_scans.is_some() || self.scan_script.is_some()
+    }
+
+    /// Build and validate the scans for progressive encoding
+    fn get_progressive_scans(
+        &self,
+        color: JpegColorType,
+    ) -> Result<Option<Vec<ScanInfo>>, EncodingError> {
+        let scans = if let Some(script) = &self.scan_script {
+            script.build(color, &self.components)
+        } else if let Some(scans) = self.progressive_scans {
+            build_scans(self.components.len(), scans, self.successive_approximation)
+        } else {
+            return Ok(None);
+        };
+
+        validate_scans(&scans, &self.components)?;
+
+        Ok(Some(scans))
+    }
+
+    fn write_frame_header(
         &mut self,
-        image: &I,
+        width: u16,
+        height: u16,
+        precision: u8,
         q_tables: &[QuantizationTable; 2],
     ) -> Result<(), EncodingError> {
+        let frame_type = match (self.is_progressive(), self.arithmetic_coding) {
+            (false, false) => SOFType::BaselineDCT,
+            (true, false) => SOFType::ProgressiveDCT,
+            (false, true) => SOFType::ExtendedSequentialDCTArithmetic,
+            (true, true) => SOFType::ProgressiveDCTArithmetic,
+        };
+
+        let arithmetic_conditioning = if self.arithmetic_coding {
+            Some(&self.arithmetic_conditioning)
+        } else {
+            None
+        };
+
+        if self.abbreviated && self.optimize_huffman_table && !self.arithmetic_coding {
+            return Err(EncodingError::Write(
+                "Abbreviated images don't support optimized Huffman tables".into(),
+            ));
+        }
+
         write_frame_header_common(
             &mut self.writer,
-            image.width(),
-            image.height(),
+            width,
+            height,
             &self.components,
-            self.progressive_scans.is_some(),
+            frame_type,
             q_tables,
             &self.huffman_tables,
+            arithmetic_conditioning,
             self.restart_interval,
-            image.get_jpeg_color_type().get_num_components(),
-        )
+            self.components.len(),
+            precision,
+            !self.abbreviated,
+        )?;
+
+        for (marker, data) in metadata_segments(
+            SegmentPosition::AfterTables,
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        ) {
+            self.writer.write_segment(marker, data)?;
+        }
+
+        Ok(())
     }
 
+    #[cfg(not(feature = "rayon"))]
     fn init_rows(&mut self, buffer_size: usize) -> [Vec<u8>; 4] {
         init_rows_for_components(self.components.len(), buffer_size)
     }
@@ -781,14 +2359,15 @@ impl<W: JfifWrite> Encoder<W> {
     /// Encode all components with one scan
     ///
     /// This is only valid for sampling factors of 1 and 2
+    #[cfg(not(feature = "rayon"))]
     fn encode_image_interleaved<I: ImageBuffer, OP: Operations>(
         &mut self,
         image: I,
         q_tables: &[QuantizationTable; 2],
     ) -> Result<(), EncodingError> {
-        self.write_frame_header(&image, q_tables)?;
+        self.write_frame_header(image.width(), image.height(), 8, q_tables)?;
         self.writer
-            .write_scan_header(&self.components.iter().collect::<Vec<_>>(), None)?;
+            .write_scan_header(&self.components.iter().collect::<Vec<_>>(), None, None)?;
 
         let (max_h_sampling, max_v_sampling) = self.get_max_sampling_size();
 
@@ -806,25 +2385,21 @@ impl<W: JfifWrite> Encoder<W> {
         let mut prev_dc = [0i16; 4];
         let mut restart = RestartState::new(self.restart_interval);
 
-        for block_y in 0..num_rows {
-            for r in &mut row {
-                r.clear();
-            }
-
-            for y in 0..(8 * max_v_sampling) {
-                let y = y + block_y * 8 * max_v_sampling;
-                let y = (y.min(height as usize - 1)) as u16;
+        let adaptive = self
+            .importance_map
+            .as_ref()
+            .map(|map| AdaptiveQuantizer::new(map, &self.components));
 
-                image.fill_buffers(y, &mut row);
-
-                for _ in usize::from(width)..buffer_width {
-                    for channel in &mut row {
-                        if !channel.is_empty() {
-                            channel.push(channel[channel.len() - 1]);
-                        }
-                    }
-                }
-            }
+        for block_y in 0..num_rows {
+            fill_mcu_row(
+                &image,
+                block_y,
+                width,
+                height,
+                max_v_sampling,
+                buffer_width,
+                &mut row,
+            );
 
             write_interleaved_mcu_row::<_, OP>(
                 &mut self.writer,
@@ -838,6 +2413,9 @@ impl<W: JfifWrite> Encoder<W> {
                 num_cols,
                 &mut restart,
                 &row,
+                adaptive.as_ref(),
+                &mut self.coefficient_hook,
+                block_y,
             )?;
         }
 
@@ -846,26 +2424,134 @@ impl<W: JfifWrite> Encoder<W> {
         Ok(())
     }
 
-    /// Encode components with one scan per component
-    fn encode_image_sequential<I: ImageBuffer, OP: Operations>(
+    /// Encode all components with one scan
+    ///
+    /// The color conversion is done on the current thread, the FDCT and quantization of
+    /// the MCU rows on several threads.
+    #[cfg(feature = "rayon")]
+    fn encode_image_interleaved<I: ImageBuffer, OP: Operations>(
+        &mut self,
+        image: I,
+        q_tables: &[QuantizationTable; 2],
+    ) -> Result<(), EncodingError> {
+        self.encode_interleaved_parallel(image.width(), image.height(), q_tables, |rows, range| {
+            rows.quantize_rows::<_, OP>(&image, range)
+        })
+    }
+
+    /// Encode all components with one scan
+    ///
+    /// The color conversion, FDCT and quantization of the MCU rows is done on several threads.
+    #[cfg(feature = "rayon")]
+    fn encode_image_interleaved_sync<I: ImageBuffer + Sync, OP: Operations>(
         &mut self,
         image: I,
         q_tables: &[QuantizationTable; 2],
     ) -> Result<(), EncodingError> {
-        let blocks = self.encode_blocks::<_, OP>(&image, q_tables);
+        self.encode_interleaved_parallel(image.width(), image.height(), q_tables, |rows, range| {
+            rows.quantize_rows_sync::<_, OP>(&image, range)
+        })
+    }
+
+    #[cfg(feature = "rayon")]
+    fn encode_interleaved_parallel<F>(
+        &mut self,
+        width: u16,
+        height: u16,
+        q_tables: &[QuantizationTable; 2],
+        mut quantize_rows: F,
+    ) -> Result<(), EncodingError>
+    where
+        F: FnMut(&McuRows, Range<usize>) -> Vec<Vec<[i16; 64]>>,
+    {
+        self.write_frame_header(width, height, 8, q_tables)?;
+        self.writer
+            .write_scan_header(&self.components.iter().collect::<Vec<_>>(), None, None)?;
+
+        let rows = McuRows::new(
+            &self.components,
+            q_tables,
+            self.importance_map.as_ref(),
+            width,
+            height,
+        );
+
+        let components = &self.components;
+        let hook = &mut self.coefficient_hook;
+
+        parallel::encode_interleaved(
+            &mut self.writer,
+            components,
+            &self.huffman_tables,
+            self.restart_interval,
+            rows.num_rows(),
+            |range| {
+                let mut blocks = quantize_rows(&rows, range.clone());
+
+                // The hook is called sequentially in the order of the MCU rows
+                if let Some(hook) = hook {
+                    for (mcu_row, blocks) in range.zip(&mut blocks) {
+                        process_mcu_row(hook.as_mut(), components, mcu_row, blocks)?;
+                    }
+                }
+
+                Ok(blocks)
+            },
+        )?;
+
+        self.writer.finalize_bit_buffer()?;
+
+        Ok(())
+    }
+
+    /// Entropy code the quantized blocks of all components after passing them to the coefficient hook
+    ///
+    /// Uses arithmetic coding, progressive scans or one sequential scan per component.
+    fn encode_quantized_blocks(
+        &mut self,
+        blocks: &mut [Vec<[i16; 64]>; 4],
+        width: u16,
+        height: u16,
+        precision: u8,
+        scans: Option<&[ScanInfo]>,
+        q_tables: &[QuantizationTable; 2],
+    ) -> Result<(), EncodingError> {
+        if let Some(hook) = &mut self.coefficient_hook {
+            process_blocks(hook.as_mut(), &self.components, width, precision, blocks)?;
+        }
+
+        let blocks = &*blocks;
+
+        if self.arithmetic_coding {
+            self.encode_image_arithmetic(blocks, width, height, precision, scans, q_tables)
+        } else if let Some(scans) = scans {
+            self.encode_image_progressive(blocks, width, height, precision, scans, q_tables)
+        } else {
+            self.encode_image_sequential(blocks, width, height, precision, q_tables)
+        }
+    }
 
+    /// Encode components with one scan per component
+    fn encode_image_sequential(
+        &mut self,
+        blocks: &[Vec<[i16; 64]>; 4],
+        width: u16,
+        height: u16,
+        precision: u8,
+        q_tables: &[QuantizationTable; 2],
+    ) -> Result<(), EncodingError> {
         if self.optimize_huffman_table {
-            self.optimize_huffman_table(&blocks);
+            self.optimize_huffman_table(blocks, None)?;
         }
 
-        self.write_frame_header(&image, q_tables)?;
+        self.write_frame_header(width, height, precision, q_tables)?;
 
         for (i, component) in self.components.iter().enumerate() {
             let restart_interval = self.restart_interval.unwrap_or(0);
             let mut restarts = 0;
             let mut restarts_to_go = restart_interval;
 
-            self.writer.write_scan_header(&[component], None)?;
+            self.writer.write_scan_header(&[component], None, None)?;
 
             let mut prev_dc = 0;
 
@@ -905,123 +2591,336 @@ impl<W: JfifWrite> Encoder<W> {
 
     /// Encode image in progressive mode
     ///
-    /// This only support spectral selection for now
-    fn encode_image_progressive<I: ImageBuffer, OP: Operations>(
+    /// Uses spectral selection and, if enabled, successive approximation
+    fn encode_image_progressive(
         &mut self,
-        image: I,
-        scans: u8,
+        blocks: &[Vec<[i16; 64]>; 4],
+        width: u16,
+        height: u16,
+        precision: u8,
+        scans: &[ScanInfo],
         q_tables: &[QuantizationTable; 2],
     ) -> Result<(), EncodingError> {
-        let blocks = self.encode_blocks::<_, OP>(&image, q_tables);
+        let layout = BlockLayout::new(width, height, &self.components);
 
         if self.optimize_huffman_table {
-            self.optimize_huffman_table(&blocks);
+            self.optimize_huffman_table(blocks, Some((scans, &layout)))?;
         }
 
-        self.write_frame_header(&image, q_tables)?;
+        self.write_frame_header(width, height, precision, q_tables)?;
 
-        // Phase 1: DC Scan
-        //          Only the DC coefficients can be transfer in the first component scans
-        for (i, component) in self.components.iter().enumerate() {
-            self.writer.write_scan_header(&[component], Some((0, 0)))?;
+        // The default huffman tables don't contain symbols for EOB runs
+        let max_eob_run = if self.optimize_huffman_table {
+            MAX_EOB_RUN
+        } else {
+            1
+        };
 
-            let restart_interval = self.restart_interval.unwrap_or(0);
-            let mut restarts = 0;
-            let mut restarts_to_go = restart_interval;
+        write_progressive_scans(
+            &mut self.writer,
+            &self.components,
+            &self.huffman_tables,
+            scans,
+            &layout,
+            blocks,
+            self.restart_interval,
+            max_eob_run,
+        )
+    }
 
-            let mut prev_dc = 0;
+    /// Encode image with arithmetic coding
+    ///
+    /// Uses the given progressive scans or a sequential scan
+    fn encode_image_arithmetic(
+        &mut self,
+        blocks: &[Vec<[i16; 64]>; 4],
+        width: u16,
+        height: u16,
+        precision: u8,
+        scans: Option<&[ScanInfo]>,
+        q_tables: &[QuantizationTable; 2],
+    ) -> Result<(), EncodingError> {
+        let layout = BlockLayout::new(width, height, &self.components);
 
-            for block in &blocks[i] {
-                if restart_interval > 0 && restarts_to_go == 0 {
-                    self.writer.finalize_bit_buffer()?;
-                    self.writer
-                        .write_marker(Marker::RST((restarts % 8) as u8))?;
+        self.write_frame_header(width, height, precision, q_tables)?;
 
-                    prev_dc = 0;
-                }
+        let sequential_scans;
 
-                self.writer.write_dc(
-                    block[0],
-                    prev_dc,
-                    &self.huffman_tables[component.dc_huffman_table as usize].0,
-                )?;
+        let scans = match scans {
+            Some(scans) => scans,
+            None => {
+                let num_components = self.components.len();
 
-                prev_dc = block[0];
+                sequential_scans = if self.sampling_factor.supports_interleaved() {
+                    let all: Vec<usize> = (0..num_components).collect();
+                    vec![ScanInfo::new(&all, 0, 63, 0, 0)]
+                } else {
+                    (0..num_components)
+                        .map(|i| ScanInfo::new(&[i], 0, 63, 0, 0))
+                        .collect()
+                };
 
-                if restart_interval > 0 {
-                    if restarts_to_go == 0 {
-                        restarts_to_go = restart_interval;
-                        restarts += 1;
-                        restarts &= 7;
+                &sequential_scans
+            }
+        };
+
+        let progressive = self.is_progressive();
+
+        for scan in scans {
+            let components: Vec<_> = scan
+                .components
+                .iter()
+                .map(|&i| &self.components[i])
+                .collect();
+
+            self.writer.write_scan_header(
+                &components,
+                Some((scan.ss, scan.se)),
+                Some((scan.ah, scan.al)),
+            )?;
+
+            encode_scan_arithmetic(
+                &mut self.writer,
+                scan,
+                progressive,
+                &self.components,
+                &self.arithmetic_conditioning,
+                &layout,
+                blocks,
+                self.restart_interval,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Create the DCT coefficients of all blocks in natural order
+    fn transform_blocks<I: ImageBuffer, OP: Operations>(&self, image: &I) -> [Vec<[i16; 64]>; 4] {
+        let (row, buffer_width) = self.fill_rows(image.width(), image.height(), |y, row| {
+            image.fill_buffers(y, row);
+        });
+
+        let mut blocks = self.collect_blocks(
+            image.width(),
+            image.height(),
+            |i, start_x, start_y, h_scale, v_scale| {
+                OP::get_block(&row[i], start_x, start_y, h_scale, v_scale, buffer_width)
+            },
+        );
+
+        for component in &mut blocks {
+            #[cfg(feature = "rayon")]
+            component.par_iter_mut().for_each(OP::fdct);
+
+            #[cfg(not(feature = "rayon"))]
+            component.iter_mut().for_each(OP::fdct);
+        }
+
+        blocks
+    }
+
+    /// Quantize the DCT coefficients created by [transform_blocks](Encoder::transform_blocks) in place
+    fn quantize_coefficients<OP: Operations>(
+        &self,
+        blocks: &mut [Vec<[i16; 64]>; 4],
+        width: u16,
+        q_tables: &[QuantizationTable; 2],
+    ) {
+        let trellis = self.trellis.as_ref().map(TrellisQuantizer::new);
+        let mut dc_values: [Vec<(i32, f32)>; 4] = Default::default();
+
+        let adaptive = self.adaptive_quantizer();
+
+        let (max_h_sampling, _) = self.get_max_sampling_size();
+        let num_cols = ceil_div(usize::from(width), 8);
+
+        for (i, component) in self.components.iter().enumerate() {
+            let q_table = &q_tables[component.quantization_table as usize];
+            let ac_table = &self.huffman_tables[component.ac_huffman_table as usize].1;
+
+            let h_scale = max_h_sampling / component.horizontal_sampling_factor as usize;
+            let cols = ceil_div(num_cols, h_scale);
+
+            // Returns the DC value and lambda of the block if the DC trellis is enabled
+            let quantize = |(n, block): (usize, &mut [i16; 64])| {
+                let level = adaptive
+                    .as_ref()
+                    .map_or(BlockLevel::FULL, |a| a.level(i, n % cols, n / cols));
+
+                let mut q_block = [0i16; 64];
+                let mut dc_value = None;
+
+                if let Some(quantizer) = &trellis {
+                    let block_wide = block.map(i32::from);
+
+                    let lambda = trellis_quantize_block(
+                        quantizer,
+                        &block_wide,
+                        &mut q_block,
+                        q_table,
+                        ac_table,
+                        &level,
+                    );
+
+                    if quantizer.optimize_dc() {
+                        dc_value = Some((block_wide[0], lambda));
                     }
-                    restarts_to_go -= 1;
+                } else if level.is_full() {
+                    OP::quantize_block(block, &mut q_block, q_table);
+                } else {
+                    level.quantize(&block.map(i32::from), &mut q_block, q_table);
                 }
+
+                *block = q_block;
+
+                dc_value
+            };
+
+            #[cfg(feature = "rayon")]
+            {
+                dc_values[i] = blocks[i]
+                    .par_iter_mut()
+                    .enumerate()
+                    .filter_map(quantize)
+                    .collect();
             }
 
-            self.writer.finalize_bit_buffer()?;
+            #[cfg(not(feature = "rayon"))]
+            {
+                dc_values[i] = blocks[i]
+                    .iter_mut()
+                    .enumerate()
+                    .filter_map(quantize)
+                    .collect();
+            }
         }
 
-        // Phase 2: AC scans
-        let scans = scans as usize - 1;
+        if let Some(quantizer) = &trellis {
+            self.trellis_quantize_dc(quantizer, &dc_values, blocks, q_tables);
+        }
+    }
 
-        let values_per_scan = 64 / scans;
+    /// Create the quantized blocks of all components for samples with up to 12 bits
+    fn encode_blocks_wide<I: ImageBuffer16>(
+        &self,
+        image: &I,
+        q_tables: &[QuantizationTable; 2],
+    ) -> Result<[Vec<[i16; 64]>; 4], EncodingError> {
+        let precision = image.precision();
+        let max_value = ((1u32 << precision) - 1) as u16;
 
-        for scan in 0..scans {
-            let start = (scan * values_per_scan).max(1);
-            let end = if scan == scans - 1 {
-                // ensure last scan is always transfers the remaining coefficients
-                64
-            } else {
-                (scan + 1) * values_per_scan
-            };
+        let (row, buffer_width) = self.fill_rows(image.width(), image.height(), |y, row| {
+            image.fill_buffers(y, row);
+        });
 
-            for (i, component) in self.components.iter().enumerate() {
-                let restart_interval = self.restart_interval.unwrap_or(0);
-                let mut restarts = 0;
-                let mut restarts_to_go = restart_interval;
+        if let Some(&value) = row.iter().flatten().find(|&&value| value > max_value) {
+            return Err(EncodingError::SampleOutOfRange { value, precision });
+        }
 
-                self.writer
-                    .write_scan_header(&[component], Some((start as u8, end as u8 - 1)))?;
+        let level_shift = 1 << (precision - 1);
 
-                for block in &blocks[i] {
-                    if restart_interval > 0 && restarts_to_go == 0 {
-                        self.writer.finalize_bit_buffer()?;
-                        self.writer
-                            .write_marker(Marker::RST((restarts % 8) as u8))?;
-                    }
+        let trellis = self.trellis.as_ref().map(TrellisQuantizer::new);
+        let mut dc_values: [Vec<(i32, f32)>; 4] = Default::default();
 
-                    self.writer.write_ac_block(
-                        block,
-                        start,
-                        end,
-                        &self.huffman_tables[component.ac_huffman_table as usize].1,
-                    )?;
+        let adaptive = self.adaptive_quantizer();
+
+        let mut blocks = self.collect_blocks(
+            image.width(),
+            image.height(),
+            |i, start_x, start_y, h_scale, v_scale| {
+                let q_table = &q_tables[self.components[i].quantization_table as usize];
+
+                let level = adaptive.as_ref().map_or(BlockLevel::FULL, |a| {
+                    a.level(i, start_x / (8 * h_scale), start_y / (8 * v_scale))
+                });
+
+                let mut block = get_block_wide(
+                    &row[i],
+                    start_x,
+                    start_y,
+                    h_scale,
+                    v_scale,
+                    buffer_width,
+                    level_shift,
+                );
+
+                fdct_wide(&mut block);
+
+                let mut q_block = [0i16; 64];
+
+                if let Some(quantizer) = &trellis {
+                    let ac_table =
+                        &self.huffman_tables[self.components[i].ac_huffman_table as usize].1;
+
+                    let lambda = trellis_quantize_block(
+                        quantizer,
+                        &block,
+                        &mut q_block,
+                        q_table,
+                        ac_table,
+                        &level,
+                    );
 
-                    if restart_interval > 0 {
-                        if restarts_to_go == 0 {
-                            restarts_to_go = restart_interval;
-                            restarts += 1;
-                            restarts &= 7;
-                        }
-                        restarts_to_go -= 1;
+                    if quantizer.optimize_dc() {
+                        dc_values[i].push((block[0], lambda));
                     }
+                } else if level.is_full() {
+                    for (i, q) in q_block.iter_mut().enumerate() {
+                        let z = ZIGZAG[i] as usize & 0x3f;
+                        *q = q_table.quantize_wide(block[z], z);
+                    }
+                } else {
+                    level.quantize(&block, &mut q_block, q_table);
                 }
 
-                self.writer.finalize_bit_buffer()?;
-            }
+                q_block
+            },
+        );
+
+        if let Some(quantizer) = &trellis {
+            self.trellis_quantize_dc(quantizer, &dc_values, &mut blocks, q_tables);
         }
 
-        Ok(())
+        Ok(blocks)
     }
 
-    fn encode_blocks<I: ImageBuffer, OP: Operations>(
-        &mut self,
-        image: &I,
+    fn adaptive_quantizer(&self) -> Option<AdaptiveQuantizer<'_>> {
+        self.importance_map
+            .as_ref()
+            .map(|map| AdaptiveQuantizer::new(map, &self.components))
+    }
+
+    /// Optimize the DC coefficients of all components if enabled
+    fn trellis_quantize_dc(
+        &self,
+        quantizer: &TrellisQuantizer,
+        dc_values: &[Vec<(i32, f32)>; 4],
+        blocks: &mut [Vec<[i16; 64]>; 4],
         q_tables: &[QuantizationTable; 2],
-    ) -> [Vec<[i16; 64]>; 4] {
-        let width = image.width();
-        let height = image.height();
+    ) {
+        if !quantizer.optimize_dc() {
+            return;
+        }
+
+        for (i, component) in self.components.iter().enumerate() {
+            quantizer.quantize_dc(
+                &dc_values[i],
+                &mut blocks[i],
+                &q_tables[component.quantization_table as usize],
+                &self.huffman_tables[component.dc_huffman_table as usize].0,
+            );
+        }
+    }
 
+    /// Fill the component rows for all MCUs and return them with the buffer width
+    ///
+    /// The rows are padded to full MCUs by repeating the last column and row.
+    fn fill_rows<T: Copy, F: FnMut(u16, &mut [Vec<T>; 4])>(
+        &self,
+        width: u16,
+        height: u16,
+        mut fill_buffers: F,
+    ) -> ([Vec<T>; 4], usize) {
         let (max_h_sampling, max_v_sampling) = self.get_max_sampling_size();
 
         let num_cols = ceil_div(usize::from(width), 8 * max_h_sampling) * max_h_sampling;
@@ -1033,12 +2932,12 @@ impl<W: JfifWrite> Encoder<W> {
         let buffer_width = num_cols * 8;
         let buffer_size = num_cols * num_rows * 64;
 
-        let mut row: [Vec<_>; 4] = self.init_rows(buffer_size);
+        let mut row: [Vec<_>; 4] = init_rows_for_components(self.components.len(), buffer_size);
 
         for y in 0..num_rows * 8 {
             let y = (y.min(usize::from(height) - 1)) as u16;
 
-            image.fill_buffers(y, &mut row);
+            fill_buffers(y, &mut row);
 
             for _ in usize::from(width)..num_cols * 8 {
                 for channel in &mut row {
@@ -1049,13 +2948,30 @@ impl<W: JfifWrite> Encoder<W> {
             }
         }
 
+        (row, buffer_width)
+    }
+
+    /// Create the blocks of all components
+    ///
+    /// `create_block` gets the component index, the block position and the scaling of the component.
+    fn collect_blocks<F>(&self, width: u16, height: u16, mut create_block: F) -> [Vec<[i16; 64]>; 4]
+    where
+        F: FnMut(usize, usize, usize, usize, usize) -> [i16; 64],
+    {
+        let (max_h_sampling, max_v_sampling) = self.get_max_sampling_size();
+
+        let buffer_size = ceil_div(usize::from(width), 8 * max_h_sampling)
+            * max_h_sampling
+            * ceil_div(usize::from(height), 8 * max_v_sampling)
+            * max_v_sampling;
+
         let num_cols = ceil_div(usize::from(width), 8);
         let num_rows = ceil_div(usize::from(height), 8);
 
         debug_assert!(num_cols > 0);
         debug_assert!(num_rows > 0);
 
-        let mut blocks: [Vec<_>; 4] = self.init_block_buffers(buffer_size / 64);
+        let mut blocks: [Vec<_>; 4] = self.init_block_buffers(buffer_size);
 
         for (i, component) in self.components.iter().enumerate() {
             let h_scale = max_h_sampling / component.horizontal_sampling_factor as usize;
@@ -1069,33 +2985,22 @@ impl<W: JfifWrite> Encoder<W> {
 
             for block_y in 0..rows {
                 for block_x in 0..cols {
-                    let mut block = get_block(
-                        &row[i],
+                    let block = create_block(
+                        i,
                         block_x * 8 * h_scale,
                         block_y * 8 * v_scale,
                         h_scale,
                         v_scale,
-                        buffer_width,
                     );
 
-                    OP::fdct(&mut block);
-
-                    let mut q_block = [0i16; 64];
-
-                    OP::quantize_block(
-                        &block,
-                        &mut q_block,
-                        &q_tables[component.quantization_table as usize],
-                    );
-
-                    blocks[i].push(q_block);
+                    blocks[i].push(block);
                 }
             }
         }
         blocks
     }
 
-    fn init_block_buffers(&mut self, buffer_size: usize) -> [Vec<[i16; 64]>; 4] {
+    fn init_block_buffers(&self, buffer_size: usize) -> [Vec<[i16; 64]>; 4] {
         // To simplify the code and to give the compiler more infos to optimize stuff we always initialize 4 components
         // Resource overhead should be minimal because an empty Vec doesn't allocate
 
@@ -1123,121 +3028,168 @@ impl<W: JfifWrite> Encoder<W> {
     }
 
     // Create new huffman tables optimized for this image
-    fn optimize_huffman_table(&mut self, blocks: &[Vec<[i16; 64]>; 4]) {
-        // TODO: Find out if it's possible to reuse some code from the writer
+    fn optimize_huffman_table(
+        &mut self,
+        blocks: &[Vec<[i16; 64]>; 4],
+        progressive: Option<(&[ScanInfo], &BlockLayout)>,
+    ) -> Result<(), EncodingError> {
+        optimize_huffman_tables(
+            &mut self.huffman_tables,
+            &self.components,
+            self.restart_interval,
+            blocks,
+            progressive,
+        )
+    }
+}
 
-        let max_tables = self.components.len().min(2) as u8;
+/// Replace the huffman tables with tables optimized for the quantized blocks
+fn optimize_huffman_tables(
+    huffman_tables: &mut [(HuffmanTable, HuffmanTable); 2],
+    components: &[Component],
+    restart_interval: Option<u16>,
+    blocks: &[Vec<[i16; 64]>; 4],
+    progressive: Option<(&[ScanInfo], &BlockLayout)>,
+) -> Result<(), EncodingError> {
+    // TODO: Find out if it's possible to reuse some code from the writer
 
-        for table in 0..max_tables {
-            let mut dc_freq = [0u32; 257];
-            dc_freq[256] = 1;
-            let mut ac_freq = [0u32; 257];
-            ac_freq[256] = 1;
+    let max_tables = components.len().min(2);
 
-            let mut had_ac = false;
-            let mut had_dc = false;
+    let mut dc_freq = [[0u32; 257]; 2];
+    let mut ac_freq = [[0u32; 257]; 2];
 
-            for (i, component) in self.components.iter().enumerate() {
-                if component.dc_huffman_table == table {
-                    had_dc = true;
+    for table in 0..2 {
+        dc_freq[table][256] = 1;
+        ac_freq[table][256] = 1;
+    }
 
-                    let mut prev_dc = 0;
+    let mut had_dc = [false; 2];
+    let mut had_ac = [false; 2];
 
-                    debug_assert!(!blocks[i].is_empty());
+    if let Some((scans, layout)) = progressive {
+        let dc_tables = dc_table_indices(components);
+        let ac_tables = ac_table_indices(components);
 
-                    for block in &blocks[i] {
-                        let value = block[0];
-                        let diff = value - prev_dc;
-                        let num_bits = get_num_bits(diff);
+        for scan in scans {
+            let (freq, tables, had_data) = if scan.is_dc() {
+                (&mut dc_freq, &dc_tables, &mut had_dc)
+            } else {
+                (&mut ac_freq, &ac_tables, &mut had_ac)
+            };
 
-                        dc_freq[num_bits as usize] += 1;
+            for &i in &scan.components {
+                debug_assert!(!blocks[i].is_empty());
+                had_data[tables[i] as usize] = true;
+            }
 
-                        prev_dc = value;
-                    }
-                }
+            encode_scan(
+                &mut FrequencyCounter::new(freq),
+                scan,
+                tables,
+                layout,
+                blocks,
+                restart_interval,
+                MAX_EOB_RUN,
+            )?;
+        }
+    } else {
+        for (i, component) in components.iter().enumerate() {
+            let dc_freq = &mut dc_freq[component.dc_huffman_table as usize];
+            had_dc[component.dc_huffman_table as usize] = true;
 
-                if component.ac_huffman_table == table {
-                    had_ac = true;
-
-                    if let Some(scans) = self.progressive_scans {
-                        let scans = scans as usize - 1;
-
-                        let values_per_scan = 64 / scans;
-
-                        for scan in 0..scans {
-                            let start = (scan * values_per_scan).max(1);
-                            let end = if scan == scans - 1 {
-                                // Due to rounding we might need to transfer more than values_per_scan values in the last scan
-                                64
-                            } else {
-                                (scan + 1) * values_per_scan
-                            };
-
-                            debug_assert!(!blocks[i].is_empty());
-
-                            for block in &blocks[i] {
-                                let mut zero_run = 0;
-
-                                for &value in &block[start..end] {
-                                    if value == 0 {
-                                        zero_run += 1;
-                                    } else {
-                                        while zero_run > 15 {
-                                            ac_freq[0xF0] += 1;
-                                            zero_run -= 16;
-                                        }
-                                        let num_bits = get_num_bits(value);
-                                        let symbol = (zero_run << 4) | num_bits;
-
-                                        ac_freq[symbol as usize] += 1;
-
-                                        zero_run = 0;
-                                    }
-                                }
-
-                                if zero_run > 0 {
-                                    ac_freq[0] += 1;
-                                }
-                            }
-                        }
-                    } else {
-                        for block in &blocks[i] {
-                            let mut zero_run = 0;
-
-                            for &value in &block[1..] {
-                                if value == 0 {
-                                    zero_run += 1;
-                                } else {
-                                    while zero_run > 15 {
-                                        ac_freq[0xF0] += 1;
-                                        zero_run -= 16;
-                                    }
-                                    let num_bits = get_num_bits(value);
-                                    let symbol = (zero_run << 4) | num_bits;
-
-                                    ac_freq[symbol as usize] += 1;
-
-                                    zero_run = 0;
-                                }
-                            }
-
-                            if zero_run > 0 {
-                                ac_freq[0] += 1;
-                            }
-                        }
-                    }
+            let mut prev_dc = 0;
+
+            debug_assert!(!blocks[i].is_empty());
+
+            let restart_interval = usize::from(restart_interval.unwrap_or(0));
+
+            for (n, block) in blocks[i].iter().enumerate() {
+                // The DC prediction is reset at each restart
+                if restart_interval > 0 && n % restart_interval == 0 {
+                    prev_dc = 0;
                 }
+
+                let value = block[0];
+                let diff = value - prev_dc;
+                let num_bits = get_num_bits(diff);
+
+                dc_freq[num_bits as usize] += 1;
+
+                prev_dc = value;
             }
 
-            assert!(had_dc, "Missing DC data for table {}", table);
-            assert!(had_ac, "Missing AC data for table {}", table);
+            let ac_freq = &mut ac_freq[component.ac_huffman_table as usize];
+            had_ac[component.ac_huffman_table as usize] = true;
 
-            self.huffman_tables[table as usize] = (
-                HuffmanTable::new_optimized(dc_freq),
-                HuffmanTable::new_optimized(ac_freq),
-            );
+            for block in &blocks[i] {
+                count_ac_symbols(block, ac_freq);
+            }
         }
     }
+
+    for table in 0..max_tables {
+        assert!(had_dc[table], "Missing DC data for table {}", table);
+        assert!(had_ac[table], "Missing AC data for table {}", table);
+
+        huffman_tables[table] = (
+            HuffmanTable::new_optimized(dc_freq[table]),
+            HuffmanTable::new_optimized(ac_freq[table]),
+        );
+    }
+
+    Ok(())
+}
+
+fn dc_table_indices(components: &[Component]) -> Vec<u8> {
+    components.iter().map(|c| c.dc_huffman_table).collect()
+}
+
+fn ac_table_indices(components: &[Component]) -> Vec<u8> {
+    components.iter().map(|c| c.ac_huffman_table).collect()
+}
+
+/// Write the scan headers and the entropy coded data of progressive scans
+#[allow(clippy::too_many_arguments)]
+fn write_progressive_scans<W: JfifWrite>(
+    writer: &mut JfifWriter<W>,
+    components: &[Component],
+    huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    scans: &[ScanInfo],
+    layout: &BlockLayout,
+    blocks: &[Vec<[i16; 64]>; 4],
+    restart_interval: Option<u16>,
+    max_eob_run: u16,
+) -> Result<(), EncodingError> {
+    let dc_tables = dc_table_indices(components);
+    let ac_tables = ac_table_indices(components);
+
+    for scan in scans {
+        let scan_components: Vec<_> = scan.components.iter().map(|&i| &components[i]).collect();
+
+        writer.write_scan_header(
+            &scan_components,
+            Some((scan.ss, scan.se)),
+            Some((scan.ah, scan.al)),
+        )?;
+
+        let (tables, huffman_tables) = if scan.is_dc() {
+            (&dc_tables, [&huffman_tables[0].0, &huffman_tables[1].0])
+        } else {
+            (&ac_tables, [&huffman_tables[0].1, &huffman_tables[1].1])
+        };
+
+        encode_scan(
+            &mut ScanWriter::new(writer, huffman_tables),
+            scan,
+            tables,
+            layout,
+            blocks,
+            restart_interval,
+            max_eob_run,
+        )?;
+    }
+
+    Ok(())
 }
 
 pub struct StripEncoder<W: JfifWrite> {
@@ -1248,6 +3200,10 @@ enum StripEncoderVariant<W: JfifWrite> {
     Scalar(StripEncoderInner<W, DefaultOperations>),
     #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
     Avx2(StripEncoderInner<W, crate::avx2::AVX2Operations>),
+    #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+    Neon(StripEncoderInner<W, crate::neon::NeonOperations>),
+    #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+    Simd128(StripEncoderInner<W, crate::simd128::Simd128Operations>),
 }
 
 impl<W: JfifWrite> StripEncoder<W> {
@@ -1256,6 +3212,10 @@ impl<W: JfifWrite> StripEncoder<W> {
             StripEncoderVariant::Scalar(inner) => inner.write_headers(),
             #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
             StripEncoderVariant::Avx2(inner) => inner.write_headers(),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.write_headers(),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.write_headers(),
         }
     }
 
@@ -1264,6 +3224,65 @@ impl<W: JfifWrite> StripEncoder<W> {
             StripEncoderVariant::Scalar(inner) => inner.encode_strip(data),
             #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
             StripEncoderVariant::Avx2(inner) => inner.encode_strip(data),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.encode_strip(data),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.encode_strip(data),
+        }
+    }
+
+    /// Set the importance of the image regions for the following strips
+    ///
+    /// See [ImportanceMap] for details.
+    pub fn set_importance_map(&mut self, importance_map: Option<ImportanceMap>) {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.importance_map = importance_map,
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.importance_map = importance_map,
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.importance_map = importance_map,
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.importance_map = importance_map,
+        }
+    }
+
+    /// Set a hook which can inspect and modify the quantized blocks of the following strips
+    ///
+    /// See [CoefficientHook] for details.
+    pub fn set_coefficient_hook(&mut self, hook: Option<Box<dyn CoefficientHook + Send>>) {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.coefficient_hook = hook,
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.coefficient_hook = hook,
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.coefficient_hook = hook,
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.coefficient_hook = hook,
+        }
+    }
+
+    /// Buffer the quantized blocks and write all data in [finish](StripEncoder::finish)
+    fn defer_coding(&mut self, mode: DeferredMode) {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.defer_coding(mode),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.defer_coding(mode),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.defer_coding(mode),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.defer_coding(mode),
+        }
+    }
+
+    fn set_unknown_height(&mut self) {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.unknown_height = true,
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.unknown_height = true,
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.unknown_height = true,
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.unknown_height = true,
         }
     }
 
@@ -1272,7 +3291,45 @@ impl<W: JfifWrite> StripEncoder<W> {
             StripEncoderVariant::Scalar(inner) => inner.finish(),
             #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
             StripEncoderVariant::Avx2(inner) => inner.finish(),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.finish(),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.finish(),
+        }
+    }
+
+    /// Finish an image with unknown height by writing the height into the frame header
+    ///
+    /// Unlike [finish](StripEncoder::finish), no DNL segment is written for strip encoders
+    /// created by [into_strip_encoder_unknown_height](Encoder::into_strip_encoder_unknown_height).
+    /// The writer must not have been moved since the encoder wrote the image. Behaves like
+    /// [finish](StripEncoder::finish) for all other strip encoders.
+    #[cfg(feature = "std")]
+    pub fn finish_patching_height(self) -> Result<W, EncodingError>
+    where
+        W: std::io::Write + std::io::Seek,
+    {
+        let (mut writer, patch) = match self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.finish_internal(false),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.finish_internal(false),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.finish_internal(false),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.finish_internal(false),
+        }?;
+
+        if let Some(patch) = patch {
+            use std::io::SeekFrom;
+
+            let end = writer.stream_position()?;
+
+            writer.seek(SeekFrom::Start(end - patch.distance))?;
+            std::io::Write::write_all(&mut writer, &patch.height.to_be_bytes())?;
+            writer.seek(SeekFrom::Start(end))?;
         }
+
+        Ok(writer)
     }
 
     #[cfg(feature = "wasm-bindgen")]
@@ -1281,6 +3338,10 @@ impl<W: JfifWrite> StripEncoder<W> {
             StripEncoderVariant::Scalar(inner) => inner.ensure_complete(),
             #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
             StripEncoderVariant::Avx2(inner) => inner.ensure_complete(),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.ensure_complete(),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.ensure_complete(),
         }
     }
 
@@ -1289,6 +3350,10 @@ impl<W: JfifWrite> StripEncoder<W> {
             StripEncoderVariant::Scalar(inner) => inner.header_bytes(),
             #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
             StripEncoderVariant::Avx2(inner) => inner.header_bytes(),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.header_bytes(),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.header_bytes(),
         }
     }
 
@@ -1296,7 +3361,117 @@ impl<W: JfifWrite> StripEncoder<W> {
         [0xFF, Marker::EOI.into()]
     }
 
+    /// Omit the quantization and Huffman tables from the image
+    ///
+    /// The resulting abbreviated image can only be decoded together with the table
+    /// specification stream of [tables_only_bytes](StripEncoder::tables_only_bytes), as used
+    /// by Motion-JPEG, TIFF and DICOM. Must be called before the headers are written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers were already written or the encoder uses optimized
+    /// Huffman tables, which are only known at the end.
+    pub fn set_abbreviated_image(&mut self, abbreviated: bool) -> Result<(), EncodingError> {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.set_abbreviated_image(abbreviated),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.set_abbreviated_image(abbreviated),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.set_abbreviated_image(abbreviated),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.set_abbreviated_image(abbreviated),
+        }
+    }
+
+    /// Set the EXIF metadata
+    ///
+    /// See [Encoder::set_exif] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers have already been written or the data exceeds
+    /// the maximum size of an APP segment.
+    pub fn set_exif(&mut self, exif: &Exif) -> Result<(), EncodingError> {
+        self.set_exif_data(Some(exif.to_bytes()?))
+    }
+
+    fn set_exif_data(&mut self, exif: Option<Vec<u8>>) -> Result<(), EncodingError> {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.set_exif(exif),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.set_exif(exif),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.set_exif(exif),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.set_exif(exif),
+        }
+    }
+
+    /// Appends a comment
+    ///
+    /// See [Encoder::add_comment] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers have already been written or the comment exceeds
+    /// the allowed size.
+    pub fn add_comment(&mut self, comment: &[u8]) -> Result<(), EncodingError> {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.add_comment(comment),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.add_comment(comment),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.add_comment(comment),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.add_comment(comment),
+        }
+    }
+
+    /// Set the layout of the segments in the file header
+    ///
+    /// See [MarkerLayout] for details.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the headers have already been written.
+    pub fn set_marker_layout(&mut self, layout: MarkerLayout) -> Result<(), EncodingError> {
+        match &mut self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.set_marker_layout(layout),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.set_marker_layout(layout),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.set_marker_layout(layout),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.set_marker_layout(layout),
+        }
+    }
+
+    /// Returns a table specification stream with the tables of this encoder
+    ///
+    /// The stream only contains the quantization and Huffman tables between the SOI and EOI
+    /// markers. It pairs with the images of all encoders using the same tables, see
+    /// [set_abbreviated_image](StripEncoder::set_abbreviated_image).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder uses optimized Huffman tables.
+    pub fn tables_only_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        match &self.inner {
+            StripEncoderVariant::Scalar(inner) => inner.tables_only_bytes(),
+            #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
+            StripEncoderVariant::Avx2(inner) => inner.tables_only_bytes(),
+            #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+            StripEncoderVariant::Neon(inner) => inner.tables_only_bytes(),
+            #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+            StripEncoderVariant::Simd128(inner) => inner.tables_only_bytes(),
+        }
+    }
+
     #[allow(clippy::too_many_arguments)]
+    #[cfg_attr(
+        all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"),
+        allow(unreachable_code)
+    )]
     pub fn new_with_tables(
         writer: W,
         width: u16,
@@ -1368,6 +3543,54 @@ impl<W: JfifWrite> StripEncoder<W> {
             }
         }
 
+        #[cfg(all(feature = "simd", target_arch = "aarch64"))]
+        {
+            if std::arch::is_aarch64_feature_detected!("neon") {
+                use crate::neon::NeonOperations;
+
+                let inner = StripEncoderInner::<W, NeonOperations>::new(
+                    writer,
+                    density,
+                    jpeg_color_type,
+                    components,
+                    quantization_tables,
+                    huffman_tables,
+                    restart_interval,
+                    app_segments,
+                    width,
+                    height,
+                    color_type,
+                );
+
+                return Ok(StripEncoder {
+                    inner: StripEncoderVariant::Neon(inner),
+                });
+            }
+        }
+
+        #[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+        {
+            use crate::simd128::Simd128Operations;
+
+            let inner = StripEncoderInner::<W, Simd128Operations>::new(
+                writer,
+                density,
+                jpeg_color_type,
+                components,
+                quantization_tables,
+                huffman_tables,
+                restart_interval,
+                app_segments,
+                width,
+                height,
+                color_type,
+            );
+
+            return Ok(StripEncoder {
+                inner: StripEncoderVariant::Simd128(inner),
+            });
+        }
+
         let inner = StripEncoderInner::<W, DefaultOperations>::new(
             writer,
             density,
@@ -1388,15 +3611,143 @@ impl<W: JfifWrite> StripEncoder<W> {
     }
 }
 
+/// Storage of the quantized blocks until the optimized Huffman tables are known
+enum CoefficientBuffer {
+    Memory(Vec<[i16; 64]>),
+    Scratch {
+        scratch: Box<dyn StripScratch + Send>,
+        len: usize,
+    },
+}
+
+impl CoefficientBuffer {
+    fn push(&mut self, blocks: &[[i16; 64]]) -> Result<(), EncodingError> {
+        match self {
+            CoefficientBuffer::Memory(buffer) => {
+                buffer.extend_from_slice(blocks);
+                Ok(())
+            }
+            CoefficientBuffer::Scratch { scratch, len } => {
+                *len += blocks.len();
+
+                let mut bytes = Vec::with_capacity(blocks.len() * 128);
+
+                for block in blocks {
+                    for value in block {
+                        bytes.extend_from_slice(&value.to_le_bytes());
+                    }
+                }
+
+                scratch.write_all(&bytes)
+            }
+        }
+    }
+}
+
+/// How a strip encoder which writes all data in finish encodes the image
+enum DeferredMode {
+    /// One interleaved scan with optimized Huffman tables, optionally using a scratch storage
+    Sequential(Option<Box<dyn StripScratch + Send>>),
+
+    /// Progressive scans, optionally with optimized Huffman tables
+    Progressive {
+        scans: Vec<ScanInfo>,
+        optimize: bool,
+    },
+}
+
+/// Buffered blocks of a strip encoder which writes all data in finish
+// Only one instance exists per encoder, so the size of the statistics doesn't matter
+#[allow(clippy::large_enum_variant)]
+enum DeferredCoding {
+    /// Blocks in the order of the interleaved scan and their symbol statistics
+    Sequential {
+        buffer: CoefficientBuffer,
+        dc_freq: [[u32; 257]; 2],
+        ac_freq: [[u32; 257]; 2],
+    },
+
+    /// Blocks of each component in the order of a non-interleaved scan
+    Progressive {
+        scans: Vec<ScanInfo>,
+        optimize: bool,
+        layout: BlockLayout,
+        blocks: [Vec<[i16; 64]>; 4],
+    },
+}
+
+/// Blocks of a strip encoder which writes one scan per component
+///
+/// The scan of the first component is written while the strips arrive,
+/// the blocks of the other components are kept until the end.
+struct ComponentScans {
+    layout: BlockLayout,
+    /// The components with sampling factors of 1, as a MCU of a non interleaved scan is one block
+    components: Vec<Component>,
+    blocks: [Vec<[i16; 64]>; 4],
+}
+
+impl ComponentScans {
+    /// Returns the blocks for one scan per component if the components can't be interleaved
+    fn new(width: u16, height: u16, components: &[Component]) -> Option<ComponentScans> {
+        // Same as SamplingFactor::supports_interleaved
+        let interleaved = components.iter().all(|component| {
+            component.horizontal_sampling_factor <= 2 && component.vertical_sampling_factor <= 2
+        });
+
+        if interleaved {
+            return None;
+        }
+
+        Some(ComponentScans {
+            layout: BlockLayout::new(width, height, components),
+            components: components
+                .iter()
+                .map(|component| Component {
+                    horizontal_sampling_factor: 1,
+                    vertical_sampling_factor: 1,
+                    ..component.clone()
+                })
+                .collect(),
+            blocks: Default::default(),
+        })
+    }
+}
+
+/// Writer of a strip encoder which counts the written bytes
+struct CountingWriter<W: JfifWrite> {
+    writer: W,
+    count: u64,
+}
+
+impl<W: JfifWrite> JfifWrite for CountingWriter<W> {
+    #[inline(always)]
+    fn write_all(&mut self, buf: &[u8]) -> Result<(), EncodingError> {
+        self.count += buf.len() as u64;
+        self.writer.write_all(buf)
+    }
+}
+
+/// Height of an image with unknown height which must be written into the frame header
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+struct HeightPatch {
+    height: u16,
+    /// Number of bytes between the height field and the end of the image
+    distance: u64,
+}
+
 struct StripEncoderInner<W: JfifWrite, OP: Operations> {
-    writer: JfifWriter<W>,
+    writer: JfifWriter<CountingWriter<W>>,
     density: Density,
     jpeg_color_type: JpegColorType,
     components: Vec<Component>,
     quantization_tables: [QuantizationTable; 2],
     huffman_tables: [(HuffmanTable, HuffmanTable); 2],
     restart_interval: Option<u16>,
+    exif: Option<Vec<u8>>,
     app_segments: Vec<(u8, Vec<u8>)>,
+    comments: Vec<Vec<u8>>,
+    marker_layout: MarkerLayout,
     width: u16,
     height: u16,
     max_h_sampling: usize,
@@ -1410,13 +3761,22 @@ struct StripEncoderInner<W: JfifWrite, OP: Operations> {
     last_rows: [Vec<u8>; 4],
     pending_rows: usize,
     processed_rows: usize,
+    mcu_row: usize,
+    importance_map: Option<ImportanceMap>,
+    coefficient_hook: Option<Box<dyn CoefficientHook + Send>>,
     headers_written: bool,
+    deferred: Option<DeferredCoding>,
+    component_scans: Option<ComponentScans>,
+    abbreviated: bool,
+    unknown_height: bool,
+    height_position: u64,
     color_type: ColorType,
     bytes_per_pixel: usize,
     phantom: PhantomData<OP>,
 }
 
 impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
+    #[allow(clippy::too_many_arguments)]
     fn new(
         writer: W,
         density: Density,
@@ -1436,16 +3796,20 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
         let rows_per_mcu = 8 * max_v_sampling;
         let buffer_size = buffer_width * rows_per_mcu;
         let component_count = components.len();
+        let component_scans = ComponentScans::new(width, height, &components);
 
         StripEncoderInner {
-            writer: JfifWriter::new(writer),
+            writer: JfifWriter::new(CountingWriter { writer, count: 0 }),
             density,
             jpeg_color_type,
             components,
             quantization_tables,
             huffman_tables,
             restart_interval,
+            exif: None,
             app_segments,
+            comments: Vec::new(),
+            marker_layout: MarkerLayout::default(),
             width,
             height,
             max_h_sampling,
@@ -1459,45 +3823,139 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
             last_rows: init_rows_for_components(component_count, buffer_width),
             pending_rows: 0,
             processed_rows: 0,
+            mcu_row: 0,
+            importance_map: None,
+            coefficient_hook: None,
             headers_written: false,
+            deferred: None,
+            component_scans,
+            abbreviated: false,
+            unknown_height: false,
+            height_position: 0,
             color_type,
             bytes_per_pixel: color_type.get_bytes_per_pixel(),
             phantom: PhantomData,
         }
     }
 
-    fn write_headers(&mut self) -> Result<(), EncodingError> {
-        if self.headers_written {
-            return Ok(());
-        }
+    fn defer_coding(&mut self, mode: DeferredMode) {
+        let deferred = match mode {
+            DeferredMode::Sequential(scratch) => {
+                let mut dc_freq = [[0u32; 257]; 2];
+                let mut ac_freq = [[0u32; 257]; 2];
+
+                for table in 0..2 {
+                    dc_freq[table][256] = 1;
+                    ac_freq[table][256] = 1;
+                }
+
+                let buffer = match scratch {
+                    Some(scratch) => CoefficientBuffer::Scratch { scratch, len: 0 },
+                    None => CoefficientBuffer::Memory(Vec::new()),
+                };
+
+                DeferredCoding::Sequential {
+                    buffer,
+                    dc_freq,
+                    ac_freq,
+                }
+            }
+            DeferredMode::Progressive { scans, optimize } => {
+                // The progressive scans already contain the layout of the components
+                self.component_scans = None;
+
+                DeferredCoding::Progressive {
+                    scans,
+                    optimize,
+                    layout: BlockLayout::new(self.width, self.height, &self.components),
+                    blocks: Default::default(),
+                }
+            }
+        };
+
+        self.deferred = Some(deferred);
+    }
 
+    /// Write the file headers and the frame header
+    fn write_frame_headers(&mut self, frame_type: SOFType) -> Result<(), EncodingError> {
         write_file_headers(
             &mut self.writer,
             self.density,
             self.jpeg_color_type,
+            self.exif.as_deref(),
+            None,
             &self.app_segments,
+            &self.comments,
+            self.marker_layout,
         )?;
 
+        // The SOF marker, the segment length and the precision precede the height
+        self.height_position = self.writer.get_ref().count + 5;
+
+        let height = self.frame_height();
+
         write_frame_header_common(
             &mut self.writer,
             self.width,
-            self.height,
+            height,
             &self.components,
-            false,
+            frame_type,
             &self.quantization_tables,
             &self.huffman_tables,
+            None,
             self.restart_interval,
             self.jpeg_color_type.get_num_components(),
+            8,
+            !self.abbreviated,
         )?;
 
-        let component_refs: Vec<_> = self.components.iter().collect();
-        self.writer.write_scan_header(&component_refs, None)?;
+        for (marker, data) in metadata_segments(
+            SegmentPosition::AfterTables,
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        ) {
+            self.writer.write_segment(marker, data)?;
+        }
+
+        Ok(())
+    }
+
+    fn write_headers(&mut self) -> Result<(), EncodingError> {
+        // With deferred coding the headers are written by finish
+        if self.headers_written || self.deferred.is_some() {
+            return Ok(());
+        }
+
+        self.write_frame_headers(SOFType::BaselineDCT)?;
+
+        let count = self.first_scan_len();
+        let component_refs: Vec<_> = self.components[..count].iter().collect();
+        self.writer.write_scan_header(&component_refs, None, None)?;
 
         self.headers_written = true;
 
         Ok(())
     }
 
+    /// Number of components of the scan which is written while the strips arrive
+    fn first_scan_len(&self) -> usize {
+        if self.component_scans.is_some() {
+            1
+        } else {
+            self.components.len()
+        }
+    }
+
+    /// Height written into the frame header, 0 if it's defined by a DNL segment
+    fn frame_height(&self) -> u16 {
+        if self.unknown_height {
+            0
+        } else {
+            self.height
+        }
+    }
+
     fn ensure_headers(&mut self) -> Result<(), EncodingError> {
         if !self.headers_written {
             self.write_headers()?;
@@ -1506,6 +3964,13 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
     }
 
     fn header_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        if self.deferred.is_some() {
+            return Err(EncodingError::Write(
+                "Header bytes are only known in finish for progressive scans or optimized Huffman tables"
+                    .into(),
+            ));
+        }
+
         let mut buffer = Vec::new();
         let mut writer = JfifWriter::new(&mut buffer);
 
@@ -1513,23 +3978,127 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
             &mut writer,
             self.density,
             self.jpeg_color_type,
+            self.exif.as_deref(),
+            None,
             &self.app_segments,
+            &self.comments,
+            self.marker_layout,
         )?;
 
         write_frame_header_common(
             &mut writer,
             self.width,
-            self.height,
+            self.frame_height(),
             &self.components,
-            false,
+            SOFType::BaselineDCT,
             &self.quantization_tables,
             &self.huffman_tables,
+            None,
             self.restart_interval,
             self.jpeg_color_type.get_num_components(),
+            8,
+            !self.abbreviated,
+        )?;
+
+        for (marker, data) in metadata_segments(
+            SegmentPosition::AfterTables,
+            &self.app_segments,
+            &self.comments,
+            self.marker_layout,
+        ) {
+            writer.write_segment(marker, data)?;
+        }
+
+        let component_refs: Vec<_> = self.components[..self.first_scan_len()].iter().collect();
+        writer.write_scan_header(&component_refs, None, None)?;
+
+        Ok(buffer)
+    }
+
+    /// Returns if the Huffman tables are only known in finish
+    fn has_optimized_tables(&self) -> bool {
+        matches!(
+            self.deferred,
+            Some(DeferredCoding::Sequential { .. })
+                | Some(DeferredCoding::Progressive { optimize: true, .. })
+        )
+    }
+
+    fn set_abbreviated_image(&mut self, abbreviated: bool) -> Result<(), EncodingError> {
+        if self.headers_written {
+            return Err(EncodingError::Write(
+                "Headers have already been written".into(),
+            ));
+        }
+
+        if abbreviated && self.has_optimized_tables() {
+            return Err(EncodingError::Write(
+                "Abbreviated images don't support optimized Huffman tables".into(),
+            ));
+        }
+
+        self.abbreviated = abbreviated;
+
+        Ok(())
+    }
+
+    fn set_exif(&mut self, exif: Option<Vec<u8>>) -> Result<(), EncodingError> {
+        if self.headers_written {
+            return Err(EncodingError::Write(
+                "Headers have already been written".into(),
+            ));
+        }
+
+        self.exif = exif;
+
+        Ok(())
+    }
+
+    fn add_comment(&mut self, comment: &[u8]) -> Result<(), EncodingError> {
+        if self.headers_written {
+            return Err(EncodingError::Write(
+                "Headers have already been written".into(),
+            ));
+        }
+
+        self.comments.push(validate_comment(comment)?);
+
+        Ok(())
+    }
+
+    fn set_marker_layout(&mut self, layout: MarkerLayout) -> Result<(), EncodingError> {
+        if self.headers_written {
+            return Err(EncodingError::Write(
+                "Headers have already been written".into(),
+            ));
+        }
+
+        self.marker_layout = layout;
+
+        Ok(())
+    }
+
+    fn tables_only_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        if self.has_optimized_tables() {
+            return Err(EncodingError::Write(
+                "Optimized Huffman tables are only known in finish".into(),
+            ));
+        }
+
+        let mut buffer = Vec::new();
+        let mut writer = JfifWriter::new(&mut buffer);
+
+        writer.write_marker(Marker::SOI)?;
+
+        write_table_segments(
+            &mut writer,
+            &self.quantization_tables,
+            &self.huffman_tables,
+            None,
+            self.jpeg_color_type.get_num_components(),
         )?;
 
-        let component_refs: Vec<_> = self.components.iter().collect();
-        writer.write_scan_header(&component_refs, None)?;
+        writer.write_marker(Marker::EOI)?;
 
         Ok(buffer)
     }
@@ -1571,75 +4140,7 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
     }
 
     fn push_row(&mut self, row_data: &[u8]) -> Result<(), EncodingError> {
-        match self.color_type {
-            ColorType::Luma => {
-                self.row_buffers[0].extend_from_slice(row_data);
-            }
-            ColorType::Rgb => {
-                for pixel in row_data.chunks_exact(3) {
-                    let (y, cb, cr) = rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]);
-                    self.row_buffers[0].push(y);
-                    self.row_buffers[1].push(cb);
-                    self.row_buffers[2].push(cr);
-                }
-            }
-            ColorType::Rgba => {
-                for pixel in row_data.chunks_exact(4) {
-                    let (y, cb, cr) = rgb_to_ycbcr(pixel[0], pixel[1], pixel[2]);
-                    self.row_buffers[0].push(y);
-                    self.row_buffers[1].push(cb);
-                    self.row_buffers[2].push(cr);
-                }
-            }
-            ColorType::Bgr => {
-                for pixel in row_data.chunks_exact(3) {
-                    let (y, cb, cr) = rgb_to_ycbcr(pixel[2], pixel[1], pixel[0]);
-                    self.row_buffers[0].push(y);
-                    self.row_buffers[1].push(cb);
-                    self.row_buffers[2].push(cr);
-                }
-            }
-            ColorType::Bgra => {
-                for pixel in row_data.chunks_exact(4) {
-                    let (y, cb, cr) = rgb_to_ycbcr(pixel[2], pixel[1], pixel[0]);
-                    self.row_buffers[0].push(y);
-                    self.row_buffers[1].push(cb);
-                    self.row_buffers[2].push(cr);
-                }
-            }
-            ColorType::Ycbcr => {
-                for pixel in row_data.chunks_exact(3) {
-                    self.row_buffers[0].push(pixel[0]);
-                    self.row_buffers[1].push(pixel[1]);
-                    self.row_buffers[2].push(pixel[2]);
-                }
-            }
-            ColorType::Cmyk => {
-                for pixel in row_data.chunks_exact(4) {
-                    self.row_buffers[0].push(pixel[0]);
-                    self.row_buffers[1].push(pixel[1]);
-                    self.row_buffers[2].push(pixel[2]);
-                    self.row_buffers[3].push(pixel[3]);
-                }
-            }
-            ColorType::CmykAsYcck => {
-                for pixel in row_data.chunks_exact(4) {
-                    let (y, cb, cr, k) = cmyk_to_ycck(pixel[0], pixel[1], pixel[2], pixel[3]);
-                    self.row_buffers[0].push(y);
-                    self.row_buffers[1].push(cb);
-                    self.row_buffers[2].push(cr);
-                    self.row_buffers[3].push(k);
-                }
-            }
-            ColorType::Ycck => {
-                for pixel in row_data.chunks_exact(4) {
-                    self.row_buffers[0].push(pixel[0]);
-                    self.row_buffers[1].push(pixel[1]);
-                    self.row_buffers[2].push(pixel[2]);
-                    self.row_buffers[3].push(pixel[3]);
-                }
-            }
-        }
+        OP::convert_row(self.color_type, row_data, self.width, &mut self.row_buffers);
 
         let component_count = self.components.len();
 
@@ -1668,90 +4169,374 @@ impl<W: JfifWrite, OP: Operations> StripEncoderInner<W, OP> {
             self.flush_full_mcu_row()?;
         }
 
-        Ok(())
+        Ok(())
+    }
+
+    fn pad_pending_rows(&mut self) -> Result<(), EncodingError> {
+        if self.pending_rows == 0 {
+            return Ok(());
+        }
+
+        if self.rows_per_mcu == 0 {
+            return Err(EncodingError::Write(
+                "Invalid MCU configuration for strip encoder".into(),
+            ));
+        }
+
+        let missing = self.rows_per_mcu - self.pending_rows;
+
+        for _ in 0..missing {
+            for (i, buffer) in self
+                .row_buffers
+                .iter_mut()
+                .enumerate()
+                .take(self.components.len())
+            {
+                if self.last_rows[i].is_empty() {
+                    return Err(EncodingError::Write(
+                        "Cannot pad final MCU without prior row data".into(),
+                    ));
+                }
+                buffer.extend_from_slice(&self.last_rows[i]);
+            }
+        }
+
+        self.pending_rows = self.rows_per_mcu;
+        self.flush_full_mcu_row()
+    }
+
+    fn flush_full_mcu_row(&mut self) -> Result<(), EncodingError> {
+        let adaptive = self
+            .importance_map
+            .as_ref()
+            .map(|map| AdaptiveQuantizer::new(map, &self.components));
+
+        if self.deferred.is_none() && self.component_scans.is_none() {
+            write_interleaved_mcu_row::<_, OP>(
+                &mut self.writer,
+                &self.components,
+                &self.huffman_tables,
+                &self.quantization_tables,
+                &mut self.prev_dc,
+                self.max_h_sampling,
+                self.max_v_sampling,
+                self.buffer_width,
+                self.num_cols,
+                &mut self.restart_state,
+                &self.row_buffers,
+                adaptive.as_ref(),
+                &mut self.coefficient_hook,
+                self.mcu_row,
+            )?;
+        } else {
+            let mut blocks = Vec::new();
+
+            quantize_mcu_row::<OP>(
+                &self.components,
+                &self.quantization_tables,
+                self.max_h_sampling,
+                self.max_v_sampling,
+                self.buffer_width,
+                self.num_cols,
+                &self.row_buffers,
+                adaptive.as_ref(),
+                self.mcu_row,
+                &mut blocks,
+            );
+
+            if let Some(hook) = &mut self.coefficient_hook {
+                process_mcu_row(hook.as_mut(), &self.components, self.mcu_row, &mut blocks)?;
+            }
+
+            self.code_mcu_row(blocks)?;
+        }
+
+        for buffer in &mut self.row_buffers {
+            buffer.clear();
+        }
+
+        self.pending_rows = 0;
+        self.mcu_row += 1;
+
+        Ok(())
+    }
+
+    /// Write, count or buffer the quantized blocks of a MCU row
+    fn code_mcu_row(&mut self, mut blocks: Vec<[i16; 64]>) -> Result<(), EncodingError> {
+        if let Some(DeferredCoding::Progressive {
+            layout,
+            blocks: component_blocks,
+            ..
+        }) = &mut self.deferred
+        {
+            layout.append_mcu_row(self.mcu_row, &blocks, component_blocks);
+            return Ok(());
+        }
+
+        let components = match &mut self.component_scans {
+            Some(scans) => {
+                scans
+                    .layout
+                    .append_mcu_row(self.mcu_row, &blocks, &mut scans.blocks);
+
+                // Only the first component is coded now
+                blocks = core::mem::take(&mut scans.blocks[0]);
+                &scans.components[..1]
+            }
+            None => &self.components[..],
+        };
+
+        match &mut self.deferred {
+            Some(DeferredCoding::Sequential {
+                buffer,
+                dc_freq,
+                ac_freq,
+            }) => {
+                count_mcus(
+                    components,
+                    dc_freq,
+                    ac_freq,
+                    &mut self.prev_dc,
+                    &mut self.restart_state,
+                    &blocks,
+                );
+
+                buffer.push(&blocks)
+            }
+            Some(DeferredCoding::Progressive { .. }) => unreachable!(),
+            None => write_mcus(
+                &mut self.writer,
+                components,
+                &self.huffman_tables,
+                &mut self.prev_dc,
+                &mut self.restart_state,
+                &blocks,
+            ),
+        }
+    }
+
+    /// Write one scan for each component after the first with the buffered blocks
+    fn write_component_scans(&mut self) -> Result<(), EncodingError> {
+        let scans = match self.component_scans.take() {
+            Some(scans) => scans,
+            None => return Ok(()),
+        };
+
+        for (i, component) in self.components.iter().enumerate().skip(1) {
+            self.writer.write_scan_header(&[component], None, None)?;
+
+            write_mcus(
+                &mut self.writer,
+                &scans.components[i..=i],
+                &self.huffman_tables,
+                &mut [0i16; 4],
+                &mut RestartState::new(self.restart_interval),
+                &scans.blocks[i],
+            )?;
+
+            self.writer.finalize_bit_buffer()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(self) -> Result<W, EncodingError> {
+        self.finish_internal(true).map(|(writer, _)| writer)
+    }
+
+    /// Write the remaining data
+    ///
+    /// The height of an image with unknown height is written in a DNL segment if `write_dnl`
+    /// is set, otherwise it's returned with its position to patch the frame header.
+    fn finish_internal(
+        mut self,
+        write_dnl: bool,
+    ) -> Result<(W, Option<HeightPatch>), EncodingError> {
+        self.ensure_headers()?;
+        self.ensure_complete()?;
+
+        if self.unknown_height {
+            self.height = self.processed_rows as u16;
+
+            // Drop the blocks below the image when padding the last MCU row
+            if let Some(scans) = &mut self.component_scans {
+                scans.layout = BlockLayout::new(self.width, self.height, &self.components);
+            }
+        }
+
+        if self.pending_rows > 0 {
+            self.pad_pending_rows()?;
+        }
+
+        if let Some(deferred) = self.deferred.take() {
+            // The headers are written now, so they contain the actual height
+            self.unknown_height = false;
+            self.write_deferred(deferred)?;
+        }
+
+        self.writer.finalize_bit_buffer()?;
+
+        if self.unknown_height && write_dnl {
+            self.writer.write_marker(Marker::DNL)?;
+            self.writer.write_u16(4)?;
+            self.writer.write_u16(self.height)?;
+        }
+
+        self.write_component_scans()?;
+
+        self.writer.write_marker(Marker::EOI)?;
+
+        let writer = self.writer.into_inner();
+
+        let patch = if self.unknown_height && !write_dnl {
+            Some(HeightPatch {
+                height: self.height,
+                distance: writer.count - self.height_position,
+            })
+        } else {
+            None
+        };
+
+        Ok((writer.writer, patch))
+    }
+
+    /// Write the headers and the buffered blocks
+    fn write_deferred(&mut self, deferred: DeferredCoding) -> Result<(), EncodingError> {
+        match deferred {
+            DeferredCoding::Sequential {
+                buffer,
+                dc_freq,
+                ac_freq,
+            } => self.write_deferred_sequential(buffer, dc_freq, ac_freq),
+            DeferredCoding::Progressive {
+                scans,
+                optimize,
+                layout,
+                blocks,
+            } => {
+                if optimize {
+                    optimize_huffman_tables(
+                        &mut self.huffman_tables,
+                        &self.components,
+                        self.restart_interval,
+                        &blocks,
+                        Some((&scans, &layout)),
+                    )?;
+                }
+
+                self.write_frame_headers(SOFType::ProgressiveDCT)?;
+
+                // The default huffman tables don't contain symbols for EOB runs
+                let max_eob_run = if optimize { MAX_EOB_RUN } else { 1 };
+
+                write_progressive_scans(
+                    &mut self.writer,
+                    &self.components,
+                    &self.huffman_tables,
+                    &scans,
+                    &layout,
+                    &blocks,
+                    self.restart_interval,
+                    max_eob_run,
+                )
+            }
+        }
     }
 
-    fn pad_pending_rows(&mut self) -> Result<(), EncodingError> {
-        if self.pending_rows == 0 {
-            return Ok(());
+    /// Create the optimized Huffman tables and write the first scan
+    fn write_deferred_sequential(
+        &mut self,
+        buffer: CoefficientBuffer,
+        mut dc_freq: [[u32; 257]; 2],
+        mut ac_freq: [[u32; 257]; 2],
+    ) -> Result<(), EncodingError> {
+        if let Some(scans) = &self.component_scans {
+            for i in 1..self.components.len() {
+                count_mcus(
+                    &scans.components[i..=i],
+                    &mut dc_freq,
+                    &mut ac_freq,
+                    &mut [0i16; 4],
+                    &mut RestartState::new(self.restart_interval),
+                    &scans.blocks[i],
+                );
+            }
         }
 
-        if self.rows_per_mcu == 0 {
-            return Err(EncodingError::Write(
-                "Invalid MCU configuration for strip encoder".into(),
-            ));
+        for table in 0..self.components.len().min(2) {
+            self.huffman_tables[table] = (
+                HuffmanTable::new_optimized(dc_freq[table]),
+                HuffmanTable::new_optimized(ac_freq[table]),
+            );
         }
 
-        let missing = self.rows_per_mcu - self.pending_rows;
+        self.write_headers()?;
 
-        for _ in 0..missing {
-            for (i, buffer) in self
-                .row_buffers
-                .iter_mut()
-                .enumerate()
-                .take(self.components.len())
-            {
-                if self.last_rows[i].is_empty() {
-                    return Err(EncodingError::Write(
-                        "Cannot pad final MCU without prior row data".into(),
-                    ));
-                }
-                buffer.extend_from_slice(&self.last_rows[i]);
-            }
-        }
+        self.prev_dc = [0i16; 4];
+        self.restart_state = RestartState::new(self.restart_interval);
 
-        self.pending_rows = self.rows_per_mcu;
-        self.flush_full_mcu_row()
-    }
+        let components = match &self.component_scans {
+            Some(scans) => &scans.components[..1],
+            None => &self.components[..],
+        };
 
-    fn flush_full_mcu_row(&mut self) -> Result<(), EncodingError> {
-        write_interleaved_mcu_row::<_, OP>(
-            &mut self.writer,
-            &self.components,
-            &self.huffman_tables,
-            &self.quantization_tables,
-            &mut self.prev_dc,
-            self.max_h_sampling,
-            self.max_v_sampling,
-            self.buffer_width,
-            self.num_cols,
-            &mut self.restart_state,
-            &self.row_buffers,
-        )?;
+        match buffer {
+            CoefficientBuffer::Memory(blocks) => write_mcus(
+                &mut self.writer,
+                components,
+                &self.huffman_tables,
+                &mut self.prev_dc,
+                &mut self.restart_state,
+                &blocks,
+            ),
+            CoefficientBuffer::Scratch {
+                mut scratch,
+                mut len,
+            } => {
+                scratch.rewind()?;
 
-        for buffer in &mut self.row_buffers {
-            buffer.clear();
-        }
+                // A multiple of the MCU size
+                let row_blocks = self.num_cols * get_blocks_per_mcu(&self.components);
 
-        self.pending_rows = 0;
+                let mut bytes = vec![0u8; row_blocks * 128];
+                let mut blocks = vec![[0i16; 64]; row_blocks];
 
-        Ok(())
-    }
+                while len > 0 {
+                    let count = len.min(row_blocks);
+                    let bytes = &mut bytes[..count * 128];
+                    let blocks = &mut blocks[..count];
 
-    fn finish(mut self) -> Result<W, EncodingError> {
-        self.ensure_headers()?;
+                    scratch.read_exact(bytes)?;
 
-        if self.processed_rows != usize::from(self.height) {
-            return Err(EncodingError::Write(alloc::format!(
-                "Expected {} rows but received {}",
-                self.height,
-                self.processed_rows
-            )));
-        }
+                    for (block, bytes) in blocks.iter_mut().zip(bytes.chunks_exact(128)) {
+                        for (value, bytes) in block.iter_mut().zip(bytes.chunks_exact(2)) {
+                            *value = i16::from_le_bytes([bytes[0], bytes[1]]);
+                        }
+                    }
 
-        if self.pending_rows > 0 {
-            self.pad_pending_rows()?;
-        }
+                    write_mcus(
+                        &mut self.writer,
+                        components,
+                        &self.huffman_tables,
+                        &mut self.prev_dc,
+                        &mut self.restart_state,
+                        blocks,
+                    )?;
 
-        self.writer.finalize_bit_buffer()?;
-        self.writer.write_marker(Marker::EOI)?;
+                    len -= count;
+                }
 
-        Ok(self.writer.into_inner())
+                Ok(())
+            }
+        }
     }
 
-    #[cfg(feature = "wasm-bindgen")]
     fn ensure_complete(&self) -> Result<(), EncodingError> {
-        if self.processed_rows != usize::from(self.height) {
+        if self.unknown_height {
+            if self.processed_rows == 0 {
+                return Err(EncodingError::Write("Expected at least one row".into()));
+            }
+        } else if self.processed_rows != usize::from(self.height) {
             return Err(EncodingError::Write(alloc::format!(
                 "Expected {} rows but received {}",
                 self.height,
@@ -1782,14 +4567,14 @@ impl Encoder<BufWriter<File>> {
     }
 }
 
-struct RestartState {
+pub(crate) struct RestartState {
     interval: u16,
     restarts: u16,
     restarts_to_go: u16,
 }
 
 impl RestartState {
-    fn new(interval: Option<u16>) -> Self {
+    pub fn new(interval: Option<u16>) -> Self {
         let interval = interval.unwrap_or(0);
         RestartState {
             interval,
@@ -1798,13 +4583,18 @@ impl RestartState {
         }
     }
 
+    /// Returns if a restart marker precedes the next MCU
+    fn is_restart(&self) -> bool {
+        self.interval > 0 && self.restarts_to_go == 0
+    }
+
     fn before_mcu<W: JfifWrite>(
         &mut self,
         writer: &mut JfifWriter<W>,
         prev_dc: &mut [i16; 4],
         component_count: usize,
     ) -> Result<(), EncodingError> {
-        if self.interval > 0 && self.restarts_to_go == 0 {
+        if self.is_restart() {
             writer.finalize_bit_buffer()?;
             writer.write_marker(Marker::RST((self.restarts % 8) as u8))?;
 
@@ -1830,6 +4620,7 @@ impl RestartState {
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn write_interleaved_mcu_row<W: JfifWrite, OP: Operations>(
     writer: &mut JfifWriter<W>,
     components: &[Component],
@@ -1842,14 +4633,60 @@ fn write_interleaved_mcu_row<W: JfifWrite, OP: Operations>(
     num_cols: usize,
     restart: &mut RestartState,
     row: &[Vec<u8>; 4],
+    adaptive: Option<&AdaptiveQuantizer>,
+    hook: &mut Option<Box<dyn CoefficientHook + Send>>,
+    mcu_row: usize,
 ) -> Result<(), EncodingError> {
-    for block_x in 0..num_cols {
-        restart.before_mcu(writer, prev_dc, components.len())?;
+    let mut blocks = Vec::new();
+
+    quantize_mcu_row::<OP>(
+        components,
+        q_tables,
+        max_h_sampling,
+        max_v_sampling,
+        buffer_width,
+        num_cols,
+        row,
+        adaptive,
+        mcu_row,
+        &mut blocks,
+    );
+
+    if let Some(hook) = hook {
+        process_mcu_row(hook.as_mut(), components, mcu_row, &mut blocks)?;
+    }
 
+    write_mcus(
+        writer,
+        components,
+        huffman_tables,
+        prev_dc,
+        restart,
+        &blocks,
+    )
+}
+
+/// FDCT and quantization of all blocks of a MCU row
+///
+/// The blocks are appended in the order of the interleaved scan.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn quantize_mcu_row<OP: Operations>(
+    components: &[Component],
+    q_tables: &[QuantizationTable; 2],
+    max_h_sampling: usize,
+    max_v_sampling: usize,
+    buffer_width: usize,
+    num_cols: usize,
+    row: &[Vec<u8>; 4],
+    adaptive: Option<&AdaptiveQuantizer>,
+    mcu_row: usize,
+    blocks: &mut Vec<[i16; 64]>,
+) {
+    for block_x in 0..num_cols {
         for (i, component) in components.iter().enumerate() {
             for v_offset in 0..component.vertical_sampling_factor as usize {
                 for h_offset in 0..component.horizontal_sampling_factor as usize {
-                    let mut block = get_block(
+                    let mut block = OP::get_block(
                         &row[i],
                         block_x * 8 * max_h_sampling + (h_offset * 8),
                         v_offset * 8,
@@ -1860,25 +4697,156 @@ fn write_interleaved_mcu_row<W: JfifWrite, OP: Operations>(
 
                     OP::fdct(&mut block);
 
+                    let q_table = &q_tables[component.quantization_table as usize];
+
+                    let level = adaptive.map_or(BlockLevel::FULL, |a| {
+                        a.level(
+                            i,
+                            block_x * component.horizontal_sampling_factor as usize + h_offset,
+                            mcu_row * component.vertical_sampling_factor as usize + v_offset,
+                        )
+                    });
+
                     let mut q_block = [0i16; 64];
 
-                    OP::quantize_block(
-                        &block,
-                        &mut q_block,
-                        &q_tables[component.quantization_table as usize],
-                    );
+                    if level.is_full() {
+                        OP::quantize_block(&block, &mut q_block, q_table);
+                    } else {
+                        level.quantize(&block.map(i32::from), &mut q_block, q_table);
+                    }
 
-                    writer.write_block(
-                        &q_block,
-                        prev_dc[i],
-                        &huffman_tables[component.dc_huffman_table as usize].0,
-                        &huffman_tables[component.ac_huffman_table as usize].1,
-                    )?;
+                    blocks.push(q_block);
+                }
+            }
+        }
+    }
+}
+
+/// Pass the blocks of a MCU row created by [quantize_mcu_row] to the coefficient hook
+///
+/// Returns an error if the hook creates coefficients which exceed the range of 8 bit samples.
+pub(crate) fn process_mcu_row(
+    hook: &mut dyn CoefficientHook,
+    components: &[Component],
+    mcu_row: usize,
+    blocks: &mut [[i16; 64]],
+) -> Result<(), EncodingError> {
+    let blocks_per_mcu = get_blocks_per_mcu(components);
 
-                    prev_dc[i] = q_block[0];
+    for (mcu_x, mcu) in blocks.chunks_mut(blocks_per_mcu).enumerate() {
+        let mut blocks = mcu.iter_mut();
+
+        for (i, component) in components.iter().enumerate() {
+            let h_sampling = usize::from(component.horizontal_sampling_factor);
+            let v_sampling = usize::from(component.vertical_sampling_factor);
+
+            for v in 0..v_sampling {
+                for h in 0..h_sampling {
+                    if let Some(block) = blocks.next() {
+                        hook.process_block(
+                            i,
+                            mcu_x * h_sampling + h,
+                            mcu_row * v_sampling + v,
+                            block,
+                        );
+
+                        validate_coefficients(block, i, 8)?;
+                    }
                 }
             }
         }
+    }
+
+    Ok(())
+}
+
+/// Pass the blocks of all components in the layout of [collect_blocks](Encoder::collect_blocks)
+/// to the coefficient hook
+///
+/// Returns an error if the hook creates coefficients which exceed the range of the precision.
+fn process_blocks(
+    hook: &mut dyn CoefficientHook,
+    components: &[Component],
+    width: u16,
+    precision: u8,
+    blocks: &mut [Vec<[i16; 64]>; 4],
+) -> Result<(), EncodingError> {
+    let (max_h_sampling, _) = get_max_sampling_size_for(components);
+    let num_cols = ceil_div(usize::from(width), 8);
+
+    for (i, component) in components.iter().enumerate() {
+        let cols = ceil_div(
+            num_cols,
+            max_h_sampling / usize::from(component.horizontal_sampling_factor),
+        );
+
+        for (n, block) in blocks[i].iter_mut().enumerate() {
+            hook.process_block(i, n % cols, n / cols, block);
+            validate_coefficients(block, i, precision)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns an error if a quantized coefficient can't be coded for the sample precision
+///
+/// DC values can use one more negative value than the AC values (F.1.2.1 and F.1.2.2).
+fn validate_coefficients(
+    block: &[i16; 64],
+    component: usize,
+    precision: u8,
+) -> Result<(), EncodingError> {
+    let max_value = (1i16 << (precision + 2)) - 1;
+
+    let out_of_range = !(-max_value - 1..=max_value).contains(&block[0])
+        || block[1..]
+            .iter()
+            .any(|value| !(-max_value..=max_value).contains(value));
+
+    if out_of_range {
+        Err(EncodingError::Write(alloc::format!(
+            "Coefficient of component {} exceeds the range of {} bit samples",
+            component,
+            precision
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Huffman code the blocks of complete MCUs created by [quantize_mcu_row]
+pub(crate) fn write_mcus<W: JfifWrite>(
+    writer: &mut JfifWriter<W>,
+    components: &[Component],
+    huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    prev_dc: &mut [i16; 4],
+    restart: &mut RestartState,
+    blocks: &[[i16; 64]],
+) -> Result<(), EncodingError> {
+    let blocks_per_mcu = get_blocks_per_mcu(components);
+
+    debug_assert_eq!(blocks.len() % blocks_per_mcu, 0);
+
+    for mcu in blocks.chunks(blocks_per_mcu) {
+        restart.before_mcu(writer, prev_dc, components.len())?;
+
+        let mut blocks = mcu.iter();
+
+        for (i, component) in components.iter().enumerate() {
+            let count = component.horizontal_sampling_factor * component.vertical_sampling_factor;
+
+            for block in blocks.by_ref().take(usize::from(count)) {
+                writer.write_block(
+                    block,
+                    prev_dc[i],
+                    &huffman_tables[component.dc_huffman_table as usize].0,
+                    &huffman_tables[component.ac_huffman_table as usize].1,
+                )?;
+
+                prev_dc[i] = block[0];
+            }
+        }
 
         restart.after_mcu();
     }
@@ -1886,7 +4854,153 @@ fn write_interleaved_mcu_row<W: JfifWrite, OP: Operations>(
     Ok(())
 }
 
-fn get_block(
+/// Count the Huffman symbols [write_mcus] would write for the blocks of complete MCUs
+fn count_mcus(
+    components: &[Component],
+    dc_freq: &mut [[u32; 257]; 2],
+    ac_freq: &mut [[u32; 257]; 2],
+    prev_dc: &mut [i16; 4],
+    restart: &mut RestartState,
+    blocks: &[[i16; 64]],
+) {
+    let blocks_per_mcu = get_blocks_per_mcu(components);
+
+    debug_assert_eq!(blocks.len() % blocks_per_mcu, 0);
+
+    for mcu in blocks.chunks(blocks_per_mcu) {
+        if restart.is_restart() {
+            for value in prev_dc.iter_mut().take(components.len()) {
+                *value = 0;
+            }
+        }
+
+        let mut blocks = mcu.iter();
+
+        for (i, component) in components.iter().enumerate() {
+            let count = component.horizontal_sampling_factor * component.vertical_sampling_factor;
+
+            for block in blocks.by_ref().take(usize::from(count)) {
+                let num_bits = get_num_bits(block[0] - prev_dc[i]);
+                dc_freq[component.dc_huffman_table as usize][num_bits as usize] += 1;
+
+                count_ac_symbols(block, &mut ac_freq[component.ac_huffman_table as usize]);
+
+                prev_dc[i] = block[0];
+            }
+        }
+
+        restart.after_mcu();
+    }
+}
+
+/// Count the AC symbols of a block
+fn count_ac_symbols(block: &[i16; 64], ac_freq: &mut [u32; 257]) {
+    // Same symbols as JfifWriter::write_ac_block
+    let mut non_zero = non_zero_mask(block) & !1;
+    let mut index = 1;
+
+    while non_zero != 0 {
+        let next = non_zero.trailing_zeros() as usize;
+        let mut zero_run = next - index;
+
+        while zero_run > 15 {
+            ac_freq[0xF0] += 1;
+            zero_run -= 16;
+        }
+        let num_bits = get_num_bits(block[next]);
+        let symbol = ((zero_run as u8) << 4) | num_bits;
+
+        ac_freq[symbol as usize] += 1;
+
+        index = next + 1;
+        non_zero &= non_zero - 1;
+    }
+
+    if index < 64 {
+        ac_freq[0] += 1;
+    }
+}
+
+/// Number of blocks of a MCU in an interleaved scan
+pub(crate) fn get_blocks_per_mcu(components: &[Component]) -> usize {
+    components
+        .iter()
+        .map(|c| usize::from(c.horizontal_sampling_factor * c.vertical_sampling_factor))
+        .sum()
+}
+
+/// Trellis quantize a block and return its lambda for [TrellisQuantizer::quantize_dc]
+fn trellis_quantize_block(
+    quantizer: &TrellisQuantizer,
+    block: &[i32; 64],
+    q_block: &mut [i16; 64],
+    q_table: &QuantizationTable,
+    ac_table: &HuffmanTable,
+    level: &BlockLevel,
+) -> f32 {
+    // Blocks with a reduced importance are quantized coarsely anyway
+    if level.is_full() {
+        quantizer.quantize_block(block, q_block, q_table, ac_table)
+    } else {
+        level.quantize(block, q_block, q_table);
+        quantizer.lambda(block)
+    }
+}
+
+/// Convert a row of pixels into the component buffers with the scalar color conversions
+pub(crate) fn convert_row(
+    color_type: ColorType,
+    data: &[u8],
+    width: u16,
+    buffers: &mut [Vec<u8>; 4],
+) {
+    match color_type {
+        ColorType::Luma => GrayImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Rgb => RgbImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Rgba => RgbaImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Bgr => BgrImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Bgra => BgraImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Ycbcr => YCbCrImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Cmyk => CmykImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::CmykAsYcck => CmykAsYcckImage(data, width, 1).fill_buffers(0, buffers),
+        ColorType::Ycck => YcckImage(data, width, 1).fill_buffers(0, buffers),
+    }
+}
+
+/// Fill the component rows of a MCU row
+///
+/// The rows are padded to `buffer_width` by repeating the last column and rows below
+/// the image repeat the last row.
+pub(crate) fn fill_mcu_row<I: ImageBuffer>(
+    image: &I,
+    mcu_row: usize,
+    width: u16,
+    height: u16,
+    max_v_sampling: usize,
+    buffer_width: usize,
+    row: &mut [Vec<u8>; 4],
+) {
+    for r in row.iter_mut() {
+        r.clear();
+    }
+
+    for y in 0..(8 * max_v_sampling) {
+        let y = y + mcu_row * 8 * max_v_sampling;
+        let y = (y.min(height as usize - 1)) as u16;
+
+        image.fill_buffers(y, row);
+
+        for _ in usize::from(width)..buffer_width {
+            for channel in row.iter_mut() {
+                if !channel.is_empty() {
+                    channel.push(channel[channel.len() - 1]);
+                }
+            }
+        }
+    }
+}
+
+pub(crate) fn get_block(
     data: &[u8],
     start_x: usize,
     start_y: usize,
@@ -1908,11 +5022,34 @@ fn get_block(
     block
 }
 
-fn ceil_div(value: usize, div: usize) -> usize {
+fn get_block_wide(
+    data: &[u16],
+    start_x: usize,
+    start_y: usize,
+    col_stride: usize,
+    row_stride: usize,
+    width: usize,
+    level_shift: i32,
+) -> [i32; 64] {
+    let mut block = [0i32; 64];
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let ix = start_x + (x * col_stride);
+            let iy = start_y + (y * row_stride);
+
+            block[y * 8 + x] = i32::from(data[iy * width + ix]) - level_shift;
+        }
+    }
+
+    block
+}
+
+pub(crate) fn ceil_div(value: usize, div: usize) -> usize {
     value / div + usize::from(value % div != 0)
 }
 
-fn init_rows_for_components(components: usize, buffer_size: usize) -> [Vec<u8>; 4] {
+pub(crate) fn init_rows_for_components<T>(components: usize, buffer_size: usize) -> [Vec<T>; 4] {
     match components {
         1 => [
             Vec::with_capacity(buffer_size),
@@ -1936,7 +5073,7 @@ fn init_rows_for_components(components: usize, buffer_size: usize) -> [Vec<u8>;
     }
 }
 
-fn get_max_sampling_size_for(components: &[Component]) -> (usize, usize) {
+pub(crate) fn get_max_sampling_size_for(components: &[Component]) -> (usize, usize) {
     let max_h_sampling = components.iter().fold(1, |value, component| {
         value.max(component.horizontal_sampling_factor)
     });
@@ -1964,6 +5101,28 @@ fn get_num_bits(mut value: i16) -> u8 {
 }
 
 pub(crate) trait Operations {
+    /// Convert a row of pixels into the component buffers
+    #[inline(always)]
+    fn convert_row(color_type: ColorType, data: &[u8], width: u16, buffers: &mut [Vec<u8>; 4]) {
+        convert_row(color_type, data, width, buffers);
+    }
+
+    /// Get a level shifted block of a component buffer
+    ///
+    /// Components with a lower sampling factor are downsampled by only using every
+    /// `col_stride`th column and `row_stride`th row.
+    #[inline(always)]
+    fn get_block(
+        data: &[u8],
+        start_x: usize,
+        start_y: usize,
+        col_stride: usize,
+        row_stride: usize,
+        width: usize,
+    ) -> [i16; 64] {
+        get_block(data, start_x, start_y, col_stride, row_stride, width)
+    }
+
     #[inline(always)]
     fn fdct(data: &mut [i16; 64]) {
         fdct(data);
@@ -1986,9 +5145,11 @@ impl Operations for DefaultOperations {}
 mod tests {
     use alloc::vec;
 
+    use alloc::vec::Vec;
+
     use crate::encoder::get_num_bits;
     use crate::writer::get_code;
-    use crate::{Encoder, SamplingFactor};
+    use crate::{ArithmeticConditioning, Encoder, JpegColorType, SamplingFactor, ScanScript};
 
     #[test]
     fn test_get_num_bits() {
@@ -2035,5 +5196,136 @@ mod tests {
 
         encoder.set_progressive(false);
         assert_eq!(encoder.progressive_scans(), None);
+
+        encoder.set_scan_script(ScanScript::Mozjpeg);
+        assert_eq!(encoder.scan_script(), Some(&ScanScript::Mozjpeg));
+
+        encoder.set_progressive_scans(3);
+        assert_eq!(encoder.scan_script(), None);
+    }
+
+    #[test]
+    fn test_encode_coefficients() {
+        use crate::encoder::DefaultOperations;
+        use crate::image_buffer::RgbImage;
+        use crate::quantization::QuantizationTable;
+
+        let width = 61;
+        let height = 45;
+
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height))
+            .flat_map(|i| {
+                let x = i % usize::from(width);
+                let y = i / usize::from(width);
+                [(x * 4) as u8, (y * 5) as u8, ((x * y) % 256) as u8]
+            })
+            .collect();
+
+        fn configure(encoder: &mut Encoder<&mut Vec<u8>>, config: usize) {
+            match config {
+                0 => {}
+                1 => encoder.set_optimized_huffman_tables(true),
+                2 => encoder.set_progressive(true),
+                _ => encoder.set_restart_interval(3),
+            }
+        }
+
+        for config in 0..4 {
+            for sampling_factor in [SamplingFactor::F_2_2, SamplingFactor::F_4_1] {
+                let mut expected = Vec::new();
+                let mut encoder = Encoder::new(&mut expected, 80);
+                encoder.set_sampling_factor(sampling_factor);
+                configure(&mut encoder, config);
+
+                // Quantized blocks as created by encode with the default operations
+                encoder.init_components(JpegColorType::Ycbcr);
+
+                let q_tables = [
+                    QuantizationTable::new_with_quality(&encoder.quantization_tables[0], 80, true),
+                    QuantizationTable::new_with_quality(&encoder.quantization_tables[1], 80, false),
+                ];
+
+                let image = RgbImage(&data, width, height);
+                let mut blocks = encoder.transform_blocks::<_, DefaultOperations>(&image);
+                encoder.quantize_coefficients::<DefaultOperations>(&mut blocks, width, &q_tables);
+
+                let blocks: Vec<_> = blocks.into_iter().take(3).collect();
+
+                encoder
+                    .encode_image_internal::<_, DefaultOperations>(image)
+                    .unwrap();
+
+                let mut result = Vec::new();
+                let mut encoder = Encoder::new(&mut result, 80);
+                encoder.set_sampling_factor(sampling_factor);
+                configure(&mut encoder, config);
+                encoder
+                    .encode_coefficients(&blocks, width, height, JpegColorType::Ycbcr)
+                    .unwrap();
+
+                if sampling_factor.supports_interleaved() && config != 1 && config != 2 {
+                    // Sequential images with one scan per component
+                    let decode = |data: &[u8]| jpeg_decoder::Decoder::new(data).decode().unwrap();
+                    assert_eq!(decode(&result), decode(&expected));
+                } else {
+                    assert_eq!(result, expected, "{} {:?}", config, sampling_factor);
+                }
+
+                // The SIMD operations used by encode create slightly different coefficients
+                #[cfg(not(feature = "simd"))]
+                {
+                    let mut encoded = Vec::new();
+                    let mut encoder = Encoder::new(&mut encoded, 80);
+                    encoder.set_sampling_factor(sampling_factor);
+                    configure(&mut encoder, config);
+                    encoder
+                        .encode(&data, width, height, crate::ColorType::Rgb)
+                        .unwrap();
+
+                    assert_eq!(encoded, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_encode_coefficients_errors() {
+        let width = 61;
+        let height = 45;
+
+        let blocks = vec![vec![[0i16; 64]; 8 * 6]];
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder
+            .encode_coefficients(&blocks, width, height, JpegColorType::Luma)
+            .is_ok());
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder
+            .encode_coefficients(&blocks, width, height, JpegColorType::Ycbcr)
+            .is_err());
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder
+            .encode_coefficients(&blocks, width, height + 8, JpegColorType::Luma)
+            .is_err());
+
+        let mut blocks = blocks;
+        blocks[0][5][1] = -1024;
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder
+            .encode_coefficients(&blocks, width, height, JpegColorType::Luma)
+            .is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_set_invalid_arithmetic_conditioning() {
+        let mut encoder = Encoder::new(vec![], 100);
+        encoder.set_arithmetic_conditioning(
+            ArithmeticConditioning::default(),
+            ArithmeticConditioning::new(4, 2, 5),
+        );
     }
 }
@@ -2,6 +2,8 @@ use alloc::fmt::Display;
 #[cfg(feature = "std")]
 use std::error::Error;
 
+use crate::writer::MAX_SEGMENT_LENGTH;
+
 /// # The error type for encoding
 #[derive(Debug)]
 pub enum EncodingError {
@@ -20,6 +22,18 @@ pub enum EncodingError {
     /// Width or height is zero
     ZeroImageDimensions { width: u16, height: u16 },
 
+    /// The progressive scan script violates the rules of T.81
+    InvalidScanScript(alloc::string::String),
+
+    /// The sample precision is not supported
+    InvalidPrecision(u8),
+
+    /// A sample value exceeds the sample precision
+    SampleOutOfRange { value: u16, precision: u8 },
+
+    /// The image can't be encoded with the target size
+    TargetSizeNotReached { target_size: usize, min_size: usize },
+
     /// An io error occurred during writing
     #[cfg(feature = "std")]
     IoError(std::io::Error),
@@ -42,8 +56,8 @@ impl Display for EncodingError {
             InvalidAppSegment(nr) => write!(f, "Invalid app segment number: {}", nr),
             AppSegmentTooLarge(length) => write!(
                 f,
-                "App segment exceeds maximum allowed data length of 65533: {}",
-                length
+                "App segment exceeds maximum allowed data length of {}: {}",
+                MAX_SEGMENT_LENGTH, length
             ),
             IccTooLarge(length) => write!(
                 f,
@@ -58,6 +72,21 @@ impl Display for EncodingError {
             ZeroImageDimensions { width, height } => {
                 write!(f, "Image dimensions must be non zero: {}x{}", width, height)
             }
+            InvalidScanScript(reason) => write!(f, "Invalid scan script: {}", reason),
+            InvalidPrecision(precision) => write!(f, "Unsupported sample precision: {}", precision),
+            SampleOutOfRange { value, precision } => write!(
+                f,
+                "Sample value {} exceeds the sample precision of {} bits",
+                value, precision
+            ),
+            TargetSizeNotReached {
+                target_size,
+                min_size,
+            } => write!(
+                f,
+                "Target size of {} bytes can't be reached, the smallest image has {} bytes",
+                target_size, min_size
+            ),
             #[cfg(feature = "std")]
             IoError(err) => err.fmt(f),
             Write(err) => write!(f, "{}", err),
@@ -0,0 +1,733 @@
+/*
+ * EXIF metadata
+ *
+ * The data is stored as a TIFF structure in an APP1 segment as described in
+ * CIPA DC-008 (Exif 2.32) 4.5 and 4.6.
+ */
+
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::writer::MAX_SEGMENT_LENGTH;
+use crate::{Density, EncodingError};
+
+/// Identifier which precedes the TIFF structure in the APP1 segment
+const EXIF_HEADER: &[u8; 6] = b"Exif\0\0";
+
+// TIFF field types
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const UNDEFINED: u16 = 7;
+
+// Tags of the 0th and 1st IFD
+const COMPRESSION: u16 = 0x0103;
+const MAKE: u16 = 0x010F;
+const MODEL: u16 = 0x0110;
+const ORIENTATION: u16 = 0x0112;
+const X_RESOLUTION: u16 = 0x011A;
+const Y_RESOLUTION: u16 = 0x011B;
+const RESOLUTION_UNIT: u16 = 0x0128;
+const JPEG_INTERCHANGE_FORMAT: u16 = 0x0201;
+const JPEG_INTERCHANGE_FORMAT_LENGTH: u16 = 0x0202;
+const YCBCR_POSITIONING: u16 = 0x0213;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_INFO_IFD_POINTER: u16 = 0x8825;
+
+// Tags of the Exif IFD
+const EXIF_VERSION: u16 = 0x9000;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+
+// Tags of the GPS IFD
+const GPS_VERSION_ID: u16 = 0x0000;
+const GPS_LATITUDE_REF: u16 = 0x0001;
+const GPS_LATITUDE: u16 = 0x0002;
+const GPS_LONGITUDE_REF: u16 = 0x0003;
+const GPS_LONGITUDE: u16 = 0x0004;
+const GPS_ALTITUDE_REF: u16 = 0x0005;
+const GPS_ALTITUDE: u16 = 0x0006;
+
+/// Denominator used for the seconds of GPS coordinates and the altitude
+const GPS_DENOMINATOR: u32 = 1000;
+
+/// Byte order of the TIFF structure of [Exif] data
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum ByteOrder {
+    /// Intel byte order ("II")
+    LittleEndian,
+
+    /// Motorola byte order ("MM")
+    BigEndian,
+}
+
+impl ByteOrder {
+    fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+}
+
+/// # EXIF metadata
+///
+/// Builds the EXIF APP1 segment which is written with [set_exif](crate::Encoder::set_exif).
+///
+/// The primary image IFD always contains the resolution and the YCbCr positioning and
+/// the Exif IFD always contains the Exif version, as these tags are mandatory.
+/// All other tags are only written if they are set.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Exif {
+    byte_order: ByteOrder,
+    orientation: Option<u16>,
+    capture_time: Option<String>,
+    make: Option<String>,
+    model: Option<String>,
+    gps_position: Option<(f64, f64, Option<f64>)>,
+    resolution: Density,
+    thumbnail: Option<Vec<u8>>,
+}
+
+impl Exif {
+    /// Create empty EXIF metadata with the given byte order
+    ///
+    /// The resolution defaults to 72 dpi.
+    pub fn new(byte_order: ByteOrder) -> Exif {
+        Exif {
+            byte_order,
+            orientation: None,
+            capture_time: None,
+            make: None,
+            model: None,
+            gps_position: None,
+            resolution: Density::Inch { x: 72, y: 72 },
+            thumbnail: None,
+        }
+    }
+
+    /// Returns the byte order
+    pub fn byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    /// Set the orientation of the image
+    ///
+    /// The value is in the range between 1 and 8, where 1 is the unrotated image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the orientation is out of range
+    pub fn set_orientation(&mut self, orientation: u16) -> Result<(), EncodingError> {
+        if !(1..=8).contains(&orientation) {
+            return Err(EncodingError::Write(alloc::format!(
+                "Invalid EXIF orientation: {}",
+                orientation
+            )));
+        }
+
+        self.orientation = Some(orientation);
+        Ok(())
+    }
+
+    /// Set the date and time the image was captured
+    ///
+    /// The value has the format `YYYY:MM:DD HH:MM:SS` and is written as DateTimeOriginal.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value doesn't have the required format
+    pub fn set_capture_time(&mut self, date_time: &str) -> Result<(), EncodingError> {
+        let valid = date_time.len() == 19
+            && date_time.bytes().enumerate().all(|(i, b)| match i {
+                4 | 7 | 13 | 16 => b == b':',
+                10 => b == b' ',
+                _ => b.is_ascii_digit(),
+            });
+
+        if !valid {
+            return Err(EncodingError::Write(alloc::format!(
+                "Invalid EXIF date and time: {:?}",
+                date_time
+            )));
+        }
+
+        self.capture_time = Some(date_time.into());
+        Ok(())
+    }
+
+    /// Set the manufacturer of the camera
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value contains non ASCII or NUL characters
+    pub fn set_make(&mut self, make: &str) -> Result<(), EncodingError> {
+        self.make = Some(validate_ascii(make)?);
+        Ok(())
+    }
+
+    /// Set the model of the camera
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the value contains non ASCII or NUL characters
+    pub fn set_model(&mut self, model: &str) -> Result<(), EncodingError> {
+        self.model = Some(validate_ascii(model)?);
+        Ok(())
+    }
+
+    /// Set the GPS position
+    ///
+    /// Latitude and longitude are in degrees, positive values are north and east.
+    /// The optional altitude is in meters above sea level. Seconds and altitude are stored
+    /// with a precision of 1/1000.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value is out of range
+    pub fn set_gps_position(
+        &mut self,
+        latitude: f64,
+        longitude: f64,
+        altitude: Option<f64>,
+    ) -> Result<(), EncodingError> {
+        let max_altitude = f64::from(u32::MAX / GPS_DENOMINATOR);
+
+        if !(-90.0..=90.0).contains(&latitude)
+            || !(-180.0..=180.0).contains(&longitude)
+            || altitude.map_or(false, |a| !(-max_altitude..=max_altitude).contains(&a))
+        {
+            return Err(EncodingError::Write(alloc::format!(
+                "Invalid GPS position: {}, {}, {:?}",
+                latitude,
+                longitude,
+                altitude
+            )));
+        }
+
+        self.gps_position = Some((latitude, longitude, altitude));
+        Ok(())
+    }
+
+    /// Set the resolution of the image
+    ///
+    /// [Density::None] is written without an absolute unit.
+    pub fn set_resolution(&mut self, resolution: Density) {
+        self.resolution = resolution;
+    }
+
+    /// Set a JPEG encoded thumbnail
+    ///
+    /// The thumbnail is written in the 1st IFD and must fit into the APP1 segment together
+    /// with all other tags. The EXIF standard recommends a size of 160x120 pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data doesn't start with a SOI marker
+    pub fn set_thumbnail(&mut self, jpeg: &[u8]) -> Result<(), EncodingError> {
+        if !jpeg.starts_with(&[0xFF, 0xD8]) {
+            return Err(EncodingError::Write(
+                "EXIF thumbnail must be a JPEG image".into(),
+            ));
+        }
+
+        self.thumbnail = Some(jpeg.to_vec());
+        Ok(())
+    }
+
+    /// Returns the data of the APP1 segment
+    ///
+    /// The IFDs are written in the order 0th IFD, Exif IFD, GPS IFD and 1st IFD,
+    /// each followed by its values, and the thumbnail data at the end.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the data exceeds the maximum length of an APP segment
+    pub fn to_bytes(&self) -> Result<Vec<u8>, EncodingError> {
+        let mut ifd0 = Ifd::new(self.byte_order);
+
+        if let Some(make) = &self.make {
+            ifd0.ascii(MAKE, make);
+        }
+
+        if let Some(model) = &self.model {
+            ifd0.ascii(MODEL, model);
+        }
+
+        if let Some(orientation) = self.orientation {
+            ifd0.short(ORIENTATION, orientation);
+        }
+
+        ifd0.resolution(self.resolution);
+        // Centered chroma samples as in T.81
+        ifd0.short(YCBCR_POSITIONING, 1);
+        ifd0.long(EXIF_IFD_POINTER, 0);
+
+        let mut exif_ifd = Ifd::new(self.byte_order);
+        exif_ifd.entry(EXIF_VERSION, UNDEFINED, 4, b"0232".to_vec());
+
+        if let Some(date_time) = &self.capture_time {
+            exif_ifd.ascii(DATE_TIME_ORIGINAL, date_time);
+        }
+
+        let gps_ifd = self.gps_position.map(|(latitude, longitude, altitude)| {
+            let mut ifd = Ifd::new(self.byte_order);
+            ifd.entry(GPS_VERSION_ID, BYTE, 4, [2, 3, 0, 0].to_vec());
+            ifd.ascii(GPS_LATITUDE_REF, if latitude < 0.0 { "S" } else { "N" });
+            ifd.rationals(GPS_LATITUDE, &degrees_to_rationals(latitude));
+            ifd.ascii(GPS_LONGITUDE_REF, if longitude < 0.0 { "W" } else { "E" });
+            ifd.rationals(GPS_LONGITUDE, &degrees_to_rationals(longitude));
+
+            if let Some(altitude) = altitude {
+                ifd.entry(
+                    GPS_ALTITUDE_REF,
+                    BYTE,
+                    1,
+                    [u8::from(altitude < 0.0)].to_vec(),
+                );
+                ifd.rationals(GPS_ALTITUDE, &[(to_fixed_point(altitude), GPS_DENOMINATOR)]);
+            }
+
+            ifd
+        });
+
+        if gps_ifd.is_some() {
+            ifd0.long(GPS_INFO_IFD_POINTER, 0);
+        }
+
+        let ifd1 = self.thumbnail.as_ref().map(|thumbnail| {
+            let mut ifd = Ifd::new(self.byte_order);
+            // JPEG compression
+            ifd.short(COMPRESSION, 6);
+            ifd.resolution(self.resolution);
+            ifd.long(JPEG_INTERCHANGE_FORMAT, 0);
+            ifd.long(JPEG_INTERCHANGE_FORMAT_LENGTH, thumbnail.len() as u32);
+            ifd
+        });
+
+        // Offsets are relative to the start of the TIFF header
+        let exif_offset = 8 + ifd0.len();
+        let gps_offset = exif_offset + exif_ifd.len();
+        let ifd1_offset = gps_offset + gps_ifd.as_ref().map_or(0, Ifd::len);
+        let thumbnail_offset = ifd1_offset + ifd1.as_ref().map_or(0, Ifd::len);
+
+        let length = EXIF_HEADER.len()
+            + thumbnail_offset
+            + self
+                .thumbnail
+                .as_ref()
+                .map_or(0, |thumbnail| thumbnail.len());
+
+        if length > MAX_SEGMENT_LENGTH {
+            return Err(EncodingError::AppSegmentTooLarge(length));
+        }
+
+        let mut data = Vec::with_capacity(length);
+        data.extend_from_slice(EXIF_HEADER);
+
+        data.extend_from_slice(match self.byte_order {
+            ByteOrder::LittleEndian => b"II",
+            ByteOrder::BigEndian => b"MM",
+        });
+        data.extend_from_slice(&self.byte_order.u16_bytes(42));
+        data.extend_from_slice(&self.byte_order.u32_bytes(8));
+
+        ifd0.long(EXIF_IFD_POINTER, exif_offset as u32);
+
+        if gps_ifd.is_some() {
+            ifd0.long(GPS_INFO_IFD_POINTER, gps_offset as u32);
+        }
+
+        let next_ifd = if ifd1.is_some() { ifd1_offset } else { 0 };
+
+        ifd0.write(&mut data, 8, next_ifd);
+        exif_ifd.write(&mut data, exif_offset, 0);
+
+        if let Some(gps_ifd) = &gps_ifd {
+            gps_ifd.write(&mut data, gps_offset, 0);
+        }
+
+        if let (Some(mut ifd1), Some(thumbnail)) = (ifd1, &self.thumbnail) {
+            ifd1.long(JPEG_INTERCHANGE_FORMAT, thumbnail_offset as u32);
+            ifd1.write(&mut data, ifd1_offset, 0);
+
+            data.extend_from_slice(thumbnail);
+        }
+
+        debug_assert_eq!(data.len(), length);
+
+        Ok(data)
+    }
+}
+
+fn validate_ascii(value: &str) -> Result<String, EncodingError> {
+    if value.bytes().all(|b| b.is_ascii() && b != 0) {
+        Ok(value.into())
+    } else {
+        Err(EncodingError::Write(alloc::format!(
+            "EXIF value must only contain ASCII characters: {:?}",
+            value
+        )))
+    }
+}
+
+/// Converts the absolute value to a fixed point number with [GPS_DENOMINATOR]
+fn to_fixed_point(value: f64) -> u32 {
+    let value = if value < 0.0 { -value } else { value };
+    (value * f64::from(GPS_DENOMINATOR) + 0.5) as u32
+}
+
+/// Converts the absolute value of a coordinate to degrees, minutes and seconds
+///
+/// The value is rounded to whole fractions of a second before it is split, so seconds
+/// which round up to a full minute carry into the minutes and degrees.
+fn degrees_to_rationals(value: f64) -> [(u32, u32); 3] {
+    let value = if value < 0.0 { -value } else { value };
+
+    let seconds_per_minute = 60 * GPS_DENOMINATOR;
+    let seconds_per_degree = 60 * seconds_per_minute;
+
+    let total = to_fixed_point(value * 3600.0);
+
+    [
+        (total / seconds_per_degree, 1),
+        (total % seconds_per_degree / seconds_per_minute, 1),
+        (total % seconds_per_minute, GPS_DENOMINATOR),
+    ]
+}
+
+struct IfdEntry {
+    tag: u16,
+    field_type: u16,
+    count: u32,
+    value: Vec<u8>,
+}
+
+/// Image file directory with values in the byte order of the TIFF structure
+struct Ifd {
+    byte_order: ByteOrder,
+    entries: Vec<IfdEntry>,
+}
+
+impl Ifd {
+    fn new(byte_order: ByteOrder) -> Ifd {
+        Ifd {
+            byte_order,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds an entry or replaces the entry with the same tag
+    fn entry(&mut self, tag: u16, field_type: u16, count: u32, value: Vec<u8>) {
+        let entry = IfdEntry {
+            tag,
+            field_type,
+            count,
+            value,
+        };
+
+        // Entries must be sorted in ascending order of their tags
+        match self.entries.binary_search_by_key(&tag, |e| e.tag) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    fn ascii(&mut self, tag: u16, value: &str) {
+        let mut bytes = Vec::with_capacity(value.len() + 1);
+        bytes.extend_from_slice(value.as_bytes());
+        bytes.push(0);
+
+        self.entry(tag, ASCII, bytes.len() as u32, bytes);
+    }
+
+    fn short(&mut self, tag: u16, value: u16) {
+        let bytes = self.byte_order.u16_bytes(value).to_vec();
+        self.entry(tag, SHORT, 1, bytes);
+    }
+
+    fn long(&mut self, tag: u16, value: u32) {
+        let bytes = self.byte_order.u32_bytes(value).to_vec();
+        self.entry(tag, LONG, 1, bytes);
+    }
+
+    fn rationals(&mut self, tag: u16, values: &[(u32, u32)]) {
+        let mut bytes = Vec::with_capacity(values.len() * 8);
+
+        for &(numerator, denominator) in values {
+            bytes.extend_from_slice(&self.byte_order.u32_bytes(numerator));
+            bytes.extend_from_slice(&self.byte_order.u32_bytes(denominator));
+        }
+
+        self.entry(tag, RATIONAL, values.len() as u32, bytes);
+    }
+
+    fn resolution(&mut self, density: Density) {
+        let (unit, x, y) = match density {
+            Density::None => (1, 1, 1),
+            Density::Inch { x, y } => (2, x, y),
+            Density::Centimeter { x, y } => (3, x, y),
+        };
+
+        self.rationals(X_RESOLUTION, &[(u32::from(x), 1)]);
+        self.rationals(Y_RESOLUTION, &[(u32::from(y), 1)]);
+        self.short(RESOLUTION_UNIT, unit);
+    }
+
+    /// Length of the IFD including the values which don't fit into the entries
+    fn len(&self) -> usize {
+        let values: usize = self
+            .entries
+            .iter()
+            .filter(|e| e.value.len() > 4)
+            .map(|e| e.value.len() + e.value.len() % 2)
+            .sum();
+
+        2 + self.entries.len() * 12 + 4 + values
+    }
+
+    /// Appends the IFD which starts at `offset` in the TIFF structure
+    fn write(&self, data: &mut Vec<u8>, offset: usize, next_ifd: usize) {
+        let byte_order = self.byte_order;
+        let mut value_offset = offset + 2 + self.entries.len() * 12 + 4;
+
+        data.extend_from_slice(&byte_order.u16_bytes(self.entries.len() as u16));
+
+        for entry in &self.entries {
+            data.extend_from_slice(&byte_order.u16_bytes(entry.tag));
+            data.extend_from_slice(&byte_order.u16_bytes(entry.field_type));
+            data.extend_from_slice(&byte_order.u32_bytes(entry.count));
+
+            if entry.value.len() > 4 {
+                data.extend_from_slice(&byte_order.u32_bytes(value_offset as u32));
+                value_offset += entry.value.len() + entry.value.len() % 2;
+            } else {
+                // Values up to 4 bytes are stored left aligned in the entry
+                let mut value = [0; 4];
+                value[..entry.value.len()].copy_from_slice(&entry.value);
+                data.extend_from_slice(&value);
+            }
+        }
+
+        data.extend_from_slice(&byte_order.u32_bytes(next_ifd as u32));
+
+        // Values start on word boundaries
+        for entry in self.entries.iter().filter(|e| e.value.len() > 4) {
+            data.extend_from_slice(&entry.value);
+
+            if entry.value.len() % 2 != 0 {
+                data.push(0);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Returns the tag, type, count and value or offset field of all entries of an IFD
+    fn read_ifd(tiff: &[u8], offset: usize) -> Vec<(u16, u16, u32, [u8; 4])> {
+        let u16_at = |i: usize| u16::from_be_bytes([tiff[i], tiff[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_be_bytes([tiff[i], tiff[i + 1], tiff[i + 2], tiff[i + 3]]);
+
+        (0..usize::from(u16_at(offset)))
+            .map(|i| {
+                let entry = offset + 2 + i * 12;
+                let mut value = [0; 4];
+                value.copy_from_slice(&tiff[entry + 8..entry + 12]);
+                (u16_at(entry), u16_at(entry + 2), u32_at(entry + 4), value)
+            })
+            .collect()
+    }
+
+    fn rationals_at(tiff: &[u8], offset: [u8; 4], count: usize) -> Vec<(u32, u32)> {
+        let offset = u32::from_be_bytes(offset) as usize;
+
+        tiff[offset..offset + count * 8]
+            .chunks(4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>()
+            .chunks(2)
+            .map(|r| (r[0], r[1]))
+            .collect()
+    }
+
+    #[test]
+    fn test_exif_little_endian() {
+        let mut exif = Exif::new(ByteOrder::LittleEndian);
+        exif.set_orientation(6).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            b'E', b'x', b'i', b'f', 0, 0,
+            b'I', b'I', 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00,
+            // 0th IFD
+            0x06, 0x00,
+            0x12, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x00, 0x00, 0x00,
+            0x1A, 0x01, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x56, 0x00, 0x00, 0x00,
+            0x1B, 0x01, 0x05, 0x00, 0x01, 0x00, 0x00, 0x00, 0x5E, 0x00, 0x00, 0x00,
+            0x28, 0x01, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00,
+            0x13, 0x02, 0x03, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x69, 0x87, 0x04, 0x00, 0x01, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+            0x48, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            0x48, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00,
+            // Exif IFD
+            0x01, 0x00,
+            0x00, 0x90, 0x07, 0x00, 0x04, 0x00, 0x00, 0x00, b'0', b'2', b'3', b'2',
+            0x00, 0x00, 0x00, 0x00,
+        ];
+
+        assert_eq!(exif.to_bytes().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_exif_big_endian() {
+        let thumbnail = [0xFF, 0xD8, 0x01, 0x02, 0x03, 0xFF, 0xD9];
+
+        let mut exif = Exif::new(ByteOrder::BigEndian);
+        exif.set_make("LG").unwrap();
+        exif.set_model("Camera").unwrap();
+        exif.set_capture_time("2024:05:17 13:45:09").unwrap();
+        exif.set_gps_position(48.8584, -2.2945, Some(-10.5))
+            .unwrap();
+        exif.set_resolution(Density::Centimeter { x: 100, y: 50 });
+        exif.set_thumbnail(&thumbnail).unwrap();
+
+        let data = exif.to_bytes().unwrap();
+        assert_eq!(&data[..6], EXIF_HEADER);
+
+        let tiff = &data[6..];
+        assert_eq!(
+            &tiff[..8],
+            &[b'M', b'M', 0x00, 0x2A, 0x00, 0x00, 0x00, 0x08]
+        );
+
+        let ifd0 = read_ifd(tiff, 8);
+        let tags: Vec<u16> = ifd0.iter().map(|e| e.0).collect();
+        assert_eq!(
+            tags,
+            [
+                MAKE,
+                MODEL,
+                X_RESOLUTION,
+                Y_RESOLUTION,
+                RESOLUTION_UNIT,
+                YCBCR_POSITIONING,
+                EXIF_IFD_POINTER,
+                GPS_INFO_IFD_POINTER
+            ]
+        );
+
+        assert_eq!(ifd0[0], (MAKE, ASCII, 3, *b"LG\0\0"));
+        assert_eq!(ifd0[1].2, 7);
+        let model = u32::from_be_bytes(ifd0[1].3) as usize;
+        assert_eq!(&tiff[model..model + 7], b"Camera\0");
+
+        assert_eq!(rationals_at(tiff, ifd0[2].3, 1), [(100, 1)]);
+        assert_eq!(rationals_at(tiff, ifd0[3].3, 1), [(50, 1)]);
+        assert_eq!(ifd0[4].3, [0x00, 0x03, 0x00, 0x00]);
+
+        let exif_ifd = read_ifd(tiff, u32::from_be_bytes(ifd0[6].3) as usize);
+        assert_eq!(exif_ifd.len(), 2);
+        assert_eq!(exif_ifd[0], (EXIF_VERSION, UNDEFINED, 4, *b"0232"));
+        assert_eq!(exif_ifd[1].0, DATE_TIME_ORIGINAL);
+        let date_time = u32::from_be_bytes(exif_ifd[1].3) as usize;
+        assert_eq!(&tiff[date_time..date_time + 20], b"2024:05:17 13:45:09\0");
+
+        let gps_ifd = read_ifd(tiff, u32::from_be_bytes(ifd0[7].3) as usize);
+        assert_eq!(gps_ifd.len(), 7);
+        assert_eq!(gps_ifd[0], (GPS_VERSION_ID, BYTE, 4, [2, 3, 0, 0]));
+        assert_eq!(gps_ifd[1], (GPS_LATITUDE_REF, ASCII, 2, [b'N', 0, 0, 0]));
+        assert_eq!(
+            rationals_at(tiff, gps_ifd[2].3, 3),
+            [(48, 1), (51, 1), (30240, 1000)]
+        );
+        assert_eq!(gps_ifd[3], (GPS_LONGITUDE_REF, ASCII, 2, [b'W', 0, 0, 0]));
+        assert_eq!(
+            rationals_at(tiff, gps_ifd[4].3, 3),
+            [(2, 1), (17, 1), (40200, 1000)]
+        );
+        assert_eq!(gps_ifd[5], (GPS_ALTITUDE_REF, BYTE, 1, [1, 0, 0, 0]));
+        assert_eq!(rationals_at(tiff, gps_ifd[6].3, 1), [(10500, 1000)]);
+
+        // The offset of the 1st IFD follows the entries of the 0th IFD
+        let next_ifd = 8 + 2 + ifd0.len() * 12;
+        let ifd1 = read_ifd(
+            tiff,
+            u32::from_be_bytes(tiff[next_ifd..next_ifd + 4].try_into().unwrap()) as usize,
+        );
+        assert_eq!(ifd1[0], (COMPRESSION, SHORT, 1, [0x00, 0x06, 0x00, 0x00]));
+        assert_eq!(ifd1[4].0, JPEG_INTERCHANGE_FORMAT);
+        assert_eq!(
+            ifd1[5],
+            (JPEG_INTERCHANGE_FORMAT_LENGTH, LONG, 1, [0, 0, 0, 7])
+        );
+
+        let offset = u32::from_be_bytes(ifd1[4].3) as usize;
+        assert_eq!(&tiff[offset..], thumbnail);
+    }
+
+    #[test]
+    fn test_degrees_to_rationals() {
+        assert_eq!(
+            degrees_to_rationals(52.5),
+            [(52, 1), (30, 1), (0, GPS_DENOMINATOR)]
+        );
+        assert_eq!(
+            degrees_to_rationals(-13.404954),
+            [(13, 1), (24, 1), (17834, GPS_DENOMINATOR)]
+        );
+
+        // Seconds just below a whole minute carry into the minutes and degrees
+        assert_eq!(
+            degrees_to_rationals(10.0 + 59.9996 / 3600.0),
+            [(10, 1), (1, 1), (0, GPS_DENOMINATOR)]
+        );
+        assert_eq!(
+            degrees_to_rationals(10.0 + 59.0 / 60.0 + 59.9996 / 3600.0),
+            [(11, 1), (0, 1), (0, GPS_DENOMINATOR)]
+        );
+    }
+
+    #[test]
+    fn test_exif_invalid_values() {
+        let mut exif = Exif::new(ByteOrder::LittleEndian);
+
+        assert!(exif.set_orientation(0).is_err());
+        assert!(exif.set_orientation(9).is_err());
+        assert!(exif.set_capture_time("2024-05-17 13:45:09").is_err());
+        assert!(exif.set_capture_time("2024:05:17").is_err());
+        assert!(exif.set_make("Caméra").is_err());
+        assert!(exif.set_model("Camera\0").is_err());
+        assert!(exif.set_gps_position(90.5, 0.0, None).is_err());
+        assert!(exif.set_gps_position(0.0, -180.5, None).is_err());
+        assert!(exif.set_gps_position(f64::NAN, 0.0, None).is_err());
+        assert!(exif
+            .set_gps_position(0.0, 0.0, Some(f64::INFINITY))
+            .is_err());
+        assert!(exif.set_thumbnail(&[0x00, 0x01]).is_err());
+
+        assert_eq!(exif, Exif::new(ByteOrder::LittleEndian));
+
+        let mut thumbnail = vec![0xFF, 0xD8];
+        thumbnail.resize(65500, 0);
+        exif.set_thumbnail(&thumbnail).unwrap();
+
+        assert!(matches!(
+            exif.to_bytes(),
+            Err(EncodingError::AppSegmentTooLarge(_))
+        ));
+    }
+}
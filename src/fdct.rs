@@ -71,9 +71,16 @@
  * scaled fixed-point arithmetic, with a minimal number of shifts.
  */
 
+use core::ops::{Add, Mul, Shl, Shr, Sub};
+
 const CONST_BITS: i32 = 13;
+
+/// Pass 1 scaling for 8 bit samples
 const PASS1_BITS: i32 = 2;
 
+/// Pass 1 scaling for 12 bit samples to avoid overflows of the intermediate results
+const PASS1_BITS_WIDE: i32 = 1;
+
 const FIX_0_298631336: i32 = 2446;
 const FIX_0_390180644: i32 = 3196;
 const FIX_0_541196100: i32 = 4433;
@@ -90,36 +97,99 @@ const FIX_3_072711026: i32 = 25172;
 const DCT_SIZE: usize = 8;
 
 #[inline(always)]
-fn descale(x: i32, n: i32) -> i32 {
+fn descale<T: Arithmetic>(x: T, n: i32) -> T {
     // right shift with rounding
-    (x + (1 << (n - 1))) >> n
+    (x + (T::from(1) << (n - 1))) >> n
 }
 
-#[inline(always)]
-fn into_el(v: i32) -> i16 {
-    v as i16
+/// Type used for the intermediate results
+trait Arithmetic:
+    Copy
+    + From<i32>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Shl<i32, Output = Self>
+    + Shr<i32, Output = Self>
+{
+}
+
+impl Arithmetic for i32 {}
+
+impl Arithmetic for i64 {}
+
+/// Element of a DCT block
+trait Element: Copy {
+    type Arithmetic: Arithmetic;
+
+    fn to_arithmetic(self) -> Self::Arithmetic;
+    fn from_arithmetic(v: Self::Arithmetic) -> Self;
+}
+
+impl Element for i16 {
+    type Arithmetic = i32;
+
+    #[inline(always)]
+    fn to_arithmetic(self) -> i32 {
+        i32::from(self)
+    }
+
+    #[inline(always)]
+    fn from_arithmetic(v: i32) -> i16 {
+        v as i16
+    }
+}
+
+// Libjpeg uses 64 bit arithmetic for 12 bit samples on 64 bit platforms to avoid overflows
+impl Element for i32 {
+    type Arithmetic = i64;
+
+    #[inline(always)]
+    fn to_arithmetic(self) -> i64 {
+        i64::from(self)
+    }
+
+    #[inline(always)]
+    fn from_arithmetic(v: i64) -> i32 {
+        v as i32
+    }
+}
+
+/// Forward DCT for 8 bit samples
+pub fn fdct(data: &mut [i16; 64]) {
+    fdct_generic::<_, PASS1_BITS>(data);
+}
+
+/// Forward DCT for 12 bit samples
+///
+/// The results of a DCT of 12 bit samples don't fit into 16 bits.
+pub fn fdct_wide(data: &mut [i32; 64]) {
+    fdct_generic::<_, PASS1_BITS_WIDE>(data);
 }
 
 #[allow(clippy::erasing_op)]
 #[allow(clippy::identity_op)]
-pub fn fdct(data: &mut [i16; 64]) {
+#[inline(always)]
+fn fdct_generic<E: Element, const PASS1_BITS: i32>(data: &mut [E; 64]) {
     /* Pass 1: process rows. */
     /* Note results are scaled up by sqrt(8) compared to a true DCT; */
     /* furthermore, we scale the results by 2**PASS1_BITS. */
 
-    let mut data2 = [0i32; 64];
+    let c = E::Arithmetic::from;
+
+    let mut data2 = [c(0); 64];
 
     for y in 0..8 {
         let offset = y * 8;
 
-        let tmp0 = i32::from(data[offset + 0]) + i32::from(data[offset + 7]);
-        let tmp7 = i32::from(data[offset + 0]) - i32::from(data[offset + 7]);
-        let tmp1 = i32::from(data[offset + 1]) + i32::from(data[offset + 6]);
-        let tmp6 = i32::from(data[offset + 1]) - i32::from(data[offset + 6]);
-        let tmp2 = i32::from(data[offset + 2]) + i32::from(data[offset + 5]);
-        let tmp5 = i32::from(data[offset + 2]) - i32::from(data[offset + 5]);
-        let tmp3 = i32::from(data[offset + 3]) + i32::from(data[offset + 4]);
-        let tmp4 = i32::from(data[offset + 3]) - i32::from(data[offset + 4]);
+        let tmp0 = data[offset + 0].to_arithmetic() + data[offset + 7].to_arithmetic();
+        let tmp7 = data[offset + 0].to_arithmetic() - data[offset + 7].to_arithmetic();
+        let tmp1 = data[offset + 1].to_arithmetic() + data[offset + 6].to_arithmetic();
+        let tmp6 = data[offset + 1].to_arithmetic() - data[offset + 6].to_arithmetic();
+        let tmp2 = data[offset + 2].to_arithmetic() + data[offset + 5].to_arithmetic();
+        let tmp5 = data[offset + 2].to_arithmetic() - data[offset + 5].to_arithmetic();
+        let tmp3 = data[offset + 3].to_arithmetic() + data[offset + 4].to_arithmetic();
+        let tmp4 = data[offset + 3].to_arithmetic() - data[offset + 4].to_arithmetic();
 
         /* Even part per LL&M figure 1 --- note that published figure is faulty;
          * rotator "sqrt(2)*c1" should be "sqrt(2)*c6".
@@ -133,9 +203,9 @@ pub fn fdct(data: &mut [i16; 64]) {
         data2[offset + 0] = (tmp10 + tmp11) << PASS1_BITS;
         data2[offset + 4] = (tmp10 - tmp11) << PASS1_BITS;
 
-        let z1 = (tmp12 + tmp13) * FIX_0_541196100;
-        data2[offset + 2] = descale(z1 + (tmp13 * FIX_0_765366865), CONST_BITS - PASS1_BITS);
-        data2[offset + 6] = descale(z1 + (tmp12 * -FIX_1_847759065), CONST_BITS - PASS1_BITS);
+        let z1 = (tmp12 + tmp13) * c(FIX_0_541196100);
+        data2[offset + 2] = descale(z1 + (tmp13 * c(FIX_0_765366865)), CONST_BITS - PASS1_BITS);
+        data2[offset + 6] = descale(z1 + (tmp12 * c(-FIX_1_847759065)), CONST_BITS - PASS1_BITS);
 
         /* Odd part per figure 8 --- note paper omits factor of sqrt(2).
          * cK represents cos(K*pi/16).
@@ -146,16 +216,16 @@ pub fn fdct(data: &mut [i16; 64]) {
         let z2 = tmp5 + tmp6;
         let z3 = tmp4 + tmp6;
         let z4 = tmp5 + tmp7;
-        let z5 = (z3 + z4) * FIX_1_175875602; /* sqrt(2) * c3 */
+        let z5 = (z3 + z4) * c(FIX_1_175875602); /* sqrt(2) * c3 */
 
-        let tmp4 = tmp4 * FIX_0_298631336; /* sqrt(2) * (-c1+c3+c5-c7) */
-        let tmp5 = tmp5 * FIX_2_053119869; /* sqrt(2) * ( c1+c3-c5+c7) */
-        let tmp6 = tmp6 * FIX_3_072711026; /* sqrt(2) * ( c1+c3+c5-c7) */
-        let tmp7 = tmp7 * FIX_1_501321110; /* sqrt(2) * ( c1+c3-c5-c7) */
-        let z1 = z1 * -FIX_0_899976223; /* sqrt(2) * ( c7-c3) */
-        let z2 = z2 * -FIX_2_562915447; /* sqrt(2) * (-c1-c3) */
-        let z3 = z3 * -FIX_1_961570560; /* sqrt(2) * (-c3-c5) */
-        let z4 = z4 * -FIX_0_390180644; /* sqrt(2) * ( c5-c3) */
+        let tmp4 = tmp4 * c(FIX_0_298631336); /* sqrt(2) * (-c1+c3+c5-c7) */
+        let tmp5 = tmp5 * c(FIX_2_053119869); /* sqrt(2) * ( c1+c3-c5+c7) */
+        let tmp6 = tmp6 * c(FIX_3_072711026); /* sqrt(2) * ( c1+c3+c5-c7) */
+        let tmp7 = tmp7 * c(FIX_1_501321110); /* sqrt(2) * ( c1+c3-c5-c7) */
+        let z1 = z1 * c(-FIX_0_899976223); /* sqrt(2) * ( c7-c3) */
+        let z2 = z2 * c(-FIX_2_562915447); /* sqrt(2) * (-c1-c3) */
+        let z3 = z3 * c(-FIX_1_961570560); /* sqrt(2) * (-c3-c5) */
+        let z4 = z4 * c(-FIX_0_390180644); /* sqrt(2) * ( c5-c3) */
 
         let z3 = z3 + z5;
         let z4 = z4 + z5;
@@ -190,16 +260,16 @@ pub fn fdct(data: &mut [i16; 64]) {
         let tmp11 = tmp1 + tmp2;
         let tmp12 = tmp1 - tmp2;
 
-        data[DCT_SIZE * 0 + x] = into_el(descale(tmp10 + tmp11, PASS1_BITS));
-        data[DCT_SIZE * 4 + x] = into_el(descale(tmp10 - tmp11, PASS1_BITS));
+        data[DCT_SIZE * 0 + x] = E::from_arithmetic(descale(tmp10 + tmp11, PASS1_BITS));
+        data[DCT_SIZE * 4 + x] = E::from_arithmetic(descale(tmp10 - tmp11, PASS1_BITS));
 
-        let z1 = (tmp12 + tmp13) * FIX_0_541196100;
-        data[DCT_SIZE * 2 + x] = into_el(descale(
-            z1 + tmp13 * FIX_0_765366865,
+        let z1 = (tmp12 + tmp13) * c(FIX_0_541196100);
+        data[DCT_SIZE * 2 + x] = E::from_arithmetic(descale(
+            z1 + tmp13 * c(FIX_0_765366865),
             CONST_BITS + PASS1_BITS,
         ));
-        data[DCT_SIZE * 6 + x] = into_el(descale(
-            z1 + tmp12 * -FIX_1_847759065,
+        data[DCT_SIZE * 6 + x] = E::from_arithmetic(descale(
+            z1 + tmp12 * c(-FIX_1_847759065),
             CONST_BITS + PASS1_BITS,
         ));
 
@@ -212,24 +282,28 @@ pub fn fdct(data: &mut [i16; 64]) {
         let z2 = tmp5 + tmp6;
         let z3 = tmp4 + tmp6;
         let z4 = tmp5 + tmp7;
-        let z5 = (z3 + z4) * FIX_1_175875602; /* sqrt(2) * c3 */
+        let z5 = (z3 + z4) * c(FIX_1_175875602); /* sqrt(2) * c3 */
 
-        let tmp4 = tmp4 * FIX_0_298631336; /* sqrt(2) * (-c1+c3+c5-c7) */
-        let tmp5 = tmp5 * FIX_2_053119869; /* sqrt(2) * ( c1+c3-c5+c7) */
-        let tmp6 = tmp6 * FIX_3_072711026; /* sqrt(2) * ( c1+c3+c5-c7) */
-        let tmp7 = tmp7 * FIX_1_501321110; /* sqrt(2) * ( c1+c3-c5-c7) */
-        let z1 = z1 * -FIX_0_899976223; /* sqrt(2) * ( c7-c3) */
-        let z2 = z2 * -FIX_2_562915447; /* sqrt(2) * (-c1-c3) */
-        let z3 = z3 * -FIX_1_961570560; /* sqrt(2) * (-c3-c5) */
-        let z4 = z4 * -FIX_0_390180644; /* sqrt(2) * ( c5-c3) */
+        let tmp4 = tmp4 * c(FIX_0_298631336); /* sqrt(2) * (-c1+c3+c5-c7) */
+        let tmp5 = tmp5 * c(FIX_2_053119869); /* sqrt(2) * ( c1+c3-c5+c7) */
+        let tmp6 = tmp6 * c(FIX_3_072711026); /* sqrt(2) * ( c1+c3+c5-c7) */
+        let tmp7 = tmp7 * c(FIX_1_501321110); /* sqrt(2) * ( c1+c3-c5-c7) */
+        let z1 = z1 * c(-FIX_0_899976223); /* sqrt(2) * ( c7-c3) */
+        let z2 = z2 * c(-FIX_2_562915447); /* sqrt(2) * (-c1-c3) */
+        let z3 = z3 * c(-FIX_1_961570560); /* sqrt(2) * (-c3-c5) */
+        let z4 = z4 * c(-FIX_0_390180644); /* sqrt(2) * ( c5-c3) */
 
         let z3 = z3 + z5;
         let z4 = z4 + z5;
 
-        data[DCT_SIZE * 7 + x] = into_el(descale(tmp4 + z1 + z3, CONST_BITS + PASS1_BITS));
-        data[DCT_SIZE * 5 + x] = into_el(descale(tmp5 + z2 + z4, CONST_BITS + PASS1_BITS));
-        data[DCT_SIZE * 3 + x] = into_el(descale(tmp6 + z2 + z3, CONST_BITS + PASS1_BITS));
-        data[DCT_SIZE * 1 + x] = into_el(descale(tmp7 + z1 + z4, CONST_BITS + PASS1_BITS));
+        data[DCT_SIZE * 7 + x] =
+            E::from_arithmetic(descale(tmp4 + z1 + z3, CONST_BITS + PASS1_BITS));
+        data[DCT_SIZE * 5 + x] =
+            E::from_arithmetic(descale(tmp5 + z2 + z4, CONST_BITS + PASS1_BITS));
+        data[DCT_SIZE * 3 + x] =
+            E::from_arithmetic(descale(tmp6 + z2 + z3, CONST_BITS + PASS1_BITS));
+        data[DCT_SIZE * 1 + x] =
+            E::from_arithmetic(descale(tmp7 + z1 + z4, CONST_BITS + PASS1_BITS));
     }
 }
 
@@ -238,7 +312,7 @@ mod tests {
 
     // Inputs and outputs are taken from libjpegs jpeg_fdct_islow for a typical image
 
-    use super::fdct;
+    use super::{fdct, fdct_wide};
 
     const INPUT1: [i16; 64] = [
         -70, -71, -70, -68, -67, -67, -67, -67, -72, -73, -72, -70, -69, -69, -68, -69, -75, -76,
@@ -269,12 +343,42 @@ mod tests {
 
     #[test]
     pub fn test_fdct_libjpeg() {
-        let mut i1 = INPUT1.clone();
+        let mut i1 = INPUT1;
         fdct(&mut i1);
         assert_eq!(i1, OUTPUT1);
 
-        let mut i2 = INPUT2.clone();
+        let mut i2 = INPUT2;
         fdct(&mut i2);
         assert_eq!(i2, OUTPUT2);
     }
+
+    #[test]
+    pub fn test_fdct_wide() {
+        // Scaled 8 bit inputs must result in scaled outputs apart from rounding errors
+        for (input, output) in [(INPUT1, OUTPUT1), (INPUT2, OUTPUT2)] {
+            let mut wide = input.map(|v| i32::from(v) * 16);
+            fdct_wide(&mut wide);
+
+            for (&w, &o) in wide.iter().zip(output.iter()) {
+                assert!((w - i32::from(o) * 16).abs() <= 16, "{} {}", w, o);
+            }
+        }
+
+        // Extreme values of 12 bit samples
+        let mut wide = [-2048; 64];
+        fdct_wide(&mut wide);
+        assert_eq!(wide[0], -2048 * 64);
+        assert!(wide[1..].iter().all(|&v| v == 0));
+
+        let mut wide = [0; 64];
+        for (i, v) in wide.iter_mut().enumerate() {
+            *v = if (i + i / 8) % 2 == 0 { 2047 } else { -2048 };
+        }
+        fdct_wide(&mut wide);
+
+        // Reference values of a floating point dct scaled by 8
+        assert_eq!(wide[0], -32);
+        assert!((wide[9] - 4257).abs() <= 2, "{}", wide[9]);
+        assert!((wide[63] - 107593).abs() <= 2, "{}", wide[63]);
+    }
 }
@@ -63,6 +63,7 @@ static DEFAULT_CHROMA_AC_VALUES: [u8; 162] = [
     0xF9, 0xFA,
 ];
 
+#[derive(Clone)]
 pub struct HuffmanTable {
     lookup_table: [(u8, u16); 256],
     length: [u8; 16],
@@ -227,6 +228,12 @@ impl HuffmanTable {
         res
     }
 
+    /// Returns the code length of a value or 0 if the value isn't part of the table
+    #[inline]
+    pub fn code_length(&self, value: u8) -> u8 {
+        self.lookup_table[value as usize].0
+    }
+
     pub fn length(&self) -> &[u8; 16] {
         &self.length
     }
@@ -2,7 +2,7 @@
 
 use alloc::vec::Vec;
 
-use crate::encoder::JpegColorType;
+use crate::encoder::{ColorType, JpegColorType};
 
 /// Conversion from RGB to YCbCr
 #[inline]
@@ -30,6 +30,27 @@ pub fn rgb_to_ycbcr(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
     (y as u8, cb as u8, cr as u8)
 }
 
+/// Conversion from RGB to YCbCr for samples with the given precision
+#[inline]
+fn rgb_to_ycbcr_wide(r: u16, g: u16, b: u16, precision: u8) -> (u16, u16, u16) {
+    // Same conversion as for 8 bit samples with an offset of half the sample range
+    let r = r as i32;
+    let g = g as i32;
+    let b = b as i32;
+
+    let offset = 1 << (precision - 1);
+
+    let y = 19595 * r + 38470 * g + 7471 * b;
+    let cb = -11059 * r - 21709 * g + 32768 * b + (offset << 16);
+    let cr = 32768 * r - 27439 * g - 5329 * b + (offset << 16);
+
+    let y = (y + 0x7FFF) >> 16;
+    let cb = (cb + 0x7FFF) >> 16;
+    let cr = (cr + 0x7FFF) >> 16;
+
+    (y as u16, cb as u16, cr as u16)
+}
+
 /// Conversion from CMYK to YCCK (YCbCrK)
 #[inline]
 pub fn cmyk_to_ycck(c: u8, m: u8, y: u8, k: u8) -> (u8, u8, u8, u8) {
@@ -95,6 +116,197 @@ pub trait ImageBuffer {
 
     /// Add color values for the row to color component buffers
     fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]);
+
+    /// The color type of the samples added by [fill_lossless_buffers](ImageBuffer::fill_lossless_buffers)
+    ///
+    /// Defaults to [get_jpeg_color_type](ImageBuffer::get_jpeg_color_type).
+    fn get_lossless_color_type(&self) -> JpegColorType {
+        self.get_jpeg_color_type()
+    }
+
+    /// Add color values for the row to color component buffers in lossless mode
+    ///
+    /// The color conversion of RGB to YCbCr isn't lossless. Buffers which convert the colors in
+    /// [fill_buffers](ImageBuffer::fill_buffers) should add the untransformed samples here and
+    /// return their color type from [get_lossless_color_type](ImageBuffer::get_lossless_color_type).
+    ///
+    /// Defaults to [fill_buffers](ImageBuffer::fill_buffers).
+    fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+        self.fill_buffers(y, buffers)
+    }
+}
+
+/// # Buffer with up to 16 bit samples used as input value for high precision encoding
+///
+/// Used by [Encoder::encode_image_16](crate::Encoder::encode_image_16) for lossless encoding
+/// and 12 bit DCT encoding.
+/// Each sample must be smaller than `2^precision`.
+pub trait ImageBuffer16 {
+    /// The color type used in the image encoding
+    fn get_jpeg_color_type(&self) -> JpegColorType;
+
+    /// Width of the image
+    fn width(&self) -> u16;
+
+    /// Height of the image
+    fn height(&self) -> u16;
+
+    /// Sample precision in bits
+    fn precision(&self) -> u8;
+
+    /// Add color values for the row to color component buffers
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u16>; 4]);
+}
+
+/// Image with 8 or 16 bit samples
+///
+/// Without color conversion RGB and BGR data is stored as RGB, CMYK data is inverted like in [CmykImage].
+/// With color conversion RGB and BGR data is converted to YCbCr and CMYK to YCCK if requested.
+pub(crate) struct RawImage<'a, T> {
+    data: &'a [T],
+    width: u16,
+    height: u16,
+    color_type: ColorType,
+    precision: u8,
+    color_conversion: bool,
+}
+
+impl<'a, T> RawImage<'a, T> {
+    pub fn new(
+        data: &'a [T],
+        width: u16,
+        height: u16,
+        color_type: ColorType,
+        precision: u8,
+    ) -> Self {
+        RawImage {
+            data,
+            width,
+            height,
+            color_type,
+            precision,
+            color_conversion: false,
+        }
+    }
+
+    /// Enable the conversion of RGB to YCbCr
+    pub fn with_color_conversion(mut self) -> Self {
+        self.color_conversion = true;
+        self
+    }
+}
+
+impl<'a, T: Copy + Into<u16>> ImageBuffer16 for RawImage<'a, T> {
+    fn get_jpeg_color_type(&self) -> JpegColorType {
+        use ColorType::*;
+
+        match self.color_type {
+            Luma => JpegColorType::Luma,
+            Rgb | Rgba | Bgr | Bgra if self.color_conversion => JpegColorType::Ycbcr,
+            Rgb | Rgba | Bgr | Bgra => JpegColorType::Rgb,
+            Ycbcr => JpegColorType::Ycbcr,
+            CmykAsYcck if self.color_conversion => JpegColorType::Ycck,
+            Cmyk | CmykAsYcck => JpegColorType::Cmyk,
+            Ycck => JpegColorType::Ycck,
+        }
+    }
+
+    fn width(&self) -> u16 {
+        self.width
+    }
+
+    fn height(&self) -> u16 {
+        self.height
+    }
+
+    fn precision(&self) -> u8 {
+        self.precision
+    }
+
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u16>; 4]) {
+        use ColorType::*;
+
+        let num_colors = self.color_type.get_bytes_per_pixel();
+
+        let width = usize::from(self.width);
+        let start = usize::from(y) * width * num_colors;
+        let line = &self.data[start..start + width * num_colors];
+
+        let offsets: &[usize] = match self.color_type {
+            Luma => &[0],
+            Rgb | Rgba | Ycbcr => &[0, 1, 2],
+            Bgr | Bgra => &[2, 1, 0],
+            Cmyk | CmykAsYcck | Ycck => &[0, 1, 2, 3],
+        };
+
+        let invert = matches!(self.color_type, Cmyk | CmykAsYcck);
+        let max_value = ((1u32 << self.precision) - 1) as u16;
+
+        if self.color_conversion && !matches!(self.color_type, Luma | Ycbcr | Cmyk | Ycck) {
+            for pixel in line.chunks_exact(num_colors) {
+                let (y, cb, cr) = rgb_to_ycbcr_wide(
+                    pixel[offsets[0]].into(),
+                    pixel[offsets[1]].into(),
+                    pixel[offsets[2]].into(),
+                    self.precision,
+                );
+
+                buffers[0].push(y);
+                buffers[1].push(cb);
+                buffers[2].push(cr);
+
+                if self.color_type == CmykAsYcck {
+                    buffers[3].push(max_value.wrapping_sub(pixel[3].into()));
+                }
+            }
+
+            return;
+        }
+
+        for pixel in line.chunks_exact(num_colors) {
+            for (buffer, &offset) in buffers.iter_mut().zip(offsets) {
+                let value = pixel[offset].into();
+
+                // Out of range values wrap around and are rejected by the encoder
+                buffer.push(if invert {
+                    max_value.wrapping_sub(value)
+                } else {
+                    value
+                });
+            }
+        }
+    }
+}
+
+/// Adapter to use an 8 bit [ImageBuffer] as [ImageBuffer16]
+/// Samples of an 8 bit image for lossless encoding
+pub(crate) struct WideImage<I: ImageBuffer>(pub I);
+
+impl<I: ImageBuffer> ImageBuffer16 for WideImage<I> {
+    fn get_jpeg_color_type(&self) -> JpegColorType {
+        self.0.get_lossless_color_type()
+    }
+
+    fn width(&self) -> u16 {
+        self.0.width()
+    }
+
+    fn height(&self) -> u16 {
+        self.0.height()
+    }
+
+    fn precision(&self) -> u8 {
+        8
+    }
+
+    fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u16>; 4]) {
+        let mut row: [Vec<u8>; 4] = Default::default();
+        self.0.fill_lossless_buffers(y, &mut row);
+
+        for (buffer, row) in buffers.iter_mut().zip(row.iter()) {
+            buffer.extend(row.iter().map(|&v| u16::from(v)));
+        }
+    }
 }
 
 pub(crate) struct GrayImage<'a>(pub &'a [u8], pub u16, pub u16);
@@ -132,6 +344,25 @@ fn get_line(data: &[u8], y: u16, width: u16, num_colors: usize) -> &[u8] {
     &data[start..end]
 }
 
+/// Add the untransformed RGB samples of the row, `offsets` are the positions of R, G and B in a pixel
+#[inline(always)]
+pub(crate) fn fill_rgb_buffers(
+    data: &[u8],
+    y: u16,
+    width: u16,
+    num_colors: usize,
+    offsets: [usize; 3],
+    buffers: &mut [Vec<u8>; 4],
+) {
+    let line = get_line(data, y, width, num_colors);
+
+    for pixel in line.chunks_exact(num_colors) {
+        buffers[0].push(pixel[offsets[0]]);
+        buffers[1].push(pixel[offsets[1]]);
+        buffers[2].push(pixel[offsets[2]]);
+    }
+}
+
 macro_rules! ycbcr_image {
     ($name:ident, $num_colors:expr, $o1:expr, $o2:expr, $o3:expr) => {
         pub(crate) struct $name<'a>(pub &'a [u8], pub u16, pub u16);
@@ -161,6 +392,15 @@ macro_rules! ycbcr_image {
                     buffers[2].push(cr);
                 }
             }
+
+            fn get_lossless_color_type(&self) -> JpegColorType {
+                JpegColorType::Rgb
+            }
+
+            fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let offsets = [$o1, $o2, $o3];
+                fill_rgb_buffers(self.0, y, self.1, $num_colors, offsets, buffers);
+            }
         }
     };
 }
@@ -250,6 +490,14 @@ impl<'a> ImageBuffer for CmykAsYcckImage<'a> {
             buffers[3].push(k);
         }
     }
+
+    fn get_lossless_color_type(&self) -> JpegColorType {
+        JpegColorType::Cmyk
+    }
+
+    fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+        CmykImage(self.0, self.1, self.2).fill_buffers(y, buffers)
+    }
 }
 
 pub(crate) struct YcckImage<'a>(pub &'a [u8], pub u16, pub u16);
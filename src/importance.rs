@@ -0,0 +1,227 @@
+use alloc::vec::Vec;
+
+use crate::encoder::{get_max_sampling_size_for, Component};
+use crate::quantization::QuantizationTable;
+use crate::writer::ZIGZAG;
+
+/// Largest scaling of the AC quantization steps for blocks without importance
+const MAX_SCALE: usize = 4;
+
+/// Smallest number of coefficients in zigzag order that are kept for blocks without importance
+const MIN_COEFFICIENTS: usize = 16;
+
+/// # Importance of image regions
+///
+/// Contains one value per 8x8 block of the image which controls the quantization of the block.
+/// A value of 255 encodes the block with the normal quantization tables. Lower values
+/// scale the AC quantization steps by up to 4 and drop high frequency coefficients,
+/// which reduces the size of the block in regions like flat backgrounds. The quantization
+/// tables in the file aren't changed, so any decoder can read the image.
+///
+/// The map should have `ceil(width / 8)` columns and `ceil(height / 8)` rows.
+/// Blocks outside of the map use the value of the nearest block. For chroma subsampled
+/// components the highest importance of all covered blocks is used.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportanceMap {
+    width: usize,
+    height: usize,
+    values: Vec<u8>,
+}
+
+impl ImportanceMap {
+    /// Create a map with the given number of block columns and rows from values in row order
+    ///
+    /// # Panics
+    /// If the dimensions are zero or don't match the number of values
+    pub fn new(width: usize, height: usize, values: Vec<u8>) -> ImportanceMap {
+        assert!(
+            width > 0 && height > 0,
+            "Importance map dimensions must be non zero"
+        );
+        assert_eq!(
+            width * height,
+            values.len(),
+            "Importance map size doesn't match its dimensions"
+        );
+
+        ImportanceMap {
+            width,
+            height,
+            values,
+        }
+    }
+
+    /// Create a map by calling `importance` with the column and row of each block
+    ///
+    /// # Panics
+    /// If the dimensions are zero
+    pub fn from_fn<F: FnMut(usize, usize) -> u8>(
+        width: usize,
+        height: usize,
+        mut importance: F,
+    ) -> ImportanceMap {
+        let mut values = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                values.push(importance(x, y));
+            }
+        }
+
+        ImportanceMap::new(width, height, values)
+    }
+
+    /// Number of block columns
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    /// Number of block rows
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the importance of a block or of the nearest block if it is outside of the map
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let x = x.min(self.width - 1);
+        let y = y.min(self.height - 1);
+
+        self.values[y * self.width + x]
+    }
+}
+
+/// Quantization settings of a single block
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct BlockLevel {
+    scale: i32,
+    coefficients: usize,
+}
+
+impl BlockLevel {
+    /// Level of blocks with the normal quantization
+    pub const FULL: BlockLevel = BlockLevel {
+        scale: 1,
+        coefficients: 64,
+    };
+
+    fn new(importance: u8) -> BlockLevel {
+        let reduction = usize::from(255 - importance);
+
+        BlockLevel {
+            scale: 1 + (reduction * (MAX_SCALE - 1) / 255) as i32,
+            coefficients: 64 - reduction * (64 - MIN_COEFFICIENTS) / 255,
+        }
+    }
+
+    /// Returns true if the block uses the normal quantization
+    pub fn is_full(&self) -> bool {
+        self.scale == 1 && self.coefficients == 64
+    }
+
+    /// Quantize a block of DCT coefficients in natural order
+    ///
+    /// The AC coefficients are quantized with scaled steps and the high frequencies are dropped.
+    /// The result is in zigzag order.
+    pub fn quantize(&self, block: &[i32; 64], q_block: &mut [i16; 64], table: &QuantizationTable) {
+        q_block[0] = table.quantize_wide(block[0], 0);
+
+        for (i, q) in q_block.iter_mut().enumerate().skip(1) {
+            *q = if i < self.coefficients {
+                let z = ZIGZAG[i] as usize & 0x3f;
+                table.quantize_scaled(block[z], z, self.scale)
+            } else {
+                0
+            };
+        }
+    }
+}
+
+/// Quantization levels of blocks based on an [ImportanceMap]
+pub(crate) struct AdaptiveQuantizer<'a> {
+    map: &'a ImportanceMap,
+    scaling: [(usize, usize); 4],
+}
+
+impl<'a> AdaptiveQuantizer<'a> {
+    pub fn new(map: &'a ImportanceMap, components: &[Component]) -> AdaptiveQuantizer<'a> {
+        let (max_h_sampling, max_v_sampling) = get_max_sampling_size_for(components);
+
+        let mut scaling = [(1, 1); 4];
+        for (scale, component) in scaling.iter_mut().zip(components) {
+            *scale = (
+                max_h_sampling / usize::from(component.horizontal_sampling_factor),
+                max_v_sampling / usize::from(component.vertical_sampling_factor),
+            );
+        }
+
+        AdaptiveQuantizer { map, scaling }
+    }
+
+    /// Returns the level of the block at the given block position of a component
+    pub fn level(&self, component: usize, block_x: usize, block_y: usize) -> BlockLevel {
+        let (h_scale, v_scale) = self.scaling[component];
+
+        let mut importance = 0;
+
+        for y in block_y * v_scale..(block_y + 1) * v_scale {
+            for x in block_x * h_scale..(block_x + 1) * h_scale {
+                importance = importance.max(self.map.get(x, y));
+            }
+        }
+
+        BlockLevel::new(importance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantization::QuantizationTableType;
+
+    #[test]
+    fn test_block_level() {
+        assert_eq!(BlockLevel::new(255), BlockLevel::FULL);
+        assert!(BlockLevel::FULL.is_full());
+        assert!(!BlockLevel::new(200).is_full());
+
+        assert_eq!(
+            BlockLevel::new(0),
+            BlockLevel {
+                scale: MAX_SCALE as i32,
+                coefficients: MIN_COEFFICIENTS
+            }
+        );
+    }
+
+    #[test]
+    fn test_quantize() {
+        let table = QuantizationTable::new_with_quality(&QuantizationTableType::Flat, 50, true);
+
+        // The flat table at quality 50 has a step of 16 which is 128 with the DCT scaling
+        let block = [128 * 5 + 10; 64];
+
+        let mut q_block = [0i16; 64];
+        BlockLevel::new(255).quantize(&block, &mut q_block, &table);
+        assert!(q_block.iter().all(|&v| v == 5));
+
+        BlockLevel::new(0).quantize(&block, &mut q_block, &table);
+        assert_eq!(q_block[0], 5);
+        assert!(q_block[1..MIN_COEFFICIENTS].iter().all(|&v| v == 4));
+        assert!(q_block[MIN_COEFFICIENTS..].iter().all(|&v| v == 0));
+    }
+
+    #[test]
+    fn test_map_outside() {
+        let map = ImportanceMap::from_fn(3, 2, |x, y| (y * 3 + x) as u8);
+
+        assert_eq!(map.get(1, 1), 4);
+        assert_eq!(map.get(7, 0), 2);
+        assert_eq!(map.get(7, 9), 5);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_map_invalid_size() {
+        ImportanceMap::new(3, 2, alloc::vec![255; 5]);
+    }
+}
@@ -0,0 +1,273 @@
+/*
+ * IPTC metadata
+ *
+ * The IPTC-IIM datasets are stored in an image resource block of the "Photoshop 3.0"
+ * APP13 segments as described in the IPTC Photo Metadata Standard and the
+ * Adobe Photoshop File Formats Specification.
+ */
+
+use alloc::vec::Vec;
+
+use crate::writer::MAX_SEGMENT_LENGTH;
+use crate::EncodingError;
+
+/// Identifier which precedes the image resource blocks in each APP13 segment
+const PHOTOSHOP_HEADER: &[u8; 14] = b"Photoshop 3.0\0";
+
+/// Maximum length of the resource blocks in one segment
+const MAX_CHUNK_LENGTH: usize = MAX_SEGMENT_LENGTH - PHOTOSHOP_HEADER.len();
+
+/// Image resource ID of the IPTC-NAA record
+pub(crate) const IPTC_RESOURCE_ID: u16 = 0x0404;
+
+/// Coded character set escape sequence for UTF-8
+const UTF_8: &[u8; 3] = b"\x1B%G";
+
+/// # IPTC-IIM metadata
+///
+/// Encodes datasets of the IPTC Information Interchange Model which are written with
+/// [add_iptc](crate::Encoder::add_iptc).
+///
+/// Text is encoded as UTF-8, which is declared with the coded character set dataset (1:90)
+/// of the envelope record. The application record always starts with the record version (2:00).
+/// The datasets are written in ascending order of their record and dataset numbers,
+/// repeated datasets in the order they were added.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Iptc {
+    datasets: Vec<(u8, u8, Vec<u8>)>,
+}
+
+impl Iptc {
+    /// Create empty IPTC metadata
+    pub fn new() -> Iptc {
+        Iptc::default()
+    }
+
+    /// Add a dataset with the given record and dataset number
+    ///
+    /// The record version and the coded character set are written automatically.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the record number is 0 or the dataset is one of the automatically
+    /// written datasets
+    pub fn add_dataset(
+        &mut self,
+        record: u8,
+        dataset: u8,
+        data: &[u8],
+    ) -> Result<(), EncodingError> {
+        if record == 0 || (record, dataset) == (1, 90) || (record, dataset) == (2, 0) {
+            return Err(EncodingError::Write(alloc::format!(
+                "Invalid IPTC dataset: {}:{}",
+                record,
+                dataset
+            )));
+        }
+
+        self.datasets.push((record, dataset, data.to_vec()));
+        Ok(())
+    }
+
+    /// Set the caption (2:120)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the caption exceeds 2000 bytes
+    pub fn set_caption(&mut self, caption: &str) -> Result<(), EncodingError> {
+        validate_length("caption", caption, 2000)?;
+
+        self.datasets
+            .retain(|&(record, dataset, _)| (record, dataset) != (2, 120));
+        self.add_dataset(2, 120, caption.as_bytes())
+    }
+
+    /// Add a byline with the name of the creator (2:80)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the byline exceeds 32 bytes
+    pub fn add_byline(&mut self, byline: &str) -> Result<(), EncodingError> {
+        validate_length("byline", byline, 32)?;
+        self.add_dataset(2, 80, byline.as_bytes())
+    }
+
+    /// Add a keyword (2:25)
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keyword exceeds 64 bytes
+    pub fn add_keyword(&mut self, keyword: &str) -> Result<(), EncodingError> {
+        validate_length("keyword", keyword, 64)?;
+        self.add_dataset(2, 25, keyword.as_bytes())
+    }
+
+    /// Returns the encoded datasets
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut datasets: Vec<(u8, u8, &[u8])> = self
+            .datasets
+            .iter()
+            .map(|(record, dataset, data)| (*record, *dataset, data.as_slice()))
+            .collect();
+
+        datasets.push((1, 90, UTF_8));
+        // Version 4 of the IIM
+        datasets.push((2, 0, &[0x00, 0x04]));
+
+        // Stable sort to keep the order of repeated datasets
+        datasets.sort_by_key(|&(record, dataset, _)| (record, dataset));
+
+        let mut bytes = Vec::new();
+
+        for (record, dataset, data) in datasets {
+            bytes.extend_from_slice(&[0x1C, record, dataset]);
+
+            if data.len() < 0x8000 {
+                bytes.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            } else {
+                // Extended dataset with a 4 byte length field
+                bytes.extend_from_slice(&0x8004u16.to_be_bytes());
+                bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            }
+
+            bytes.extend_from_slice(data);
+        }
+
+        bytes
+    }
+}
+
+fn validate_length(name: &str, value: &str, max_length: usize) -> Result<(), EncodingError> {
+    if value.len() > max_length {
+        Err(EncodingError::Write(alloc::format!(
+            "IPTC {} exceeds maximum length of {} bytes: {}",
+            name,
+            max_length,
+            value.len()
+        )))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the data of the APP13 segments for an image resource block
+///
+/// Blocks which don't fit into a single segment are split across several segments,
+/// each starting with the "Photoshop 3.0" identifier. Readers concatenate the data
+/// of all segments.
+pub(crate) fn photoshop_segments(id: u16, data: &[u8]) -> Result<Vec<Vec<u8>>, EncodingError> {
+    let length = u32::try_from(data.len()).map_err(|_| {
+        EncodingError::Write(alloc::format!(
+            "Image resource exceeds maximum allowed data length: {}",
+            data.len()
+        ))
+    })?;
+
+    let mut block = Vec::with_capacity(data.len() + 13);
+    block.extend_from_slice(b"8BIM");
+    block.extend_from_slice(&id.to_be_bytes());
+    // Empty name as even padded pascal string
+    block.extend_from_slice(&[0, 0]);
+    block.extend_from_slice(&length.to_be_bytes());
+    block.extend_from_slice(data);
+
+    // The data is padded to an even length
+    if data.len() % 2 != 0 {
+        block.push(0);
+    }
+
+    let segments = block
+        .chunks(MAX_CHUNK_LENGTH)
+        .map(|chunk| {
+            let mut segment = Vec::with_capacity(PHOTOSHOP_HEADER.len() + chunk.len());
+            segment.extend_from_slice(PHOTOSHOP_HEADER);
+            segment.extend_from_slice(chunk);
+            segment
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    #[test]
+    fn test_iptc_datasets() {
+        let mut iptc = Iptc::new();
+        iptc.add_keyword("b").unwrap();
+        iptc.set_caption("Old").unwrap();
+        iptc.add_byline("Me").unwrap();
+        iptc.add_keyword("a").unwrap();
+        iptc.set_caption("Capt").unwrap();
+        iptc.add_dataset(3, 1, &[0xAA]).unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            0x1C, 1, 90, 0x00, 0x03, 0x1B, b'%', b'G',
+            0x1C, 2, 0, 0x00, 0x02, 0x00, 0x04,
+            0x1C, 2, 25, 0x00, 0x01, b'b',
+            0x1C, 2, 25, 0x00, 0x01, b'a',
+            0x1C, 2, 80, 0x00, 0x02, b'M', b'e',
+            0x1C, 2, 120, 0x00, 0x04, b'C', b'a', b'p', b't',
+            0x1C, 3, 1, 0x00, 0x01, 0xAA,
+        ];
+
+        assert_eq!(iptc.to_bytes(), expected);
+
+        let mut iptc = Iptc::new();
+        iptc.add_dataset(2, 202, &vec![7; 0x8000]).unwrap();
+
+        let bytes = iptc.to_bytes();
+        assert_eq!(
+            &bytes[15..24],
+            &[0x1C, 2, 202, 0x80, 0x04, 0x00, 0x00, 0x80, 0x00]
+        );
+        assert_eq!(bytes.len(), 24 + 0x8000);
+    }
+
+    #[test]
+    fn test_iptc_invalid_datasets() {
+        let mut iptc = Iptc::new();
+
+        assert!(iptc.add_dataset(0, 1, b"").is_err());
+        assert!(iptc.add_dataset(1, 90, b"").is_err());
+        assert!(iptc.add_dataset(2, 0, b"").is_err());
+        assert!(iptc.set_caption(&"x".repeat(2001)).is_err());
+        assert!(iptc.add_byline(&"x".repeat(33)).is_err());
+        assert!(iptc.add_keyword(&"x".repeat(65)).is_err());
+
+        assert_eq!(iptc, Iptc::new());
+    }
+
+    #[test]
+    fn test_photoshop_segments() {
+        let segments = photoshop_segments(IPTC_RESOURCE_ID, &[1, 2, 3]).unwrap();
+
+        assert_eq!(
+            segments,
+            [[
+                PHOTOSHOP_HEADER.as_ref(),
+                b"8BIM\x04\x04\0\0\0\0\0\x03\x01\x02\x03\0"
+            ]
+            .concat()]
+        );
+
+        let data: Vec<u8> = (0..150_000).map(|i| i as u8).collect();
+        let segments = photoshop_segments(IPTC_RESOURCE_ID, &data).unwrap();
+        assert_eq!(segments.len(), 3);
+
+        let mut block = Vec::new();
+
+        for segment in &segments {
+            assert!(segment.len() <= MAX_SEGMENT_LENGTH);
+            assert_eq!(&segment[..14], PHOTOSHOP_HEADER);
+            block.extend_from_slice(&segment[14..]);
+        }
+
+        assert_eq!(&block[..12], b"8BIM\x04\x04\0\0\x00\x02\x49\xF0");
+        assert_eq!(&block[12..], data);
+    }
+}
@@ -38,24 +38,50 @@ extern crate wee_alloc;
 #[global_allocator]
 static GLOBAL: wee_alloc::WeeAlloc = wee_alloc::WeeAlloc::INIT;
 
+mod arithmetic;
 #[cfg(all(feature = "simd", any(target_arch = "x86", target_arch = "x86_64")))]
 mod avx2;
 mod encoder;
 mod error;
+mod exif;
 mod fdct;
 mod huffman;
 mod image_buffer;
+mod importance;
+mod iptc;
+mod lossless;
 mod marker;
+#[cfg(all(feature = "simd", target_arch = "aarch64"))]
+mod neon;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod progressive;
 mod quantization;
-mod writer;
+#[cfg(all(feature = "simd", target_arch = "wasm32", target_feature = "simd128"))]
+mod simd128;
+mod thumbnail;
+mod trellis;
 #[cfg(feature = "wasm-bindgen")]
 pub mod wasm;
+mod writer;
+mod xmp;
 
-pub use encoder::{ColorType, ComponentSpec, Encoder, JpegColorType, SamplingFactor, StripEncoder};
+pub use arithmetic::ArithmeticConditioning;
+pub use encoder::{
+    ColorType, ComponentSpec, Encoder, JpegColorType, SamplingFactor, StripEncoder,
+    TargetSizeResult,
+};
 pub use error::EncodingError;
-pub use image_buffer::{cmyk_to_ycck, rgb_to_ycbcr, ImageBuffer};
-pub use quantization::QuantizationTableType;
-pub use writer::{Density, JfifWrite};
+pub use exif::{ByteOrder, Exif};
+pub use image_buffer::{cmyk_to_ycck, rgb_to_ycbcr, ImageBuffer, ImageBuffer16};
+pub use importance::ImportanceMap;
+pub use iptc::Iptc;
+pub use lossless::Predictor;
+pub use progressive::{ScanInfo, ScanScript};
+pub use quantization::{CoefficientHook, QuantizationTableType};
+pub use thumbnail::JfifThumbnail;
+pub use trellis::TrellisQuantization;
+pub use writer::{Density, JfifWrite, MarkerLayout, SegmentPosition, StripScratch};
 
 #[cfg(all(
     feature = "benchmark",
@@ -65,16 +91,32 @@ pub use writer::{Density, JfifWrite};
 pub use avx2::fdct_avx2;
 #[cfg(feature = "benchmark")]
 pub use fdct::fdct;
+#[cfg(all(feature = "benchmark", feature = "simd", target_arch = "aarch64"))]
+pub use neon::fdct_neon;
+#[cfg(all(
+    feature = "benchmark",
+    feature = "simd",
+    target_arch = "wasm32",
+    target_feature = "simd128"
+))]
+pub use simd128::fdct_simd128;
 
 #[cfg(test)]
 mod tests {
-    use crate::image_buffer::rgb_to_ycbcr;
-    use crate::{ColorType, Encoder, QuantizationTableType, SamplingFactor, StripEncoder};
+    use crate::image_buffer::{rgb_to_ycbcr, CmykAsYcckImage, RgbImage};
+    use crate::{
+        ArithmeticConditioning, ByteOrder, CoefficientHook, ColorType, Encoder, EncodingError,
+        Exif, ImportanceMap, Iptc, JfifThumbnail, JfifWrite, MarkerLayout, Predictor,
+        QuantizationTableType, SamplingFactor, ScanInfo, ScanScript, SegmentPosition, StripEncoder,
+        TargetSizeResult, TrellisQuantization,
+    };
     use jpeg_decoder::{Decoder, ImageInfo, PixelFormat};
 
     use alloc::boxed::Box;
+    use alloc::sync::Arc;
     use alloc::vec;
     use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
 
     fn create_test_img_rgb() -> (Vec<u8>, u16, u16) {
         // Ensure size which which ensures an odd MCU count per row to test chroma subsampling
@@ -161,10 +203,10 @@ mod tests {
         data: Vec<u8>,
         width: u16,
         height: u16,
-        result: &mut Vec<u8>,
+        result: &[u8],
         pixel_format: PixelFormat,
     ) {
-        let (img, info) = decode(&result);
+        let (img, info) = decode(result);
 
         assert_eq!(info.pixel_format, pixel_format);
         assert_eq!(info.width, width);
@@ -183,6 +225,28 @@ mod tests {
         }
     }
 
+    /// Returns the marker and data of all segments up to the first scan
+    fn header_segments(data: &[u8]) -> Vec<(u8, &[u8])> {
+        assert_eq!(&data[..2], &[0xFF, 0xD8]);
+
+        let mut segments = Vec::new();
+        let mut pos = 2;
+
+        loop {
+            assert_eq!(data[pos], 0xFF);
+            let marker = data[pos + 1];
+            let length = usize::from(u16::from_be_bytes([data[pos + 2], data[pos + 3]]));
+
+            segments.push((marker, &data[pos + 4..pos + 2 + length]));
+
+            if marker == 0xDA {
+                return segments;
+            }
+
+            pos += 2 + length;
+        }
+    }
+
     #[test]
     fn test_gray_100() {
         let (data, width, height) = create_test_img_gray();
@@ -193,7 +257,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Luma)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::L8);
+        check_result(data, width, height, &result, PixelFormat::L8);
     }
 
     #[test]
@@ -206,7 +270,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -219,7 +283,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -253,6 +317,639 @@ mod tests {
         assert_eq!(&result[result.len() - footer.len()..], &footer);
     }
 
+    #[test]
+    fn test_strip_encoder_color_types_match() {
+        let (data, width, height) = create_test_img_rgba();
+
+        for color_type in [
+            ColorType::Rgb,
+            ColorType::Rgba,
+            ColorType::Bgr,
+            ColorType::Bgra,
+            ColorType::Ycbcr,
+            ColorType::Cmyk,
+            ColorType::CmykAsYcck,
+            ColorType::Ycck,
+        ] {
+            let row_stride = usize::from(width) * color_type.get_bytes_per_pixel();
+            let data = &data[..row_stride * usize::from(height)];
+
+            for sampling_factor in [SamplingFactor::F_1_1, SamplingFactor::F_2_2] {
+                let mut expected = Vec::new();
+                let mut encoder = Encoder::new(&mut expected, 80);
+                encoder.set_sampling_factor(sampling_factor);
+                encoder.encode(data, width, height, color_type).unwrap();
+
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(sampling_factor);
+                let mut strip_encoder = encoder
+                    .into_strip_encoder(width, height, color_type)
+                    .unwrap();
+
+                for chunk in data.chunks(row_stride * 7) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                assert_eq!(
+                    strip_encoder.finish().unwrap(),
+                    expected,
+                    "{:?} {:?}",
+                    color_type,
+                    sampling_factor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_strip_encoder_optimized_matches() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_sampling_factor(SamplingFactor::F_1_1);
+        encoder.set_optimized_huffman_tables(true);
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_sampling_factor(SamplingFactor::F_1_1);
+        encoder.set_optimized_huffman_tables(true);
+        let mut strip_encoder = encoder
+            .into_strip_encoder(width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert!(strip_encoder.header_bytes().is_err());
+
+        strip_encoder.write_headers().unwrap();
+
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        for chunk in data.chunks(row_stride * 5) {
+            strip_encoder.encode_strip(chunk).unwrap();
+        }
+
+        let result = strip_encoder.finish().unwrap();
+
+        // The encoder uses one scan per component with optimized tables,
+        // so only the decoded images are identical
+        assert_eq!(decode(&result).0, decode(&expected).0);
+    }
+
+    #[test]
+    fn test_strip_encoder_progressive_matches() {
+        let (data, width, height) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        fn configure<W: JfifWrite>(encoder: &mut Encoder<W>, config: usize) {
+            match config {
+                0 => encoder.set_progressive(true),
+                1 => {
+                    encoder.set_scan_script(ScanScript::Libjpeg);
+                    encoder.set_optimized_huffman_tables(true);
+                }
+                2 => {
+                    encoder.set_scan_script(ScanScript::Mozjpeg);
+                    encoder.set_optimized_huffman_tables(true);
+                    encoder.set_restart_interval(5);
+                }
+                _ => {
+                    encoder.set_progressive(true);
+                    encoder.set_successive_approximation(2);
+                    encoder.set_restart_interval(3);
+                }
+            }
+        }
+
+        for config in 0..4 {
+            for sampling_factor in [SamplingFactor::F_1_1, SamplingFactor::F_2_2] {
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(sampling_factor);
+                configure(&mut encoder, config);
+
+                let mut expected = Vec::new();
+                let mut reference = Encoder::new(&mut expected, 80);
+                reference.set_sampling_factor(sampling_factor);
+                configure(&mut reference, config);
+                reference
+                    .encode(&data, width, height, ColorType::Rgb)
+                    .unwrap();
+
+                let mut strip_encoder = encoder
+                    .into_strip_encoder(width, height, ColorType::Rgb)
+                    .unwrap();
+
+                assert!(strip_encoder.header_bytes().is_err());
+
+                for chunk in data.chunks(row_stride * 7) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                assert_eq!(
+                    strip_encoder.finish().unwrap(),
+                    expected,
+                    "{} {:?}",
+                    config,
+                    sampling_factor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_strip_encoder_non_interleaved_matches() {
+        let (data, width, _) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        // Not a multiple of the MCU height
+        let height = 117;
+        let data = &data[..row_stride * usize::from(height)];
+
+        fn configure<W: JfifWrite>(encoder: &mut Encoder<W>, config: usize) {
+            match config {
+                0 => {}
+                1 => encoder.set_restart_interval(5),
+                2 => encoder.set_optimized_huffman_tables(true),
+                3 => {
+                    encoder.set_optimized_huffman_tables(true);
+                    encoder.set_restart_interval(3);
+                }
+                _ => encoder.set_progressive(true),
+            }
+        }
+
+        for config in 0..5 {
+            for sampling_factor in [
+                SamplingFactor::F_4_1,
+                SamplingFactor::F_4_2,
+                SamplingFactor::F_1_4,
+                SamplingFactor::F_2_4,
+                SamplingFactor::R_4_4_1,
+                SamplingFactor::R_4_2_1,
+                SamplingFactor::R_4_1_1,
+                SamplingFactor::R_4_1_0,
+            ] {
+                let mut expected = Vec::new();
+                let mut reference = Encoder::new(&mut expected, 80);
+                reference.set_sampling_factor(sampling_factor);
+                configure(&mut reference, config);
+                reference
+                    .encode(data, width, height, ColorType::Rgb)
+                    .unwrap();
+
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(sampling_factor);
+                configure(&mut encoder, config);
+                let mut strip_encoder = encoder
+                    .into_strip_encoder(width, height, ColorType::Rgb)
+                    .unwrap();
+
+                for chunk in data.chunks(row_stride * 13) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                assert_eq!(
+                    strip_encoder.finish().unwrap(),
+                    expected,
+                    "{} {:?}",
+                    config,
+                    sampling_factor
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_strip_encoder_non_interleaved_unknown_height() {
+        let (data, width, _) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        let height: u16 = 117;
+        let data = &data[..row_stride * usize::from(height)];
+
+        for restart_interval in [0, 4] {
+            let mut expected = Vec::new();
+            let mut reference = Encoder::new(&mut expected, 80);
+            reference.set_sampling_factor(SamplingFactor::F_4_2);
+            reference.set_restart_interval(restart_interval);
+            reference
+                .encode(data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            let mut encoder = Encoder::new(std::io::Cursor::new(Vec::new()), 80);
+            encoder.set_sampling_factor(SamplingFactor::F_4_2);
+            encoder.set_restart_interval(restart_interval);
+            let mut strip_encoder = encoder
+                .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+                .unwrap();
+
+            for chunk in data.chunks(row_stride * 10) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            let patched = strip_encoder.finish_patching_height().unwrap();
+            assert_eq!(patched.into_inner(), expected);
+
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            encoder.set_sampling_factor(SamplingFactor::F_4_2);
+            encoder.set_restart_interval(restart_interval);
+            let mut strip_encoder = encoder
+                .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+                .unwrap();
+
+            for chunk in data.chunks(row_stride * 10) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            let result = strip_encoder.finish().unwrap();
+
+            // The DNL segment follows the first scan
+            let (_, sof) = header_segments(&expected)
+                .into_iter()
+                .find(|&(marker, _)| marker == 0xC0)
+                .unwrap();
+            let height_offset = sof.as_ptr() as usize - expected.as_ptr() as usize + 1;
+
+            let (_, sos) = *header_segments(&expected).last().unwrap();
+            let scan_start = sos.as_ptr() as usize - expected.as_ptr() as usize;
+            let first_scan_end = scan_start
+                + expected[scan_start..]
+                    .windows(2)
+                    .position(|bytes| bytes == [0xFF, 0xDA])
+                    .unwrap();
+
+            let mut expected_dnl = expected[..first_scan_end].to_vec();
+            expected_dnl[height_offset..height_offset + 2].copy_from_slice(&[0, 0]);
+            expected_dnl.extend_from_slice(&[0xFF, 0xDC, 0, 4]);
+            expected_dnl.extend_from_slice(&height.to_be_bytes());
+            expected_dnl.extend_from_slice(&expected[first_scan_end..]);
+
+            assert_eq!(result, expected_dnl);
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_strip_encoder_non_interleaved_scratch() {
+        let (data, width, height) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        let mut expected = Vec::new();
+        let mut reference = Encoder::new(&mut expected, 80);
+        reference.set_sampling_factor(SamplingFactor::R_4_1_0);
+        reference.set_restart_interval(7);
+        reference.set_optimized_huffman_tables(true);
+        reference
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_sampling_factor(SamplingFactor::R_4_1_0);
+        encoder.set_restart_interval(7);
+        let mut strip_encoder = encoder
+            .into_strip_encoder_with_scratch(
+                width,
+                height,
+                ColorType::Rgb,
+                std::io::Cursor::new(Vec::new()),
+            )
+            .unwrap();
+
+        for chunk in data.chunks(row_stride * 9) {
+            strip_encoder.encode_strip(chunk).unwrap();
+        }
+
+        assert_eq!(strip_encoder.finish().unwrap(), expected);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_strip_encoder_optimized_scratch() {
+        let (data, width, height) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        for restart_interval in [0, 3] {
+            let encode = |optimized: bool, scratch: bool| {
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(SamplingFactor::F_2_2);
+                encoder.set_restart_interval(restart_interval);
+                encoder.set_optimized_huffman_tables(optimized);
+
+                let mut strip_encoder = if scratch {
+                    encoder
+                        .into_strip_encoder_with_scratch(
+                            width,
+                            height,
+                            ColorType::Rgb,
+                            std::io::Cursor::new(Vec::new()),
+                        )
+                        .unwrap()
+                } else {
+                    encoder
+                        .into_strip_encoder(width, height, ColorType::Rgb)
+                        .unwrap()
+                };
+
+                for chunk in data.chunks(row_stride * 7) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                strip_encoder.finish().unwrap()
+            };
+
+            let default = encode(false, false);
+            let in_memory = encode(true, false);
+            let scratch = encode(true, true);
+
+            assert_eq!(in_memory, scratch);
+            assert!(in_memory.len() < default.len());
+
+            // The quantized coefficients are identical, only the entropy coding differs
+            assert_eq!(decode(&in_memory).0, decode(&default).0);
+        }
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_progressive(true);
+        assert!(encoder
+            .into_strip_encoder_with_scratch(
+                width,
+                height,
+                ColorType::Rgb,
+                std::io::Cursor::new(Vec::new())
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_strip_encoder_unknown_height() {
+        let (data, width, height) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        fn configure<W: JfifWrite>(encoder: &mut Encoder<W>, restart_interval: u16) {
+            encoder.set_sampling_factor(SamplingFactor::F_2_2);
+            encoder.set_restart_interval(restart_interval);
+        }
+
+        for (restart_interval, strip_height) in [(0, 1), (0, 7), (3, 16), (5, 40)] {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            configure(&mut encoder, restart_interval);
+            let mut strip_encoder = encoder
+                .into_strip_encoder(width, height, ColorType::Rgb)
+                .unwrap();
+
+            for chunk in data.chunks(row_stride * strip_height) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            let expected = strip_encoder.finish().unwrap();
+
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            configure(&mut encoder, restart_interval);
+            let mut strip_encoder = encoder
+                .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+                .unwrap();
+
+            for chunk in data.chunks(row_stride * strip_height) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            let result = strip_encoder.finish().unwrap();
+
+            // Same stream with a zero height in the frame header and a DNL segment before EOI
+            let (_, sof) = header_segments(&expected)
+                .into_iter()
+                .find(|&(marker, _)| marker == 0xC0)
+                .unwrap();
+            let height_offset = sof.as_ptr() as usize - expected.as_ptr() as usize + 1;
+
+            let mut expected_dnl = expected.clone();
+            expected_dnl[height_offset..height_offset + 2].copy_from_slice(&[0, 0]);
+            expected_dnl.truncate(expected.len() - 2);
+            expected_dnl.extend_from_slice(&[0xFF, 0xDC, 0, 4]);
+            expected_dnl.extend_from_slice(&height.to_be_bytes());
+            expected_dnl.extend_from_slice(&[0xFF, 0xD9]);
+
+            assert_eq!(result, expected_dnl);
+
+            #[cfg(feature = "std")]
+            {
+                let mut encoder = Encoder::new(std::io::Cursor::new(Vec::new()), 80);
+                configure(&mut encoder, restart_interval);
+                let mut strip_encoder = encoder
+                    .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+                    .unwrap();
+
+                for chunk in data.chunks(row_stride * strip_height) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                let result = strip_encoder.finish_patching_height().unwrap();
+
+                assert_eq!(result.into_inner(), expected);
+            }
+        }
+
+        // Optimized tables write the frame header at the end
+        let encode = |unknown_height: bool| {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            encoder.set_optimized_huffman_tables(true);
+
+            let mut strip_encoder = if unknown_height {
+                encoder
+                    .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+                    .unwrap()
+            } else {
+                encoder
+                    .into_strip_encoder(width, height, ColorType::Rgb)
+                    .unwrap()
+            };
+
+            strip_encoder.encode_strip(&data).unwrap();
+            strip_encoder.finish().unwrap()
+        };
+
+        assert_eq!(encode(true), encode(false));
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        let strip_encoder = encoder
+            .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+            .unwrap();
+        assert!(strip_encoder.finish().is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_progressive(true);
+        assert!(encoder
+            .into_strip_encoder_unknown_height(width, ColorType::Rgb)
+            .is_err());
+    }
+
+    #[test]
+    fn test_strip_encoder_abbreviated() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let encode = |abbreviated: bool| {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            encoder.set_sampling_factor(SamplingFactor::F_2_2);
+            encoder.set_restart_interval(4);
+            let mut strip_encoder = encoder
+                .into_strip_encoder(width, height, ColorType::Rgb)
+                .unwrap();
+
+            strip_encoder.set_abbreviated_image(abbreviated).unwrap();
+
+            let tables = strip_encoder.tables_only_bytes().unwrap();
+            let header = strip_encoder.header_bytes().unwrap();
+
+            strip_encoder.encode_strip(&data).unwrap();
+            assert!(strip_encoder.set_abbreviated_image(false).is_err());
+
+            let result = strip_encoder.finish().unwrap();
+            assert_eq!(&result[..header.len()], header.as_slice());
+
+            (tables, result)
+        };
+
+        let (tables, full) = encode(false);
+        let (abbreviated_tables, abbreviated) = encode(true);
+
+        assert_eq!(tables, abbreviated_tables);
+
+        // The full image is the abbreviated image with the segments of the tables-only stream
+        let mut expected_tables = vec![0xFF, 0xD8];
+        let mut expected_abbreviated = vec![0xFF, 0xD8];
+        let mut header_length = 2;
+
+        for (marker, data) in header_segments(&full) {
+            let mut segment = vec![0xFF, marker];
+            segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+            segment.extend_from_slice(data);
+
+            header_length += segment.len();
+
+            if marker == 0xDB || marker == 0xC4 {
+                expected_tables.extend_from_slice(&segment);
+            } else {
+                expected_abbreviated.extend_from_slice(&segment);
+            }
+        }
+
+        expected_tables.extend_from_slice(&[0xFF, 0xD9]);
+        expected_abbreviated.extend_from_slice(&full[header_length..]);
+
+        assert_eq!(tables, expected_tables);
+        assert_eq!(abbreviated, expected_abbreviated);
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_optimized_huffman_tables(true);
+        let mut strip_encoder = encoder
+            .into_strip_encoder(width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert!(strip_encoder.tables_only_bytes().is_err());
+        assert!(strip_encoder.set_abbreviated_image(true).is_err());
+    }
+
+    #[test]
+    fn test_abbreviated_image() {
+        let (data, width, height) = create_test_img_rgb();
+
+        fn configure<W: JfifWrite>(encoder: &mut Encoder<W>, config: usize) {
+            match config {
+                0 => encoder.set_restart_interval(4),
+                1 => encoder.set_progressive(true),
+                _ => encoder.set_arithmetic_coding(true),
+            }
+        }
+
+        for config in 0..3 {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            configure(&mut encoder, config);
+            let tables = encoder.tables_only_bytes().unwrap();
+
+            let mut full = Vec::new();
+            let mut encoder = Encoder::new(&mut full, 80);
+            configure(&mut encoder, config);
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            let mut abbreviated = Vec::new();
+            let mut encoder = Encoder::new(&mut abbreviated, 80);
+            configure(&mut encoder, config);
+            encoder.set_abbreviated_image(true);
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            // The full image is the abbreviated image with the segments of the tables-only stream
+            let mut expected_tables = vec![0xFF, 0xD8];
+            let mut expected_abbreviated = vec![0xFF, 0xD8];
+            let mut header_length = 2;
+
+            for (marker, data) in header_segments(&full) {
+                let mut segment = vec![0xFF, marker];
+                segment.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+                segment.extend_from_slice(data);
+
+                header_length += segment.len();
+
+                if marker == 0xDB || marker == 0xC4 || marker == 0xCC {
+                    expected_tables.extend_from_slice(&segment);
+                } else {
+                    expected_abbreviated.extend_from_slice(&segment);
+                }
+            }
+
+            expected_tables.extend_from_slice(&[0xFF, 0xD9]);
+            expected_abbreviated.extend_from_slice(&full[header_length..]);
+
+            assert_eq!(tables, expected_tables);
+            assert_eq!(abbreviated, expected_abbreviated);
+        }
+
+        // The setting is passed on to strip encoders
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_abbreviated_image(true);
+        let tables = encoder.tables_only_bytes().unwrap();
+
+        let strip_encoder = encoder
+            .into_strip_encoder(width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert_eq!(strip_encoder.tables_only_bytes().unwrap(), tables);
+        let header = strip_encoder.header_bytes().unwrap();
+        assert!(header_segments(&header)
+            .iter()
+            .all(|(marker, _)| *marker != 0xDB && *marker != 0xC4));
+
+        // Optimized Huffman tables are only known while encoding
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_abbreviated_image(true);
+        encoder.set_optimized_huffman_tables(true);
+        assert!(encoder.tables_only_bytes().is_err());
+        assert!(encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_abbreviated_image(true);
+        encoder.set_lossless(Some(Predictor::Left));
+        assert!(encoder.tables_only_bytes().is_err());
+        assert!(encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .is_err());
+
+        let (data, width, height) = create_test_img_gray_12();
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_abbreviated_image(true);
+        assert!(encoder
+            .encode_16(&data, width, height, ColorType::Luma, 12)
+            .is_err());
+    }
+
     #[test]
     fn test_rgb_strip_encoder_large_strip_height() {
         let (data, width, height) = create_test_img_rgb();
@@ -367,7 +1064,7 @@ mod tests {
 
         let (data, width, height) = create_test_img_rgb();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -389,7 +1086,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -403,7 +1100,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -417,7 +1114,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -431,7 +1128,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -445,7 +1142,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -459,7 +1156,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -475,7 +1172,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -491,7 +1188,7 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
@@ -508,101 +1205,1369 @@ mod tests {
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
-    fn test_cmyk() {
-        let (data, width, height) = create_test_img_cmyk();
+    fn test_rgb_progressive_successive_approximation() {
+        let (data, width, height) = create_test_img_rgb();
 
         let mut result = Vec::new();
-        let encoder = Encoder::new(&mut result, 100);
+        let mut encoder = Encoder::new(&mut result, 100);
+        encoder.set_sampling_factor(SamplingFactor::F_2_1);
+        encoder.set_progressive(true);
+        encoder.set_successive_approximation(2);
+
         encoder
-            .encode(&data, width, height, ColorType::Cmyk)
+            .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::CMYK32);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
-    fn test_ycck() {
-        let (data, width, height) = create_test_img_cmyk();
+    fn test_rgb_optimized_progressive_successive_approximation() {
+        let (data, width, height) = create_test_img_rgb();
 
         let mut result = Vec::new();
-        let encoder = Encoder::new(&mut result, 100);
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_sampling_factor(SamplingFactor::F_2_2);
+        encoder.set_progressive_scans(3);
+        encoder.set_successive_approximation(3);
+        encoder.set_optimized_huffman_tables(true);
+        encoder.set_restart_interval(7);
+
         encoder
-            .encode(&data, width, height, ColorType::CmykAsYcck)
+            .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        check_result(data, width, height, &mut result, PixelFormat::CMYK32);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
-    fn test_restart_interval() {
-        let (data, width, height) = create_test_img_rgb();
+    fn test_gray_progressive_successive_approximation_lossless_bits() {
+        let (data, width, height) = create_test_img_gray();
 
-        let mut result = Vec::new();
-        let mut encoder = Encoder::new(&mut result, 100);
+        let encode = |point_transform: u8| {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 90);
+            encoder.set_progressive(true);
+            encoder.set_successive_approximation(point_transform);
+            encoder.set_optimized_huffman_tables(true);
+            encoder
+                .encode(&data, width, height, ColorType::Luma)
+                .unwrap();
+            result
+        };
 
-        encoder.set_restart_interval(32);
-        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+        // Successive approximation must not change the decoded coefficients
+        let (expected, _) = decode(&encode(0));
+        let (img, _) = decode(&encode(4));
 
-        encoder
-            .encode(&data, width, height, ColorType::Rgb)
-            .unwrap();
+        assert_eq!(img, expected);
+    }
 
-        assert!(result
-            .as_slice()
-            .windows(DRI_DATA.len())
-            .any(|w| w == DRI_DATA));
+    #[test]
+    fn test_scan_script_presets() {
+        let (data, width, height) = create_test_img_rgb();
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        for script in [ScanScript::Libjpeg, ScanScript::Mozjpeg] {
+            for sampling_factor in [
+                SamplingFactor::F_1_1,
+                SamplingFactor::F_2_2,
+                SamplingFactor::F_4_1,
+            ] {
+                for optimized in [false, true] {
+                    let mut result = Vec::new();
+                    let mut encoder = Encoder::new(&mut result, 90);
+                    encoder.set_sampling_factor(sampling_factor);
+                    encoder.set_scan_script(script.clone());
+                    encoder.set_optimized_huffman_tables(optimized);
+                    encoder.set_restart_interval(5);
+
+                    encoder
+                        .encode(&data, width, height, ColorType::Rgb)
+                        .unwrap();
+
+                    check_result(data.clone(), width, height, &result, PixelFormat::RGB24);
+                }
+            }
+        }
     }
 
     #[test]
-    fn test_restart_interval_4_1() {
+    fn test_scan_script_presets_gray_cmyk() {
+        for script in [ScanScript::Libjpeg, ScanScript::Mozjpeg] {
+            let (data, width, height) = create_test_img_gray();
+
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 90);
+            encoder.set_scan_script(script.clone());
+            encoder.set_optimized_huffman_tables(true);
+            encoder
+                .encode(&data, width, height, ColorType::Luma)
+                .unwrap();
+
+            check_result(data, width, height, &result, PixelFormat::L8);
+
+            let (data, width, height) = create_test_img_cmyk();
+
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 100);
+            encoder.set_scan_script(script);
+            encoder
+                .encode(&data, width, height, ColorType::CmykAsYcck)
+                .unwrap();
+
+            check_result(data, width, height, &result, PixelFormat::CMYK32);
+        }
+    }
+
+    #[test]
+    fn test_scan_script_custom() {
         let (data, width, height) = create_test_img_rgb();
 
-        let mut result = Vec::new();
-        let mut encoder = Encoder::new(&mut result, 100);
-        encoder.set_sampling_factor(SamplingFactor::F_4_1);
+        let script = ScanScript::Custom(vec![
+            ScanInfo::new(&[0, 1, 2], 0, 0, 0, 0),
+            ScanInfo::new(&[0], 1, 9, 0, 1),
+            ScanInfo::new(&[1], 1, 63, 0, 0),
+            ScanInfo::new(&[2], 1, 63, 0, 0),
+            ScanInfo::new(&[0], 10, 63, 0, 1),
+            ScanInfo::new(&[0], 1, 63, 1, 0),
+        ]);
 
-        encoder.set_restart_interval(32);
-        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 90);
+        encoder.set_scan_script(script);
+        assert!(encoder.scan_script().is_some());
 
         encoder
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        assert!(result
-            .as_slice()
-            .windows(DRI_DATA.len())
-            .any(|w| w == DRI_DATA));
-
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
     }
 
     #[test]
-    fn test_restart_interval_progressive() {
+    fn test_scan_script_invalid() {
         let (data, width, height) = create_test_img_rgb();
 
+        let script = ScanScript::Custom(vec![
+            ScanInfo::new(&[0, 1, 2], 0, 0, 0, 0),
+            ScanInfo::new(&[0], 1, 63, 0, 0),
+            ScanInfo::new(&[1], 1, 63, 0, 0),
+        ]);
+
         let mut result = Vec::new();
-        let mut encoder = Encoder::new(&mut result, 85);
-        encoder.set_progressive(true);
+        let mut encoder = Encoder::new(&mut result, 90);
+        encoder.set_scan_script(script);
 
-        encoder.set_restart_interval(32);
-        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+        let err = encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap_err();
+
+        assert!(matches!(err, EncodingError::InvalidScanScript(_)));
+        assert!(result.is_empty());
+    }
 
+    #[test]
+    fn test_rgb_arithmetic() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut huffman = Vec::new();
+        let encoder = Encoder::new(&mut huffman, 80);
         encoder
             .encode(&data, width, height, ColorType::Rgb)
             .unwrap();
 
-        assert!(result
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_arithmetic_coding(true);
+        encoder.set_restart_interval(5);
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments = header_segments(&result);
+        let markers: Vec<u8> = segments.iter().map(|&(marker, _)| marker).collect();
+
+        // SOF9 with DAC instead of DHT segments
+        assert!(markers.contains(&0xC9));
+        assert!(markers.contains(&0xCC));
+        assert!(!markers.contains(&0xC4));
+
+        // Default conditioning for luma and chroma tables
+        let dac = segments
+            .iter()
+            .find(|&&(marker, _)| marker == 0xCC)
+            .unwrap()
+            .1;
+        assert_eq!(dac, &[0x00, 0x10, 0x10, 5, 0x01, 0x10, 0x11, 5]);
+
+        assert_eq!(&result[result.len() - 2..], &[0xFF, 0xD9]);
+        assert!(result.len() < huffman.len());
+    }
+
+    #[test]
+    fn test_rgb_arithmetic_progressive() {
+        let (data, width, height) = create_test_img_rgb();
+
+        for script in [ScanScript::Libjpeg, ScanScript::Mozjpeg] {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_arithmetic_coding(true);
+            encoder.set_scan_script(script);
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            let markers: Vec<u8> = header_segments(&result)
+                .iter()
+                .map(|&(marker, _)| marker)
+                .collect();
+
+            assert!(markers.contains(&0xCA));
+            assert!(markers.contains(&0xCC));
+            assert!(!markers.contains(&0xC4));
+        }
+    }
+
+    #[test]
+    fn test_gray_arithmetic_conditioning() {
+        let (data, width, height) = create_test_img_gray();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_arithmetic_coding(true);
+        encoder.set_arithmetic_conditioning(
+            ArithmeticConditioning::new(1, 4, 12),
+            ArithmeticConditioning::default(),
+        );
+        encoder
+            .encode(&data, width, height, ColorType::Luma)
+            .unwrap();
+
+        let segments = header_segments(&result);
+
+        // Only the luma tables are used
+        let dac = segments
+            .iter()
+            .find(|&&(marker, _)| marker == 0xCC)
+            .unwrap()
+            .1;
+        assert_eq!(dac, &[0x00, 0x41, 0x10, 12]);
+    }
+
+    #[test]
+    fn test_strip_encoder_arithmetic() {
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_arithmetic_coding(true);
+
+        assert!(encoder.into_strip_encoder(16, 16, ColorType::Rgb).is_err());
+    }
+
+    #[test]
+    fn test_rgb_trellis() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let encode = |trellis: Option<TrellisQuantization>| {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_optimized_huffman_tables(true);
+            encoder.set_trellis_quantization(trellis);
+
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            result
+        };
+
+        let default = encode(None);
+        let trellis = encode(Some(TrellisQuantization::default()));
+        let trellis_ac = encode(Some(TrellisQuantization::new(false, 14.75, 16.5)));
+
+        assert!(trellis.len() < default.len());
+        assert!(trellis_ac.len() < default.len());
+        assert_ne!(trellis, trellis_ac);
+
+        check_result(data.clone(), width, height, &trellis, PixelFormat::RGB24);
+        check_result(data, width, height, &trellis_ac, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_rgb_trellis_progressive() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_progressive(true);
+        encoder.set_restart_interval(3);
+        encoder.set_trellis_quantization(Some(TrellisQuantization::default()));
+
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_gray_12_bit_trellis() {
+        let (data, width, height) = create_test_img_gray_12();
+
+        let encode = |trellis: Option<TrellisQuantization>| {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_trellis_quantization(trellis);
+
+            encoder
+                .encode_16(&data, width, height, ColorType::Luma, 12)
+                .unwrap();
+
+            result
+        };
+
+        let default = encode(None);
+        let trellis = encode(Some(TrellisQuantization::default()));
+
+        assert!(trellis.len() < default.len());
+    }
+
+    #[test]
+    fn test_strip_encoder_trellis() {
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_trellis_quantization(Some(TrellisQuantization::default()));
+
+        assert!(encoder.into_strip_encoder(16, 16, ColorType::Rgb).is_err());
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_trellis_invalid_lambda() {
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_trellis_quantization(Some(TrellisQuantization::new(true, f32::NAN, 16.5)));
+    }
+
+    #[test]
+    fn test_rgb_encode_to_size() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut last_quality = 0;
+
+        for target_size in [1200, 2000, 3000, 4000, 1000000] {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_optimized_huffman_tables(true);
+
+            let TargetSizeResult {
+                quality,
+                ac_coefficients,
+            } = encoder
+                .encode_to_size(&data, width, height, ColorType::Rgb, target_size)
+                .unwrap();
+
+            assert_eq!(ac_coefficients, 63);
+            assert!(result.len() <= target_size);
+            assert!(quality >= last_quality);
+            last_quality = quality;
+
+            // The result must be the same as with an encoder using the chosen quality
+            let mut expected = Vec::new();
+            let mut encoder = Encoder::new(&mut expected, quality);
+            encoder.set_sampling_factor(SamplingFactor::F_2_2);
+            encoder.set_optimized_huffman_tables(true);
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            assert_eq!(result, expected);
+
+            // The next higher quality must exceed the target size
+            if quality < 100 {
+                let mut larger = Vec::new();
+                let mut encoder = Encoder::new(&mut larger, quality + 1);
+                encoder.set_sampling_factor(SamplingFactor::F_2_2);
+                encoder.set_optimized_huffman_tables(true);
+                encoder
+                    .encode(&data, width, height, ColorType::Rgb)
+                    .unwrap();
+
+                assert!(larger.len() > target_size);
+            }
+        }
+
+        assert_eq!(last_quality, 100);
+    }
+
+    #[test]
+    fn test_gray_encode_to_size_progressive_trellis() {
+        let (data, width, height) = create_test_img_gray();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_progressive(true);
+        encoder.set_trellis_quantization(Some(TrellisQuantization::default()));
+        encoder.add_app_segment(15, b"HOHOHO").unwrap();
+
+        let size_result = encoder
+            .encode_to_size(&data, width, height, ColorType::Luma, 3000)
+            .unwrap();
+
+        assert!(size_result.quality < 100);
+        assert!(result.len() <= 3000);
+
+        let segments = header_segments(&result);
+        assert!(segments.contains(&(0xEF, &b"HOHOHO"[..])));
+
+        let (img, info) = decode(&result);
+        assert_eq!(info.width, width);
+        assert_eq!(info.height, height);
+        assert_eq!(img.len(), data.len());
+    }
+
+    #[test]
+    fn test_encode_to_size_below_quality_1() {
+        // Noise keeps a lot of AC coefficients even with a quality of 1
+        let (width, height) = (128, 128);
+        let mut state = 1u32;
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height) * 3)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 24) as u8
+            })
+            .collect();
+
+        let mut quality_1 = Vec::new();
+        Encoder::new(&mut quality_1, 1)
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let target_size = quality_1.len() - 200;
+
+        let mut result = Vec::new();
+        let size_result = Encoder::new(&mut result, 80)
+            .encode_to_size(&data, width, height, ColorType::Rgb, target_size)
+            .unwrap();
+
+        assert_eq!(size_result.quality, 1);
+        assert!(size_result.ac_coefficients < 63);
+        assert!(result.len() <= target_size);
+
+        let (img, info) = decode(&result);
+        assert_eq!(info.width, width);
+        assert_eq!(info.height, height);
+        assert_eq!(img.len(), data.len());
+    }
+
+    #[test]
+    fn test_encode_to_size_not_reached() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut quality_1 = Vec::new();
+        Encoder::new(&mut quality_1, 1)
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let mut result = Vec::new();
+        let encoder = Encoder::new(&mut result, 80);
+
+        let err = encoder
+            .encode_to_size(&data, width, height, ColorType::Rgb, 500)
+            .unwrap_err();
+
+        // The smallest attempt only keeps the DC coefficients
+        assert!(matches!(
+            err,
+            EncodingError::TargetSizeNotReached {
+                target_size: 500,
+                min_size,
+            } if min_size > 500 && min_size < quality_1.len()
+        ));
+        assert!(result.is_empty());
+    }
+
+    fn create_half_importance_map(width: u16, height: u16, importance: u8) -> ImportanceMap {
+        let cols = (usize::from(width) + 7) / 8;
+        let rows = (usize::from(height) + 7) / 8;
+
+        ImportanceMap::from_fn(
+            cols,
+            rows,
+            |x, _| if x < cols / 2 { 255 } else { importance },
+        )
+    }
+
+    #[test]
+    fn test_rgb_importance_map() {
+        let (data, width, height) = create_test_img_rgb();
+
+        for optimize in [false, true] {
+            let encode = |map: Option<ImportanceMap>| {
+                let mut result = Vec::new();
+                let mut encoder = Encoder::new(&mut result, 80);
+                encoder.set_optimized_huffman_tables(optimize);
+                encoder.set_importance_map(map);
+
+                encoder
+                    .encode(&data, width, height, ColorType::Rgb)
+                    .unwrap();
+
+                result
+            };
+
+            let default = encode(None);
+            let full = encode(Some(create_half_importance_map(width, height, 255)));
+            let half = encode(Some(create_half_importance_map(width, height, 0)));
+
+            assert_eq!(default, full);
+            assert!(half.len() < default.len());
+
+            let (default_img, _) = decode(&default);
+            let (half_img, info) = decode(&half);
+
+            assert_eq!(info.width, width);
+            assert_eq!(info.height, height);
+
+            // The important part of the image isn't changed
+            let row_stride = usize::from(width) * 3;
+
+            for (default_row, half_row) in default_img
+                .chunks(row_stride)
+                .zip(half_img.chunks(row_stride))
+            {
+                assert_eq!(&default_row[..96 * 3], &half_row[..96 * 3]);
+                assert_ne!(&default_row[160 * 3..], &half_row[160 * 3..]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_importance_map_progressive_trellis() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_progressive(true);
+        encoder.set_trellis_quantization(Some(TrellisQuantization::default()));
+        encoder.set_importance_map(Some(create_half_importance_map(width, height, 200)));
+
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_gray_12_bit_importance_map() {
+        let (data, width, height) = create_test_img_gray_12();
+
+        let encode = |map: Option<ImportanceMap>| {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_importance_map(map);
+
+            encoder
+                .encode_16(&data, width, height, ColorType::Luma, 12)
+                .unwrap();
+
+            result
+        };
+
+        let default = encode(None);
+        let half = encode(Some(create_half_importance_map(width, height, 0)));
+
+        assert!(half.len() < default.len());
+    }
+
+    #[test]
+    fn test_strip_encoder_importance_map() {
+        let (data, width, height) = create_test_img_rgb();
+        let map = create_half_importance_map(width, height, 64);
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_importance_map(Some(map.clone()));
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        for set_on_encoder in [true, false] {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+
+            if set_on_encoder {
+                encoder.set_importance_map(Some(map.clone()));
+            }
+
+            let mut strip_encoder = encoder
+                .into_strip_encoder(width, height, ColorType::Rgb)
+                .unwrap();
+
+            if !set_on_encoder {
+                strip_encoder.set_importance_map(Some(map.clone()));
+            }
+
+            for chunk in data.chunks(row_stride * 7) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            assert_eq!(strip_encoder.finish().unwrap(), expected);
+        }
+    }
+
+    /// Hook which zeroes the AC coefficients and counts the processed blocks of each component
+    fn dc_only_hook(counts: &Arc<[AtomicUsize; 4]>) -> Box<dyn CoefficientHook + Send> {
+        let counts = counts.clone();
+
+        Box::new(
+            move |component: usize, _x: usize, _y: usize, block: &mut [i16; 64]| {
+                counts[component].fetch_add(1, Ordering::Relaxed);
+                block[1..].fill(0);
+            },
+        )
+    }
+
+    fn load_counts(counts: &[AtomicUsize; 4]) -> [usize; 4] {
+        [0, 1, 2, 3].map(|i| counts[i].load(Ordering::Relaxed))
+    }
+
+    #[test]
+    fn test_coefficient_hook() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let encode = |config: usize, hook: Option<Box<dyn CoefficientHook + Send>>| {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_sampling_factor(SamplingFactor::F_2_2);
+            encoder.set_coefficient_hook(hook);
+
+            match config {
+                0 => {}
+                1 => encoder.set_optimized_huffman_tables(true),
+                _ => encoder.set_progressive(true),
+            }
+
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            result
+        };
+
+        let mut expected_pixels = None;
+
+        for config in 0..3 {
+            let no_op = |_: usize, _: usize, _: usize, _: &mut [i16; 64]| {};
+            assert_eq!(encode(config, Some(Box::new(no_op))), encode(config, None));
+
+            let counts = Arc::new(<[AtomicUsize; 4]>::default());
+            let result = encode(config, Some(dc_only_hook(&counts)));
+
+            // Interleaved scans also pass the luma blocks which only pad the last MCU column
+            let luma_blocks = if config == 0 { 34 * 16 } else { 33 * 16 };
+            assert_eq!(load_counts(&counts), [luma_blocks, 17 * 8, 17 * 8, 0]);
+
+            assert_ne!(result, encode(config, None));
+
+            // All paths decode to the same image with flat blocks
+            let (img, _) = decode(&result);
+            assert_eq!(img[..8 * 3], [img[0], img[1], img[2]].repeat(8));
+
+            match &expected_pixels {
+                None => expected_pixels = Some(img),
+                Some(expected) => assert_eq!(&img, expected),
+            }
+        }
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_coefficient_hook(Some(dc_only_hook(&Arc::default())));
+        assert!(encoder
+            .encode_to_size(&data, width, height, ColorType::Rgb, 10_000)
+            .is_err());
+    }
+
+    #[test]
+    fn test_strip_encoder_coefficient_hook() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_coefficient_hook(Some(dc_only_hook(&Arc::default())));
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        for set_on_encoder in [true, false] {
+            let counts = Arc::new(<[AtomicUsize; 4]>::default());
+            let mut encoder = Encoder::new(Vec::new(), 80);
+
+            if set_on_encoder {
+                encoder.set_coefficient_hook(Some(dc_only_hook(&counts)));
+            }
+
+            let mut strip_encoder = encoder
+                .into_strip_encoder(width, height, ColorType::Rgb)
+                .unwrap();
+
+            if !set_on_encoder {
+                strip_encoder.set_coefficient_hook(Some(dc_only_hook(&counts)));
+            }
+
+            for chunk in data.chunks(row_stride * 7) {
+                strip_encoder.encode_strip(chunk).unwrap();
+            }
+
+            assert_eq!(strip_encoder.finish().unwrap(), expected);
+            assert_eq!(load_counts(&counts), [544, 136, 136, 0]);
+        }
+    }
+
+    #[test]
+    fn test_coefficient_hook_out_of_range() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let set_ac = |value: i16| -> Box<dyn CoefficientHook + Send> {
+            Box::new(move |_: usize, _: usize, _: usize, block: &mut [i16; 64]| {
+                block[1] = value;
+            })
+        };
+
+        for config in 0..3 {
+            let encode = |hook: Box<dyn CoefficientHook + Send>| {
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_coefficient_hook(Some(hook));
+
+                match config {
+                    0 => {}
+                    1 => encoder.set_optimized_huffman_tables(true),
+                    _ => encoder.set_progressive(true),
+                }
+
+                encoder.encode(&data, width, height, ColorType::Rgb)
+            };
+
+            assert!(encode(set_ac(1023)).is_ok());
+            assert!(encode(set_ac(-1024)).is_err());
+            assert!(encode(set_ac(2000)).is_err());
+        }
+
+        let mut strip_encoder = Encoder::new(Vec::new(), 80)
+            .into_strip_encoder(width, height, ColorType::Rgb)
+            .unwrap();
+        strip_encoder.set_coefficient_hook(Some(set_ac(2000)));
+        assert!(strip_encoder.encode_strip(&data).is_err());
+
+        // 12 bit samples allow larger coefficients
+        let (data, width, height) = create_test_img_gray_12();
+
+        let encode_12 = |hook: Box<dyn CoefficientHook + Send>| {
+            let mut encoder = Encoder::new(Vec::new(), 80);
+            encoder.set_coefficient_hook(Some(hook));
+            encoder.encode_16(&data, width, height, ColorType::Luma, 12)
+        };
+
+        assert!(encode_12(set_ac(2000)).is_ok());
+        assert!(encode_12(set_ac(16384)).is_err());
+    }
+
+    const PREDICTORS: [Predictor; 7] = [
+        Predictor::Left,
+        Predictor::Above,
+        Predictor::AboveLeft,
+        Predictor::Gradient,
+        Predictor::GradientLeft,
+        Predictor::GradientAbove,
+        Predictor::Average,
+    ];
+
+    #[test]
+    fn test_gray_lossless() {
+        let (data, width, height) = create_test_img_gray();
+
+        for predictor in PREDICTORS {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_lossless(Some(predictor));
+            encoder
+                .encode(&data, width, height, ColorType::Luma)
+                .unwrap();
+
+            let (img, info) = decode(&result);
+
+            assert_eq!(info.pixel_format, PixelFormat::L8);
+            assert_eq!(img, data, "Predictor {:?}", predictor);
+        }
+    }
+
+    #[test]
+    fn test_rgb_lossless() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::Gradient));
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments = header_segments(&result);
+        let markers: Vec<u8> = segments.iter().map(|&(marker, _)| marker).collect();
+
+        // SOF3 without JFIF header and quantization tables
+        assert!(markers.contains(&0xC3));
+        assert!(!markers.contains(&0xE0));
+        assert!(!markers.contains(&0xDB));
+
+        let (img, info) = decode(&result);
+
+        assert_eq!(info.pixel_format, PixelFormat::RGB24);
+        assert_eq!(img, data);
+    }
+
+    #[test]
+    fn test_rgb_image_lossless() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder
+            .encode_image(RgbImage(&data, width, height))
+            .unwrap();
+
+        // RGB buffers store the untransformed samples like the byte slice path
+        let (img, info) = decode(&result);
+        assert_eq!(info.pixel_format, PixelFormat::RGB24);
+        assert_eq!(img, data);
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert_eq!(result, expected);
+
+        // CMYK isn't converted to YCCK
+        let cmyk: Vec<u8> = data
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 7])
+            .collect();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder
+            .encode_image(CmykAsYcckImage(&cmyk, width, height))
+            .unwrap();
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder
+            .encode(&cmyk, width, height, ColorType::CmykAsYcck)
+            .unwrap();
+
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_bgra_lossless() {
+        let (data, width, height) = create_test_img_rgba();
+
+        let bgra: Vec<u8> = data
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::Average));
+        encoder
+            .encode(&bgra, width, height, ColorType::Bgra)
+            .unwrap();
+
+        let (img, _) = decode(&result);
+        let rgb: Vec<u8> = data
+            .chunks_exact(4)
+            .flat_map(|p| [p[0], p[1], p[2]])
+            .collect();
+
+        assert_eq!(img, rgb);
+    }
+
+    #[test]
+    fn test_gray_lossless_16() {
+        let width = 131;
+        let height = 67;
+
+        for precision in [9, 12, 16] {
+            let max_value = ((1u32 << precision) - 1) as u16;
+
+            let data: Vec<u16> = (0..width * height)
+                .map(|i| {
+                    let (x, y) = (i % width, i / width);
+                    ((x * 997 + y * 331 + (x * y) % 17) as u16) & max_value
+                })
+                .collect();
+
+            for predictor in PREDICTORS {
+                let mut result = Vec::new();
+                let mut encoder = Encoder::new(&mut result, 80);
+                encoder.set_lossless(Some(predictor));
+                encoder
+                    .encode_16(
+                        &data,
+                        width as u16,
+                        height as u16,
+                        ColorType::Luma,
+                        precision,
+                    )
+                    .unwrap();
+                let (img, info) = decode(&result);
+
+                assert_eq!(info.pixel_format, PixelFormat::L16);
+
+                let img: Vec<u16> = img
+                    .chunks_exact(2)
+                    .map(|v| u16::from_ne_bytes([v[0], v[1]]))
+                    .collect();
+
+                assert_eq!(img, data, "Precision {} {:?}", precision, predictor);
+            }
+        }
+    }
+
+    /// Returns the entropy coded data following the scan header
+    fn scan_data(data: &[u8]) -> &[u8] {
+        let (_, sos) = *header_segments(data).last().unwrap();
+        let end = sos.as_ptr() as usize - data.as_ptr() as usize + sos.len();
+        &data[end..]
+    }
+
+    #[test]
+    fn test_gray_lossless_point_transform() {
+        let (data, width, height) = create_test_img_gray();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::GradientAbove));
+        encoder.set_lossless_point_transform(3);
+        encoder
+            .encode(&data, width, height, ColorType::Luma)
+            .unwrap();
+
+        // A point transform of 3 bits must code the same differences
+        // as the 5 bit samples without point transform
+        let shifted: Vec<u16> = data.iter().map(|&v| u16::from(v >> 3)).collect();
+
+        let mut expected = Vec::new();
+        let mut encoder = Encoder::new(&mut expected, 80);
+        encoder.set_lossless(Some(Predictor::GradientAbove));
+        encoder
+            .encode_16(&shifted, width, height, ColorType::Luma, 5)
+            .unwrap();
+
+        let sos = header_segments(&result).last().unwrap().1;
+        assert_eq!(sos[sos.len() - 1], 3);
+
+        assert_eq!(scan_data(&result), scan_data(&expected));
+    }
+
+    #[test]
+    fn test_lossless_restart_interval() {
+        let (data, width, height) = create_test_img_gray();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder.set_restart_interval(width * 4);
+        encoder
+            .encode(&data, width, height, ColorType::Luma)
+            .unwrap();
+
+        let markers: Vec<u8> = header_segments(&result)
+            .iter()
+            .map(|&(marker, _)| marker)
+            .collect();
+        assert!(markers.contains(&0xDD));
+
+        let num_restarts = result
+            .windows(2)
+            .filter(|w| w[0] == 0xFF && (0xD0..=0xD7).contains(&w[1]))
+            .count();
+        assert_eq!(num_restarts, usize::from(height / 4) - 1);
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        encoder.set_restart_interval(width + 1);
+        assert!(encoder
+            .encode(&data, width, height, ColorType::Luma)
+            .is_err());
+    }
+
+    #[test]
+    fn test_lossless_invalid_samples() {
+        let data = [0u16, 1, 2, 4096];
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        let err = encoder
+            .encode_16(&data, 2, 2, ColorType::Luma, 12)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EncodingError::SampleOutOfRange {
+                value: 4096,
+                precision: 12
+            }
+        ));
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_lossless(Some(Predictor::Left));
+        let err = encoder
+            .encode_16(&data, 2, 2, ColorType::Luma, 17)
+            .unwrap_err();
+        assert!(matches!(err, EncodingError::InvalidPrecision(17)));
+
+        // DCT based encoding only supports 8 and 12 bit samples
+        let encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder.encode_16(&data, 2, 2, ColorType::Luma, 16).is_err());
+    }
+
+    #[test]
+    fn test_rgb_16_bit_samples_8_bit_precision() {
+        let (data, width, height) = create_test_img_rgb();
+        let wide: Vec<u16> = data.iter().map(|&v| u16::from(v)).collect();
+
+        let mut result = Vec::new();
+        let encoder = Encoder::new(&mut result, 80);
+        encoder
+            .encode_16(&wide, width, height, ColorType::Rgb, 8)
+            .unwrap();
+
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    /// Create a gray 12 bit image with the full sample range
+    fn create_test_img_gray_12() -> (Vec<u16>, u16, u16) {
+        let width = 97;
+        let height = 71;
+
+        let data = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                ((x * 4095 / width + (y * 73) % 900 + ((x * y) % 13) * 20) % 4096) as u16
+            })
+            .collect();
+
+        (data, width as u16, height as u16)
+    }
+
+    #[test]
+    fn test_gray_12_bit() {
+        let (data, width, height) = create_test_img_gray_12();
+
+        for quality in [1, 80, 100] {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, quality);
+            encoder.set_restart_interval(7);
+            encoder
+                .encode_16(&data, width, height, ColorType::Luma, 12)
+                .unwrap();
+
+            let segments = header_segments(&result);
+
+            // Extended sequential frame with 12 bit precision
+            let sof = segments
+                .iter()
+                .find(|&&(marker, _)| marker == 0xC1)
+                .unwrap()
+                .1;
+            assert_eq!(sof[0], 12);
+
+            // Low qualities need quantization tables with 16 bit values
+            for &(_, dqt) in segments.iter().filter(|&&(marker, _)| marker == 0xDB) {
+                if quality == 1 {
+                    assert_eq!(dqt.len(), 1 + 128);
+                    assert_eq!(dqt[0] >> 4, 1);
+                } else {
+                    assert_eq!(dqt.len(), 1 + 64);
+                    assert_eq!(dqt[0] >> 4, 0);
+                }
+            }
+
+            assert_eq!(&result[result.len() - 2..], &[0xFF, 0xD9]);
+        }
+    }
+
+    #[test]
+    fn test_rgb_12_bit_progressive_and_arithmetic() {
+        let (data, width, height) = create_test_img_rgb();
+        let wide: Vec<u16> = data.iter().map(|&v| u16::from(v) << 4).collect();
+
+        for (progressive, arithmetic, sof) in
+            [(true, false, 0xC2), (false, true, 0xC9), (true, true, 0xCA)]
+        {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder.set_progressive(progressive);
+            encoder.set_arithmetic_coding(arithmetic);
+            encoder.set_sampling_factor(SamplingFactor::R_4_2_0);
+            encoder
+                .encode_16(&wide, width, height, ColorType::Rgb, 12)
+                .unwrap();
+
+            let segments = header_segments(&result);
+
+            let frame = segments
+                .iter()
+                .find(|&&(marker, _)| marker == sof)
+                .unwrap()
+                .1;
+            assert_eq!(frame[0], 12);
+
+            // Three components converted to YCbCr
+            assert_eq!(frame[5], 3);
+            assert!(segments.iter().any(|&(marker, _)| marker == 0xE0));
+        }
+    }
+
+    #[test]
+    fn test_16_bit_quantization_table() {
+        let (data, width, height) = create_test_img_gray();
+
+        let mut table = [300u16; 64];
+        table[0] = 16;
+
+        let dqt = |result: &[u8]| -> Vec<u8> {
+            header_segments(result)
+                .iter()
+                .find(|&&(marker, _)| marker == 0xDB)
+                .unwrap()
+                .1
+                .to_vec()
+        };
+
+        // 8 bit images require 8 bit tables, so the values are limited to 255
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_quantization_tables(
+            QuantizationTableType::Custom(Box::new(table)),
+            QuantizationTableType::Default,
+        );
+        encoder
+            .encode(&data, width, height, ColorType::Luma)
+            .unwrap();
+
+        let markers: Vec<u8> = header_segments(&result)
+            .iter()
+            .map(|&(marker, _)| marker)
+            .collect();
+        assert!(markers.contains(&0xC0));
+
+        let dqt_8 = dqt(&result);
+        assert_eq!(dqt_8[0], 0x00);
+        assert_eq!(&dqt_8[1..3], &[16, 255]);
+
+        let (img, _) = decode(&result);
+        assert_eq!(img.len(), data.len());
+
+        // 12 bit images can use 16 bit tables
+        let data: Vec<u16> = data.iter().map(|&v| u16::from(v) << 4).collect();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_quantization_tables(
+            QuantizationTableType::Custom(Box::new(table)),
+            QuantizationTableType::Default,
+        );
+        encoder
+            .encode_16(&data, width, height, ColorType::Luma, 12)
+            .unwrap();
+
+        let dqt_12 = dqt(&result);
+        assert_eq!(dqt_12[0], 0x10);
+        assert_eq!(&dqt_12[1..5], &[0, 16, 1, 44]);
+    }
+
+    #[test]
+    fn test_12_bit_invalid_samples() {
+        let data = [0u16, 1, 2, 4096];
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        let err = encoder
+            .encode_16(&data, 2, 2, ColorType::Luma, 12)
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            EncodingError::SampleOutOfRange {
+                value: 4096,
+                precision: 12
+            }
+        ));
+
+        // Invalid samples must be detected before the color conversion
+        let data = [4096u16, 0, 0];
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        let err = encoder
+            .encode_16(&data, 1, 1, ColorType::Rgb, 12)
+            .unwrap_err();
+        assert!(matches!(err, EncodingError::SampleOutOfRange { .. }));
+
+        let encoder = Encoder::new(Vec::new(), 80);
+        let err = encoder
+            .encode_16(&data, 1, 1, ColorType::Rgb, 10)
+            .unwrap_err();
+        assert!(matches!(err, EncodingError::InvalidPrecision(10)));
+    }
+
+    #[test]
+    fn test_cmyk() {
+        let (data, width, height) = create_test_img_cmyk();
+
+        let mut result = Vec::new();
+        let encoder = Encoder::new(&mut result, 100);
+        encoder
+            .encode(&data, width, height, ColorType::Cmyk)
+            .unwrap();
+
+        check_result(data, width, height, &result, PixelFormat::CMYK32);
+    }
+
+    #[test]
+    fn test_ycck() {
+        let (data, width, height) = create_test_img_cmyk();
+
+        let mut result = Vec::new();
+        let encoder = Encoder::new(&mut result, 100);
+        encoder
+            .encode(&data, width, height, ColorType::CmykAsYcck)
+            .unwrap();
+
+        check_result(data, width, height, &result, PixelFormat::CMYK32);
+    }
+
+    #[test]
+    fn test_restart_interval() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 100);
+
+        encoder.set_restart_interval(32);
+        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert!(result
+            .as_slice()
+            .windows(DRI_DATA.len())
+            .any(|w| w == DRI_DATA));
+
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_restart_interval_4_1() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 100);
+        encoder.set_sampling_factor(SamplingFactor::F_4_1);
+
+        encoder.set_restart_interval(32);
+        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert!(result
+            .as_slice()
+            .windows(DRI_DATA.len())
+            .any(|w| w == DRI_DATA));
+
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_restart_interval_progressive() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 85);
+        encoder.set_progressive(true);
+
+        encoder.set_restart_interval(32);
+        const DRI_DATA: &[u8; 6] = b"\xFF\xDD\0\x04\0\x20";
+
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        assert!(result
             .as_slice()
             .windows(DRI_DATA.len())
             .any(|w| w == DRI_DATA));
 
-        check_result(data, width, height, &mut result, PixelFormat::RGB24);
+        check_result(data, width, height, &result, PixelFormat::RGB24);
+    }
+
+    #[test]
+    fn test_restart_interval_strip_encoder_matches() {
+        let (data, width, height) = create_test_img_rgb();
+        let row_stride = usize::from(width) * ColorType::Rgb.get_bytes_per_pixel();
+
+        for sampling_factor in [
+            SamplingFactor::F_1_1,
+            SamplingFactor::F_2_1,
+            SamplingFactor::F_1_2,
+            SamplingFactor::F_2_2,
+        ] {
+            for interval in [1, 5, 32, 1000] {
+                let mut expected = Vec::new();
+                let mut encoder = Encoder::new(&mut expected, 80);
+                encoder.set_sampling_factor(sampling_factor);
+                encoder.set_restart_interval(interval);
+                encoder
+                    .encode(&data, width, height, ColorType::Rgb)
+                    .unwrap();
+
+                let mut result = Vec::new();
+                let mut encoder = Encoder::new(&mut result, 80);
+                encoder.set_sampling_factor(sampling_factor);
+                encoder.set_restart_interval(interval);
+                encoder
+                    .encode_image(RgbImage(&data, width, height))
+                    .unwrap();
+
+                assert_eq!(result, expected);
+
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(sampling_factor);
+                encoder.set_restart_interval(interval);
+
+                let mut strip_encoder = encoder
+                    .into_strip_encoder(width, height, ColorType::Rgb)
+                    .unwrap();
+
+                for chunk in data.chunks(row_stride * 11) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                assert_eq!(strip_encoder.finish().unwrap(), expected);
+            }
+        }
     }
 
     #[test]
@@ -661,6 +2626,454 @@ mod tests {
         assert_eq!(icc, icc_out);
     }
 
+    #[test]
+    fn test_xmp() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let small = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+        let large: Vec<u8> = (0..200_000).map(|i| b'a' + (i % 26) as u8).collect();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.add_xmp(small).unwrap();
+        encoder.add_xmp(&large).unwrap();
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments: Vec<&[u8]> = header_segments(&result)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE1)
+            .map(|(_, data)| data)
+            .collect();
+
+        // The small packet, the standard packet of the large one and 4 extended XMP chunks
+        assert_eq!(segments.len(), 6);
+        assert_eq!(
+            segments[0],
+            [b"http://ns.adobe.com/xap/1.0/\0".as_ref(), small].concat()
+        );
+        assert!(segments[1].starts_with(b"http://ns.adobe.com/xap/1.0/\0"));
+
+        let extended: Vec<u8> = segments[2..]
+            .iter()
+            .flat_map(|segment| {
+                assert!(segment.starts_with(b"http://ns.adobe.com/xmp/extension/\0"));
+                segment[75..].iter().copied()
+            })
+            .collect();
+
+        assert_eq!(extended, large);
+
+        decode(&result);
+
+        // A separately given standard packet has to reference the GUID of the extended XMP
+        let guid = core::str::from_utf8(&segments[2][35..67]).unwrap();
+        let standard = alloc::format!(
+            "<rdf:Description dc:format=\"image/jpeg\" xmpNote:HasExtendedXMP=\"{}\"/>",
+            guid
+        );
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        assert!(encoder.add_extended_xmp(small, &large).is_err());
+        encoder
+            .add_extended_xmp(standard.as_bytes(), &large)
+            .unwrap();
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments: Vec<&[u8]> = header_segments(&result)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xE1)
+            .map(|(_, data)| data)
+            .collect();
+
+        assert_eq!(segments.len(), 5);
+        assert_eq!(
+            segments[0],
+            [
+                b"http://ns.adobe.com/xap/1.0/\0".as_ref(),
+                standard.as_bytes()
+            ]
+            .concat()
+        );
+        assert_eq!(&segments[1][35..67], guid.as_bytes());
+    }
+
+    #[test]
+    fn test_iptc() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut iptc = Iptc::new();
+        iptc.set_caption("A test image").unwrap();
+        iptc.add_byline("jpeg-encoder").unwrap();
+        iptc.add_keyword("test").unwrap();
+
+        let large = vec![0x55; 100_000];
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.add_iptc(&iptc).unwrap();
+        encoder.add_photoshop_resource(0x0FA0, &large).unwrap();
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments: Vec<&[u8]> = header_segments(&result)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xED)
+            .map(|(_, data)| data)
+            .collect();
+
+        assert_eq!(segments.len(), 3);
+
+        // Readers concatenate the resource blocks of all segments
+        let mut blocks = Vec::new();
+
+        for segment in segments {
+            assert!(segment.starts_with(b"Photoshop 3.0\0"));
+            blocks.extend_from_slice(&segment[14..]);
+        }
+
+        let iptc_data = iptc.to_bytes();
+        let (iptc_block, large_block) = blocks.split_at(12 + iptc_data.len() + iptc_data.len() % 2);
+
+        assert_eq!(&iptc_block[..6], b"8BIM\x04\x04");
+        assert_eq!(&iptc_block[8..12], &(iptc_data.len() as u32).to_be_bytes());
+        assert_eq!(&iptc_block[12..12 + iptc_data.len()], iptc_data.as_slice());
+
+        assert_eq!(&large_block[..6], b"8BIM\x0F\xA0");
+        assert_eq!(&large_block[12..], large.as_slice());
+
+        decode(&result);
+    }
+
+    #[test]
+    fn test_comment_and_marker_layout() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut exif = Exif::new(ByteOrder::BigEndian);
+        exif.set_orientation(1).unwrap();
+
+        fn configure<W: JfifWrite>(encoder: &mut Encoder<W>, layout: MarkerLayout, exif: &Exif) {
+            encoder.set_marker_layout(layout);
+            encoder.set_exif(exif).unwrap();
+            encoder.add_app_segment(15, b"HOHOHO\0").unwrap();
+            encoder.add_comment(b"First").unwrap();
+            encoder.add_comment(b"Second").unwrap();
+        }
+
+        let markers = |result: &[u8]| -> Vec<u8> {
+            header_segments(result)
+                .into_iter()
+                .map(|(marker, _)| marker)
+                .collect()
+        };
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        configure(&mut encoder, MarkerLayout::default(), &exif);
+        encoder
+            .encode(&data, width, height, ColorType::Ycbcr)
+            .unwrap();
+
+        assert_eq!(
+            markers(&result),
+            [0xE1, 0xE0, 0xEF, 0xFE, 0xFE, 0xC0, 0xDB, 0xDB, 0xC4, 0xC4, 0xC4, 0xC4, 0xDA]
+        );
+
+        let comments: Vec<&[u8]> = header_segments(&result)
+            .into_iter()
+            .filter(|(marker, _)| *marker == 0xFE)
+            .map(|(_, data)| data)
+            .collect();
+        assert_eq!(comments, [b"First".as_ref(), b"Second".as_ref()]);
+
+        // EXIF first without JFIF, the APP segments directly before the first scan
+        let layout = MarkerLayout {
+            jfif: false,
+            app_segments: SegmentPosition::AfterTables,
+            comments: SegmentPosition::BeforeTables,
+        };
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        configure(&mut encoder, layout, &exif);
+        encoder.set_restart_interval(4);
+        encoder
+            .encode(&data, width, height, ColorType::Ycbcr)
+            .unwrap();
+
+        assert_eq!(
+            markers(&result),
+            [0xE1, 0xFE, 0xFE, 0xC0, 0xDB, 0xDB, 0xC4, 0xC4, 0xC4, 0xC4, 0xDD, 0xEF, 0xDA]
+        );
+        decode(&result);
+
+        // The strip encoder writes the same layout
+        let mut strip_encoder = Encoder::new(Vec::new(), 80);
+        strip_encoder.set_restart_interval(4);
+        strip_encoder.set_exif(&exif).unwrap();
+        strip_encoder.add_app_segment(15, b"HOHOHO\0").unwrap();
+        let mut strip_encoder = strip_encoder
+            .into_strip_encoder(width, height, ColorType::Ycbcr)
+            .unwrap();
+        strip_encoder.set_marker_layout(layout).unwrap();
+        strip_encoder.add_comment(b"First").unwrap();
+        strip_encoder.add_comment(b"Second").unwrap();
+
+        let header = strip_encoder.header_bytes().unwrap();
+        assert_eq!(header, result[..header.len()]);
+
+        strip_encoder.encode_strip(&data).unwrap();
+        assert!(strip_encoder.add_comment(b"Third").is_err());
+        assert!(strip_encoder.set_marker_layout(layout).is_err());
+        assert_eq!(strip_encoder.finish().unwrap(), result);
+
+        // Comments after the tables of progressive, optimized and lossless images
+        let layout = MarkerLayout {
+            jfif: true,
+            app_segments: SegmentPosition::BeforeTables,
+            comments: SegmentPosition::AfterTables,
+        };
+
+        for config in 0..3 {
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            configure(&mut encoder, layout, &exif);
+
+            match config {
+                0 => encoder.set_progressive(true),
+                1 => encoder.set_optimized_huffman_tables(true),
+                _ => encoder.set_lossless(Some(Predictor::Left)),
+            }
+
+            encoder
+                .encode(&data, width, height, ColorType::Rgb)
+                .unwrap();
+
+            let markers = markers(&result);
+
+            // Lossless RGB images have an Adobe instead of a JFIF segment
+            let jfif_or_adobe = if config == 2 { 0xEE } else { 0xE0 };
+            assert_eq!(markers[..3], [0xE1, jfif_or_adobe, 0xEF]);
+            assert_eq!(markers[markers.len() - 3..], [0xFE, 0xFE, 0xDA]);
+
+            decode(&result);
+        }
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        configure(&mut encoder, layout, &exif);
+        encoder
+            .encode_to_size(&data, width, height, ColorType::Rgb, 10_000)
+            .unwrap();
+
+        let markers = markers(&result);
+        assert_eq!(markers[..3], [0xE1, 0xE0, 0xEF]);
+        assert_eq!(markers[markers.len() - 3..], [0xFE, 0xFE, 0xDA]);
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        assert!(encoder.add_comment(&[0; 65533]).is_ok());
+        assert!(encoder.add_comment(&[0; 65534]).is_err());
+    }
+
+    #[test]
+    fn test_jfif_thumbnail() {
+        let (data, width, height) = create_test_img_rgb();
+
+        // Uncompressed RGB thumbnail in the JFIF segment
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder
+            .set_jfif_thumbnail(Some(JfifThumbnail::Rgb {
+                max_width: 64,
+                max_height: 64,
+            }))
+            .unwrap();
+        encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .unwrap();
+
+        let segments = header_segments(&result);
+        let (marker, jfif) = segments[0];
+        assert_eq!(marker, 0xE0);
+        assert_eq!(&jfif[..5], b"JFIF\0");
+        assert_eq!(&jfif[12..14], &[64, 32]);
+        assert_eq!(jfif.len(), 14 + 64 * 32 * 3);
+
+        let pixels = &jfif[14..];
+        for &(tx, ty) in &[(0, 0), (10, 20), (40, 7), (63, 31)] {
+            let x = ((2 * tx + 1) * usize::from(width) / 128).min(255);
+            let y = (2 * ty + 1) * usize::from(height) / 64;
+            let expected = [x as u8, (y * 2) as u8, ((x + y * 2) / 2) as u8];
+
+            for c in 0..3 {
+                let v = pixels[(ty * 64 + tx) * 3 + c];
+                assert!((i16::from(v) - i16::from(expected[c])).abs() <= 6);
+            }
+        }
+
+        decode(&result);
+
+        // JPEG thumbnail in a JFXX segment after the JFIF segment
+        for &(color_type, pixel_format) in &[
+            (ColorType::Rgb, PixelFormat::RGB24),
+            (ColorType::Luma, PixelFormat::L8),
+        ] {
+            let data = if color_type == ColorType::Luma {
+                create_test_img_gray().0
+            } else {
+                data.clone()
+            };
+
+            let mut result = Vec::new();
+            let mut encoder = Encoder::new(&mut result, 80);
+            encoder
+                .set_jfif_thumbnail(Some(JfifThumbnail::Jpeg {
+                    max_width: 100,
+                    max_height: 100,
+                    quality: 70,
+                }))
+                .unwrap();
+            encoder.encode(&data, width, height, color_type).unwrap();
+
+            let segments = header_segments(&result);
+            assert_eq!(segments[0].0, 0xE0);
+            assert_eq!(&segments[0].1[12..], &[0, 0]);
+
+            let (marker, jfxx) = segments[1];
+            assert_eq!(marker, 0xE0);
+            assert_eq!(&jfxx[..6], b"JFXX\0\x10");
+
+            // The thumbnail itself has no JFIF segment
+            let thumbnail = &jfxx[6..];
+            assert_eq!(header_segments(thumbnail)[0].0, 0xC0);
+
+            let (_, info) = decode(thumbnail);
+            assert_eq!((info.width, info.height), (100, 50));
+            assert_eq!(info.pixel_format, pixel_format);
+
+            decode(&result);
+        }
+
+        // The header with the thumbnail is written once for a target size
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder
+            .set_jfif_thumbnail(Some(JfifThumbnail::Jpeg {
+                max_width: 32,
+                max_height: 32,
+                quality: 50,
+            }))
+            .unwrap();
+        encoder
+            .encode_to_size(&data, width, height, ColorType::Rgb, 10_000)
+            .unwrap();
+
+        let segments = header_segments(&result);
+        assert_eq!(&segments[1].1[..5], b"JFXX\0");
+        decode(&result);
+
+        // Invalid settings
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        let rgb = |max_width, max_height| {
+            Some(JfifThumbnail::Rgb {
+                max_width,
+                max_height,
+            })
+        };
+        assert!(encoder.set_jfif_thumbnail(rgb(0, 10)).is_err());
+        assert!(encoder.set_jfif_thumbnail(rgb(148, 148)).is_err());
+        assert!(encoder.set_jfif_thumbnail(rgb(147, 148)).is_ok());
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_jfif_thumbnail(rgb(16, 16)).unwrap();
+        encoder.set_marker_layout(MarkerLayout {
+            jfif: false,
+            ..MarkerLayout::default()
+        });
+        assert!(encoder
+            .encode(&data, width, height, ColorType::Rgb)
+            .is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_jfif_thumbnail(rgb(16, 16)).unwrap();
+        assert!(encoder
+            .encode(&[0; 16 * 16 * 4], 16, 16, ColorType::Cmyk)
+            .is_err());
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_jfif_thumbnail(rgb(16, 16)).unwrap();
+        assert!(encoder
+            .into_strip_encoder(width, height, ColorType::Rgb)
+            .is_err());
+    }
+
+    #[test]
+    fn test_exif() {
+        let (data, width, height) = create_test_img_rgb();
+
+        let mut thumbnail = Vec::new();
+        Encoder::new(&mut thumbnail, 50)
+            .encode(&data[..16 * 16 * 3], 16, 16, ColorType::Rgb)
+            .unwrap();
+
+        let mut exif = Exif::new(ByteOrder::LittleEndian);
+        exif.set_orientation(3).unwrap();
+        exif.set_make("jpeg-encoder").unwrap();
+        exif.set_capture_time("2024:05:17 13:45:09").unwrap();
+        exif.set_gps_position(52.52, 13.405, Some(34.0)).unwrap();
+        exif.set_thumbnail(&thumbnail).unwrap();
+
+        let exif_data = exif.to_bytes().unwrap();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_exif(&exif).unwrap();
+        encoder.add_app_segment(15, b"HOHOHO\0").unwrap();
+        encoder
+            .encode(&data, width, height, ColorType::Ycbcr)
+            .unwrap();
+
+        // The EXIF segment directly follows SOI
+        let segments = header_segments(&result);
+        assert_eq!(segments[0], (0xE1, exif_data.as_slice()));
+        assert_eq!(segments[1].0, 0xE0);
+        assert_eq!(segments[2], (0xEF, b"HOHOHO\0".as_ref()));
+
+        let mut decoder = Decoder::new(result.as_slice());
+        decoder.decode().unwrap();
+        assert_eq!(decoder.exif_data(), Some(&exif_data[6..]));
+
+        // The strip encoder writes the same segment if it's set on the encoder or the strip encoder
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.set_exif(&exif).unwrap();
+        encoder.add_app_segment(15, b"HOHOHO\0").unwrap();
+        let strip_encoder = encoder
+            .into_strip_encoder(width, height, ColorType::Ycbcr)
+            .unwrap();
+        let header = strip_encoder.header_bytes().unwrap();
+        assert_eq!(header, result[..header.len()]);
+
+        let mut encoder = Encoder::new(Vec::new(), 80);
+        encoder.add_app_segment(15, b"HOHOHO\0").unwrap();
+        let mut strip_encoder = encoder
+            .into_strip_encoder(width, height, ColorType::Ycbcr)
+            .unwrap();
+        strip_encoder.set_exif(&exif).unwrap();
+
+        let row_stride = usize::from(width) * 3;
+        for chunk in data.chunks(row_stride * 16) {
+            strip_encoder.encode_strip(chunk).unwrap();
+        }
+
+        assert!(strip_encoder.set_exif(&exif).is_err());
+        assert_eq!(strip_encoder.finish().unwrap(), result);
+    }
+
     #[test]
     fn test_rgb_optimized_missing_table_frequency() {
         let data = vec![0xfb, 0x15, 0x15];
@@ -672,6 +3085,6 @@ mod tests {
 
         encoder.encode(&data, 1, 1, ColorType::Rgb).unwrap();
 
-        check_result(data, 1, 1, &mut result, PixelFormat::RGB24);
+        check_result(data, 1, 1, &result, PixelFormat::RGB24);
     }
 }
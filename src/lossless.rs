@@ -0,0 +1,317 @@
+/*
+ * Lossless encoding as described in Annex H of T.81.
+ */
+
+use alloc::vec::Vec;
+
+use crate::encoder::Component;
+use crate::huffman::{CodingClass, HuffmanTable};
+use crate::image_buffer::ImageBuffer16;
+use crate::marker::{Marker, SOFType};
+use crate::writer::{JfifWrite, JfifWriter};
+use crate::EncodingError;
+
+/// # Predictors for lossless encoding
+///
+/// The predictors of Table H.1 of T.81 where `Ra` is the reconstructed sample to the left,
+/// `Rb` the sample above and `Rc` the sample above left of the current sample.
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Predictor {
+    /// Ra
+    Left = 1,
+
+    /// Rb
+    Above = 2,
+
+    /// Rc
+    AboveLeft = 3,
+
+    /// Ra + Rb - Rc
+    Gradient = 4,
+
+    /// Ra + ((Rb - Rc) >> 1)
+    GradientLeft = 5,
+
+    /// Rb + ((Ra - Rc) >> 1)
+    GradientAbove = 6,
+
+    /// (Ra + Rb) / 2
+    Average = 7,
+}
+
+impl Predictor {
+    #[inline]
+    fn predict(self, ra: i32, rb: i32, rc: i32) -> i32 {
+        use Predictor::*;
+
+        match self {
+            Left => ra,
+            Above => rb,
+            AboveLeft => rc,
+            Gradient => ra + rb - rc,
+            GradientLeft => ra + ((rb - rc) >> 1),
+            GradientAbove => rb + ((ra - rc) >> 1),
+            Average => (ra + rb) >> 1,
+        }
+    }
+}
+
+/// Samples of all components after the point transform
+struct Samples {
+    width: usize,
+    height: usize,
+    components: [Vec<u16>; 4],
+}
+
+impl Samples {
+    fn read<I: ImageBuffer16>(
+        image: &I,
+        num_components: usize,
+        point_transform: u8,
+    ) -> Result<Samples, EncodingError> {
+        let width = usize::from(image.width());
+        let height = usize::from(image.height());
+        let precision = image.precision();
+        let max_value = ((1u32 << precision) - 1) as u16;
+
+        let mut components: [Vec<u16>; 4] = Default::default();
+        for component in &mut components[..num_components] {
+            component.reserve_exact(width * height);
+        }
+
+        for y in 0..image.height() {
+            image.fill_buffers(y, &mut components);
+        }
+
+        for component in &mut components[..num_components] {
+            debug_assert_eq!(component.len(), width * height);
+
+            for value in component.iter_mut() {
+                if *value > max_value {
+                    return Err(EncodingError::SampleOutOfRange {
+                        value: *value,
+                        precision,
+                    });
+                }
+
+                *value >>= point_transform;
+            }
+        }
+
+        Ok(Samples {
+            width,
+            height,
+            components,
+        })
+    }
+
+    /// Call `f` with the component index and difference of each sample in interleaved order
+    ///
+    /// `restart_interval` must be a multiple of the width.
+    fn for_each_difference<F>(
+        &self,
+        num_components: usize,
+        predictor: Predictor,
+        initial_prediction: i32,
+        restart_interval: usize,
+        mut f: F,
+    ) -> Result<(), EncodingError>
+    where
+        F: FnMut(usize, usize, i16) -> Result<(), EncodingError>,
+    {
+        let width = self.width;
+
+        for y in 0..self.height {
+            // The first line of each restart interval is predicted like the first line of the image
+            let first_line =
+                y == 0 || (restart_interval > 0 && (y * width) % restart_interval == 0);
+
+            for x in 0..width {
+                let pos = y * width + x;
+
+                for (i, samples) in self.components[..num_components].iter().enumerate() {
+                    let prediction = match (first_line, x) {
+                        (true, 0) => initial_prediction,
+                        (true, _) => i32::from(samples[pos - 1]),
+                        (false, 0) => i32::from(samples[pos - width]),
+                        (false, _) => predictor.predict(
+                            i32::from(samples[pos - 1]),
+                            i32::from(samples[pos - width]),
+                            i32::from(samples[pos - width - 1]),
+                        ),
+                    };
+
+                    // The difference is calculated modulo 2^16
+                    let difference = (i32::from(samples[pos]) - prediction) as i16;
+
+                    f(pos, i, difference)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Returns the magnitude category and additional bits of a difference
+///
+/// Table H.2, the difference 32768 has no additional bits.
+#[inline]
+fn difference_code(difference: i16) -> (u8, u16) {
+    if difference == i16::MIN {
+        return (16, 0);
+    }
+
+    let value = i32::from(difference);
+    let magnitude = value.unsigned_abs();
+    let size = 32 - magnitude.leading_zeros();
+
+    // Negative differences are coded as the one's complement of the magnitude
+    let bits = if value < 0 { value - 1 } else { value };
+
+    (size as u8, (bits & ((1 << size) - 1)) as u16)
+}
+
+/// Encode the image in a single interleaved lossless scan
+///
+/// Writes the frame header, the optimized huffman tables, the given segments and the scan.
+/// All components must have a sampling factor of 1.
+pub(crate) fn encode_lossless<W: JfifWrite, I: ImageBuffer16>(
+    writer: &mut JfifWriter<W>,
+    image: &I,
+    components: &[Component],
+    predictor: Predictor,
+    point_transform: u8,
+    restart_interval: Option<u16>,
+    segments_after_tables: &[(Marker, &[u8])],
+) -> Result<(), EncodingError> {
+    let precision = image.precision();
+    let num_components = components.len();
+
+    debug_assert!((2..=16).contains(&precision));
+    debug_assert!(point_transform < precision);
+
+    let samples = Samples::read(image, num_components, point_transform)?;
+
+    let initial_prediction = 1 << (precision - point_transform - 1);
+    let restart_interval = usize::from(restart_interval.unwrap_or(0));
+
+    // Build optimized huffman tables for the differences
+    let mut freq = [[0u32; 257]; 2];
+    let mut used_tables = [false; 2];
+
+    for table_freq in &mut freq {
+        table_freq[256] = 1;
+    }
+
+    for component in components {
+        used_tables[usize::from(component.dc_huffman_table)] = true;
+    }
+
+    samples.for_each_difference(
+        num_components,
+        predictor,
+        initial_prediction,
+        restart_interval,
+        |_, i, difference| {
+            let (size, _) = difference_code(difference);
+            freq[usize::from(components[i].dc_huffman_table)][usize::from(size)] += 1;
+            Ok(())
+        },
+    )?;
+
+    let tables = [0, 1].map(|i| used_tables[i].then(|| HuffmanTable::new_optimized(freq[i])));
+
+    writer.write_frame_header(
+        image.width(),
+        image.height(),
+        components,
+        SOFType::Lossless,
+        precision,
+    )?;
+
+    for (i, table) in tables.iter().enumerate() {
+        if let Some(table) = table {
+            writer.write_huffman_segment(CodingClass::Dc, i as u8, table)?;
+        }
+    }
+
+    let component_tables: Vec<&HuffmanTable> = components
+        .iter()
+        .filter_map(|component| tables[usize::from(component.dc_huffman_table)].as_ref())
+        .collect();
+
+    if restart_interval > 0 {
+        writer.write_dri(restart_interval as u16)?;
+    }
+
+    for &(marker, data) in segments_after_tables {
+        writer.write_segment(marker, data)?;
+    }
+
+    writer.write_scan_header(
+        &components.iter().collect::<Vec<_>>(),
+        Some((predictor as u8, 0)),
+        Some((0, point_transform)),
+    )?;
+
+    let mut restarts = 0;
+
+    samples.for_each_difference(
+        num_components,
+        predictor,
+        initial_prediction,
+        restart_interval,
+        |pos, i, difference| {
+            if restart_interval > 0 && i == 0 && pos > 0 && pos % restart_interval == 0 {
+                writer.finalize_bit_buffer()?;
+                writer.write_marker(Marker::RST(restarts))?;
+                restarts = (restarts + 1) & 7;
+            }
+
+            let (size, value) = difference_code(difference);
+
+            // Category 16 is coded without additional bits
+            let num_bits = if size == 16 { 0 } else { size };
+
+            writer.huffman_encode_value(num_bits, size, value, component_tables[i])
+        },
+    )?;
+
+    writer.finalize_bit_buffer()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predictors() {
+        let (ra, rb, rc) = (100, 60, 81);
+
+        let expected = [
+            (Predictor::Left, 100),
+            (Predictor::Above, 60),
+            (Predictor::AboveLeft, 81),
+            (Predictor::Gradient, 79),
+            (Predictor::GradientLeft, 89),
+            (Predictor::GradientAbove, 69),
+            (Predictor::Average, 80),
+        ];
+
+        for (predictor, value) in expected {
+            assert_eq!(predictor.predict(ra, rb, rc), value, "{:?}", predictor);
+        }
+    }
+
+    #[test]
+    fn test_difference_code() {
+        assert_eq!(difference_code(0), (0, 0));
+        assert_eq!(difference_code(1), (1, 1));
+        assert_eq!(difference_code(-1), (1, 0));
+        assert_eq!(difference_code(32767), (15, 32767));
+        assert_eq!(difference_code(-32767), (15, 0));
+        assert_eq!(difference_code(i16::MIN), (16, 0));
+    }
+}
@@ -0,0 +1,260 @@
+/*
+ * NEON version of the forward DCT in fdct.rs
+ *
+ * The 1-D DCTs of 8 rows or columns are done at once with one row or column per lane.
+ * All products are calculated with 32 bits, so the results are identical to the scalar
+ * implementation.
+ */
+
+use core::arch::aarch64::{
+    int16x8_t, int32x4_t, vaddq_s32, vdupq_n_s16, vget_low_s16, vld1q_s16, vmovl_high_s16,
+    vmovl_s16, vmovn_high_s32, vmovn_s32, vmulq_n_s32, vreinterpretq_s16_s64,
+    vreinterpretq_s32_s16, vreinterpretq_s64_s32, vrshrq_n_s32, vshlq_n_s32, vst1q_s16, vsubq_s32,
+    vtrn1q_s16, vtrn1q_s32, vtrn1q_s64, vtrn2q_s16, vtrn2q_s32, vtrn2q_s64,
+};
+
+const CONST_BITS: i32 = 13;
+const PASS1_BITS: i32 = 2;
+
+const FIX_0_298631336: i32 = 2446;
+const FIX_0_390180644: i32 = 3196;
+const FIX_0_541196100: i32 = 4433;
+const FIX_0_765366865: i32 = 6270;
+const FIX_0_899976223: i32 = 7373;
+const FIX_1_175875602: i32 = 9633;
+const FIX_1_501321110: i32 = 12299;
+const FIX_1_847759065: i32 = 15137;
+const FIX_1_961570560: i32 = 16069;
+const FIX_2_053119869: i32 = 16819;
+const FIX_2_562915447: i32 = 20995;
+const FIX_3_072711026: i32 = 25172;
+
+const DESCALE_P1: i32 = CONST_BITS - PASS1_BITS;
+const DESCALE_P2: i32 = CONST_BITS + PASS1_BITS;
+
+/// 8 lanes of 32 bit values
+#[derive(Copy, Clone)]
+struct Lanes(int32x4_t, int32x4_t);
+
+#[inline(always)]
+unsafe fn widen(v: int16x8_t) -> Lanes {
+    Lanes(vmovl_s16(vget_low_s16(v)), vmovl_high_s16(v))
+}
+
+#[inline(always)]
+unsafe fn narrow(v: Lanes) -> int16x8_t {
+    vmovn_high_s32(vmovn_s32(v.0), v.1)
+}
+
+#[inline(always)]
+unsafe fn add(a: Lanes, b: Lanes) -> Lanes {
+    Lanes(vaddq_s32(a.0, b.0), vaddq_s32(a.1, b.1))
+}
+
+#[inline(always)]
+unsafe fn sub(a: Lanes, b: Lanes) -> Lanes {
+    Lanes(vsubq_s32(a.0, b.0), vsubq_s32(a.1, b.1))
+}
+
+#[inline(always)]
+unsafe fn mul(a: Lanes, c: i32) -> Lanes {
+    Lanes(vmulq_n_s32(a.0, c), vmulq_n_s32(a.1, c))
+}
+
+#[inline(always)]
+unsafe fn shl<const N: i32>(a: Lanes) -> Lanes {
+    Lanes(vshlq_n_s32::<N>(a.0), vshlq_n_s32::<N>(a.1))
+}
+
+/// Right shift with rounding
+#[inline(always)]
+unsafe fn descale<const N: i32>(a: Lanes) -> Lanes {
+    Lanes(vrshrq_n_s32::<N>(a.0), vrshrq_n_s32::<N>(a.1))
+}
+
+#[inline(always)]
+unsafe fn transpose(v: [int16x8_t; 8]) -> [int16x8_t; 8] {
+    let t0 = vreinterpretq_s32_s16(vtrn1q_s16(v[0], v[1]));
+    let t1 = vreinterpretq_s32_s16(vtrn2q_s16(v[0], v[1]));
+    let t2 = vreinterpretq_s32_s16(vtrn1q_s16(v[2], v[3]));
+    let t3 = vreinterpretq_s32_s16(vtrn2q_s16(v[2], v[3]));
+    let t4 = vreinterpretq_s32_s16(vtrn1q_s16(v[4], v[5]));
+    let t5 = vreinterpretq_s32_s16(vtrn2q_s16(v[4], v[5]));
+    let t6 = vreinterpretq_s32_s16(vtrn1q_s16(v[6], v[7]));
+    let t7 = vreinterpretq_s32_s16(vtrn2q_s16(v[6], v[7]));
+
+    let u0 = vreinterpretq_s64_s32(vtrn1q_s32(t0, t2));
+    let u1 = vreinterpretq_s64_s32(vtrn1q_s32(t1, t3));
+    let u2 = vreinterpretq_s64_s32(vtrn2q_s32(t0, t2));
+    let u3 = vreinterpretq_s64_s32(vtrn2q_s32(t1, t3));
+    let u4 = vreinterpretq_s64_s32(vtrn1q_s32(t4, t6));
+    let u5 = vreinterpretq_s64_s32(vtrn1q_s32(t5, t7));
+    let u6 = vreinterpretq_s64_s32(vtrn2q_s32(t4, t6));
+    let u7 = vreinterpretq_s64_s32(vtrn2q_s32(t5, t7));
+
+    [
+        vreinterpretq_s16_s64(vtrn1q_s64(u0, u4)),
+        vreinterpretq_s16_s64(vtrn1q_s64(u1, u5)),
+        vreinterpretq_s16_s64(vtrn1q_s64(u2, u6)),
+        vreinterpretq_s16_s64(vtrn1q_s64(u3, u7)),
+        vreinterpretq_s16_s64(vtrn2q_s64(u0, u4)),
+        vreinterpretq_s16_s64(vtrn2q_s64(u1, u5)),
+        vreinterpretq_s16_s64(vtrn2q_s64(u2, u6)),
+        vreinterpretq_s16_s64(vtrn2q_s64(u3, u7)),
+    ]
+}
+
+/// 1-D DCT of the 8 lanes
+///
+/// The first pass scales the results by 2**PASS1_BITS, the second one removes this scaling.
+#[inline(always)]
+unsafe fn dct_1d<const FIRST_PASS: bool>(v: [int16x8_t; 8]) -> [int16x8_t; 8] {
+    let v0 = widen(v[0]);
+    let v1 = widen(v[1]);
+    let v2 = widen(v[2]);
+    let v3 = widen(v[3]);
+    let v4 = widen(v[4]);
+    let v5 = widen(v[5]);
+    let v6 = widen(v[6]);
+    let v7 = widen(v[7]);
+
+    let tmp0 = add(v0, v7);
+    let tmp7 = sub(v0, v7);
+    let tmp1 = add(v1, v6);
+    let tmp6 = sub(v1, v6);
+    let tmp2 = add(v2, v5);
+    let tmp5 = sub(v2, v5);
+    let tmp3 = add(v3, v4);
+    let tmp4 = sub(v3, v4);
+
+    let descale_odd = |x: Lanes| {
+        if FIRST_PASS {
+            descale::<DESCALE_P1>(x)
+        } else {
+            descale::<DESCALE_P2>(x)
+        }
+    };
+
+    // Even part
+
+    let tmp10 = add(tmp0, tmp3);
+    let tmp13 = sub(tmp0, tmp3);
+    let tmp11 = add(tmp1, tmp2);
+    let tmp12 = sub(tmp1, tmp2);
+
+    let (out0, out4) = if FIRST_PASS {
+        (
+            shl::<PASS1_BITS>(add(tmp10, tmp11)),
+            shl::<PASS1_BITS>(sub(tmp10, tmp11)),
+        )
+    } else {
+        (
+            descale::<PASS1_BITS>(add(tmp10, tmp11)),
+            descale::<PASS1_BITS>(sub(tmp10, tmp11)),
+        )
+    };
+
+    let z1 = mul(add(tmp12, tmp13), FIX_0_541196100);
+    let out2 = descale_odd(add(z1, mul(tmp13, FIX_0_765366865)));
+    let out6 = descale_odd(add(z1, mul(tmp12, -FIX_1_847759065)));
+
+    // Odd part
+
+    let z1 = add(tmp4, tmp7);
+    let z2 = add(tmp5, tmp6);
+    let z3 = add(tmp4, tmp6);
+    let z4 = add(tmp5, tmp7);
+    let z5 = mul(add(z3, z4), FIX_1_175875602);
+
+    let tmp4 = mul(tmp4, FIX_0_298631336);
+    let tmp5 = mul(tmp5, FIX_2_053119869);
+    let tmp6 = mul(tmp6, FIX_3_072711026);
+    let tmp7 = mul(tmp7, FIX_1_501321110);
+    let z1 = mul(z1, -FIX_0_899976223);
+    let z2 = mul(z2, -FIX_2_562915447);
+    let z3 = mul(z3, -FIX_1_961570560);
+    let z4 = mul(z4, -FIX_0_390180644);
+
+    let z3 = add(z3, z5);
+    let z4 = add(z4, z5);
+
+    let out7 = descale_odd(add(add(tmp4, z1), z3));
+    let out5 = descale_odd(add(add(tmp5, z2), z4));
+    let out3 = descale_odd(add(add(tmp6, z2), z3));
+    let out1 = descale_odd(add(add(tmp7, z1), z4));
+
+    [
+        narrow(out0),
+        narrow(out1),
+        narrow(out2),
+        narrow(out3),
+        narrow(out4),
+        narrow(out5),
+        narrow(out6),
+        narrow(out7),
+    ]
+}
+
+#[inline(always)]
+pub fn fdct_neon(data: &mut [i16; 64]) {
+    unsafe {
+        fdct_neon_internal(data);
+    }
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn fdct_neon_internal(data: &mut [i16; 64]) {
+    let mut rows = [vdupq_n_s16(0); 8];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        *row = vld1q_s16(data.as_ptr().add(i * 8));
+    }
+
+    // Pass 1: process rows with one row per lane.
+    // The results of 8 bit samples fit into 16 bits.
+    let columns = dct_1d::<true>(transpose(rows));
+
+    // Pass 2: process columns with one column per lane
+    let rows = dct_1d::<false>(transpose(columns));
+
+    for (i, row) in rows.iter().enumerate() {
+        vst1q_s16(data.as_mut_ptr().add(i * 8), *row);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fdct_neon;
+    use crate::fdct::fdct;
+
+    #[test]
+    fn test_fdct_neon() {
+        let mut seed = 1u32;
+
+        for _ in 0..1000 {
+            let mut block = [0i16; 64];
+
+            for v in block.iter_mut() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                *v = ((seed >> 16) % 256) as i16 - 128;
+            }
+
+            let mut expected = block;
+            fdct(&mut expected);
+
+            fdct_neon(&mut block);
+
+            assert_eq!(block, expected);
+        }
+
+        for value in [-128, 127] {
+            let mut block = [value; 64];
+            let mut expected = block;
+
+            fdct(&mut expected);
+            fdct_neon(&mut block);
+
+            assert_eq!(block, expected);
+        }
+    }
+}
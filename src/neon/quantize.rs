@@ -0,0 +1,106 @@
+use core::arch::aarch64::{
+    int32x4_t, vabsq_s32, vaddq_s32, vbslq_s32, vcltzq_s32, vget_low_s16, vld1q_s16, vld1q_s32,
+    vmovl_high_s16, vmovl_s16, vmovn_high_s32, vmovn_s32, vmulq_s32, vnegq_s32, vshrq_n_s32,
+    vst1q_s16,
+};
+
+use crate::quantization::{QuantizationTable, SHIFT};
+use crate::writer::ZIGZAG;
+
+/// Quantize a block of DCT coefficients in natural order into zigzag order
+///
+/// Uses the same reciprocals as [QuantizationTable::quantize], so the results are identical.
+#[inline(always)]
+pub(crate) fn quantize_block_neon(
+    block: &[i16; 64],
+    q_block: &mut [i16; 64],
+    table: &QuantizationTable,
+) {
+    unsafe {
+        quantize_block_neon_internal(block, q_block, table);
+    }
+}
+
+#[target_feature(enable = "neon")]
+unsafe fn quantize_block_neon_internal(
+    block: &[i16; 64],
+    q_block: &mut [i16; 64],
+    table: &QuantizationTable,
+) {
+    #[inline(always)]
+    unsafe fn quantize(
+        values: int32x4_t,
+        reciprocals: int32x4_t,
+        corrections: int32x4_t,
+    ) -> int32x4_t {
+        let abs_values = vabsq_s32(values);
+
+        let product = vmulq_s32(vaddq_s32(abs_values, corrections), reciprocals);
+        let product = vshrq_n_s32::<{ SHIFT as i32 }>(product);
+
+        vbslq_s32(vcltzq_s32(values), vnegq_s32(product), product)
+    }
+
+    let (reciprocals, corrections) = table.reciprocals();
+
+    let mut natural = [0i16; 64];
+
+    for i in (0..64).step_by(8) {
+        let values = vld1q_s16(block.as_ptr().add(i));
+
+        let low = quantize(
+            vmovl_s16(vget_low_s16(values)),
+            vld1q_s32(reciprocals.as_ptr().add(i)),
+            vld1q_s32(corrections.as_ptr().add(i)),
+        );
+
+        let high = quantize(
+            vmovl_high_s16(values),
+            vld1q_s32(reciprocals.as_ptr().add(i + 4)),
+            vld1q_s32(corrections.as_ptr().add(i + 4)),
+        );
+
+        vst1q_s16(
+            natural.as_mut_ptr().add(i),
+            vmovn_high_s32(vmovn_s32(low), high),
+        );
+    }
+
+    for (q, &z) in q_block.iter_mut().zip(ZIGZAG.iter()) {
+        *q = natural[z as usize & 0x3f];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantize_block_neon;
+    use crate::encoder::{DefaultOperations, Operations};
+    use crate::quantization::{QuantizationTable, QuantizationTableType};
+
+    #[test]
+    fn test_quantize_neon() {
+        let mut block = [0i16; 64];
+
+        for (i, v) in block.iter_mut().enumerate() {
+            *v = (i as i16 * 257 - 8000) * if i % 3 == 0 { -1 } else { 1 };
+        }
+
+        for quality in [1, 50, 90, 100] {
+            for luma in [true, false] {
+                let table = QuantizationTable::new_with_quality(
+                    &QuantizationTableType::Default,
+                    quality,
+                    luma,
+                );
+
+                let mut expected = [0i16; 64];
+                DefaultOperations::quantize_block(&block, &mut expected, &table);
+
+                let mut q_block = [0i16; 64];
+                quantize_block_neon(&block, &mut q_block, &table);
+
+                assert_eq!(q_block, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use core::arch::aarch64::{
+    int32x4_t, uint8x16_t, vaddq_s32, vdupq_n_s32, vget_low_u16, vget_low_u8, vld3q_u8, vld4q_u8,
+    vmlaq_n_s32, vmovl_high_u16, vmovl_high_u8, vmovl_u16, vmovl_u8, vmovn_high_u16,
+    vmovn_high_u32, vmovn_u16, vmovn_u32, vmulq_n_s32, vreinterpretq_s32_u32,
+    vreinterpretq_u32_s32, vshrq_n_u32, vst1q_u8,
+};
+
+use alloc::vec::Vec;
+
+use crate::image_buffer::fill_rgb_buffers;
+use crate::{rgb_to_ycbcr, ImageBuffer, JpegColorType};
+
+/// Widen 16 samples to 4 vectors of 32 bit values
+#[inline(always)]
+unsafe fn widen(v: uint8x16_t) -> [int32x4_t; 4] {
+    let low = vmovl_u8(vget_low_u8(v));
+    let high = vmovl_high_u8(v);
+
+    [
+        vreinterpretq_s32_u32(vmovl_u16(vget_low_u16(low))),
+        vreinterpretq_s32_u32(vmovl_high_u16(low)),
+        vreinterpretq_s32_u32(vmovl_u16(vget_low_u16(high))),
+        vreinterpretq_s32_u32(vmovl_high_u16(high)),
+    ]
+}
+
+/// Descale 4 vectors of values scaled by 2^16 and narrow them to 16 samples
+#[inline(always)]
+unsafe fn descale(v: [int32x4_t; 4]) -> uint8x16_t {
+    // The results of the conversion are never negative
+    let v0 = vshrq_n_u32::<16>(vreinterpretq_u32_s32(vaddq_s32(v[0], vdupq_n_s32(0x7FFF))));
+    let v1 = vshrq_n_u32::<16>(vreinterpretq_u32_s32(vaddq_s32(v[1], vdupq_n_s32(0x7FFF))));
+    let v2 = vshrq_n_u32::<16>(vreinterpretq_u32_s32(vaddq_s32(v[2], vdupq_n_s32(0x7FFF))));
+    let v3 = vshrq_n_u32::<16>(vreinterpretq_u32_s32(vaddq_s32(v[3], vdupq_n_s32(0x7FFF))));
+
+    let low = vmovn_high_u32(vmovn_u32(v0), v1);
+    let high = vmovn_high_u32(vmovn_u32(v2), v3);
+
+    vmovn_high_u16(vmovn_u16(low), high)
+}
+
+/// Convert 16 pixels with the same calculation as [rgb_to_ycbcr]
+#[inline(always)]
+unsafe fn convert(
+    r: uint8x16_t,
+    g: uint8x16_t,
+    b: uint8x16_t,
+) -> (uint8x16_t, uint8x16_t, uint8x16_t) {
+    let r = widen(r);
+    let g = widen(g);
+    let b = widen(b);
+
+    let mut y = [vdupq_n_s32(0); 4];
+    let mut cb = [vdupq_n_s32(0); 4];
+    let mut cr = [vdupq_n_s32(0); 4];
+
+    for i in 0..4 {
+        y[i] = vmulq_n_s32(r[i], 19595);
+        y[i] = vmlaq_n_s32(y[i], g[i], 38470);
+        y[i] = vmlaq_n_s32(y[i], b[i], 7471);
+
+        cb[i] = vmlaq_n_s32(vdupq_n_s32(128 << 16), r[i], -11059);
+        cb[i] = vmlaq_n_s32(cb[i], g[i], -21709);
+        cb[i] = vmlaq_n_s32(cb[i], b[i], 32768);
+
+        cr[i] = vmlaq_n_s32(vdupq_n_s32(128 << 16), r[i], 32768);
+        cr[i] = vmlaq_n_s32(cr[i], g[i], -27439);
+        cr[i] = vmlaq_n_s32(cr[i], b[i], -5329);
+    }
+
+    (descale(y), descale(cb), descale(cr))
+}
+
+macro_rules! ycbcr_image_neon {
+    ($name:ident, $num_colors:expr, $load:ident, $o1:tt, $o2:tt, $o3:tt) => {
+        pub(crate) struct $name<'a>(pub &'a [u8], pub u16, pub u16);
+
+        impl<'a> $name<'a> {
+            #[target_feature(enable = "neon")]
+            unsafe fn fill_buffers_neon(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let width = usize::from(self.width());
+
+                let start = usize::from(y) * width * $num_colors;
+                let line = &self.0[start..start + width * $num_colors];
+
+                let mut chunks = line.chunks_exact(16 * $num_colors);
+
+                let mut values = [[0u8; 16]; 3];
+
+                for chunk in &mut chunks {
+                    let pixels = $load(chunk.as_ptr());
+
+                    let (y, cb, cr) = convert(pixels.$o1, pixels.$o2, pixels.$o3);
+
+                    vst1q_u8(values[0].as_mut_ptr(), y);
+                    vst1q_u8(values[1].as_mut_ptr(), cb);
+                    vst1q_u8(values[2].as_mut_ptr(), cr);
+
+                    for (buffer, values) in buffers.iter_mut().zip(values.iter()) {
+                        buffer.extend_from_slice(values);
+                    }
+                }
+
+                for pixel in chunks.remainder().chunks_exact($num_colors) {
+                    let (y, cb, cr) = rgb_to_ycbcr(pixel[$o1], pixel[$o2], pixel[$o3]);
+
+                    buffers[0].push(y);
+                    buffers[1].push(cb);
+                    buffers[2].push(cr);
+                }
+            }
+        }
+
+        impl<'a> ImageBuffer for $name<'a> {
+            fn get_jpeg_color_type(&self) -> JpegColorType {
+                JpegColorType::Ycbcr
+            }
+
+            fn width(&self) -> u16 {
+                self.1
+            }
+
+            fn height(&self) -> u16 {
+                self.2
+            }
+
+            #[inline(always)]
+            fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                unsafe {
+                    self.fill_buffers_neon(y, buffers);
+                }
+            }
+
+            fn get_lossless_color_type(&self) -> JpegColorType {
+                JpegColorType::Rgb
+            }
+
+            fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let offsets = [$o1, $o2, $o3];
+                fill_rgb_buffers(self.0, y, self.1, $num_colors, offsets, buffers);
+            }
+        }
+    };
+}
+
+ycbcr_image_neon!(RgbImageNeon, 3, vld3q_u8, 0, 1, 2);
+ycbcr_image_neon!(RgbaImageNeon, 4, vld4q_u8, 0, 1, 2);
+ycbcr_image_neon!(BgrImageNeon, 3, vld3q_u8, 2, 1, 0);
+ycbcr_image_neon!(BgraImageNeon, 4, vld4q_u8, 2, 1, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_buffer::{BgraImage, RgbImage};
+    use alloc::vec;
+
+    fn fill<I: ImageBuffer>(image: &I) -> [Vec<u8>; 4] {
+        let mut buffers = [vec![], vec![], vec![], vec![]];
+
+        for y in 0..image.height() {
+            image.fill_buffers(y, &mut buffers);
+        }
+
+        buffers
+    }
+
+    #[test]
+    fn test_ycbcr_neon() {
+        let width = 37;
+        let height = 3;
+
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height) * 4)
+            .map(|i| (i * 97 % 256) as u8)
+            .collect();
+
+        let rgb = &data[..usize::from(width) * usize::from(height) * 3];
+
+        assert_eq!(
+            fill(&RgbImageNeon(rgb, width, height)),
+            fill(&RgbImage(rgb, width, height))
+        );
+
+        assert_eq!(
+            fill(&BgraImageNeon(&data, width, height)),
+            fill(&BgraImage(&data, width, height))
+        );
+
+        // Extreme values
+        let data = [0, 0, 255, 255, 255, 0, 255, 0, 0, 0, 255, 0].repeat(16);
+
+        assert_eq!(
+            fill(&RgbImageNeon(&data, 64, 1)),
+            fill(&RgbImage(&data, 64, 1))
+        );
+    }
+}
@@ -0,0 +1,341 @@
+/*
+ * Multithreaded encoding of interleaved scans
+ *
+ * Batches of MCU rows are color converted, transformed and quantized in parallel.
+ * The huffman coding is sequential, except for restart intervals which are independent
+ * of each other and can be coded in parallel. The output is identical to the single
+ * threaded encoder.
+ */
+
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::encoder::{
+    ceil_div, fill_mcu_row, get_blocks_per_mcu, get_max_sampling_size_for,
+    init_rows_for_components, quantize_mcu_row, write_mcus, Component, Operations, RestartState,
+};
+use crate::huffman::HuffmanTable;
+use crate::image_buffer::ImageBuffer;
+use crate::importance::{AdaptiveQuantizer, ImportanceMap};
+use crate::marker::Marker;
+use crate::quantization::QuantizationTable;
+use crate::writer::{JfifWrite, JfifWriter};
+use crate::EncodingError;
+
+/// Number of MCU rows processed at once per thread
+const ROWS_PER_THREAD: usize = 4;
+
+/// Quantization of the MCU rows of an interleaved scan
+pub(crate) struct McuRows<'a> {
+    components: &'a [Component],
+    q_tables: &'a [QuantizationTable; 2],
+    adaptive: Option<AdaptiveQuantizer<'a>>,
+    width: u16,
+    height: u16,
+    max_h_sampling: usize,
+    max_v_sampling: usize,
+    num_cols: usize,
+    num_rows: usize,
+    buffer_width: usize,
+}
+
+impl<'a> McuRows<'a> {
+    pub fn new(
+        components: &'a [Component],
+        q_tables: &'a [QuantizationTable; 2],
+        importance_map: Option<&'a ImportanceMap>,
+        width: u16,
+        height: u16,
+    ) -> McuRows<'a> {
+        let (max_h_sampling, max_v_sampling) = get_max_sampling_size_for(components);
+
+        let num_cols = ceil_div(usize::from(width), 8 * max_h_sampling);
+        let num_rows = ceil_div(usize::from(height), 8 * max_v_sampling);
+
+        McuRows {
+            components,
+            q_tables,
+            adaptive: importance_map.map(|map| AdaptiveQuantizer::new(map, components)),
+            width,
+            height,
+            max_h_sampling,
+            max_v_sampling,
+            num_cols,
+            num_rows,
+            buffer_width: num_cols * 8 * max_h_sampling,
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    fn fill<I: ImageBuffer>(&self, image: &I, mcu_row: usize) -> [Vec<u8>; 4] {
+        let mut row = init_rows_for_components(
+            self.components.len(),
+            self.buffer_width * 8 * self.max_v_sampling,
+        );
+
+        fill_mcu_row(
+            image,
+            mcu_row,
+            self.width,
+            self.height,
+            self.max_v_sampling,
+            self.buffer_width,
+            &mut row,
+        );
+
+        row
+    }
+
+    fn quantize<OP: Operations>(&self, row: &[Vec<u8>; 4], mcu_row: usize) -> Vec<[i16; 64]> {
+        let mut blocks = Vec::with_capacity(self.num_cols * get_blocks_per_mcu(self.components));
+
+        quantize_mcu_row::<OP>(
+            self.components,
+            self.q_tables,
+            self.max_h_sampling,
+            self.max_v_sampling,
+            self.buffer_width,
+            self.num_cols,
+            row,
+            self.adaptive.as_ref(),
+            mcu_row,
+            &mut blocks,
+        );
+
+        blocks
+    }
+
+    /// Quantize MCU rows with a sequential color conversion
+    pub fn quantize_rows<I: ImageBuffer, OP: Operations>(
+        &self,
+        image: &I,
+        mcu_rows: Range<usize>,
+    ) -> Vec<Vec<[i16; 64]>> {
+        let rows: Vec<_> = mcu_rows
+            .map(|mcu_row| (mcu_row, self.fill(image, mcu_row)))
+            .collect();
+
+        rows.par_iter()
+            .map(|(mcu_row, row)| self.quantize::<OP>(row, *mcu_row))
+            .collect()
+    }
+
+    /// Quantize MCU rows with a parallel color conversion
+    pub fn quantize_rows_sync<I: ImageBuffer + Sync, OP: Operations>(
+        &self,
+        image: &I,
+        mcu_rows: Range<usize>,
+    ) -> Vec<Vec<[i16; 64]>> {
+        mcu_rows
+            .into_par_iter()
+            .map(|mcu_row| self.quantize::<OP>(&self.fill(image, mcu_row), mcu_row))
+            .collect()
+    }
+}
+
+/// Huffman code an interleaved scan
+///
+/// `quantize_rows` returns the quantized blocks of each MCU row of a range.
+pub(crate) fn encode_interleaved<W, F>(
+    writer: &mut JfifWriter<W>,
+    components: &[Component],
+    huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    restart_interval: Option<u16>,
+    num_rows: usize,
+    mut quantize_rows: F,
+) -> Result<(), EncodingError>
+where
+    W: JfifWrite,
+    F: FnMut(Range<usize>) -> Result<Vec<Vec<[i16; 64]>>, EncodingError>,
+{
+    let batch_size = rayon::current_num_threads().max(1) * ROWS_PER_THREAD;
+
+    let interval = match restart_interval {
+        Some(interval) if interval > 0 => usize::from(interval),
+        _ => {
+            let mut prev_dc = [0i16; 4];
+            let mut restart = RestartState::new(None);
+
+            for start in (0..num_rows).step_by(batch_size) {
+                let end = (start + batch_size).min(num_rows);
+
+                for blocks in quantize_rows(start..end)? {
+                    write_mcus(
+                        writer,
+                        components,
+                        huffman_tables,
+                        &mut prev_dc,
+                        &mut restart,
+                        &blocks,
+                    )?;
+                }
+            }
+
+            return Ok(());
+        }
+    };
+
+    let segment_size = interval * get_blocks_per_mcu(components);
+
+    let mut pending = Vec::new();
+    let mut segment_count = 0;
+
+    for start in (0..num_rows).step_by(batch_size) {
+        let end = (start + batch_size).min(num_rows);
+
+        for blocks in quantize_rows(start..end)? {
+            pending.extend_from_slice(&blocks);
+        }
+
+        // Only the last segment can be incomplete
+        let length = if end == num_rows {
+            pending.len()
+        } else {
+            pending.len() / segment_size * segment_size
+        };
+
+        let segments: Vec<_> = pending[..length]
+            .par_chunks(segment_size)
+            .map(|segment| encode_segment(components, huffman_tables, segment))
+            .collect();
+
+        for segment in segments {
+            if segment_count > 0 {
+                writer.write_marker(Marker::RST(((segment_count - 1) % 8) as u8))?;
+            }
+
+            writer.write(&segment?)?;
+            segment_count += 1;
+        }
+
+        pending.drain(..length);
+    }
+
+    Ok(())
+}
+
+/// Huffman code the blocks of a restart interval
+fn encode_segment(
+    components: &[Component],
+    huffman_tables: &[(HuffmanTable, HuffmanTable); 2],
+    blocks: &[[i16; 64]],
+) -> Result<Vec<u8>, EncodingError> {
+    let mut writer = JfifWriter::new(Vec::new());
+
+    write_mcus(
+        &mut writer,
+        components,
+        huffman_tables,
+        &mut [0i16; 4],
+        &mut RestartState::new(None),
+        blocks,
+    )?;
+
+    writer.finalize_bit_buffer()?;
+
+    Ok(writer.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use crate::{ColorType, Encoder, ImportanceMap, SamplingFactor};
+
+    fn create_test_img() -> (Vec<u8>, u16, u16) {
+        let width = 45;
+        let height = 517;
+
+        let mut data = Vec::with_capacity(usize::from(width) * usize::from(height) * 3);
+
+        for y in 0..usize::from(height) {
+            for x in 0..usize::from(width) {
+                data.push((x * 5 + y) as u8);
+                data.push((y * 3) as u8);
+                data.push((x * y / 7) as u8);
+            }
+        }
+
+        (data, width, height)
+    }
+
+    fn encode(
+        data: &[u8],
+        width: u16,
+        height: u16,
+        sampling_factor: SamplingFactor,
+        restart_interval: Option<u16>,
+        threads: usize,
+    ) -> Vec<u8> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        let mut result = Vec::new();
+        let mut encoder = Encoder::new(&mut result, 80);
+        encoder.set_sampling_factor(sampling_factor);
+
+        if let Some(interval) = restart_interval {
+            encoder.set_restart_interval(interval);
+        }
+
+        encoder.set_importance_map(Some(ImportanceMap::from_fn(3, 40, |x, y| {
+            (x * 100 + y * 10) as u8
+        })));
+
+        pool.install(|| encoder.encode(data, width, height, ColorType::Rgb))
+            .unwrap();
+
+        result
+    }
+
+    #[test]
+    fn test_thread_count_matches_strip_encoder() {
+        let (data, width, height) = create_test_img();
+        let row_stride = usize::from(width) * 3;
+
+        for sampling_factor in [SamplingFactor::F_1_1, SamplingFactor::F_2_2] {
+            for restart_interval in [None, Some(1), Some(7), Some(100)] {
+                let mut encoder = Encoder::new(Vec::new(), 80);
+                encoder.set_sampling_factor(sampling_factor);
+
+                if let Some(interval) = restart_interval {
+                    encoder.set_restart_interval(interval);
+                }
+
+                let mut strip_encoder = encoder
+                    .into_strip_encoder(width, height, ColorType::Rgb)
+                    .unwrap();
+
+                strip_encoder.set_importance_map(Some(ImportanceMap::from_fn(3, 40, |x, y| {
+                    (x * 100 + y * 10) as u8
+                })));
+
+                for chunk in data.chunks(row_stride * 16) {
+                    strip_encoder.encode_strip(chunk).unwrap();
+                }
+
+                let expected = strip_encoder.finish().unwrap();
+
+                for threads in [1, 3, 8] {
+                    let result = encode(
+                        &data,
+                        width,
+                        height,
+                        sampling_factor,
+                        restart_interval,
+                        threads,
+                    );
+
+                    assert_eq!(result, expected);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,998 @@
+/*
+ * Progressive scan encoding as described in Annex G.1.2 of T.81.
+ *
+ * The EOB run and correction bit handling as well as the scan script validation
+ * and presets follow libjpeg's jcphuff.c, jcmaster.c and jcparam.c.
+ */
+
+use alloc::format;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::encoder::{ceil_div, get_max_sampling_size_for, Component, JpegColorType};
+use crate::huffman::HuffmanTable;
+use crate::marker::Marker;
+use crate::writer::{get_code, JfifWrite, JfifWriter};
+use crate::EncodingError;
+
+/// Longest EOB run that can be coded with the EOB14 symbol
+pub(crate) const MAX_EOB_RUN: u16 = 0x7FFF;
+
+/// Maximum number of correction bits buffered for a pending EOB run
+const MAX_CORRECTION_BITS: usize = 1000;
+
+/// Maximum number of blocks in the MCU of an interleaved scan
+const MAX_BLOCKS_IN_MCU: usize = 10;
+
+/// # A single scan of a progressive image
+///
+/// Equivalent to libjpeg's `jpeg_scan_info`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanInfo {
+    /// Indices of the components in this scan in frame order
+    ///
+    /// Only DC scans can contain more than one component.
+    pub components: Vec<usize>,
+
+    /// Start of spectral selection
+    pub ss: u8,
+
+    /// End of spectral selection
+    pub se: u8,
+
+    /// Successive approximation bit position high
+    pub ah: u8,
+
+    /// Successive approximation bit position low
+    pub al: u8,
+}
+
+impl ScanInfo {
+    /// Create a new scan for the given component indices
+    pub fn new(components: &[usize], ss: u8, se: u8, ah: u8, al: u8) -> ScanInfo {
+        ScanInfo {
+            components: components.to_vec(),
+            ss,
+            se,
+            ah,
+            al,
+        }
+    }
+
+    pub(crate) fn is_dc(&self) -> bool {
+        self.ss == 0
+    }
+}
+
+/// # Scan script for progressive encoding
+///
+/// Defines the components, the spectral selection and the successive approximation of each
+/// scan similar to libjpeg's `-scans` option.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScanScript {
+    /// The default script of libjpeg (`jpeg_simple_progression`)
+    Libjpeg,
+
+    /// The script used by mozjpeg for maximum compression
+    ///
+    /// Based on `jpeg_scan_rgb.txt` and `jpeg_scan_bw.txt` from jpgcrush
+    Mozjpeg,
+
+    /// A user supplied script
+    ///
+    /// The script is validated against the rules of T.81 before encoding.
+    /// Each coefficient of each component must be transmitted completely.
+    Custom(Vec<ScanInfo>),
+}
+
+impl ScanScript {
+    pub(crate) fn build(
+        &self,
+        color_type: JpegColorType,
+        components: &[Component],
+    ) -> Vec<ScanInfo> {
+        let num_components = components.len();
+        let ycbcr = color_type == JpegColorType::Ycbcr;
+
+        let mut scans = Vec::new();
+
+        let dc_scans = |scans: &mut Vec<ScanInfo>, ah: u8, al: u8| {
+            if blocks_in_mcu(components, 0..num_components) <= MAX_BLOCKS_IN_MCU {
+                let all: Vec<usize> = (0..num_components).collect();
+                scans.push(ScanInfo::new(&all, 0, 0, ah, al));
+            } else {
+                component_scans(scans, num_components, 0, 0, ah, al);
+            }
+        };
+
+        match self {
+            ScanScript::Libjpeg if ycbcr => {
+                dc_scans(&mut scans, 0, 1);
+                scans.push(ScanInfo::new(&[0], 1, 5, 0, 2));
+                scans.push(ScanInfo::new(&[2], 1, 63, 0, 1));
+                scans.push(ScanInfo::new(&[1], 1, 63, 0, 1));
+                scans.push(ScanInfo::new(&[0], 6, 63, 0, 2));
+                scans.push(ScanInfo::new(&[0], 1, 63, 2, 1));
+                dc_scans(&mut scans, 1, 0);
+                scans.push(ScanInfo::new(&[2], 1, 63, 1, 0));
+                scans.push(ScanInfo::new(&[1], 1, 63, 1, 0));
+                scans.push(ScanInfo::new(&[0], 1, 63, 1, 0));
+            }
+            ScanScript::Libjpeg => {
+                dc_scans(&mut scans, 0, 1);
+                component_scans(&mut scans, num_components, 1, 5, 0, 2);
+                component_scans(&mut scans, num_components, 6, 63, 0, 2);
+                component_scans(&mut scans, num_components, 1, 63, 2, 1);
+                dc_scans(&mut scans, 1, 0);
+                component_scans(&mut scans, num_components, 1, 63, 1, 0);
+            }
+            ScanScript::Mozjpeg if ycbcr => {
+                component_scans(&mut scans, num_components, 0, 0, 0, 0);
+                scans.push(ScanInfo::new(&[0], 1, 8, 0, 2));
+                scans.push(ScanInfo::new(&[1], 1, 8, 0, 0));
+                scans.push(ScanInfo::new(&[2], 1, 8, 0, 0));
+                scans.push(ScanInfo::new(&[0], 9, 63, 0, 2));
+                scans.push(ScanInfo::new(&[0], 1, 63, 2, 1));
+                scans.push(ScanInfo::new(&[0], 1, 63, 1, 0));
+                scans.push(ScanInfo::new(&[1], 9, 63, 0, 0));
+                scans.push(ScanInfo::new(&[2], 9, 63, 0, 0));
+            }
+            ScanScript::Mozjpeg => {
+                dc_scans(&mut scans, 0, 0);
+                component_scans(&mut scans, num_components, 1, 8, 0, 2);
+                component_scans(&mut scans, num_components, 9, 63, 0, 2);
+                component_scans(&mut scans, num_components, 1, 63, 2, 1);
+                component_scans(&mut scans, num_components, 1, 63, 1, 0);
+            }
+            ScanScript::Custom(script) => scans.extend_from_slice(script),
+        }
+
+        scans
+    }
+}
+
+fn component_scans(
+    scans: &mut Vec<ScanInfo>,
+    num_components: usize,
+    ss: u8,
+    se: u8,
+    ah: u8,
+    al: u8,
+) {
+    for component in 0..num_components {
+        scans.push(ScanInfo::new(&[component], ss, se, ah, al));
+    }
+}
+
+fn blocks_in_mcu<I: IntoIterator<Item = usize>>(components: &[Component], indices: I) -> usize {
+    indices
+        .into_iter()
+        .map(|i| {
+            let component = &components[i];
+            usize::from(component.horizontal_sampling_factor)
+                * usize::from(component.vertical_sampling_factor)
+        })
+        .sum()
+}
+
+/// Build the scans used for progressive encoding
+///
+/// The AC coefficients are split into `num_scans - 1` bands per component. If `point_transform`
+/// is greater than zero, the first scans omit that many low bits which are then sent
+/// one bit plane at a time in refinement scans.
+pub(crate) fn build_scans(
+    num_components: usize,
+    num_scans: u8,
+    point_transform: u8,
+) -> Vec<ScanInfo> {
+    let ac_scans = num_scans as usize - 1;
+    let values_per_scan = 64 / ac_scans;
+
+    let bands: Vec<(u8, u8)> = (0..ac_scans)
+        .map(|scan| {
+            let start = (scan * values_per_scan).max(1);
+            let end = if scan == ac_scans - 1 {
+                // ensure last scan is always transfers the remaining coefficients
+                64
+            } else {
+                (scan + 1) * values_per_scan
+            };
+
+            (start as u8, end as u8 - 1)
+        })
+        .collect();
+
+    let mut scans = Vec::new();
+
+    // Phase 1: DC Scan
+    //          Only the DC coefficients can be transfer in the first component scans
+    component_scans(&mut scans, num_components, 0, 0, 0, point_transform);
+
+    // Phase 2: AC scans
+    for &(ss, se) in &bands {
+        component_scans(&mut scans, num_components, ss, se, 0, point_transform);
+    }
+
+    // Phase 3: Refinement scans adding one bit plane at a time
+    for al in (0..point_transform).rev() {
+        let ah = al + 1;
+
+        component_scans(&mut scans, num_components, 0, 0, ah, al);
+
+        for &(ss, se) in &bands {
+            component_scans(&mut scans, num_components, ss, se, ah, al);
+        }
+    }
+
+    scans
+}
+
+/// Validate the scans of a progressive image as described in G.1.1.1.1 of T.81
+pub(crate) fn validate_scans(
+    scans: &[ScanInfo],
+    components: &[Component],
+) -> Result<(), EncodingError> {
+    fn invalid(nr: usize, reason: &str) -> EncodingError {
+        EncodingError::InvalidScanScript(format!("scan {}: {}", nr, reason))
+    }
+
+    if scans.is_empty() {
+        return Err(EncodingError::InvalidScanScript("script is empty".into()));
+    }
+
+    // Last successive approximation bit position sent for each coefficient or -1 if not sent yet
+    let mut last_bit_pos = vec![[-1i8; 64]; components.len()];
+
+    for (nr, scan) in scans.iter().enumerate() {
+        if scan.components.is_empty() || scan.components.len() > 4 {
+            return Err(invalid(
+                nr,
+                "a scan must contain between 1 and 4 components",
+            ));
+        }
+
+        for (i, &component) in scan.components.iter().enumerate() {
+            if component >= components.len() {
+                return Err(invalid(nr, "component index out of range"));
+            }
+
+            if i > 0 && component <= scan.components[i - 1] {
+                return Err(invalid(nr, "components must be in frame order"));
+            }
+        }
+
+        if scan.components.len() > 1
+            && blocks_in_mcu(components, scan.components.iter().copied()) > MAX_BLOCKS_IN_MCU
+        {
+            return Err(invalid(nr, "too many blocks in MCU of interleaved scan"));
+        }
+
+        if scan.se > 63 || scan.ss > scan.se {
+            return Err(invalid(nr, "invalid spectral selection"));
+        }
+
+        if scan.ah > 13 || scan.al > 13 {
+            return Err(invalid(nr, "invalid successive approximation"));
+        }
+
+        if scan.ss == 0 {
+            if scan.se != 0 {
+                return Err(invalid(nr, "DC scans must not contain AC coefficients"));
+            }
+        } else if scan.components.len() != 1 {
+            return Err(invalid(nr, "AC scans must contain a single component"));
+        }
+
+        for &component in &scan.components {
+            let last_bit_pos = &mut last_bit_pos[component];
+
+            if scan.ss > 0 && last_bit_pos[0] < 0 {
+                return Err(invalid(
+                    nr,
+                    "AC scan before the first DC scan of the component",
+                ));
+            }
+
+            for pos in &mut last_bit_pos[scan.ss as usize..=scan.se as usize] {
+                if *pos < 0 {
+                    if scan.ah != 0 {
+                        return Err(invalid(nr, "refinement scan without prior first scan"));
+                    }
+                } else if scan.ah as i8 != *pos || scan.al + 1 != scan.ah {
+                    return Err(invalid(nr, "invalid successive approximation sequence"));
+                }
+
+                *pos = scan.al as i8;
+            }
+        }
+    }
+
+    for (component, last_bit_pos) in last_bit_pos.iter().enumerate() {
+        if last_bit_pos.iter().any(|&pos| pos != 0) {
+            return Err(EncodingError::InvalidScanScript(format!(
+                "coefficients of component {} are not transmitted completely",
+                component
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Layout of the coefficient blocks of all components
+///
+/// Blocks are stored per component in the order of a non-interleaved scan.
+pub(crate) struct BlockLayout {
+    mcu_cols: usize,
+    mcu_rows: usize,
+    block_cols: [usize; 4],
+    block_rows: [usize; 4],
+    sampling_factors: [(usize, usize); 4],
+}
+
+impl BlockLayout {
+    pub fn new(width: u16, height: u16, components: &[Component]) -> BlockLayout {
+        let (max_h_sampling, max_v_sampling) = get_max_sampling_size_for(components);
+
+        let num_cols = ceil_div(usize::from(width), 8);
+        let num_rows = ceil_div(usize::from(height), 8);
+
+        let mut layout = BlockLayout {
+            mcu_cols: ceil_div(usize::from(width), 8 * max_h_sampling),
+            mcu_rows: ceil_div(usize::from(height), 8 * max_v_sampling),
+            block_cols: [0; 4],
+            block_rows: [0; 4],
+            sampling_factors: [(0, 0); 4],
+        };
+
+        for (i, component) in components.iter().enumerate() {
+            let h_sampling = usize::from(component.horizontal_sampling_factor);
+            let v_sampling = usize::from(component.vertical_sampling_factor);
+
+            layout.block_cols[i] = ceil_div(num_cols, max_h_sampling / h_sampling);
+            layout.block_rows[i] = ceil_div(num_rows, max_v_sampling / v_sampling);
+            layout.sampling_factors[i] = (h_sampling, v_sampling);
+        }
+
+        layout
+    }
+
+    /// Append the blocks of a MCU row in the order of the interleaved scan to the blocks of each component
+    ///
+    /// Blocks which only pad the MCUs beyond the area of a component are dropped.
+    pub fn append_mcu_row(
+        &self,
+        mcu_y: usize,
+        mcu_row: &[[i16; 64]],
+        blocks: &mut [Vec<[i16; 64]>; 4],
+    ) {
+        let blocks_per_mcu: usize = self.sampling_factors.iter().map(|&(h, v)| h * v).sum();
+
+        debug_assert_eq!(mcu_row.len(), blocks_per_mcu * self.mcu_cols);
+
+        let mut offset = 0;
+
+        for (i, &(h_sampling, v_sampling)) in self.sampling_factors.iter().enumerate() {
+            for v in 0..v_sampling {
+                if mcu_y * v_sampling + v >= self.block_rows[i] {
+                    break;
+                }
+
+                for x in 0..self.block_cols[i] {
+                    let mcu_x = x / h_sampling;
+                    let h = x % h_sampling;
+
+                    blocks[i].push(mcu_row[mcu_x * blocks_per_mcu + offset + v * h_sampling + h]);
+                }
+            }
+
+            offset += h_sampling * v_sampling;
+        }
+    }
+}
+
+/// Receiver for the entropy coded data of a scan
+pub(crate) trait ScanSink {
+    /// Huffman code a symbol with the given table followed by `size` additional bits of `value`
+    fn write_symbol(
+        &mut self,
+        table: u8,
+        symbol: u8,
+        value: u16,
+        size: u8,
+    ) -> Result<(), EncodingError>;
+
+    /// Append raw bits without huffman coding
+    fn write_bits(&mut self, value: u16, size: u8) -> Result<(), EncodingError>;
+
+    /// Terminate the current interval and emit the restart marker with the given number
+    fn restart(&mut self, nr: u8) -> Result<(), EncodingError>;
+
+    /// Terminate the scan
+    fn finish(&mut self) -> Result<(), EncodingError>;
+}
+
+/// Writes the scan data using the given DC or AC huffman tables
+pub(crate) struct ScanWriter<'a, W: JfifWrite> {
+    writer: &'a mut JfifWriter<W>,
+    tables: [&'a HuffmanTable; 2],
+}
+
+impl<'a, W: JfifWrite> ScanWriter<'a, W> {
+    pub fn new(writer: &'a mut JfifWriter<W>, tables: [&'a HuffmanTable; 2]) -> Self {
+        ScanWriter { writer, tables }
+    }
+}
+
+impl<'a, W: JfifWrite> ScanSink for ScanWriter<'a, W> {
+    #[inline]
+    fn write_symbol(
+        &mut self,
+        table: u8,
+        symbol: u8,
+        value: u16,
+        size: u8,
+    ) -> Result<(), EncodingError> {
+        self.writer
+            .huffman_encode_value(size, symbol, value, self.tables[table as usize])
+    }
+
+    #[inline]
+    fn write_bits(&mut self, value: u16, size: u8) -> Result<(), EncodingError> {
+        self.writer.write_bits(value as u32, size)
+    }
+
+    fn restart(&mut self, nr: u8) -> Result<(), EncodingError> {
+        self.writer.finalize_bit_buffer()?;
+        self.writer.write_marker(Marker::RST(nr))
+    }
+
+    fn finish(&mut self) -> Result<(), EncodingError> {
+        self.writer.finalize_bit_buffer()
+    }
+}
+
+/// Counts the symbol frequencies of a scan for optimized huffman tables
+pub(crate) struct FrequencyCounter<'a> {
+    freq: &'a mut [[u32; 257]; 2],
+}
+
+impl<'a> FrequencyCounter<'a> {
+    pub fn new(freq: &'a mut [[u32; 257]; 2]) -> Self {
+        FrequencyCounter { freq }
+    }
+}
+
+impl<'a> ScanSink for FrequencyCounter<'a> {
+    #[inline]
+    fn write_symbol(
+        &mut self,
+        table: u8,
+        symbol: u8,
+        _value: u16,
+        _size: u8,
+    ) -> Result<(), EncodingError> {
+        self.freq[table as usize][symbol as usize] += 1;
+        Ok(())
+    }
+
+    #[inline]
+    fn write_bits(&mut self, _value: u16, _size: u8) -> Result<(), EncodingError> {
+        Ok(())
+    }
+
+    fn restart(&mut self, _nr: u8) -> Result<(), EncodingError> {
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), EncodingError> {
+        Ok(())
+    }
+}
+
+struct ScanEncoder<'a, S: ScanSink> {
+    sink: &'a mut S,
+    eob_run: u16,
+    max_eob_run: u16,
+    correction_bits: Vec<u8>,
+    block_bits: Vec<u8>,
+}
+
+impl<'a, S: ScanSink> ScanEncoder<'a, S> {
+    fn flush_eob_run(&mut self, table: u8) -> Result<(), EncodingError> {
+        if self.eob_run > 0 {
+            let size = 15 - self.eob_run.leading_zeros() as u8;
+            let value = self.eob_run & ((1 << size) - 1);
+
+            self.sink.write_symbol(table, size << 4, value, size)?;
+            self.eob_run = 0;
+
+            for &bit in &self.correction_bits {
+                self.sink.write_bits(u16::from(bit), 1)?;
+            }
+            self.correction_bits.clear();
+        }
+
+        Ok(())
+    }
+
+    fn flush_block_bits(&mut self) -> Result<(), EncodingError> {
+        for &bit in &self.block_bits {
+            self.sink.write_bits(u16::from(bit), 1)?;
+        }
+        self.block_bits.clear();
+
+        Ok(())
+    }
+
+    fn dc_first(&mut self, table: u8, value: i16, prev_dc: i16) -> Result<(), EncodingError> {
+        let (size, value) = get_code(value - prev_dc);
+        self.sink.write_symbol(table, size, value, size)
+    }
+
+    fn dc_refine(&mut self, value: i16, al: u8) -> Result<(), EncodingError> {
+        self.sink.write_bits(((value >> al) & 1) as u16, 1)
+    }
+
+    fn ac_first(
+        &mut self,
+        table: u8,
+        block: &[i16; 64],
+        scan: &ScanInfo,
+    ) -> Result<(), EncodingError> {
+        let mut zero_run = 0;
+
+        for &coefficient in &block[scan.ss as usize..=scan.se as usize] {
+            let magnitude = (coefficient.unsigned_abs() >> scan.al) as i16;
+
+            if magnitude == 0 {
+                zero_run += 1;
+                continue;
+            }
+
+            self.flush_eob_run(table)?;
+
+            while zero_run > 15 {
+                self.sink.write_symbol(table, 0xF0, 0, 0)?;
+                zero_run -= 16;
+            }
+
+            let value = if coefficient < 0 {
+                -magnitude
+            } else {
+                magnitude
+            };
+
+            let (size, value) = get_code(value);
+            self.sink
+                .write_symbol(table, (zero_run << 4) | size, value, size)?;
+
+            zero_run = 0;
+        }
+
+        if zero_run > 0 {
+            self.eob_run += 1;
+
+            if self.eob_run == self.max_eob_run {
+                self.flush_eob_run(table)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ac_refine(
+        &mut self,
+        table: u8,
+        block: &[i16; 64],
+        scan: &ScanInfo,
+    ) -> Result<(), EncodingError> {
+        let ss = scan.ss as usize;
+        let se = scan.se as usize;
+
+        let mut magnitudes = [0u16; 64];
+        let mut eob = 0;
+
+        // Find the last coefficient which becomes non zero in this scan
+        for k in ss..=se {
+            let magnitude = block[k].unsigned_abs() >> scan.al;
+            magnitudes[k] = magnitude;
+
+            if magnitude == 1 {
+                eob = k;
+            }
+        }
+
+        let mut zero_run = 0;
+
+        for k in ss..=se {
+            let magnitude = magnitudes[k];
+
+            if magnitude == 0 {
+                zero_run += 1;
+                continue;
+            }
+
+            while zero_run > 15 && k <= eob {
+                self.flush_eob_run(table)?;
+                self.sink.write_symbol(table, 0xF0, 0, 0)?;
+                zero_run -= 16;
+                self.flush_block_bits()?;
+            }
+
+            if magnitude > 1 {
+                // Coefficient was already non zero in a previous scan, only send the correction bit
+                self.block_bits.push((magnitude & 1) as u8);
+                continue;
+            }
+
+            self.flush_eob_run(table)?;
+            self.sink
+                .write_symbol(table, (zero_run << 4) | 1, u16::from(block[k] >= 0), 1)?;
+            self.flush_block_bits()?;
+
+            zero_run = 0;
+        }
+
+        if zero_run > 0 || !self.block_bits.is_empty() {
+            self.eob_run += 1;
+            self.correction_bits.append(&mut self.block_bits);
+
+            if self.eob_run == self.max_eob_run
+                || self.correction_bits.len() > MAX_CORRECTION_BITS - 64 + 1
+            {
+                self.flush_eob_run(table)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Entropy code a single scan
+///
+/// `tables` contains the DC or AC huffman table index of each component.
+/// `max_eob_run` limits the length of EOB runs. Huffman tables without EOBn symbols
+/// (like the default tables of Annex K) need a limit of 1.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn encode_scan<S: ScanSink>(
+    sink: &mut S,
+    scan: &ScanInfo,
+    tables: &[u8],
+    layout: &BlockLayout,
+    blocks: &[Vec<[i16; 64]>; 4],
+    restart_interval: Option<u16>,
+    max_eob_run: u16,
+) -> Result<(), EncodingError> {
+    debug_assert!(max_eob_run > 0 && max_eob_run <= MAX_EOB_RUN);
+
+    let mut encoder = ScanEncoder {
+        sink,
+        eob_run: 0,
+        max_eob_run,
+        correction_bits: Vec::new(),
+        block_bits: Vec::new(),
+    };
+
+    let mut prev_dc = [0i16; 4];
+
+    // The table used by EOB runs which are only possible in single component AC scans
+    let eob_table = tables[scan.components[0]];
+
+    for_each_mcu(scan, layout, blocks, restart_interval, |restart, mcu| {
+        if let Some(nr) = restart {
+            encoder.flush_eob_run(eob_table)?;
+            encoder.sink.restart(nr)?;
+
+            prev_dc = [0i16; 4];
+        }
+
+        for (component, block) in mcu {
+            let table = tables[component];
+
+            match (scan.is_dc(), scan.ah == 0) {
+                (true, true) => {
+                    let value = block[0] >> scan.al;
+                    encoder.dc_first(table, value, prev_dc[component])?;
+                    prev_dc[component] = value;
+                }
+                (true, false) => encoder.dc_refine(block[0], scan.al)?,
+                (false, true) => encoder.ac_first(table, block, scan)?,
+                (false, false) => encoder.ac_refine(table, block, scan)?,
+            }
+        }
+
+        Ok(())
+    })?;
+
+    encoder.flush_eob_run(eob_table)?;
+    encoder.sink.finish()
+}
+
+/// Call `encode_mcu` for each MCU of a scan
+///
+/// The first argument of `encode_mcu` contains the number of the restart marker which must be
+/// emitted before the MCU, the second the component index and block of each block in the MCU.
+pub(crate) fn for_each_mcu<F>(
+    scan: &ScanInfo,
+    layout: &BlockLayout,
+    blocks: &[Vec<[i16; 64]>; 4],
+    restart_interval: Option<u16>,
+    mut encode_mcu: F,
+) -> Result<(), EncodingError>
+where
+    F: FnMut(
+        Option<u8>,
+        &mut dyn Iterator<Item = (usize, &[i16; 64])>,
+    ) -> Result<(), EncodingError>,
+{
+    let restart_interval = restart_interval.unwrap_or(0);
+    let mut restarts = 0;
+    let mut restarts_to_go = restart_interval;
+
+    let mut encode_mcu = |mcu: &mut dyn Iterator<Item = (usize, &[i16; 64])>| {
+        let mut restart = None;
+
+        if restart_interval > 0 {
+            if restarts_to_go == 0 {
+                restart = Some(restarts);
+
+                restarts = (restarts + 1) & 7;
+                restarts_to_go = restart_interval;
+            }
+            restarts_to_go -= 1;
+        }
+
+        encode_mcu(restart, mcu)
+    };
+
+    if let [component] = scan.components[..] {
+        for block in &blocks[component] {
+            encode_mcu(&mut core::iter::once((component, block)))?;
+        }
+    } else {
+        // Interleaved scans cover the whole MCU area, blocks outside the component are
+        // filled with the nearest block of the component.
+        for mcu_y in 0..layout.mcu_rows {
+            for mcu_x in 0..layout.mcu_cols {
+                let mut mcu = scan.components.iter().flat_map(|&component| {
+                    let (h_sampling, v_sampling) = layout.sampling_factors[component];
+                    let cols = layout.block_cols[component];
+                    let rows = layout.block_rows[component];
+
+                    (0..v_sampling).flat_map(move |v| {
+                        (0..h_sampling).map(move |h| {
+                            let y = (mcu_y * v_sampling + v).min(rows - 1);
+                            let x = (mcu_x * h_sampling + h).min(cols - 1);
+
+                            (component, &blocks[component][y * cols + x])
+                        })
+                    })
+                });
+
+                encode_mcu(&mut mcu)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn components(num_components: usize, h: u8, v: u8) -> Vec<Component> {
+        (0..num_components)
+            .map(|i| Component {
+                id: i as u8,
+                quantization_table: 0,
+                dc_huffman_table: 0,
+                ac_huffman_table: 0,
+                horizontal_sampling_factor: if i == 0 { h } else { 1 },
+                vertical_sampling_factor: if i == 0 { v } else { 1 },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_build_scans() {
+        let scans = build_scans(1, 4, 0);
+
+        let expected = [(0, 0), (1, 20), (21, 41), (42, 63)];
+        assert_eq!(scans.len(), expected.len());
+
+        for (scan, &(ss, se)) in scans.iter().zip(expected.iter()) {
+            assert_eq!((scan.ss, scan.se, scan.ah, scan.al), (ss, se, 0, 0));
+        }
+    }
+
+    #[test]
+    fn test_build_scans_successive_approximation() {
+        let scans = build_scans(3, 2, 2);
+
+        // DC + AC first scans and two refinement passes with DC + AC scans for each component
+        assert_eq!(scans.len(), 3 * 2 * 3);
+
+        for scan in &scans[..6] {
+            assert_eq!((scan.ah, scan.al), (0, 2));
+        }
+
+        for scan in &scans[6..12] {
+            assert_eq!((scan.ah, scan.al), (2, 1));
+        }
+
+        for scan in &scans[12..] {
+            assert_eq!((scan.ah, scan.al), (1, 0));
+        }
+
+        assert!(validate_scans(&scans, &components(3, 2, 2)).is_ok());
+    }
+
+    #[test]
+    fn test_presets_are_valid() {
+        for script in [ScanScript::Libjpeg, ScanScript::Mozjpeg] {
+            for (color_type, num_components) in [
+                (JpegColorType::Luma, 1),
+                (JpegColorType::Ycbcr, 3),
+                (JpegColorType::Cmyk, 4),
+                (JpegColorType::Ycck, 4),
+            ] {
+                for (h, v) in [(1, 1), (2, 2), (4, 2), (2, 4)] {
+                    let components = components(num_components, h, v);
+                    let scans = script.build(color_type, &components);
+
+                    validate_scans(&scans, &components).unwrap();
+                }
+            }
+        }
+
+        let scans = ScanScript::Libjpeg.build(JpegColorType::Ycbcr, &components(3, 2, 2));
+        assert_eq!(scans.len(), 10);
+        assert_eq!(scans[0].components, [0, 1, 2]);
+
+        let scans = ScanScript::Mozjpeg.build(JpegColorType::Ycbcr, &components(3, 2, 2));
+        assert_eq!(scans.len(), 11);
+    }
+
+    #[test]
+    fn test_validate_scans() {
+        let components = components(3, 2, 2);
+
+        let check = |scans: &[ScanInfo]| validate_scans(scans, &components).is_ok();
+
+        let dc = ScanInfo::new(&[0, 1, 2], 0, 0, 0, 0);
+        let ac = |c| ScanInfo::new(&[c], 1, 63, 0, 0);
+
+        assert!(check(&[dc.clone(), ac(0), ac(1), ac(2)]));
+
+        // Missing AC coefficients
+        assert!(!check(&[dc.clone(), ac(0), ac(1)]));
+
+        // AC before DC
+        assert!(!check(&[ac(0), dc.clone(), ac(1), ac(2)]));
+
+        // Interleaved AC scan
+        assert!(!check(&[
+            dc.clone(),
+            ScanInfo::new(&[0, 1], 1, 63, 0, 0),
+            ac(2)
+        ]));
+
+        // DC and AC in the same scan
+        assert!(!check(&[ScanInfo::new(&[0], 0, 63, 0, 0), ac(1), ac(2)]));
+
+        // Components out of order
+        assert!(!check(&[
+            ScanInfo::new(&[1, 0, 2], 0, 0, 0, 0),
+            ac(0),
+            ac(1),
+            ac(2)
+        ]));
+
+        // Refinement with wrong bit position
+        assert!(!check(&[
+            ScanInfo::new(&[0, 1, 2], 0, 0, 0, 2),
+            ScanInfo::new(&[0, 1, 2], 0, 0, 1, 0),
+            ac(0),
+            ac(1),
+            ac(2),
+        ]));
+
+        // Refinement of not yet sent coefficients
+        assert!(!check(&[
+            dc,
+            ScanInfo::new(&[0], 1, 63, 1, 0),
+            ac(1),
+            ac(2)
+        ]));
+
+        // Too many blocks in MCU
+        let components = self::components(3, 4, 4);
+        assert!(validate_scans(
+            &[ScanInfo::new(&[0, 1, 2], 0, 0, 0, 0), ac(0), ac(1), ac(2)],
+            &components
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_eob_run_symbols() {
+        let mut freq = [[0u32; 257]; 2];
+        let blocks = [vec![[0i16; 64]; 5], Vec::new(), Vec::new(), Vec::new()];
+        let layout = BlockLayout::new(40, 8, &components(1, 1, 1));
+
+        let scan = ScanInfo::new(&[0], 1, 63, 0, 0);
+
+        encode_scan(
+            &mut FrequencyCounter::new(&mut freq),
+            &scan,
+            &[0],
+            &layout,
+            &blocks,
+            None,
+            MAX_EOB_RUN,
+        )
+        .unwrap();
+
+        // A single EOB2 symbol for a run of 5 blocks
+        assert_eq!(freq[0][0x20], 1);
+        assert_eq!(freq[0].iter().sum::<u32>(), 1);
+
+        let mut freq = [[0u32; 257]; 2];
+
+        encode_scan(
+            &mut FrequencyCounter::new(&mut freq),
+            &scan,
+            &[0],
+            &layout,
+            &blocks,
+            None,
+            1,
+        )
+        .unwrap();
+
+        assert_eq!(freq[0][0x00], 5);
+        assert_eq!(freq[0].iter().sum::<u32>(), 5);
+    }
+
+    #[test]
+    fn test_append_mcu_row() {
+        let components = components(3, 2, 2);
+        let layout = BlockLayout::new(40, 24, &components);
+
+        let mut blocks = [Vec::new(), Vec::new(), Vec::new(), Vec::new()];
+
+        for mcu_y in 0..2 {
+            let mut mcu_row = Vec::new();
+
+            for mcu_x in 0..3 {
+                for (i, component) in components.iter().enumerate() {
+                    let h_sampling = usize::from(component.horizontal_sampling_factor);
+                    let v_sampling = usize::from(component.vertical_sampling_factor);
+
+                    for v in 0..v_sampling {
+                        for h in 0..h_sampling {
+                            let y = mcu_y * v_sampling + v;
+                            let x = mcu_x * h_sampling + h;
+
+                            let mut block = [0i16; 64];
+                            block[0] = (i * 1000 + y * 100 + x) as i16;
+                            mcu_row.push(block);
+                        }
+                    }
+                }
+            }
+
+            layout.append_mcu_row(mcu_y, &mcu_row, &mut blocks);
+        }
+
+        for (i, &(cols, rows)) in [(5, 3), (3, 2), (3, 2)].iter().enumerate() {
+            assert_eq!(blocks[i].len(), cols * rows);
+
+            for (n, block) in blocks[i].iter().enumerate() {
+                assert_eq!(block[0], (i * 1000 + (n / cols) * 100 + n % cols) as i16);
+            }
+        }
+    }
+}
@@ -1,5 +1,31 @@
 use alloc::boxed::Box;
-use core::num::NonZeroU16;
+use core::num::NonZeroU32;
+
+/// Inspects or modifies the quantized blocks before they are entropy coded
+///
+/// The hook is called on the thread which writes the image, in the order the blocks are
+/// created. Closures taking the same arguments as [process_block](CoefficientHook::process_block)
+/// implement this trait.
+pub trait CoefficientHook {
+    /// Process a quantized block
+    ///
+    /// `component` is the index of the component in the frame, `x` and `y` the position
+    /// of the block in blocks of the component. The 64 coefficients are in zigzag order.
+    ///
+    /// Interleaved scans also pass the blocks which only pad the MCUs beyond the area
+    /// of a component.
+    ///
+    /// The modified coefficients must be codable with the sample precision of the image:
+    /// AC values up to ±1023 for 8 bit and ±16383 for 12 bit samples, DC values additionally
+    /// -1024 or -16384. Otherwise encoding fails with an error.
+    fn process_block(&mut self, component: usize, x: usize, y: usize, block: &mut [i16; 64]);
+}
+
+impl<F: FnMut(usize, usize, usize, &mut [i16; 64])> CoefficientHook for F {
+    fn process_block(&mut self, component: usize, x: usize, y: usize, block: &mut [i16; 64]) {
+        self(component, x, y, block)
+    }
+}
 
 /// # Quantization table used for encoding
 ///
@@ -36,6 +62,9 @@ pub enum QuantizationTableType {
     ImprovedDetectionModel,
 
     /// A user supplied quantization table
+    ///
+    /// The values are limited to 255 for images with 8 bit samples, which require 8 bit
+    /// tables, and to 32767 for images with 12 bit samples.
     Custom(Box<[u16; 64]>),
 }
 
@@ -182,7 +211,10 @@ static DEFAULT_CHROMA_TABLES: [[u16; 64]; 9] = [
     ],
 ];
 
-const SHIFT: u32 = 2 * 8 - 1;
+pub(crate) const SHIFT: u32 = 2 * 8 - 1;
+
+/// Largest table value for a sample precision of more than 8 bits
+const MAX_WIDE_VALUE: u16 = 32767;
 
 fn compute_reciprocal(divisor: u32) -> (i32, i32) {
     if divisor <= 1 {
@@ -207,7 +239,7 @@ fn compute_reciprocal(divisor: u32) -> (i32, i32) {
 }
 
 pub struct QuantizationTable {
-    table: [NonZeroU16; 64],
+    table: [NonZeroU32; 64],
     reciprocals: [i32; 64],
     corrections: [i32; 64],
 }
@@ -218,15 +250,29 @@ impl QuantizationTable {
         quality: u8,
         luma: bool,
     ) -> QuantizationTable {
+        Self::new_with_precision(table, quality, luma, 8)
+    }
+
+    /// Create a table for the given sample precision
+    ///
+    /// For a precision of more than 8 bits the table values aren't limited to 8 bits.
+    pub fn new_with_precision(
+        table: &QuantizationTableType,
+        quality: u8,
+        luma: bool,
+        precision: u8,
+    ) -> QuantizationTable {
+        let wide = precision > 8;
+
         let table = match table {
-            QuantizationTableType::Custom(table) => Self::get_user_table(table),
+            QuantizationTableType::Custom(table) => Self::get_user_table(table, wide),
             table => {
                 let table = if luma {
                     &DEFAULT_LUMA_TABLES[table.index()]
                 } else {
                     &DEFAULT_CHROMA_TABLES[table.index()]
                 };
-                Self::get_with_quality(table, quality)
+                Self::get_with_quality(table, quality, wide)
             }
         };
 
@@ -234,7 +280,7 @@ impl QuantizationTable {
         let mut corrections = [0i32; 64];
 
         for i in 0..64 {
-            let (reciprocal, correction) = compute_reciprocal(table[i].get() as u32);
+            let (reciprocal, correction) = compute_reciprocal(table[i].get());
 
             reciprocals[i] = reciprocal;
             corrections[i] = correction;
@@ -247,10 +293,13 @@ impl QuantizationTable {
         }
     }
 
-    fn get_user_table(table: &[u16; 64]) -> [NonZeroU16; 64] {
-        let mut q_table = [NonZeroU16::new(1).unwrap(); 64];
+    fn get_user_table(table: &[u16; 64], wide: bool) -> [NonZeroU32; 64] {
+        // 16 bit tables are only allowed for 12 bit samples (B.2.4.1)
+        let max_value = if wide { MAX_WIDE_VALUE } else { 255 };
+
+        let mut q_table = [NonZeroU32::new(1).unwrap(); 64];
         for (i, &v) in table.iter().enumerate() {
-            q_table[i] = match NonZeroU16::new(v.clamp(1, 2 << 10) << 3) {
+            q_table[i] = match NonZeroU32::new(u32::from(v.clamp(1, max_value)) << 3) {
                 Some(v) => v,
                 None => panic!("Invalid quantization table value: {}", v),
             };
@@ -258,7 +307,7 @@ impl QuantizationTable {
         q_table
     }
 
-    fn get_with_quality(table: &[u16; 64], quality: u8) -> [NonZeroU16; 64] {
+    fn get_with_quality(table: &[u16; 64], quality: u8, wide: bool) -> [NonZeroU32; 64] {
         let quality = quality.clamp(1, 100) as u32;
 
         let scale = if quality < 50 {
@@ -267,24 +316,45 @@ impl QuantizationTable {
             200 - quality * 2
         };
 
-        let mut q_table = [NonZeroU16::new(1).unwrap(); 64];
+        let max_value = if wide { MAX_WIDE_VALUE } else { 255 };
+
+        let mut q_table = [NonZeroU32::new(1).unwrap(); 64];
 
         for (i, &v) in table.iter().enumerate() {
             let v = v as u32;
 
             let v = (v * scale + 50) / 100;
 
-            let v = v.clamp(1, 255) as u16;
+            let v = v.clamp(1, u32::from(max_value));
 
             // Table values are premultiplied with 8 because dct is scaled by 8
-            q_table[i] = NonZeroU16::new(v << 3).unwrap();
+            q_table[i] = NonZeroU32::new(v << 3).unwrap();
         }
         q_table
     }
 
     #[inline]
-    pub fn get(&self, index: usize) -> u8 {
-        (self.table[index].get() >> 3) as u8
+    pub fn get(&self, index: usize) -> u16 {
+        (self.table[index].get() >> 3) as u16
+    }
+
+    /// Returns the reciprocals and rounding corrections used by [quantize](QuantizationTable::quantize)
+    #[cfg(all(
+        feature = "simd",
+        any(
+            target_arch = "x86",
+            target_arch = "x86_64",
+            target_arch = "aarch64",
+            all(target_arch = "wasm32", target_feature = "simd128")
+        )
+    ))]
+    pub(crate) fn reciprocals(&self) -> (&[i32; 64], &[i32; 64]) {
+        (&self.reciprocals, &self.corrections)
+    }
+
+    /// Returns true if the table contains values that don't fit into 8 bits
+    pub fn is_16_bit(&self) -> bool {
+        self.table.iter().any(|v| v.get() > 255 << 3)
     }
 
     #[inline]
@@ -305,6 +375,29 @@ impl QuantizationTable {
 
         product as i16
     }
+
+    /// Quantize a value of a dct with more than 8 bit input samples
+    #[inline]
+    pub fn quantize_wide(&self, in_value: i32, index: usize) -> i16 {
+        self.quantize_scaled(in_value, index, 1)
+    }
+
+    /// Quantize a value with a step that is `scale` times larger than the table value
+    ///
+    /// The result is a multiple of `scale` relative to the table value.
+    #[inline]
+    pub fn quantize_scaled(&self, in_value: i32, index: usize, scale: i32) -> i16 {
+        let divisor = self.table[index].get() as i32 * scale;
+
+        let abs_value = in_value.abs();
+        let product = ((abs_value + (divisor >> 1)) / divisor * scale).min(i32::from(i16::MAX));
+
+        if in_value < 0 {
+            -product as i16
+        } else {
+            product as i16
+        }
+    }
 }
 
 #[cfg(test)]
@@ -336,4 +429,41 @@ mod tests {
             assert_eq!(i, q.quantize(i << 3, 0));
         }
     }
+
+    #[test]
+    fn test_custom_table_limits() {
+        let table = QuantizationTableType::Custom(alloc::boxed::Box::new([1000; 64]));
+
+        let q = QuantizationTable::new_with_quality(&table, 80, true);
+        assert!(!q.is_16_bit());
+        assert_eq!(q.get(0), 255);
+
+        let q = QuantizationTable::new_with_precision(&table, 80, true, 12);
+        assert!(q.is_16_bit());
+        assert_eq!(q.get(0), 1000);
+    }
+
+    #[test]
+    fn test_quantize_wide() {
+        let q = QuantizationTable::new_with_precision(&QuantizationTableType::Flat, 50, true, 12);
+
+        assert!(!q.is_16_bit());
+        assert_eq!(q.get(0), 16);
+
+        // Rounds to the nearest value like the 8 bit quantization
+        for i in -255 * 128..255 * 128 {
+            assert_eq!(q.quantize_wide(i, 0), q.quantize(i as i16, 0), "{}", i);
+        }
+
+        assert_eq!(q.quantize_wide(16384 * 128, 0), 16384);
+        assert_eq!(q.quantize_wide(-16384 * 128, 0), -16384);
+
+        let q = QuantizationTable::new_with_precision(&QuantizationTableType::Default, 1, true, 12);
+        assert!(q.is_16_bit());
+        assert_eq!(q.get(63), 99 * 50);
+
+        let q = QuantizationTable::new_with_quality(&QuantizationTableType::Default, 1, true);
+        assert!(!q.is_16_bit());
+        assert_eq!(q.get(63), 255);
+    }
 }
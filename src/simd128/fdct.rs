@@ -0,0 +1,254 @@
+/*
+ * WebAssembly SIMD version of the forward DCT in fdct.rs
+ *
+ * The 1-D DCTs of 8 rows or columns are done at once with one row or column per lane.
+ * All products are calculated with 32 bits, so the results are identical to the scalar
+ * implementation.
+ */
+
+use core::arch::wasm32::{
+    i16x8_shuffle, i32x4_add, i32x4_extend_high_i16x8, i32x4_extend_low_i16x8, i32x4_mul,
+    i32x4_shl, i32x4_shr, i32x4_shuffle, i32x4_splat, i32x4_sub, i64x2_shuffle, i8x16_shuffle,
+    v128, v128_load, v128_store,
+};
+
+const CONST_BITS: u32 = 13;
+const PASS1_BITS: u32 = 2;
+
+const FIX_0_298631336: i32 = 2446;
+const FIX_0_390180644: i32 = 3196;
+const FIX_0_541196100: i32 = 4433;
+const FIX_0_765366865: i32 = 6270;
+const FIX_0_899976223: i32 = 7373;
+const FIX_1_175875602: i32 = 9633;
+const FIX_1_501321110: i32 = 12299;
+const FIX_1_847759065: i32 = 15137;
+const FIX_1_961570560: i32 = 16069;
+const FIX_2_053119869: i32 = 16819;
+const FIX_2_562915447: i32 = 20995;
+const FIX_3_072711026: i32 = 25172;
+
+/// 8 lanes of 32 bit values
+#[derive(Copy, Clone)]
+struct Lanes(v128, v128);
+
+#[inline(always)]
+fn widen(v: v128) -> Lanes {
+    Lanes(i32x4_extend_low_i16x8(v), i32x4_extend_high_i16x8(v))
+}
+
+/// Truncate the lanes to 16 bits
+#[inline(always)]
+fn narrow(v: Lanes) -> v128 {
+    i8x16_shuffle::<0, 1, 4, 5, 8, 9, 12, 13, 16, 17, 20, 21, 24, 25, 28, 29>(v.0, v.1)
+}
+
+#[inline(always)]
+fn add(a: Lanes, b: Lanes) -> Lanes {
+    Lanes(i32x4_add(a.0, b.0), i32x4_add(a.1, b.1))
+}
+
+#[inline(always)]
+fn sub(a: Lanes, b: Lanes) -> Lanes {
+    Lanes(i32x4_sub(a.0, b.0), i32x4_sub(a.1, b.1))
+}
+
+#[inline(always)]
+fn mul(a: Lanes, c: i32) -> Lanes {
+    let c = i32x4_splat(c);
+    Lanes(i32x4_mul(a.0, c), i32x4_mul(a.1, c))
+}
+
+#[inline(always)]
+fn shl(a: Lanes, n: u32) -> Lanes {
+    Lanes(i32x4_shl(a.0, n), i32x4_shl(a.1, n))
+}
+
+/// Right shift with rounding
+#[inline(always)]
+fn descale(a: Lanes, n: u32) -> Lanes {
+    let a = add(
+        a,
+        Lanes(i32x4_splat(1 << (n - 1)), i32x4_splat(1 << (n - 1))),
+    );
+    Lanes(i32x4_shr(a.0, n), i32x4_shr(a.1, n))
+}
+
+#[inline(always)]
+fn transpose(v: [v128; 8]) -> [v128; 8] {
+    let t0 = i16x8_shuffle::<0, 8, 2, 10, 4, 12, 6, 14>(v[0], v[1]);
+    let t1 = i16x8_shuffle::<1, 9, 3, 11, 5, 13, 7, 15>(v[0], v[1]);
+    let t2 = i16x8_shuffle::<0, 8, 2, 10, 4, 12, 6, 14>(v[2], v[3]);
+    let t3 = i16x8_shuffle::<1, 9, 3, 11, 5, 13, 7, 15>(v[2], v[3]);
+    let t4 = i16x8_shuffle::<0, 8, 2, 10, 4, 12, 6, 14>(v[4], v[5]);
+    let t5 = i16x8_shuffle::<1, 9, 3, 11, 5, 13, 7, 15>(v[4], v[5]);
+    let t6 = i16x8_shuffle::<0, 8, 2, 10, 4, 12, 6, 14>(v[6], v[7]);
+    let t7 = i16x8_shuffle::<1, 9, 3, 11, 5, 13, 7, 15>(v[6], v[7]);
+
+    let u0 = i32x4_shuffle::<0, 4, 2, 6>(t0, t2);
+    let u1 = i32x4_shuffle::<0, 4, 2, 6>(t1, t3);
+    let u2 = i32x4_shuffle::<1, 5, 3, 7>(t0, t2);
+    let u3 = i32x4_shuffle::<1, 5, 3, 7>(t1, t3);
+    let u4 = i32x4_shuffle::<0, 4, 2, 6>(t4, t6);
+    let u5 = i32x4_shuffle::<0, 4, 2, 6>(t5, t7);
+    let u6 = i32x4_shuffle::<1, 5, 3, 7>(t4, t6);
+    let u7 = i32x4_shuffle::<1, 5, 3, 7>(t5, t7);
+
+    [
+        i64x2_shuffle::<0, 2>(u0, u4),
+        i64x2_shuffle::<0, 2>(u1, u5),
+        i64x2_shuffle::<0, 2>(u2, u6),
+        i64x2_shuffle::<0, 2>(u3, u7),
+        i64x2_shuffle::<1, 3>(u0, u4),
+        i64x2_shuffle::<1, 3>(u1, u5),
+        i64x2_shuffle::<1, 3>(u2, u6),
+        i64x2_shuffle::<1, 3>(u3, u7),
+    ]
+}
+
+/// 1-D DCT of the 8 lanes
+///
+/// The first pass scales the results by 2**PASS1_BITS, the second one removes this scaling.
+#[inline(always)]
+fn dct_1d(v: [v128; 8], first_pass: bool) -> [v128; 8] {
+    let v0 = widen(v[0]);
+    let v1 = widen(v[1]);
+    let v2 = widen(v[2]);
+    let v3 = widen(v[3]);
+    let v4 = widen(v[4]);
+    let v5 = widen(v[5]);
+    let v6 = widen(v[6]);
+    let v7 = widen(v[7]);
+
+    let tmp0 = add(v0, v7);
+    let tmp7 = sub(v0, v7);
+    let tmp1 = add(v1, v6);
+    let tmp6 = sub(v1, v6);
+    let tmp2 = add(v2, v5);
+    let tmp5 = sub(v2, v5);
+    let tmp3 = add(v3, v4);
+    let tmp4 = sub(v3, v4);
+
+    let descale_bits = if first_pass {
+        CONST_BITS - PASS1_BITS
+    } else {
+        CONST_BITS + PASS1_BITS
+    };
+
+    // Even part
+
+    let tmp10 = add(tmp0, tmp3);
+    let tmp13 = sub(tmp0, tmp3);
+    let tmp11 = add(tmp1, tmp2);
+    let tmp12 = sub(tmp1, tmp2);
+
+    let (out0, out4) = if first_pass {
+        (
+            shl(add(tmp10, tmp11), PASS1_BITS),
+            shl(sub(tmp10, tmp11), PASS1_BITS),
+        )
+    } else {
+        (
+            descale(add(tmp10, tmp11), PASS1_BITS),
+            descale(sub(tmp10, tmp11), PASS1_BITS),
+        )
+    };
+
+    let z1 = mul(add(tmp12, tmp13), FIX_0_541196100);
+    let out2 = descale(add(z1, mul(tmp13, FIX_0_765366865)), descale_bits);
+    let out6 = descale(add(z1, mul(tmp12, -FIX_1_847759065)), descale_bits);
+
+    // Odd part
+
+    let z1 = add(tmp4, tmp7);
+    let z2 = add(tmp5, tmp6);
+    let z3 = add(tmp4, tmp6);
+    let z4 = add(tmp5, tmp7);
+    let z5 = mul(add(z3, z4), FIX_1_175875602);
+
+    let tmp4 = mul(tmp4, FIX_0_298631336);
+    let tmp5 = mul(tmp5, FIX_2_053119869);
+    let tmp6 = mul(tmp6, FIX_3_072711026);
+    let tmp7 = mul(tmp7, FIX_1_501321110);
+    let z1 = mul(z1, -FIX_0_899976223);
+    let z2 = mul(z2, -FIX_2_562915447);
+    let z3 = mul(z3, -FIX_1_961570560);
+    let z4 = mul(z4, -FIX_0_390180644);
+
+    let z3 = add(z3, z5);
+    let z4 = add(z4, z5);
+
+    let out7 = descale(add(add(tmp4, z1), z3), descale_bits);
+    let out5 = descale(add(add(tmp5, z2), z4), descale_bits);
+    let out3 = descale(add(add(tmp6, z2), z3), descale_bits);
+    let out1 = descale(add(add(tmp7, z1), z4), descale_bits);
+
+    [
+        narrow(out0),
+        narrow(out1),
+        narrow(out2),
+        narrow(out3),
+        narrow(out4),
+        narrow(out5),
+        narrow(out6),
+        narrow(out7),
+    ]
+}
+
+pub fn fdct_simd128(data: &mut [i16; 64]) {
+    let mut rows = [i32x4_splat(0); 8];
+
+    for (i, row) in rows.iter_mut().enumerate() {
+        *row = unsafe { v128_load(data.as_ptr().add(i * 8) as *const v128) };
+    }
+
+    // Pass 1: process rows with one row per lane.
+    // The results of 8 bit samples fit into 16 bits.
+    let columns = dct_1d(transpose(rows), true);
+
+    // Pass 2: process columns with one column per lane
+    let rows = dct_1d(transpose(columns), false);
+
+    for (i, row) in rows.iter().enumerate() {
+        unsafe {
+            v128_store(data.as_mut_ptr().add(i * 8) as *mut v128, *row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fdct_simd128;
+    use crate::fdct::fdct;
+
+    #[test]
+    fn test_fdct_simd128() {
+        let mut seed = 1u32;
+
+        for _ in 0..1000 {
+            let mut block = [0i16; 64];
+
+            for v in block.iter_mut() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                *v = ((seed >> 16) % 256) as i16 - 128;
+            }
+
+            let mut expected = block;
+            fdct(&mut expected);
+
+            fdct_simd128(&mut block);
+
+            assert_eq!(block, expected);
+        }
+
+        for value in [-128, 127] {
+            let mut block = [value; 64];
+            let mut expected = block;
+
+            fdct(&mut expected);
+            fdct_simd128(&mut block);
+
+            assert_eq!(block, expected);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+mod fdct;
+mod quantize;
+mod ycbcr;
+
+use alloc::vec::Vec;
+
+use crate::encoder::{convert_row, Operations};
+use crate::quantization::QuantizationTable;
+use crate::{ColorType, ImageBuffer};
+pub use fdct::fdct_simd128;
+pub(crate) use quantize::quantize_block_simd128;
+pub(crate) use ycbcr::*;
+
+pub(crate) struct Simd128Operations;
+
+impl Operations for Simd128Operations {
+    #[inline(always)]
+    fn convert_row(color_type: ColorType, data: &[u8], width: u16, buffers: &mut [Vec<u8>; 4]) {
+        match color_type {
+            ColorType::Rgb => RgbImageSimd128(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Rgba => RgbaImageSimd128(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Bgr => BgrImageSimd128(data, width, 1).fill_buffers(0, buffers),
+            ColorType::Bgra => BgraImageSimd128(data, width, 1).fill_buffers(0, buffers),
+            _ => convert_row(color_type, data, width, buffers),
+        }
+    }
+
+    #[inline(always)]
+    fn fdct(data: &mut [i16; 64]) {
+        fdct_simd128(data);
+    }
+
+    #[inline(always)]
+    fn quantize_block(block: &[i16; 64], q_block: &mut [i16; 64], table: &QuantizationTable) {
+        quantize_block_simd128(block, q_block, table);
+    }
+}
@@ -0,0 +1,98 @@
+use core::arch::wasm32::{
+    i32x4_abs, i32x4_add, i32x4_extend_high_i16x8, i32x4_extend_low_i16x8, i32x4_lt, i32x4_mul,
+    i32x4_neg, i32x4_shr, i32x4_splat, i8x16_shuffle, v128, v128_bitselect, v128_load, v128_store,
+};
+
+use crate::quantization::{QuantizationTable, SHIFT};
+use crate::writer::ZIGZAG;
+
+#[inline(always)]
+fn quantize(values: v128, reciprocals: v128, corrections: v128) -> v128 {
+    let abs_values = i32x4_abs(values);
+
+    let product = i32x4_mul(i32x4_add(abs_values, corrections), reciprocals);
+    let product = i32x4_shr(product, SHIFT);
+
+    v128_bitselect(
+        i32x4_neg(product),
+        product,
+        i32x4_lt(values, i32x4_splat(0)),
+    )
+}
+
+/// Quantize a block of DCT coefficients in natural order into zigzag order
+///
+/// Uses the same reciprocals as [QuantizationTable::quantize], so the results are identical.
+#[inline(always)]
+pub(crate) fn quantize_block_simd128(
+    block: &[i16; 64],
+    q_block: &mut [i16; 64],
+    table: &QuantizationTable,
+) {
+    let (reciprocals, corrections) = table.reciprocals();
+
+    let mut natural = [0i16; 64];
+
+    for i in (0..64).step_by(8) {
+        unsafe {
+            let values = v128_load(block.as_ptr().add(i) as *const v128);
+
+            let low = quantize(
+                i32x4_extend_low_i16x8(values),
+                v128_load(reciprocals.as_ptr().add(i) as *const v128),
+                v128_load(corrections.as_ptr().add(i) as *const v128),
+            );
+
+            let high = quantize(
+                i32x4_extend_high_i16x8(values),
+                v128_load(reciprocals.as_ptr().add(i + 4) as *const v128),
+                v128_load(corrections.as_ptr().add(i + 4) as *const v128),
+            );
+
+            v128_store(
+                natural.as_mut_ptr().add(i) as *mut v128,
+                i8x16_shuffle::<0, 1, 4, 5, 8, 9, 12, 13, 16, 17, 20, 21, 24, 25, 28, 29>(
+                    low, high,
+                ),
+            );
+        }
+    }
+
+    for (q, &z) in q_block.iter_mut().zip(ZIGZAG.iter()) {
+        *q = natural[z as usize & 0x3f];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantize_block_simd128;
+    use crate::encoder::{DefaultOperations, Operations};
+    use crate::quantization::{QuantizationTable, QuantizationTableType};
+
+    #[test]
+    fn test_quantize_simd128() {
+        let mut block = [0i16; 64];
+
+        for (i, v) in block.iter_mut().enumerate() {
+            *v = (i as i16 * 257 - 8000) * if i % 3 == 0 { -1 } else { 1 };
+        }
+
+        for quality in [1, 50, 90, 100] {
+            for luma in [true, false] {
+                let table = QuantizationTable::new_with_quality(
+                    &QuantizationTableType::Default,
+                    quality,
+                    luma,
+                );
+
+                let mut expected = [0i16; 64];
+                DefaultOperations::quantize_block(&block, &mut expected, &table);
+
+                let mut q_block = [0i16; 64];
+                quantize_block_simd128(&block, &mut q_block, &table);
+
+                assert_eq!(q_block, expected);
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use core::arch::wasm32::{
+    i32x4_add, i32x4_mul, i32x4_splat, i8x16_shuffle, u16x8_extend_high_u8x16,
+    u16x8_extend_low_u8x16, u16x8_narrow_i32x4, u32x4_extend_high_u16x8, u32x4_extend_low_u16x8,
+    u32x4_shr, u8x16_narrow_i16x8, v128, v128_load, v128_store,
+};
+
+use alloc::vec::Vec;
+
+use crate::image_buffer::fill_rgb_buffers;
+use crate::{rgb_to_ycbcr, ImageBuffer, JpegColorType};
+
+/// Deinterleave 16 pixels with 3 samples each
+#[inline(always)]
+fn load3(data: &[u8]) -> (v128, v128, v128) {
+    let (a, b, c) = unsafe {
+        (
+            v128_load(data.as_ptr() as *const v128),
+            v128_load(data.as_ptr().add(16) as *const v128),
+            v128_load(data.as_ptr().add(32) as *const v128),
+        )
+    };
+
+    let t = i8x16_shuffle::<0, 3, 6, 9, 12, 15, 18, 21, 24, 27, 30, 0, 0, 0, 0, 0>(a, b);
+    let s0 = i8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 17, 20, 23, 26, 29>(t, c);
+
+    let t = i8x16_shuffle::<1, 4, 7, 10, 13, 16, 19, 22, 25, 28, 31, 0, 0, 0, 0, 0>(a, b);
+    let s1 = i8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 18, 21, 24, 27, 30>(t, c);
+
+    let t = i8x16_shuffle::<2, 5, 8, 11, 14, 17, 20, 23, 26, 29, 0, 0, 0, 0, 0, 0>(a, b);
+    let s2 = i8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 16, 19, 22, 25, 28, 31>(t, c);
+
+    (s0, s1, s2)
+}
+
+/// Deinterleave 16 pixels with 4 samples each, ignoring the last sample
+#[inline(always)]
+fn load4(data: &[u8]) -> (v128, v128, v128) {
+    let (a, b, c, d) = unsafe {
+        (
+            v128_load(data.as_ptr() as *const v128),
+            v128_load(data.as_ptr().add(16) as *const v128),
+            v128_load(data.as_ptr().add(32) as *const v128),
+            v128_load(data.as_ptr().add(48) as *const v128),
+        )
+    };
+
+    // Samples 0 and 1 of pixels 0-7 and 8-15
+    let ab01 = i8x16_shuffle::<0, 4, 8, 12, 16, 20, 24, 28, 1, 5, 9, 13, 17, 21, 25, 29>(a, b);
+    let cd01 = i8x16_shuffle::<0, 4, 8, 12, 16, 20, 24, 28, 1, 5, 9, 13, 17, 21, 25, 29>(c, d);
+
+    // Sample 2 of pixels 0-7 and 8-15
+    let ab2 = i8x16_shuffle::<2, 6, 10, 14, 18, 22, 26, 30, 0, 0, 0, 0, 0, 0, 0, 0>(a, b);
+    let cd2 = i8x16_shuffle::<2, 6, 10, 14, 18, 22, 26, 30, 0, 0, 0, 0, 0, 0, 0, 0>(c, d);
+
+    (
+        i8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 16, 17, 18, 19, 20, 21, 22, 23>(ab01, cd01),
+        i8x16_shuffle::<8, 9, 10, 11, 12, 13, 14, 15, 24, 25, 26, 27, 28, 29, 30, 31>(ab01, cd01),
+        i8x16_shuffle::<0, 1, 2, 3, 4, 5, 6, 7, 16, 17, 18, 19, 20, 21, 22, 23>(ab2, cd2),
+    )
+}
+
+/// Widen 16 samples to 4 vectors of 32 bit values
+#[inline(always)]
+fn widen(v: v128) -> [v128; 4] {
+    let low = u16x8_extend_low_u8x16(v);
+    let high = u16x8_extend_high_u8x16(v);
+
+    [
+        u32x4_extend_low_u16x8(low),
+        u32x4_extend_high_u16x8(low),
+        u32x4_extend_low_u16x8(high),
+        u32x4_extend_high_u16x8(high),
+    ]
+}
+
+/// Descale 4 vectors of values scaled by 2^16 and narrow them to 16 samples
+#[inline(always)]
+fn descale(v: [v128; 4]) -> v128 {
+    // The results of the conversion are never negative and fit into 8 bits,
+    // so the saturating narrowing doesn't change them
+    let v0 = u32x4_shr(i32x4_add(v[0], i32x4_splat(0x7FFF)), 16);
+    let v1 = u32x4_shr(i32x4_add(v[1], i32x4_splat(0x7FFF)), 16);
+    let v2 = u32x4_shr(i32x4_add(v[2], i32x4_splat(0x7FFF)), 16);
+    let v3 = u32x4_shr(i32x4_add(v[3], i32x4_splat(0x7FFF)), 16);
+
+    u8x16_narrow_i16x8(u16x8_narrow_i32x4(v0, v1), u16x8_narrow_i32x4(v2, v3))
+}
+
+/// Convert 16 pixels with the same calculation as [rgb_to_ycbcr]
+#[inline(always)]
+fn convert(r: v128, g: v128, b: v128) -> (v128, v128, v128) {
+    let r = widen(r);
+    let g = widen(g);
+    let b = widen(b);
+
+    let mut y = [i32x4_splat(0); 4];
+    let mut cb = [i32x4_splat(0); 4];
+    let mut cr = [i32x4_splat(0); 4];
+
+    let mul_add = |a: v128, v: v128, c: i32| i32x4_add(a, i32x4_mul(v, i32x4_splat(c)));
+
+    for i in 0..4 {
+        y[i] = i32x4_mul(r[i], i32x4_splat(19595));
+        y[i] = mul_add(y[i], g[i], 38470);
+        y[i] = mul_add(y[i], b[i], 7471);
+
+        cb[i] = mul_add(i32x4_splat(128 << 16), r[i], -11059);
+        cb[i] = mul_add(cb[i], g[i], -21709);
+        cb[i] = mul_add(cb[i], b[i], 32768);
+
+        cr[i] = mul_add(i32x4_splat(128 << 16), r[i], 32768);
+        cr[i] = mul_add(cr[i], g[i], -27439);
+        cr[i] = mul_add(cr[i], b[i], -5329);
+    }
+
+    (descale(y), descale(cb), descale(cr))
+}
+
+macro_rules! ycbcr_image_simd128 {
+    ($name:ident, $num_colors:expr, $load:ident, $o1:tt, $o2:tt, $o3:tt) => {
+        pub(crate) struct $name<'a>(pub &'a [u8], pub u16, pub u16);
+
+        impl<'a> ImageBuffer for $name<'a> {
+            fn get_jpeg_color_type(&self) -> JpegColorType {
+                JpegColorType::Ycbcr
+            }
+
+            fn width(&self) -> u16 {
+                self.1
+            }
+
+            fn height(&self) -> u16 {
+                self.2
+            }
+
+            fn fill_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let width = usize::from(self.width());
+
+                let start = usize::from(y) * width * $num_colors;
+                let line = &self.0[start..start + width * $num_colors];
+
+                let mut chunks = line.chunks_exact(16 * $num_colors);
+
+                let mut values = [[0u8; 16]; 3];
+
+                for chunk in &mut chunks {
+                    let pixels = $load(chunk);
+
+                    let (y, cb, cr) = convert(pixels.$o1, pixels.$o2, pixels.$o3);
+
+                    unsafe {
+                        v128_store(values[0].as_mut_ptr() as *mut v128, y);
+                        v128_store(values[1].as_mut_ptr() as *mut v128, cb);
+                        v128_store(values[2].as_mut_ptr() as *mut v128, cr);
+                    }
+
+                    for (buffer, values) in buffers.iter_mut().zip(values.iter()) {
+                        buffer.extend_from_slice(values);
+                    }
+                }
+
+                for pixel in chunks.remainder().chunks_exact($num_colors) {
+                    let (y, cb, cr) = rgb_to_ycbcr(pixel[$o1], pixel[$o2], pixel[$o3]);
+
+                    buffers[0].push(y);
+                    buffers[1].push(cb);
+                    buffers[2].push(cr);
+                }
+            }
+
+            fn get_lossless_color_type(&self) -> JpegColorType {
+                JpegColorType::Rgb
+            }
+
+            fn fill_lossless_buffers(&self, y: u16, buffers: &mut [Vec<u8>; 4]) {
+                let offsets = [$o1, $o2, $o3];
+                fill_rgb_buffers(self.0, y, self.1, $num_colors, offsets, buffers);
+            }
+        }
+    };
+}
+
+ycbcr_image_simd128!(RgbImageSimd128, 3, load3, 0, 1, 2);
+ycbcr_image_simd128!(RgbaImageSimd128, 4, load4, 0, 1, 2);
+ycbcr_image_simd128!(BgrImageSimd128, 3, load3, 2, 1, 0);
+ycbcr_image_simd128!(BgraImageSimd128, 4, load4, 2, 1, 0);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_buffer::{BgraImage, RgbImage, RgbaImage};
+    use alloc::vec;
+
+    fn fill<I: ImageBuffer>(image: &I) -> [Vec<u8>; 4] {
+        let mut buffers = [vec![], vec![], vec![], vec![]];
+
+        for y in 0..image.height() {
+            image.fill_buffers(y, &mut buffers);
+        }
+
+        buffers
+    }
+
+    #[test]
+    fn test_ycbcr_simd128() {
+        let width = 37;
+        let height = 3;
+
+        let data: Vec<u8> = (0..usize::from(width) * usize::from(height) * 4)
+            .map(|i| (i * 97 % 256) as u8)
+            .collect();
+
+        let rgb = &data[..usize::from(width) * usize::from(height) * 3];
+
+        assert_eq!(
+            fill(&RgbImageSimd128(rgb, width, height)),
+            fill(&RgbImage(rgb, width, height))
+        );
+
+        assert_eq!(
+            fill(&RgbaImageSimd128(&data, width, height)),
+            fill(&RgbaImage(&data, width, height))
+        );
+
+        assert_eq!(
+            fill(&BgraImageSimd128(&data, width, height)),
+            fill(&BgraImage(&data, width, height))
+        );
+
+        // Extreme values
+        let data = [0, 0, 255, 255, 255, 0, 255, 0, 0, 0, 255, 0].repeat(16);
+
+        assert_eq!(
+            fill(&RgbImageSimd128(&data, 64, 1)),
+            fill(&RgbImage(&data, 64, 1))
+        );
+    }
+}
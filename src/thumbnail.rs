@@ -0,0 +1,341 @@
+/*
+ * JFIF thumbnails
+ *
+ * Uncompressed RGB thumbnails are stored in the JFIF APP0 segment, JPEG compressed thumbnails
+ * in a JFXX APP0 extension segment as described in JPEG File Interchange Format Version 1.02.
+ */
+
+use alloc::vec::Vec;
+
+use crate::encoder::JpegColorType;
+use crate::writer::MAX_SEGMENT_LENGTH;
+use crate::{ColorType, Encoder, EncodingError, MarkerLayout};
+
+/// Maximum length of the RGB data in the JFIF segment after the 14 bytes of header fields
+const MAX_RGB_LENGTH: usize = MAX_SEGMENT_LENGTH - 14;
+
+/// Maximum length of the JPEG data in the JFXX segment after the identifier and extension code
+pub(crate) const MAX_JPEG_LENGTH: usize = MAX_SEGMENT_LENGTH - 6;
+
+/// # Thumbnail embedded in the JFIF header
+///
+/// The thumbnail is downscaled from the encoded image with a box filter. It keeps the aspect
+/// ratio of the image and fits into the given maximum dimensions. Images which already fit
+/// keep their size.
+///
+/// Thumbnails are part of the JFIF segment, so they can only be written for grayscale and
+/// YCbCr images with [MarkerLayout::jfif] enabled.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum JfifThumbnail {
+    /// Uncompressed 24 bit RGB thumbnail in the JFIF APP0 segment
+    ///
+    /// The maximum dimensions must not exceed 21839 pixels, e.g. 147x147.
+    Rgb { max_width: u8, max_height: u8 },
+
+    /// Thumbnail encoded by this crate with the given quality in a JFXX APP0 extension segment
+    ///
+    /// The encoded thumbnail must not exceed 65527 bytes.
+    Jpeg {
+        max_width: u16,
+        max_height: u16,
+        quality: u8,
+    },
+}
+
+impl JfifThumbnail {
+    pub(crate) fn validate(&self) -> Result<(), EncodingError> {
+        let (max_width, max_height) = self.max_dimensions();
+
+        if max_width == 0 || max_height == 0 {
+            return Err(EncodingError::Write(alloc::format!(
+                "Thumbnail dimensions must be non zero: {}x{}",
+                max_width,
+                max_height
+            )));
+        }
+
+        if let JfifThumbnail::Rgb { .. } = self {
+            let length = usize::from(max_width) * usize::from(max_height) * 3;
+
+            if length > MAX_RGB_LENGTH {
+                return Err(EncodingError::Write(alloc::format!(
+                    "RGB thumbnail of {}x{} exceeds the JFIF segment",
+                    max_width,
+                    max_height
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn max_dimensions(&self) -> (u16, u16) {
+        match *self {
+            JfifThumbnail::Rgb {
+                max_width,
+                max_height,
+            } => (u16::from(max_width), u16::from(max_height)),
+            JfifThumbnail::Jpeg {
+                max_width,
+                max_height,
+                ..
+            } => (max_width, max_height),
+        }
+    }
+}
+
+/// Thumbnail data for the JFIF header
+pub(crate) enum Thumbnail {
+    /// Interleaved RGB samples for the JFIF segment
+    Rgb {
+        width: u8,
+        height: u8,
+        pixels: Vec<u8>,
+    },
+
+    /// JPEG file for the JFXX segment
+    Jpeg(Vec<u8>),
+}
+
+/// Create the thumbnail of an image
+///
+/// `fill_buffers` adds the samples of a row to the component buffers like
+/// [ImageBuffer::fill_buffers](crate::ImageBuffer::fill_buffers), the samples have the
+/// given precision.
+pub(crate) fn create_thumbnail<T, F>(
+    settings: JfifThumbnail,
+    jpeg_color_type: JpegColorType,
+    width: u16,
+    height: u16,
+    precision: u8,
+    fill_buffers: F,
+) -> Result<Thumbnail, EncodingError>
+where
+    T: Copy + Into<u32>,
+    F: FnMut(u16, &mut [Vec<T>; 4]),
+{
+    let num_components = match jpeg_color_type {
+        JpegColorType::Luma => 1,
+        JpegColorType::Ycbcr => 3,
+        _ => {
+            return Err(EncodingError::Write(alloc::format!(
+                "JFIF thumbnails are not supported for {:?} images",
+                jpeg_color_type
+            )))
+        }
+    };
+
+    let (max_width, max_height) = settings.max_dimensions();
+    let (thumb_width, thumb_height) = thumbnail_size(width, height, max_width, max_height);
+
+    let samples = downscale(
+        width,
+        height,
+        thumb_width,
+        thumb_height,
+        num_components,
+        precision,
+        fill_buffers,
+    );
+
+    match settings {
+        JfifThumbnail::Rgb { .. } => {
+            let pixels = if num_components == 1 {
+                samples.iter().flat_map(|&v| [v, v, v]).collect()
+            } else {
+                samples
+                    .chunks_exact(3)
+                    .flat_map(|p| {
+                        let (r, g, b) = ycbcr_to_rgb(p[0], p[1], p[2]);
+                        [r, g, b]
+                    })
+                    .collect()
+            };
+
+            Ok(Thumbnail::Rgb {
+                width: thumb_width as u8,
+                height: thumb_height as u8,
+                pixels,
+            })
+        }
+        JfifThumbnail::Jpeg { quality, .. } => {
+            let color_type = if num_components == 1 {
+                ColorType::Luma
+            } else {
+                ColorType::Ycbcr
+            };
+
+            let mut data = Vec::new();
+
+            let mut encoder = Encoder::new(&mut data, quality);
+            encoder.set_marker_layout(MarkerLayout {
+                jfif: false,
+                ..MarkerLayout::default()
+            });
+            encoder.encode(&samples, thumb_width, thumb_height, color_type)?;
+
+            if data.len() > MAX_JPEG_LENGTH {
+                return Err(EncodingError::Write(alloc::format!(
+                    "JPEG thumbnail exceeds maximum allowed data length of {}: {}",
+                    MAX_JPEG_LENGTH,
+                    data.len()
+                )));
+            }
+
+            Ok(Thumbnail::Jpeg(data))
+        }
+    }
+}
+
+/// Returns the largest size with the aspect ratio of the image within the maximum dimensions
+fn thumbnail_size(width: u16, height: u16, max_width: u16, max_height: u16) -> (u16, u16) {
+    if width <= max_width && height <= max_height {
+        return (width, height);
+    }
+
+    let (width, height) = (u32::from(width), u32::from(height));
+    let (max_width, max_height) = (u32::from(max_width), u32::from(max_height));
+
+    if width * max_height >= height * max_width {
+        let scaled = (height * max_width + width / 2) / width;
+        (max_width as u16, scaled.max(1) as u16)
+    } else {
+        let scaled = (width * max_height + height / 2) / height;
+        (scaled.max(1) as u16, max_height as u16)
+    }
+}
+
+/// Downscale the image with a box filter
+///
+/// Returns the interleaved 8 bit samples of the thumbnail.
+fn downscale<T, F>(
+    width: u16,
+    height: u16,
+    thumb_width: u16,
+    thumb_height: u16,
+    num_components: usize,
+    precision: u8,
+    mut fill_buffers: F,
+) -> Vec<u8>
+where
+    T: Copy + Into<u32>,
+    F: FnMut(u16, &mut [Vec<T>; 4]),
+{
+    let (width, height) = (usize::from(width), usize::from(height));
+    let (thumb_width, thumb_height) = (usize::from(thumb_width), usize::from(thumb_height));
+
+    // Each source row and column belongs to exactly one thumbnail row and column
+    let column_of = |x: usize| x * thumb_width / width;
+    let row_of = |y: usize| y * thumb_height / height;
+
+    let mut column_counts = alloc::vec![0u64; thumb_width];
+    for x in 0..width {
+        column_counts[column_of(x)] += 1;
+    }
+
+    let max_value = (1u64 << precision) - 1;
+
+    let mut samples = Vec::with_capacity(thumb_width * thumb_height * num_components);
+    let mut sums = alloc::vec![0u64; thumb_width * num_components];
+    let mut row_count = 0u64;
+
+    let mut row: [Vec<T>; 4] = Default::default();
+
+    for y in 0..height {
+        for buffer in &mut row {
+            buffer.clear();
+        }
+
+        fill_buffers(y as u16, &mut row);
+
+        for (c, buffer) in row.iter().take(num_components).enumerate() {
+            for (x, &value) in buffer.iter().take(width).enumerate() {
+                sums[column_of(x) * num_components + c] += u64::from(value.into());
+            }
+        }
+
+        row_count += 1;
+
+        if y + 1 == height || row_of(y + 1) != row_of(y) {
+            for (i, sum) in sums.iter_mut().enumerate() {
+                let count = column_counts[i / num_components] * row_count * max_value;
+                samples.push(((*sum * 255 + count / 2) / count) as u8);
+                *sum = 0;
+            }
+
+            row_count = 0;
+        }
+    }
+
+    samples
+}
+
+/// Conversion from YCbCr to RGB as specified by JFIF
+fn ycbcr_to_rgb(y: u8, cb: u8, cr: u8) -> (u8, u8, u8) {
+    // Scaled by 2^16 like the conversion from RGB to YCbCr
+    //
+    // Non scaled conversion:
+    // R = Y                        + 1.40200 * (Cr - 128)
+    // G = Y - 0.34414 * (Cb - 128) - 0.71414 * (Cr - 128)
+    // B = Y + 1.77200 * (Cb - 128)
+
+    let y = (y as i32) << 16;
+    let cb = cb as i32 - 128;
+    let cr = cr as i32 - 128;
+
+    let r = y + 91881 * cr;
+    let g = y - 22554 * cb - 46802 * cr;
+    let b = y + 116130 * cb;
+
+    let clamp = |v: i32| ((v + 0x7FFF) >> 16).clamp(0, 255) as u8;
+
+    (clamp(r), clamp(g), clamp(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rgb_to_ycbcr;
+
+    #[test]
+    fn test_thumbnail_size() {
+        assert_eq!(thumbnail_size(100, 50, 160, 120), (100, 50));
+        assert_eq!(thumbnail_size(640, 480, 160, 160), (160, 120));
+        assert_eq!(thumbnail_size(480, 640, 160, 160), (120, 160));
+        assert_eq!(thumbnail_size(1000, 3, 100, 100), (100, 1));
+        assert_eq!(thumbnail_size(3, 1000, 100, 100), (1, 100));
+    }
+
+    #[test]
+    fn test_downscale() {
+        // 5x3 image with two components downscaled to 2x2
+        let samples = downscale(5, 3, 2, 2, 2, 8, |y, buffers: &mut [Vec<u8>; 4]| {
+            for x in 0..5 {
+                buffers[0].push(10 * x + 50 * y as u8);
+                buffers[1].push(255);
+            }
+        });
+
+        // Columns 0-2 and 3-4, rows 0-1 and 2
+        assert_eq!(samples, [35, 255, 60, 255, 110, 255, 135, 255]);
+
+        // 12 bit samples are scaled to 8 bit
+        let samples = downscale(2, 2, 1, 1, 1, 12, |_, buffers: &mut [Vec<u16>; 4]| {
+            buffers[0].extend_from_slice(&[4095, 0]);
+        });
+
+        assert_eq!(samples, [128]);
+    }
+
+    #[test]
+    fn test_ycbcr_to_rgb() {
+        for &(r, g, b) in &[(0, 0, 0), (255, 255, 255), (255, 0, 0), (20, 200, 120)] {
+            let (y, cb, cr) = rgb_to_ycbcr(r, g, b);
+            let (r2, g2, b2) = ycbcr_to_rgb(y, cb, cr);
+
+            assert!((i16::from(r) - i16::from(r2)).abs() <= 2);
+            assert!((i16::from(g) - i16::from(g2)).abs() <= 2);
+            assert!((i16::from(b) - i16::from(b2)).abs() <= 2);
+        }
+    }
+}
@@ -0,0 +1,524 @@
+/*
+ * Rate-distortion optimized quantization.
+ *
+ * The implementation follows the trellis quantization of mozjpeg (jcdctmgr.c).
+ * The rate is estimated with the code lengths of the huffman tables of the components.
+ */
+
+use alloc::vec::Vec;
+
+use crate::huffman::HuffmanTable;
+use crate::quantization::QuantizationTable;
+use crate::writer::ZIGZAG;
+
+/// Number of candidates for each DC coefficient
+const DC_CANDIDATES: usize = 3;
+
+/// Code length used for symbols that aren't part of a huffman table
+const MISSING_CODE_LENGTH: f32 = 16.0;
+
+/// # Trellis quantization settings
+///
+/// Trellis quantization chooses the quantized values by their rate–distortion cost
+/// instead of rounding each coefficient. The distortion is weighted by `lambda`
+/// which is calculated for each block as `2^lambda_log_scale1 / (2^lambda_log_scale2 + norm)`
+/// where `norm` is the mean energy of the AC coefficients of the block.
+/// Higher values of `lambda_log_scale1` preserve more details.
+///
+/// The defaults are the same as in mozjpeg.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TrellisQuantization {
+    /// Optimize the DC coefficients across blocks in addition to the AC coefficients
+    pub dc: bool,
+
+    /// Base 2 logarithm of the lambda scale
+    pub lambda_log_scale1: f32,
+
+    /// Base 2 logarithm of the offset added to the block norm
+    ///
+    /// If this value is 0 the block norm is ignored and lambda is `2^(lambda_log_scale1 - 12)`.
+    pub lambda_log_scale2: f32,
+}
+
+impl TrellisQuantization {
+    /// Create new trellis quantization settings
+    pub fn new(dc: bool, lambda_log_scale1: f32, lambda_log_scale2: f32) -> TrellisQuantization {
+        TrellisQuantization {
+            dc,
+            lambda_log_scale1,
+            lambda_log_scale2,
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.lambda_log_scale1.is_finite()
+            && self.lambda_log_scale2.is_finite()
+            && self.lambda_log_scale2 >= 0.0
+    }
+}
+
+impl Default for TrellisQuantization {
+    /// AC and DC trellis quantization with a lambda scale of 14.75 and 16.5
+    fn default() -> Self {
+        TrellisQuantization::new(true, 14.75, 16.5)
+    }
+}
+
+/// Base 2 exponential function as `f32::powf` isn't available without std
+fn exp2(x: f32) -> f32 {
+    let mut int = x as i32;
+    if (int as f32) > x {
+        int -= 1;
+    }
+
+    let fract = (x - int as f32) * core::f32::consts::LN_2;
+
+    // Taylor series of e^fract which is precise enough for 0 <= fract < ln(2)
+    let mut term = 1.0;
+    let mut sum = 1.0;
+
+    for i in 1..10 {
+        term *= fract / i as f32;
+        sum += term;
+    }
+
+    let factor = if int >= 0 { 2.0 } else { 0.5 };
+
+    for _ in 0..int.unsigned_abs() {
+        sum *= factor;
+    }
+
+    sum
+}
+
+/// Magnitude category of a value
+#[inline]
+fn num_bits(value: u32) -> u32 {
+    32 - value.leading_zeros()
+}
+
+#[inline]
+fn code_length(table: &HuffmanTable, symbol: u8) -> f32 {
+    match table.code_length(symbol) {
+        0 => MISSING_CODE_LENGTH,
+        length => f32::from(length),
+    }
+}
+
+/// Trellis quantizer for the blocks of an image
+pub(crate) struct TrellisQuantizer {
+    dc: bool,
+    lambda_scale1: f32,
+    lambda_scale2: f32,
+}
+
+impl TrellisQuantizer {
+    pub fn new(settings: &TrellisQuantization) -> TrellisQuantizer {
+        TrellisQuantizer {
+            dc: settings.dc,
+            lambda_scale1: exp2(settings.lambda_log_scale1),
+            lambda_scale2: if settings.lambda_log_scale2 > 0.0 {
+                exp2(settings.lambda_log_scale2)
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Returns true if the DC coefficients are optimized
+    pub fn optimize_dc(&self) -> bool {
+        self.dc
+    }
+
+    /// Returns the lambda of a block
+    pub fn lambda(&self, block: &[i32; 64]) -> f32 {
+        if self.lambda_scale2 > 0.0 {
+            let norm = block[1..]
+                .iter()
+                .map(|&v| (v as f32) * (v as f32))
+                .sum::<f32>()
+                / 63.0;
+
+            self.lambda_scale1 / (self.lambda_scale2 + norm)
+        } else {
+            self.lambda_scale1 / 4096.0
+        }
+    }
+
+    /// Quantize a block of DCT coefficients
+    ///
+    /// The coefficients are in natural order and scaled by 8, the result is in zigzag order.
+    /// The DC coefficient is rounded. Returns the lambda of the block which is needed
+    /// for the optimization of the DC coefficients.
+    pub fn quantize_block(
+        &self,
+        block: &[i32; 64],
+        q_block: &mut [i16; 64],
+        table: &QuantizationTable,
+        ac_table: &HuffmanTable,
+    ) -> f32 {
+        let lambda = self.lambda(block);
+
+        q_block[0] = table.quantize_wide(block[0], 0);
+
+        // Cost of all coefficients up to a position if they are all zero
+        let mut accumulated_zero_dist = [0f32; 64];
+
+        // Minimal cost of all coefficients up to a position if the coefficient at the position is not zero
+        let mut accumulated_cost = [f32::MAX; 64];
+        accumulated_cost[0] = 0.0;
+
+        // Previous non zero position and value of the minimal costs
+        let mut run_start = [0usize; 64];
+        let mut values = [0i16; 64];
+
+        let zrl_length = f32::from(ac_table.code_length(0xF0));
+        let eob_length = code_length(ac_table, 0x00);
+
+        for i in 1..64 {
+            let z = ZIGZAG[i] as usize & 0x3f;
+
+            let q_value = u32::from(table.get(z));
+            let q = 8.0 * q_value as f32;
+            let weight = lambda / (q_value * q_value) as f32;
+
+            let x = block[z].unsigned_abs() as f32;
+
+            accumulated_zero_dist[i] = accumulated_zero_dist[i - 1] + x * x * weight;
+
+            let rounded = table.quantize_wide(block[z], z).unsigned_abs() as u32;
+
+            if rounded == 0 {
+                continue;
+            }
+
+            // Candidates are the largest values of all smaller magnitude categories and the rounded value
+            let num_candidates = num_bits(rounded);
+
+            let mut candidates = [(0u32, 0f32); 16];
+
+            for (k, candidate) in candidates[..num_candidates as usize].iter_mut().enumerate() {
+                let value = if k as u32 + 1 < num_candidates {
+                    (2 << k) - 1
+                } else {
+                    rounded
+                };
+
+                let delta = value as f32 * q - x;
+
+                *candidate = (value, delta * delta * weight);
+            }
+
+            for j in 0..i {
+                if j > 0 && accumulated_cost[j] == f32::MAX {
+                    continue;
+                }
+
+                let zero_run = i - 1 - j;
+
+                // Later start positions have shorter runs which might still be codable
+                if zero_run >= 16 && zrl_length == 0.0 {
+                    continue;
+                }
+
+                let run_bits = (zero_run >> 4) as f32 * zrl_length;
+                let zero_run = (zero_run & 15) as u8;
+
+                let previous_cost =
+                    accumulated_cost[j] + accumulated_zero_dist[i - 1] - accumulated_zero_dist[j];
+
+                for &(value, dist) in &candidates[..num_candidates as usize] {
+                    let size = num_bits(value) as u8;
+
+                    let rate =
+                        code_length(ac_table, zero_run << 4 | size) + f32::from(size) + run_bits;
+                    let cost = previous_cost + rate + dist;
+
+                    if cost < accumulated_cost[i] {
+                        accumulated_cost[i] = cost;
+                        run_start[i] = j;
+                        values[i] = value as i16;
+                    }
+                }
+            }
+        }
+
+        // Find the best position for the last non zero coefficient
+        let mut last = 0;
+        let mut best_cost = accumulated_zero_dist[63] + eob_length;
+
+        for i in 1..64 {
+            if accumulated_cost[i] == f32::MAX {
+                continue;
+            }
+
+            let mut cost =
+                accumulated_cost[i] + accumulated_zero_dist[63] - accumulated_zero_dist[i];
+
+            if i < 63 {
+                cost += eob_length;
+            }
+
+            if cost < best_cost {
+                best_cost = cost;
+                last = i;
+            }
+        }
+
+        for value in &mut q_block[1..] {
+            *value = 0;
+        }
+
+        let mut i = last;
+        while i > 0 {
+            let z = ZIGZAG[i] as usize & 0x3f;
+
+            q_block[i] = if block[z] < 0 { -values[i] } else { values[i] };
+            i = run_start[i];
+        }
+
+        lambda
+    }
+
+    /// Optimize the DC coefficients of the blocks of a component
+    ///
+    /// `dc_values` contains the unquantized DC coefficient and the lambda of each block.
+    pub fn quantize_dc(
+        &self,
+        dc_values: &[(i32, f32)],
+        blocks: &mut [[i16; 64]],
+        table: &QuantizationTable,
+        dc_table: &HuffmanTable,
+    ) {
+        debug_assert_eq!(dc_values.len(), blocks.len());
+
+        if blocks.is_empty() {
+            return;
+        }
+
+        let q_value = u32::from(table.get(0));
+        let q = 8.0 * q_value as f32;
+        let scale = 1.0 / (q_value * q_value) as f32;
+
+        let rate = |diff: i32| {
+            let size = num_bits(diff.unsigned_abs());
+            code_length(dc_table, size as u8) + size as f32
+        };
+
+        let candidates = |n: usize| {
+            let rounded = i32::from(table.quantize_wide(dc_values[n].0, 0));
+            let max = i32::from(i16::MAX);
+
+            let mut candidates = [0i32; DC_CANDIDATES];
+            for (k, candidate) in candidates.iter_mut().enumerate() {
+                *candidate = (rounded + k as i32 - 1).clamp(-max, max);
+            }
+            candidates
+        };
+
+        let dist = |n: usize, value: i32| {
+            let (x, lambda) = dc_values[n];
+            let delta = value as f32 * q - x as f32;
+            delta * delta * lambda * scale
+        };
+
+        // Viterbi search over all blocks
+        let mut previous = candidates(0);
+        let mut costs = [0f32; DC_CANDIDATES];
+
+        for (k, cost) in costs.iter_mut().enumerate() {
+            *cost = rate(previous[k]) + dist(0, previous[k]);
+        }
+
+        let mut paths: Vec<[u8; DC_CANDIDATES]> = Vec::with_capacity(blocks.len());
+        paths.push([0; DC_CANDIDATES]);
+
+        for n in 1..blocks.len() {
+            let current = candidates(n);
+
+            let mut new_costs = [f32::MAX; DC_CANDIDATES];
+            let mut path = [0u8; DC_CANDIDATES];
+
+            for (k, &value) in current.iter().enumerate() {
+                let block_dist = dist(n, value);
+
+                for (j, &previous_value) in previous.iter().enumerate() {
+                    let cost = costs[j] + rate(value - previous_value) + block_dist;
+
+                    if cost < new_costs[k] {
+                        new_costs[k] = cost;
+                        path[k] = j as u8;
+                    }
+                }
+            }
+
+            paths.push(path);
+            previous = current;
+            costs = new_costs;
+        }
+
+        let mut best = 0;
+        for k in 1..DC_CANDIDATES {
+            if costs[k] < costs[best] {
+                best = k;
+            }
+        }
+
+        for n in (0..blocks.len()).rev() {
+            blocks[n][0] = candidates(n)[best] as i16;
+            best = usize::from(paths[n][best]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantization::QuantizationTableType;
+    use alloc::vec;
+
+    fn block() -> [i32; 64] {
+        let mut block = [0i32; 64];
+
+        for (i, v) in block.iter_mut().enumerate() {
+            let (x, y) = (i % 8, i / 8);
+            *v = ((900 - 130 * (x + y) as i32) * if (x * 3 + y) % 4 == 0 { -1 } else { 1 })
+                / (1 + x * y) as i32;
+        }
+
+        block
+    }
+
+    #[test]
+    fn test_exp2() {
+        for (x, expected) in [
+            (0.0, 1.0),
+            (1.0, 2.0),
+            (14.75, 27554.494),
+            (-2.5, 0.176_776_7),
+        ] {
+            let value = exp2(x);
+            assert!(
+                (value - expected).abs() / expected < 1e-5,
+                "{} {}",
+                x,
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn test_high_lambda_rounds() {
+        // With a very high lambda only the distortion matters
+        let quantizer = TrellisQuantizer::new(&TrellisQuantization::new(false, 60.0, 0.0));
+
+        let table = QuantizationTable::new_with_quality(&QuantizationTableType::Default, 80, true);
+        let ac_table = HuffmanTable::default_luma_ac();
+
+        let block = block();
+
+        let mut q_block = [0i16; 64];
+        quantizer.quantize_block(&block, &mut q_block, &table, &ac_table);
+
+        for i in 0..64 {
+            let z = ZIGZAG[i] as usize & 0x3f;
+            assert_eq!(q_block[i], table.quantize_wide(block[z], z));
+        }
+    }
+
+    #[test]
+    fn test_trellis_reduces_rate() {
+        let quantizer = TrellisQuantizer::new(&TrellisQuantization::default());
+
+        let table = QuantizationTable::new_with_quality(&QuantizationTableType::Default, 80, true);
+        let ac_table = HuffmanTable::default_luma_ac();
+
+        let block = block();
+
+        let mut q_block = [0i16; 64];
+        quantizer.quantize_block(&block, &mut q_block, &table, &ac_table);
+
+        let rounded: Vec<i16> = (0..64)
+            .map(|i| {
+                let z = ZIGZAG[i] as usize & 0x3f;
+                table.quantize_wide(block[z], z)
+            })
+            .collect();
+
+        // Values are never increased and keep their sign
+        for (&q, &r) in q_block.iter().zip(rounded.iter()) {
+            assert!(q.abs() <= r.abs());
+            assert!(q == 0 || q.signum() == r.signum());
+        }
+
+        let nonzero = |values: &[i16]| values.iter().filter(|&&v| v != 0).count();
+        assert!(nonzero(&q_block) < nonzero(&rounded));
+    }
+
+    #[test]
+    fn test_missing_zrl_code() {
+        // With a very high lambda only the distortion matters
+        let quantizer = TrellisQuantizer::new(&TrellisQuantization::new(false, 60.0, 0.0));
+
+        let table = QuantizationTable::new_with_quality(&QuantizationTableType::Default, 80, true);
+
+        // Default table without the code for runs of 16 zeros
+        let default_table = HuffmanTable::default_luma_ac();
+        let mut length = *default_table.length();
+        length[usize::from(default_table.code_length(0xF0)) - 1] -= 1;
+        let values: Vec<u8> = default_table
+            .values()
+            .iter()
+            .copied()
+            .filter(|&v| v != 0xF0)
+            .collect();
+        let ac_table = HuffmanTable::new(&length, &values);
+        assert_eq!(ac_table.code_length(0xF0), 0);
+
+        // The run to the second coefficient is only codable from the first one
+        let mut block = [0i32; 64];
+        block[ZIGZAG[10] as usize & 0x3f] = 8000;
+        block[ZIGZAG[20] as usize & 0x3f] = -8000;
+
+        let mut q_block = [0i16; 64];
+        quantizer.quantize_block(&block, &mut q_block, &table, &ac_table);
+
+        for i in [10, 20] {
+            let z = ZIGZAG[i] as usize & 0x3f;
+            assert_eq!(q_block[i], table.quantize_wide(block[z], z));
+        }
+    }
+
+    #[test]
+    fn test_dc_trellis() {
+        let quantizer = TrellisQuantizer::new(&TrellisQuantization::default());
+
+        let table = QuantizationTable::new_with_quality(&QuantizationTableType::Default, 50, true);
+        let dc_table = HuffmanTable::default_luma_dc();
+
+        // Slowly changing DC values which differ by one after rounding
+        let dc_values: Vec<(i32, f32)> = (0..32).map(|i| (1000 + i * 7, 0.05)).collect();
+
+        let mut blocks = vec![[0i16; 64]; dc_values.len()];
+        quantizer.quantize_dc(&dc_values, &mut blocks, &table, &dc_table);
+
+        let mut changes = 0;
+
+        for (n, block) in blocks.iter().enumerate() {
+            let rounded = table.quantize_wide(dc_values[n].0, 0);
+            assert!((block[0] - rounded).abs() <= 1);
+
+            if n > 0 && block[0] != blocks[n - 1][0] {
+                changes += 1;
+            }
+        }
+
+        let rounded_changes = (1..dc_values.len())
+            .filter(|&n| {
+                table.quantize_wide(dc_values[n].0, 0) != table.quantize_wide(dc_values[n - 1].0, 0)
+            })
+            .count();
+
+        assert!(changes < rounded_changes);
+    }
+}
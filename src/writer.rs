@@ -1,7 +1,9 @@
+use crate::arithmetic::ArithmeticConditioning;
 use crate::encoder::Component;
 use crate::huffman::{CodingClass, HuffmanTable};
 use crate::marker::{Marker, SOFType};
 use crate::quantization::QuantizationTable;
+use crate::thumbnail::Thumbnail;
 use crate::EncodingError;
 
 /// Density settings
@@ -17,10 +19,50 @@ pub enum Density {
     Centimeter { x: u16, y: u16 },
 }
 
+/// Position of metadata segments in the file header
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SegmentPosition {
+    /// After the JFIF and Adobe APP segments, before the frame header and the tables
+    BeforeTables,
+
+    /// After the frame header, the tables and the restart interval, directly before the first scan
+    AfterTables,
+}
+
+/// # Layout of the segments in the file header
+///
+/// The EXIF segment always directly follows the SOI marker, the JFIF and Adobe APP segments
+/// follow the EXIF segment. If APP segments and comments are written at the same position,
+/// the APP segments come first.
+///
+/// The default layout writes the JFIF segment and all APP segments and comments before
+/// the tables.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct MarkerLayout {
+    /// Write the JFIF APP0 segment for grayscale and YCbCr images
+    pub jfif: bool,
+
+    /// Position of the APP segments added to the encoder, including ICC profiles and XMP packets
+    pub app_segments: SegmentPosition,
+
+    /// Position of the COM segments
+    pub comments: SegmentPosition,
+}
+
+impl Default for MarkerLayout {
+    fn default() -> Self {
+        MarkerLayout {
+            jfif: true,
+            app_segments: SegmentPosition::BeforeTables,
+            comments: SegmentPosition::BeforeTables,
+        }
+    }
+}
+
 /// Zig-zag sequence of quantized DCT coefficients
 ///
 /// Figure A.6
-pub static ZIGZAG: [u8; 64] = [
+pub const ZIGZAG: [u8; 64] = [
     0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
     13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
     52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
@@ -64,6 +106,44 @@ impl<W: std::io::Write + ?Sized> JfifWrite for W {
     }
 }
 
+/// Temporary storage for the quantized coefficients of a [StripEncoder](crate::StripEncoder)
+///
+/// With optimized Huffman tables, the strip encoder writes the coefficients of all blocks
+/// into the scratch storage and reads them back once in [finish](crate::StripEncoder::finish).
+/// The storage must be empty when it's passed to the encoder.
+///
+/// This trait is implemented for types implementing `std::io::Read`, `std::io::Write` and
+/// `std::io::Seek` (e.g. `std::fs::File`) if the `std` feature is enabled.
+pub trait StripScratch: JfifWrite {
+    /// Moves the read position to the start of the written data
+    /// # Errors
+    ///
+    /// Return an error if the position can't be changed
+    fn rewind(&mut self) -> Result<(), EncodingError>;
+
+    /// Reads exactly `buf.len()` bytes. The behavior must be identical to std::io::Read::read_exact
+    /// # Errors
+    ///
+    /// Return an error if the data can't be read
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), EncodingError>;
+}
+
+#[cfg(feature = "std")]
+impl<S: std::io::Read + std::io::Write + std::io::Seek> StripScratch for S {
+    fn rewind(&mut self) -> Result<(), EncodingError> {
+        std::io::Seek::seek(self, std::io::SeekFrom::Start(0))?;
+        Ok(())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), EncodingError> {
+        std::io::Read::read_exact(self, buf)?;
+        Ok(())
+    }
+}
+
+/// Maximum length of the data of a segment, which excludes the marker and the length field
+pub(crate) const MAX_SEGMENT_LENGTH: usize = 65533;
+
 pub(crate) struct JfifWriter<W: JfifWrite> {
     w: W,
     bit_buffer: usize,
@@ -164,6 +244,10 @@ impl<W: JfifWrite> JfifWriter<W> {
         self.w
     }
 
+    pub fn get_ref(&self) -> &W {
+        &self.w
+    }
+
     pub fn write_marker(&mut self, marker: Marker) -> Result<(), EncodingError> {
         self.write(&[0xFF, marker.into()])
     }
@@ -176,9 +260,26 @@ impl<W: JfifWrite> JfifWriter<W> {
         Ok(())
     }
 
-    pub fn write_header(&mut self, density: &Density) -> Result<(), EncodingError> {
+    /// Append the JFIF segment
+    ///
+    /// An RGB thumbnail is part of the JFIF segment, a JPEG thumbnail is written in a
+    /// JFXX extension segment directly after it.
+    pub fn write_header(
+        &mut self,
+        density: &Density,
+        thumbnail: Option<&Thumbnail>,
+    ) -> Result<(), EncodingError> {
+        let (thumbnail_width, thumbnail_height, pixels) = match thumbnail {
+            Some(Thumbnail::Rgb {
+                width,
+                height,
+                pixels,
+            }) => (*width, *height, pixels.as_slice()),
+            _ => (0, 0, [].as_ref()),
+        };
+
         self.write_marker(Marker::APP(0))?;
-        self.write_u16(16)?;
+        self.write_u16(16 + pixels.len() as u16)?;
 
         self.write(b"JFIF\0")?;
         self.write(&[0x01, 0x02])?;
@@ -201,7 +302,19 @@ impl<W: JfifWrite> JfifWriter<W> {
             }
         }
 
-        self.write(&[0x00, 0x00])
+        self.write(&[thumbnail_width, thumbnail_height])?;
+        self.write(pixels)?;
+
+        if let Some(Thumbnail::Jpeg(data)) = thumbnail {
+            self.write_marker(Marker::APP(0))?;
+            self.write_u16(8 + data.len() as u16)?;
+
+            // Extension code for thumbnails coded using JPEG
+            self.write(b"JFXX\0\x10")?;
+            self.write(data)?;
+        }
+
+        Ok(())
     }
 
     /// Append huffman table segment
@@ -236,7 +349,8 @@ impl<W: JfifWrite> JfifWriter<W> {
 
     /// Append a quantization table
     ///
-    /// - `precision`: 0 which means 1 byte per value.
+    /// - `precision`: 0 which means 1 byte per value or 1 for tables with 16 bit values,
+    ///   which only occur for 12 bit samples.
     /// - `dest`: 0 for luma or 1 for chroma tables
     ///
     /// Layout:
@@ -254,12 +368,54 @@ impl<W: JfifWrite> JfifWriter<W> {
         assert!(destination < 4, "Bad destination: {}", destination);
 
         self.write_marker(Marker::DQT)?;
-        self.write_u16(2 + 1 + 64)?;
 
-        self.write_u8(destination)?;
+        if table.is_16_bit() {
+            self.write_u16(2 + 1 + 2 * 64)?;
+            self.write_u8(1 << 4 | destination)?;
+
+            for &v in ZIGZAG.iter() {
+                self.write_u16(table.get(v as usize))?;
+            }
+        } else {
+            self.write_u16(2 + 1 + 64)?;
+            self.write_u8(destination)?;
+
+            for &v in ZIGZAG.iter() {
+                self.write_u8(table.get(v as usize) as u8)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Append arithmetic coding conditioning tables
+    ///
+    /// Writes the DC and AC conditioning of the given destinations.
+    ///
+    /// Layout:
+    /// ```txt
+    /// |--------|---------------|--------------------------|------------------|-----|
+    /// | 0xFFCC | 16 bit length | 4 bit class / 4 bit dest | conditioning val | ... |
+    /// |--------|---------------|--------------------------|------------------|-----|
+    /// ```
+    ///
+    pub fn write_arithmetic_conditioning_segment(
+        &mut self,
+        conditioning: &[ArithmeticConditioning],
+    ) -> Result<(), EncodingError> {
+        assert!(conditioning.len() <= 4, "Too many conditioning tables");
+
+        self.write_marker(Marker::DAC)?;
+        self.write_u16(2 + 2 * 2 * conditioning.len() as u16)?;
 
-        for &v in ZIGZAG.iter() {
-            self.write_u8(table.get(v as usize))?;
+        for (destination, conditioning) in conditioning.iter().enumerate() {
+            let destination = destination as u8;
+
+            self.write_u8(((CodingClass::Dc as u8) << 4) | destination)?;
+            self.write_u8((conditioning.dc_upper << 4) | conditioning.dc_lower)?;
+
+            self.write_u8(((CodingClass::Ac as u8) << 4) | destination)?;
+            self.write_u8(conditioning.ac_kx)?;
         }
 
         Ok(())
@@ -326,27 +482,30 @@ impl<W: JfifWrite> JfifWriter<W> {
         end: usize,
         ac_table: &HuffmanTable,
     ) -> Result<(), EncodingError> {
-        let mut zero_run = 0;
+        let range = (u64::MAX << start) & (u64::MAX >> (64 - end));
+        let mut non_zero = non_zero_mask(block) & range;
 
-        for &value in &block[start..end] {
-            if value == 0 {
-                zero_run += 1;
-            } else {
-                while zero_run > 15 {
-                    self.huffman_encode(0xF0, ac_table)?;
-                    zero_run -= 16;
-                }
+        let mut index = start;
 
-                let (size, value) = get_code(value);
-                let symbol = (zero_run << 4) | size;
+        while non_zero != 0 {
+            let next = non_zero.trailing_zeros() as usize;
+            let mut zero_run = next - index;
 
-                self.huffman_encode_value(size, symbol, value, ac_table)?;
-
-                zero_run = 0;
+            while zero_run > 15 {
+                self.huffman_encode(0xF0, ac_table)?;
+                zero_run -= 16;
             }
+
+            let (size, value) = get_code(block[next]);
+            let symbol = ((zero_run as u8) << 4) | size;
+
+            self.huffman_encode_value(size, symbol, value, ac_table)?;
+
+            index = next + 1;
+            non_zero &= non_zero - 1;
         }
 
-        if zero_run > 0 {
+        if index < end {
             self.huffman_encode(0x00, ac_table)?;
         }
 
@@ -358,18 +517,14 @@ impl<W: JfifWrite> JfifWriter<W> {
         width: u16,
         height: u16,
         components: &[Component],
-        progressive: bool,
+        frame_type: SOFType,
+        precision: u8,
     ) -> Result<(), EncodingError> {
-        if progressive {
-            self.write_marker(Marker::SOF(SOFType::ProgressiveDCT))?;
-        } else {
-            self.write_marker(Marker::SOF(SOFType::BaselineDCT))?;
-        }
+        self.write_marker(Marker::SOF(frame_type))?;
 
         self.write_u16(2 + 1 + 2 + 2 + 1 + (components.len() as u16) * 3)?;
 
-        // Precision
-        self.write_u8(8)?;
+        self.write_u8(precision)?;
 
         self.write_u16(height)?;
         self.write_u16(width)?;
@@ -391,6 +546,7 @@ impl<W: JfifWrite> JfifWriter<W> {
         &mut self,
         components: &[&Component],
         spectral: Option<(u8, u8)>,
+        approximation: Option<(u8, u8)>,
     ) -> Result<(), EncodingError> {
         self.write_marker(Marker::SOS)?;
 
@@ -411,13 +567,42 @@ impl<W: JfifWrite> JfifWriter<W> {
         // End of spectral selection
         self.write_u8(spectral_end)?;
 
+        let (approximation_high, approximation_low) = approximation.unwrap_or((0, 0));
+
         // Successive approximation bit position high and low
-        self.write_u8(0)?;
+        self.write_u8((approximation_high << 4) | approximation_low)?;
 
         Ok(())
     }
 }
 
+/// Returns a mask with bit `i` set if `block[i]` isn't zero
+///
+/// Four coefficients are tested at once by setting the highest bit of each non-zero 16 bit lane,
+/// these bits are then gathered into the top nibble by a multiplication.
+#[inline]
+pub(crate) fn non_zero_mask(block: &[i16; 64]) -> u64 {
+    const LOW_BITS: u64 = 0x7FFF_7FFF_7FFF_7FFF;
+    const HIGH_BITS: u64 = 0x8000_8000_8000_8000;
+    const GATHER: u64 = (1 << 48) | (1 << 33) | (1 << 18) | (1 << 3);
+
+    let mut mask = 0;
+
+    for (i, values) in block.chunks_exact(4).enumerate() {
+        let lanes = (values[0] as u16 as u64)
+            | (values[1] as u16 as u64) << 16
+            | (values[2] as u16 as u64) << 32
+            | (values[3] as u16 as u64) << 48;
+
+        // Adding 0x7FFF to the lower 15 bits sets bit 15 if any of them is set
+        let non_zero = (((lanes & LOW_BITS) + LOW_BITS) | lanes) & HIGH_BITS;
+
+        mask |= ((non_zero >> 15).wrapping_mul(GATHER) >> 48) << (i * 4);
+    }
+
+    mask
+}
+
 #[inline]
 pub(crate) fn get_code(value: i16) -> (u8, u16) {
     let temp = value - (value.is_negative() as i16);
@@ -430,7 +615,101 @@ pub(crate) fn get_code(value: i16) -> (u8, u16) {
      */
     let num_bits = 15 - (temp2 << 1 | 1).leading_zeros() as u16;
 
-    let coefficient = temp & ((1 << num_bits as usize) - 1);
+    // Computed unsigned to support values with 15 bits of 12 bit images
+    let coefficient = (temp as u16) & ((1u16 << num_bits) - 1);
+
+    (num_bits as u8, coefficient)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::{get_code, non_zero_mask, JfifWriter};
+    use crate::huffman::HuffmanTable;
+
+    /// Straightforward AC encoding to check the optimized version against
+    fn write_ac_block_reference(
+        writer: &mut JfifWriter<Vec<u8>>,
+        block: &[i16; 64],
+        start: usize,
+        end: usize,
+        ac_table: &HuffmanTable,
+    ) {
+        let mut zero_run = 0;
 
-    (num_bits as u8, coefficient as u16)
+        for &value in &block[start..end] {
+            if value == 0 {
+                zero_run += 1;
+            } else {
+                while zero_run > 15 {
+                    writer.huffman_encode(0xF0, ac_table).unwrap();
+                    zero_run -= 16;
+                }
+
+                let (size, value) = get_code(value);
+                let symbol = (zero_run << 4) | size;
+
+                writer
+                    .huffman_encode_value(size, symbol, value, ac_table)
+                    .unwrap();
+
+                zero_run = 0;
+            }
+        }
+
+        if zero_run > 0 {
+            writer.huffman_encode(0x00, ac_table).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_non_zero_mask() {
+        let mut block = [0i16; 64];
+        assert_eq!(non_zero_mask(&block), 0);
+
+        for (i, value) in [1, -1, i16::MIN, i16::MAX, 0x100, -0x100]
+            .into_iter()
+            .enumerate()
+        {
+            block = [0; 64];
+            block[i * 11] = value;
+
+            assert_eq!(non_zero_mask(&block), 1 << (i * 11));
+        }
+
+        assert_eq!(non_zero_mask(&[-1; 64]), u64::MAX);
+    }
+
+    #[test]
+    fn test_write_ac_block() {
+        let table = HuffmanTable::default_luma_ac();
+
+        let mut seed = 1u32;
+
+        for _ in 0..500 {
+            let mut block = [0i16; 64];
+
+            for v in block.iter_mut() {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+
+                // Mostly zeros with long zero runs
+                if (seed >> 16) % 8 == 0 {
+                    *v = ((seed >> 8) % 2047) as i16 - 1023;
+                }
+            }
+
+            for (start, end) in [(1, 64), (1, 6), (6, 64), (20, 21), (63, 64)] {
+                let mut expected = JfifWriter::new(Vec::new());
+                write_ac_block_reference(&mut expected, &block, start, end, &table);
+                expected.finalize_bit_buffer().unwrap();
+
+                let mut writer = JfifWriter::new(Vec::new());
+                writer.write_ac_block(&block, start, end, &table).unwrap();
+                writer.finalize_bit_buffer().unwrap();
+
+                assert_eq!(writer.into_inner(), expected.into_inner());
+            }
+        }
+    }
 }
@@ -0,0 +1,299 @@
+/*
+ * XMP metadata
+ *
+ * The packets are stored in APP1 segments as described in
+ * XMP Specification Part 3 (2016) 1.1.3 JPEG.
+ */
+
+use alloc::format;
+use alloc::vec::Vec;
+
+use crate::writer::MAX_SEGMENT_LENGTH;
+use crate::EncodingError;
+
+/// Namespace which precedes a standard XMP packet
+const XMP_NAMESPACE: &[u8; 29] = b"http://ns.adobe.com/xap/1.0/\0";
+
+/// Namespace which precedes a chunk of the extended XMP
+const EXTENDED_XMP_NAMESPACE: &[u8; 35] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// Maximum length of a standard XMP packet
+const MAX_PACKET_LENGTH: usize = MAX_SEGMENT_LENGTH - XMP_NAMESPACE.len();
+
+/// Maximum length of the extended XMP in one segment after the GUID, full length and offset
+const MAX_CHUNK_LENGTH: usize = MAX_SEGMENT_LENGTH - EXTENDED_XMP_NAMESPACE.len() - 32 - 4 - 4;
+
+/// Returns the data of the APP1 segments for the XMP packet
+///
+/// Packets which exceed a single segment are stored completely as extended XMP.
+/// The standard packet then only contains the `xmpNote:HasExtendedXMP` property with the
+/// GUID of the extended XMP, so readers which ignore extended XMP see none of the properties.
+pub(crate) fn xmp_segments(xmp: &[u8]) -> Result<Vec<Vec<u8>>, EncodingError> {
+    if xmp.len() <= MAX_PACKET_LENGTH {
+        return Ok(alloc::vec![standard_segment(xmp)]);
+    }
+
+    let guid = extended_xmp_guid(xmp);
+
+    let standard = format!(
+        concat!(
+            "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>",
+            "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">",
+            "<rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">",
+            "<rdf:Description rdf:about=\"\" xmlns:xmpNote=\"http://ns.adobe.com/xmp/note/\"",
+            " xmpNote:HasExtendedXMP=\"{}\"/>",
+            "</rdf:RDF></x:xmpmeta>",
+            "<?xpacket end=\"w\"?>"
+        ),
+        core::str::from_utf8(&guid).unwrap()
+    );
+
+    let mut segments = alloc::vec![standard_segment(standard.as_bytes())];
+    segments.extend(extended_segments(xmp, &guid)?);
+
+    Ok(segments)
+}
+
+/// Returns the data of the APP1 segments for a standard packet and its extended XMP
+///
+/// The standard packet has to fit into a single segment and must reference the extended XMP
+/// with the `xmpNote:HasExtendedXMP` property.
+pub(crate) fn extended_xmp_segments(
+    standard: &[u8],
+    extended: &[u8],
+) -> Result<Vec<Vec<u8>>, EncodingError> {
+    if standard.len() > MAX_PACKET_LENGTH {
+        return Err(EncodingError::Write(format!(
+            "Standard XMP packet exceeds maximum allowed data length of {}: {}",
+            MAX_PACKET_LENGTH,
+            standard.len()
+        )));
+    }
+
+    let guid = extended_xmp_guid(extended);
+
+    if !standard.windows(guid.len()).any(|window| window == guid) {
+        return Err(EncodingError::Write(format!(
+            "Standard XMP packet doesn't reference the GUID of the extended XMP: {}",
+            core::str::from_utf8(&guid).unwrap()
+        )));
+    }
+
+    let mut segments = alloc::vec![standard_segment(standard)];
+    segments.extend(extended_segments(extended, &guid)?);
+
+    Ok(segments)
+}
+
+/// Returns the GUID of the extended XMP, which is the MD5 digest of its data as
+/// 32 uppercase hexadecimal digits
+fn extended_xmp_guid(extended: &[u8]) -> Vec<u8> {
+    md5(extended)
+        .iter()
+        .flat_map(|&b| [b >> 4, b & 0xF])
+        .map(|d| b"0123456789ABCDEF"[usize::from(d)])
+        .collect()
+}
+
+fn standard_segment(packet: &[u8]) -> Vec<u8> {
+    let mut segment = Vec::with_capacity(XMP_NAMESPACE.len() + packet.len());
+    segment.extend_from_slice(XMP_NAMESPACE);
+    segment.extend_from_slice(packet);
+    segment
+}
+
+/// Split the extended XMP into chunks with the GUID, full length and offset
+fn extended_segments(extended: &[u8], guid: &[u8]) -> Result<Vec<Vec<u8>>, EncodingError> {
+    let full_length = u32::try_from(extended.len()).map_err(|_| {
+        EncodingError::Write(format!(
+            "XMP packet exceeds maximum allowed data length: {}",
+            extended.len()
+        ))
+    })?;
+
+    let segments = extended
+        .chunks(MAX_CHUNK_LENGTH)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let offset = (i * MAX_CHUNK_LENGTH) as u32;
+
+            let mut segment = Vec::with_capacity(MAX_SEGMENT_LENGTH);
+            segment.extend_from_slice(EXTENDED_XMP_NAMESPACE);
+            segment.extend_from_slice(guid);
+            segment.extend_from_slice(&full_length.to_be_bytes());
+            segment.extend_from_slice(&offset.to_be_bytes());
+            segment.extend_from_slice(chunk);
+            segment
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+/// MD5 message digest as specified in RFC 1321
+fn md5(data: &[u8]) -> [u8; 16] {
+    const SHIFTS: [u32; 64] = [
+        7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 7, 12, 17, 22, 5, 9, 14, 20, 5, 9, 14, 20, 5,
+        9, 14, 20, 5, 9, 14, 20, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 4, 11, 16, 23, 6, 10,
+        15, 21, 6, 10, 15, 21, 6, 10, 15, 21, 6, 10, 15, 21,
+    ];
+
+    // Integer part of abs(sin(i + 1)) * 2^32
+    const K: [u32; 64] = [
+        0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee, 0xf57c0faf, 0x4787c62a, 0xa8304613,
+        0xfd469501, 0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be, 0x6b901122, 0xfd987193,
+        0xa679438e, 0x49b40821, 0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa, 0xd62f105d,
+        0x02441453, 0xd8a1e681, 0xe7d3fbc8, 0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+        0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a, 0xfffa3942, 0x8771f681, 0x6d9d6122,
+        0xfde5380c, 0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70, 0x289b7ec6, 0xeaa127fa,
+        0xd4ef3085, 0x04881d05, 0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665, 0xf4292244,
+        0x432aff97, 0xab9423a7, 0xfc93a039, 0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+        0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1, 0xf7537e82, 0xbd3af235, 0x2ad7d2bb,
+        0xeb86d391,
+    ];
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+
+    // Padding with a single 1 bit, zeros and the message length in bits
+    let mut tail = Vec::with_capacity(128);
+    tail.extend_from_slice(&data[data.len() / 64 * 64..]);
+    tail.push(0x80);
+
+    while tail.len() % 64 != 56 {
+        tail.push(0);
+    }
+
+    tail.extend_from_slice(&((data.len() as u64).wrapping_mul(8)).to_le_bytes());
+
+    for chunk in data.chunks_exact(64).chain(tail.chunks_exact(64)) {
+        let mut m = [0u32; 16];
+
+        for (word, bytes) in m.iter_mut().zip(chunk.chunks_exact(4)) {
+            *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+
+        let [mut a, mut b, mut c, mut d] = state;
+
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+
+            let f = f.wrapping_add(a).wrapping_add(K[i]).wrapping_add(m[g]);
+
+            a = d;
+            d = c;
+            c = b;
+            b = b.wrapping_add(f.rotate_left(SHIFTS[i]));
+        }
+
+        state[0] = state[0].wrapping_add(a);
+        state[1] = state[1].wrapping_add(b);
+        state[2] = state[2].wrapping_add(c);
+        state[3] = state[3].wrapping_add(d);
+    }
+
+    let mut digest = [0u8; 16];
+
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state.iter()) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_md5() {
+        let hex = |digest: [u8; 16]| {
+            digest
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<alloc::string::String>()
+        };
+
+        assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(
+            hex(md5(b"The quick brown fox jumps over the lazy dog")),
+            "9e107d9d372bb6826bd81d3542a419d6"
+        );
+
+        // Messages which need an additional block for the padding
+        assert_eq!(hex(md5(&[b'a'; 56])), "3b0c8ac703f828b04c6c197006d17218");
+        assert_eq!(hex(md5(&[b'a'; 1000])), "cabe45dcc9ae5b66ba86600cca6b8ba8");
+    }
+
+    #[test]
+    fn test_xmp_segments() {
+        let packet = b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>";
+
+        let segments = xmp_segments(packet).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(&segments[0][..29], XMP_NAMESPACE);
+        assert_eq!(&segments[0][29..], packet);
+
+        let segments = xmp_segments(&[b'x'; MAX_PACKET_LENGTH]).unwrap();
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].len(), MAX_SEGMENT_LENGTH);
+
+        let xmp: Vec<u8> = (0..MAX_CHUNK_LENGTH * 2 + 100)
+            .map(|i| b'a' + (i % 26) as u8)
+            .collect();
+
+        let segments = xmp_segments(&xmp).unwrap();
+        assert_eq!(segments.len(), 4);
+
+        let guid = &segments[1][35..67];
+        let standard = core::str::from_utf8(&segments[0][29..]).unwrap();
+        assert!(standard.contains(&format!(
+            "xmpNote:HasExtendedXMP=\"{}\"",
+            core::str::from_utf8(guid).unwrap()
+        )));
+
+        let mut extended = Vec::new();
+
+        for segment in &segments[1..] {
+            assert!(segment.len() <= MAX_SEGMENT_LENGTH);
+            assert_eq!(&segment[..35], EXTENDED_XMP_NAMESPACE);
+            assert_eq!(&segment[35..67], guid);
+            assert_eq!(&segment[67..71], &(xmp.len() as u32).to_be_bytes());
+            assert_eq!(&segment[71..75], &(extended.len() as u32).to_be_bytes());
+
+            extended.extend_from_slice(&segment[75..]);
+        }
+
+        assert_eq!(extended, xmp);
+    }
+
+    #[test]
+    fn test_extended_xmp_segments() {
+        let extended: Vec<u8> = (0..MAX_CHUNK_LENGTH + 100)
+            .map(|i| b'a' + (i % 26) as u8)
+            .collect();
+
+        let guid = extended_xmp_guid(&extended);
+        let standard = format!(
+            "<rdf:Description dc:format=\"image/jpeg\" xmpNote:HasExtendedXMP=\"{}\"/>",
+            core::str::from_utf8(&guid).unwrap()
+        );
+
+        let segments = extended_xmp_segments(standard.as_bytes(), &extended).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(&segments[0][29..], standard.as_bytes());
+        assert_eq!(&segments[1][35..67], &guid[..]);
+        assert_eq!([&segments[1][75..], &segments[2][75..]].concat(), extended);
+
+        // The standard packet has to reference the extended XMP and fit into a segment
+        assert!(extended_xmp_segments(b"<rdf:Description/>", &extended).is_err());
+
+        let mut large = standard.into_bytes();
+        large.resize(MAX_PACKET_LENGTH + 1, b' ');
+        assert!(extended_xmp_segments(&large, &extended).is_err());
+    }
+}
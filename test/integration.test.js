@@ -1,4 +1,5 @@
 import init, { StreamingJpegEncoder, WasmColorType } from '../pkg/esm/jpeg_encoder.js';
+import initSimd, { StreamingJpegEncoder as SimdStreamingJpegEncoder } from '../pkg/esm/jpeg_encoder_simd.js';
 import { strict as assert } from 'assert';
 import { readFileSync } from 'fs';
 import { fileURLToPath } from 'url';
@@ -11,6 +12,9 @@ const __dirname = dirname(__filename);
 const wasmBytes = readFileSync(join(__dirname, '../pkg/esm/jpeg_encoder_bg.wasm'));
 await init(wasmBytes);
 
+const simdWasmBytes = readFileSync(join(__dirname, '../pkg/esm/jpeg_encoder_simd_bg.wasm'));
+await initSimd(simdWasmBytes);
+
 /**
  * Creates a simple solid color image
  */
@@ -335,6 +339,32 @@ function main() {
         failed++;
     }
 
+    // Test: SIMD build
+    if (test('SIMD build produces identical output', () => {
+        const width = 67;
+        const height = 45;
+
+        for (const [colorType, bytesPerPixel] of [[WasmColorType.Rgb, 3], [WasmColorType.Rgba, 4], [WasmColorType.Luma, 1]]) {
+            const pixels = new Uint8Array(width * height * bytesPerPixel);
+            for (let i = 0; i < pixels.length; i++) {
+                pixels[i] = (i * 97 + (i >> 7)) % 256;
+            }
+
+            const encoder = new StreamingJpegEncoder(width, height, colorType, 85);
+            const expected = concatUint8Arrays(encoder.encode_strip(pixels), encoder.finish());
+
+            const simdEncoder = new SimdStreamingJpegEncoder(width, height, colorType, 85);
+            const actual = concatUint8Arrays(simdEncoder.encode_strip(pixels), simdEncoder.finish());
+
+            validateJpeg(actual);
+            assert.deepEqual(actual, expected, 'SIMD output should match the baseline output');
+        }
+    })) {
+        passed++;
+    } else {
+        failed++;
+    }
+
     console.log('\n====================================');
     console.log(`Tests passed: ${passed}`);
     console.log(`Tests failed: ${failed}`);